@@ -1,4 +1,28 @@
+use crate::models::{Preset, REMOVEBG_MODEL_ID, UpscalerModel};
+use crate::presets;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::net::SocketAddr;
+
+/// Minimum TLS protocol version accepted by the HTTPS listener.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// TLS 1.2 and newer.
+    Tls12,
+    /// TLS 1.3 only.
+    Tls13,
+}
+
+/// Where processing results are persisted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Results are not persisted.
+    None,
+    /// Files in `result_storage_dir`.
+    Local,
+    /// Objects in an S3-compatible bucket.
+    S3,
+}
 
 /// Application configuration structure.
 ///
@@ -18,6 +42,220 @@ pub struct Config {
     pub rate_limit_per_second: u64,
     /// Rate limit: burst size
     pub rate_limit_burst: u32,
+    /// Timeout for a single worker health probe, in milliseconds
+    pub health_probe_timeout_ms: u64,
+    /// How long a worker health probe result is reused, in seconds
+    pub health_cache_ttl_secs: u64,
+    /// Time spent reporting "not ready" before shutting down, in seconds
+    pub shutdown_grace_period_secs: u64,
+    /// PEM certificate chain; TLS is enabled when both this and the key are set
+    pub tls_cert_path: Option<String>,
+    /// PEM private key matching the certificate
+    pub tls_key_path: Option<String>,
+    /// Minimum TLS version to accept
+    pub tls_min_version: TlsVersion,
+    /// Whether to advertise HTTP/2 via ALPN
+    pub tls_alpn_h2: bool,
+    /// How often certificate files are checked for changes, in seconds
+    pub tls_reload_interval_secs: u64,
+    /// PEM CA bundle used to verify client certificates (enables mutual TLS)
+    pub tls_client_ca_path: Option<String>,
+    /// Reject connections without a client certificate when mutual TLS is enabled
+    pub tls_client_auth_required: bool,
+    /// Client certificate common name to tenant mapping
+    pub tls_client_tenants: HashMap<String, String>,
+    /// Public TCP addresses; when empty, `host:port` is used
+    pub listen_addrs: Vec<SocketAddr>,
+    /// Unix domain socket serving the public API (e.g., for sidecars)
+    pub unix_socket_path: Option<String>,
+    /// Permission bits applied to the Unix domain socket
+    pub unix_socket_mode: u32,
+    /// Address of the admin listener serving health, metrics and admin endpoints
+    pub admin_listen_addr: Option<SocketAddr>,
+    /// Maximum request body size for `/removebg`, in bytes
+    pub max_body_bytes_removebg: usize,
+    /// Maximum request body size for `/upscale`, in bytes
+    pub max_body_bytes_upscale: usize,
+    /// Maximum request body size for `/batch`, in bytes
+    pub max_body_bytes_batch: usize,
+    /// Maximum size of an uploaded image field, in bytes
+    pub max_image_field_bytes: usize,
+    /// Maximum size of a multipart text field, in bytes
+    pub max_text_field_bytes: usize,
+    /// Uploads larger than this are spooled to a temporary file, in bytes
+    pub upload_spool_threshold_bytes: usize,
+    /// Directory for spooled uploads; the system temp directory when unset
+    pub upload_temp_dir: Option<String>,
+    /// Reject image and callback URLs resolving to private or local addresses
+    pub fetch_deny_private_networks: bool,
+    /// Maximum width of an input image, in pixels
+    pub max_image_width: u32,
+    /// Maximum height of an input image, in pixels
+    pub max_image_height: u32,
+    /// Maximum pixel count of an input image, in megapixels
+    pub max_image_megapixels: f64,
+    /// Maximum pixel count of an `/upscale` result, in megapixels
+    pub max_upscale_output_megapixels: f64,
+    /// Inputs above this many megapixels are upscaled in tiles; at the
+    /// default scale of 4 only inputs up to a sixteenth of
+    /// `max_upscale_output_megapixels` are accepted, so this should stay
+    /// below that
+    pub upscale_tile_threshold_megapixels: f64,
+    /// Edge length of an upscale tile, in input pixels
+    pub upscale_tile_size: u32,
+    /// Overlap between neighbouring tiles, in input pixels
+    pub upscale_tile_overlap: u32,
+    /// Maximum number of tiles sent to the upscaler worker at once
+    pub upscale_tile_concurrency: usize,
+    /// `/removebg` inputs above this many megapixels use the low-resolution mask path
+    pub removebg_mask_threshold_megapixels: f64,
+    /// Longer side of the downsampled input sent on the mask path, in pixels
+    pub removebg_mask_size: u32,
+    /// Model IDs that are rejected and reported as unavailable
+    pub disabled_models: Vec<String>,
+    /// Model IDs left out of the `GET /models` catalog
+    pub hidden_models: Vec<String>,
+    /// Named presets loaded from `PRESETS_PATH`
+    pub presets: BTreeMap<String, Preset>,
+    /// Maximum number of images in a `/batch` request
+    pub batch_max_items: usize,
+    /// Batch images processed at once per request, or per tenant
+    pub batch_concurrency: usize,
+    /// Per-tenant overrides of `batch_max_items`
+    pub batch_tenant_max_items: HashMap<String, usize>,
+    /// Per-tenant overrides of `batch_concurrency`
+    pub batch_tenant_concurrency: HashMap<String, usize>,
+    /// Total size of the encoded results a `/batch` request may hold
+    pub batch_max_result_bytes: usize,
+    /// Secret used to sign webhook events; callbacks are refused without one
+    pub webhook_secret: Option<String>,
+    /// Tenant to webhook secret mapping, overriding `webhook_secret`
+    pub webhook_tenant_secrets: HashMap<String, String>,
+    /// Delivery attempts made for a webhook event before giving up
+    pub webhook_max_attempts: u32,
+    /// Delay before the first webhook retry, doubled on each retry, in milliseconds
+    pub webhook_retry_base_ms: u64,
+    /// Timeout for a single webhook delivery attempt, in milliseconds
+    pub webhook_timeout_ms: u64,
+    /// How long finished jobs and their results are kept, in seconds
+    pub job_ttl_secs: u64,
+    /// Background jobs running at once across all clients
+    pub job_max_running: usize,
+    /// Background jobs running at once per tenant; clients without a tenant share one limit
+    pub job_tenant_max_running: usize,
+    /// Jobs kept in memory, running or finished
+    pub job_max_stored: usize,
+    /// Total size of the job results kept in memory, in bytes
+    pub job_max_stored_bytes: usize,
+    /// How long shutdown waits for running jobs before cancelling them, in seconds
+    pub job_drain_timeout_secs: u64,
+    /// Backend persisting results
+    pub result_storage: StorageBackend,
+    /// Directory holding results with the local backend
+    pub result_storage_dir: String,
+    /// S3 endpoint (e.g., "https://s3.eu-central-1.amazonaws.com"), addressed path-style
+    pub result_s3_endpoint: String,
+    /// S3 bucket holding results
+    pub result_s3_bucket: String,
+    /// S3 region used for request signing
+    pub result_s3_region: String,
+    /// S3 access key ID
+    pub result_s3_access_key: String,
+    /// S3 secret access key
+    pub result_s3_secret_key: String,
+    /// Prefix of result object keys in the bucket
+    pub result_s3_prefix: String,
+    /// How long stored results are served, in seconds
+    pub result_ttl_secs: u64,
+    /// How often expired results are deleted, in seconds
+    pub result_sweep_interval_secs: u64,
+    /// Keys signing result download URLs as `(key ID, secret)`; the first one signs new URLs
+    pub result_signing_keys: Vec<(String, String)>,
+    /// Lifetime of signed result download URLs, in seconds
+    pub result_url_ttl_secs: u64,
+    /// Secrets signing `/t/...` transformation URLs; the first one signs new URLs
+    pub transform_signing_keys: Vec<String>,
+    /// `max-age` of successful transformation responses, in seconds
+    pub transform_max_age_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3000,
+            modal_removebg_url: "http://localhost:8000".to_string(),
+            modal_upscaler_url: "http://localhost:8001".to_string(),
+            rate_limit_per_second: 50,
+            rate_limit_burst: 100,
+            health_probe_timeout_ms: 2000,
+            health_cache_ttl_secs: 10,
+            shutdown_grace_period_secs: 0,
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_min_version: TlsVersion::Tls12,
+            tls_alpn_h2: true,
+            tls_reload_interval_secs: 30,
+            tls_client_ca_path: None,
+            tls_client_auth_required: true,
+            tls_client_tenants: HashMap::new(),
+            listen_addrs: Vec::new(),
+            unix_socket_path: None,
+            unix_socket_mode: 0o660,
+            admin_listen_addr: None,
+            max_body_bytes_removebg: 25 * 1024 * 1024,
+            max_body_bytes_upscale: 25 * 1024 * 1024,
+            max_body_bytes_batch: 100 * 1024 * 1024,
+            max_image_field_bytes: 20 * 1024 * 1024,
+            max_text_field_bytes: 4096,
+            upload_spool_threshold_bytes: 1024 * 1024,
+            upload_temp_dir: None,
+            fetch_deny_private_networks: true,
+            max_image_width: 8192,
+            max_image_height: 8192,
+            max_image_megapixels: 40.0,
+            max_upscale_output_megapixels: 64.0,
+            upscale_tile_threshold_megapixels: 2.0,
+            upscale_tile_size: 512,
+            upscale_tile_overlap: 32,
+            upscale_tile_concurrency: 4,
+            removebg_mask_threshold_megapixels: 4.0,
+            removebg_mask_size: 1024,
+            disabled_models: Vec::new(),
+            hidden_models: Vec::new(),
+            presets: BTreeMap::new(),
+            batch_max_items: 20,
+            batch_concurrency: 4,
+            batch_tenant_max_items: HashMap::new(),
+            batch_tenant_concurrency: HashMap::new(),
+            batch_max_result_bytes: 104857600,
+            webhook_secret: None,
+            webhook_tenant_secrets: HashMap::new(),
+            webhook_max_attempts: 5,
+            webhook_retry_base_ms: 1000,
+            webhook_timeout_ms: 10000,
+            job_ttl_secs: 3600,
+            job_max_running: 64,
+            job_tenant_max_running: 16,
+            job_max_stored: 10000,
+            job_max_stored_bytes: 268435456,
+            job_drain_timeout_secs: 30,
+            result_storage: StorageBackend::None,
+            result_storage_dir: "results".to_string(),
+            result_s3_endpoint: String::new(),
+            result_s3_bucket: String::new(),
+            result_s3_region: "us-east-1".to_string(),
+            result_s3_access_key: String::new(),
+            result_s3_secret_key: String::new(),
+            result_s3_prefix: "results/".to_string(),
+            result_ttl_secs: 86400,
+            result_sweep_interval_secs: 300,
+            result_signing_keys: Vec::new(),
+            result_url_ttl_secs: 900,
+            transform_signing_keys: Vec::new(),
+            transform_max_age_secs: 31536000,
+        }
+    }
 }
 
 impl Config {
@@ -46,6 +284,293 @@ impl Config {
             .unwrap_or_else(|_| "100".to_string())
             .parse::<u32>()
             .expect("RATE_LIMIT_BURST must be a valid u32");
+        let health_probe_timeout_ms = env::var("HEALTH_PROBE_TIMEOUT_MS")
+            .unwrap_or_else(|_| "2000".to_string())
+            .parse::<u64>()
+            .expect("HEALTH_PROBE_TIMEOUT_MS must be a valid u64");
+        let health_cache_ttl_secs = env::var("HEALTH_CACHE_TTL_SECS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<u64>()
+            .expect("HEALTH_CACHE_TTL_SECS must be a valid u64");
+        let shutdown_grace_period_secs = env::var("SHUTDOWN_GRACE_PERIOD_SECS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<u64>()
+            .expect("SHUTDOWN_GRACE_PERIOD_SECS must be a valid u64");
+        let tls_cert_path = env::var("TLS_CERT_PATH").ok();
+        let tls_key_path = env::var("TLS_KEY_PATH").ok();
+        let tls_min_version = match env::var("TLS_MIN_VERSION")
+            .unwrap_or_else(|_| "1.2".to_string())
+            .as_str()
+        {
+            "1.2" => TlsVersion::Tls12,
+            "1.3" => TlsVersion::Tls13,
+            other => panic!("TLS_MIN_VERSION must be 1.2 or 1.3, got {}", other),
+        };
+        let tls_alpn_h2 = env::var("TLS_ALPN_H2")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse::<bool>()
+            .expect("TLS_ALPN_H2 must be true or false");
+        let tls_reload_interval_secs = env::var("TLS_RELOAD_INTERVAL_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .expect("TLS_RELOAD_INTERVAL_SECS must be a valid u64");
+        let tls_client_ca_path = env::var("TLS_CLIENT_CA_PATH").ok();
+        let tls_client_auth_required = env::var("TLS_CLIENT_AUTH_REQUIRED")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse::<bool>()
+            .expect("TLS_CLIENT_AUTH_REQUIRED must be true or false");
+        let tls_client_tenants = parse_pairs(
+            "TLS_CLIENT_TENANTS",
+            &env::var("TLS_CLIENT_TENANTS").unwrap_or_default(),
+        );
+        let listen_addrs = env::var("LISTEN_ADDRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .expect("LISTEN_ADDRS must be a comma-separated list of socket addresses")
+            })
+            .collect();
+        let unix_socket_path = env::var("UNIX_SOCKET_PATH").ok();
+        let unix_socket_mode = u32::from_str_radix(
+            &env::var("UNIX_SOCKET_MODE").unwrap_or_else(|_| "660".to_string()),
+            8,
+        )
+        .expect("UNIX_SOCKET_MODE must be an octal permission mode");
+        let admin_listen_addr = env::var("ADMIN_LISTEN_ADDR").ok().map(|addr| {
+            addr.parse::<SocketAddr>()
+                .expect("ADMIN_LISTEN_ADDR must be a valid socket address")
+        });
+        let max_body_bytes_removebg = env::var("MAX_BODY_BYTES_REMOVEBG")
+            .unwrap_or_else(|_| "26214400".to_string())
+            .parse::<usize>()
+            .expect("MAX_BODY_BYTES_REMOVEBG must be a valid usize");
+        let max_body_bytes_upscale = env::var("MAX_BODY_BYTES_UPSCALE")
+            .unwrap_or_else(|_| "26214400".to_string())
+            .parse::<usize>()
+            .expect("MAX_BODY_BYTES_UPSCALE must be a valid usize");
+        let max_body_bytes_batch = env::var("MAX_BODY_BYTES_BATCH")
+            .unwrap_or_else(|_| "104857600".to_string())
+            .parse::<usize>()
+            .expect("MAX_BODY_BYTES_BATCH must be a valid usize");
+        let max_image_field_bytes = env::var("MAX_IMAGE_FIELD_BYTES")
+            .unwrap_or_else(|_| "20971520".to_string())
+            .parse::<usize>()
+            .expect("MAX_IMAGE_FIELD_BYTES must be a valid usize");
+        let max_text_field_bytes = env::var("MAX_TEXT_FIELD_BYTES")
+            .unwrap_or_else(|_| "4096".to_string())
+            .parse::<usize>()
+            .expect("MAX_TEXT_FIELD_BYTES must be a valid usize");
+        let upload_spool_threshold_bytes = env::var("UPLOAD_SPOOL_THRESHOLD_BYTES")
+            .unwrap_or_else(|_| "1048576".to_string())
+            .parse::<usize>()
+            .expect("UPLOAD_SPOOL_THRESHOLD_BYTES must be a valid usize");
+        let upload_temp_dir = env::var("UPLOAD_TEMP_DIR").ok();
+        let fetch_deny_private_networks = env::var("FETCH_DENY_PRIVATE_NETWORKS")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse::<bool>()
+            .expect("FETCH_DENY_PRIVATE_NETWORKS must be true or false");
+        let max_image_width = env::var("MAX_IMAGE_WIDTH")
+            .unwrap_or_else(|_| "8192".to_string())
+            .parse::<u32>()
+            .expect("MAX_IMAGE_WIDTH must be a valid u32");
+        let max_image_height = env::var("MAX_IMAGE_HEIGHT")
+            .unwrap_or_else(|_| "8192".to_string())
+            .parse::<u32>()
+            .expect("MAX_IMAGE_HEIGHT must be a valid u32");
+        let max_image_megapixels = env::var("MAX_IMAGE_MEGAPIXELS")
+            .unwrap_or_else(|_| "40".to_string())
+            .parse::<f64>()
+            .expect("MAX_IMAGE_MEGAPIXELS must be a valid number");
+        let max_upscale_output_megapixels = env::var("MAX_UPSCALE_OUTPUT_MEGAPIXELS")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse::<f64>()
+            .expect("MAX_UPSCALE_OUTPUT_MEGAPIXELS must be a valid number");
+        let upscale_tile_threshold_megapixels = env::var("UPSCALE_TILE_THRESHOLD_MEGAPIXELS")
+            .unwrap_or_else(|_| "2".to_string())
+            .parse::<f64>()
+            .expect("UPSCALE_TILE_THRESHOLD_MEGAPIXELS must be a valid number");
+        let upscale_tile_size = env::var("UPSCALE_TILE_SIZE")
+            .unwrap_or_else(|_| "512".to_string())
+            .parse::<u32>()
+            .expect("UPSCALE_TILE_SIZE must be a valid u32");
+        let upscale_tile_overlap = env::var("UPSCALE_TILE_OVERLAP")
+            .unwrap_or_else(|_| "32".to_string())
+            .parse::<u32>()
+            .expect("UPSCALE_TILE_OVERLAP must be a valid u32");
+        assert!(
+            upscale_tile_overlap < upscale_tile_size / 2,
+            "UPSCALE_TILE_OVERLAP must be less than half of UPSCALE_TILE_SIZE"
+        );
+        let upscale_tile_concurrency = env::var("UPSCALE_TILE_CONCURRENCY")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("UPSCALE_TILE_CONCURRENCY must be a positive integer");
+        let removebg_mask_threshold_megapixels = env::var("REMOVEBG_MASK_THRESHOLD_MEGAPIXELS")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse::<f64>()
+            .expect("REMOVEBG_MASK_THRESHOLD_MEGAPIXELS must be a valid number");
+        let removebg_mask_size = env::var("REMOVEBG_MASK_SIZE")
+            .unwrap_or_else(|_| "1024".to_string())
+            .parse::<u32>()
+            .expect("REMOVEBG_MASK_SIZE must be a valid u32");
+        let disabled_models = parse_models(
+            "DISABLED_MODELS",
+            &env::var("DISABLED_MODELS").unwrap_or_default(),
+        );
+        let hidden_models = parse_models(
+            "HIDDEN_MODELS",
+            &env::var("HIDDEN_MODELS").unwrap_or_default(),
+        );
+        let presets = env::var("PRESETS_PATH")
+            .ok()
+            .map(|path| presets::load(&path).unwrap_or_else(|e| panic!("PRESETS_PATH: {}", e)))
+            .unwrap_or_default();
+        let batch_max_items = env::var("BATCH_MAX_ITEMS")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("BATCH_MAX_ITEMS must be a positive integer");
+        let batch_concurrency = env::var("BATCH_CONCURRENCY")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("BATCH_CONCURRENCY must be a positive integer");
+        let batch_tenant_max_items = parse_counts(
+            "BATCH_TENANT_MAX_ITEMS",
+            &env::var("BATCH_TENANT_MAX_ITEMS").unwrap_or_default(),
+        );
+        let batch_tenant_concurrency = parse_counts(
+            "BATCH_TENANT_CONCURRENCY",
+            &env::var("BATCH_TENANT_CONCURRENCY").unwrap_or_default(),
+        );
+        let batch_max_result_bytes = env::var("BATCH_MAX_RESULT_BYTES")
+            .unwrap_or_else(|_| "104857600".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("BATCH_MAX_RESULT_BYTES must be a positive integer");
+        let webhook_secret = env::var("WEBHOOK_SECRET").ok();
+        let webhook_tenant_secrets = parse_pairs(
+            "WEBHOOK_TENANT_SECRETS",
+            &env::var("WEBHOOK_TENANT_SECRETS").unwrap_or_default(),
+        );
+        let webhook_max_attempts = env::var("WEBHOOK_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse::<u32>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("WEBHOOK_MAX_ATTEMPTS must be a positive integer");
+        let webhook_retry_base_ms = env::var("WEBHOOK_RETRY_BASE_MS")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse::<u64>()
+            .expect("WEBHOOK_RETRY_BASE_MS must be a valid u64");
+        let webhook_timeout_ms = env::var("WEBHOOK_TIMEOUT_MS")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse::<u64>()
+            .expect("WEBHOOK_TIMEOUT_MS must be a valid u64");
+        let job_ttl_secs = env::var("JOB_TTL_SECS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse::<u64>()
+            .expect("JOB_TTL_SECS must be a valid u64");
+        let job_max_running = env::var("JOB_MAX_RUNNING")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("JOB_MAX_RUNNING must be a positive integer");
+        let job_tenant_max_running = env::var("JOB_TENANT_MAX_RUNNING")
+            .unwrap_or_else(|_| "16".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("JOB_TENANT_MAX_RUNNING must be a positive integer");
+        let job_max_stored = env::var("JOB_MAX_STORED")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("JOB_MAX_STORED must be a positive integer");
+        let job_max_stored_bytes = env::var("JOB_MAX_STORED_BYTES")
+            .unwrap_or_else(|_| "268435456".to_string())
+            .parse::<usize>()
+            .expect("JOB_MAX_STORED_BYTES must be a valid usize");
+        let job_drain_timeout_secs = env::var("JOB_DRAIN_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .expect("JOB_DRAIN_TIMEOUT_SECS must be a valid u64");
+        let result_storage = match env::var("RESULT_STORAGE")
+            .unwrap_or_else(|_| "none".to_string())
+            .as_str()
+        {
+            "none" => StorageBackend::None,
+            "local" => StorageBackend::Local,
+            "s3" => StorageBackend::S3,
+            other => panic!("RESULT_STORAGE must be none, local or s3, got {}", other),
+        };
+        let result_storage_dir =
+            env::var("RESULT_STORAGE_DIR").unwrap_or_else(|_| "results".to_string());
+        let s3_setting = |name: &str| {
+            let value = env::var(name).unwrap_or_default();
+            if result_storage == StorageBackend::S3 && value.is_empty() {
+                panic!("{} must be set when RESULT_STORAGE is s3", name);
+            }
+            value
+        };
+        let result_s3_endpoint = s3_setting("RESULT_S3_ENDPOINT");
+        let result_s3_bucket = s3_setting("RESULT_S3_BUCKET");
+        let result_s3_access_key = s3_setting("RESULT_S3_ACCESS_KEY");
+        let result_s3_secret_key = s3_setting("RESULT_S3_SECRET_KEY");
+        let result_s3_region =
+            env::var("RESULT_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let result_s3_prefix =
+            env::var("RESULT_S3_PREFIX").unwrap_or_else(|_| "results/".to_string());
+        let result_ttl_secs = env::var("RESULT_TTL_SECS")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse::<u64>()
+            .expect("RESULT_TTL_SECS must be a valid u64");
+        let result_sweep_interval_secs = env::var("RESULT_SWEEP_INTERVAL_SECS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse::<u64>()
+            .ok()
+            .filter(|n| *n > 0)
+            .expect("RESULT_SWEEP_INTERVAL_SECS must be a positive integer");
+        let result_signing_keys: Vec<(String, String)> = parse_pairs(
+            "RESULT_SIGNING_KEYS",
+            &env::var("RESULT_SIGNING_KEYS").unwrap_or_default(),
+        );
+        for (index, (id, secret)) in result_signing_keys.iter().enumerate() {
+            if id.is_empty() || secret.is_empty() {
+                panic!("RESULT_SIGNING_KEYS entries must look like id=secret");
+            }
+            if result_signing_keys[..index]
+                .iter()
+                .any(|(other, _)| other == id)
+            {
+                panic!("RESULT_SIGNING_KEYS lists key '{}' twice", id);
+            }
+        }
+        let result_url_ttl_secs = env::var("RESULT_URL_TTL_SECS")
+            .unwrap_or_else(|_| "900".to_string())
+            .parse::<u64>()
+            .expect("RESULT_URL_TTL_SECS must be a valid u64");
+        let transform_signing_keys = env::var("TRANSFORM_SIGNING_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect();
+        let transform_max_age_secs = env::var("TRANSFORM_MAX_AGE_SECS")
+            .unwrap_or_else(|_| "31536000".to_string())
+            .parse::<u64>()
+            .expect("TRANSFORM_MAX_AGE_SECS must be a valid u64");
 
         Self {
             host,
@@ -54,6 +579,182 @@ impl Config {
             modal_upscaler_url,
             rate_limit_per_second,
             rate_limit_burst,
+            health_probe_timeout_ms,
+            health_cache_ttl_secs,
+            shutdown_grace_period_secs,
+            tls_cert_path,
+            tls_key_path,
+            tls_min_version,
+            tls_alpn_h2,
+            tls_reload_interval_secs,
+            tls_client_ca_path,
+            tls_client_auth_required,
+            tls_client_tenants,
+            listen_addrs,
+            unix_socket_path,
+            unix_socket_mode,
+            admin_listen_addr,
+            max_body_bytes_removebg,
+            max_body_bytes_upscale,
+            max_body_bytes_batch,
+            max_image_field_bytes,
+            max_text_field_bytes,
+            upload_spool_threshold_bytes,
+            upload_temp_dir,
+            fetch_deny_private_networks,
+            max_image_width,
+            max_image_height,
+            max_image_megapixels,
+            max_upscale_output_megapixels,
+            upscale_tile_threshold_megapixels,
+            upscale_tile_size,
+            upscale_tile_overlap,
+            upscale_tile_concurrency,
+            removebg_mask_threshold_megapixels,
+            removebg_mask_size,
+            disabled_models,
+            hidden_models,
+            presets,
+            batch_max_items,
+            batch_concurrency,
+            batch_tenant_max_items,
+            batch_tenant_concurrency,
+            batch_max_result_bytes,
+            webhook_secret,
+            webhook_tenant_secrets,
+            webhook_max_attempts,
+            webhook_retry_base_ms,
+            webhook_timeout_ms,
+            job_ttl_secs,
+            job_max_running,
+            job_tenant_max_running,
+            job_max_stored,
+            job_max_stored_bytes,
+            job_drain_timeout_secs,
+            result_storage,
+            result_storage_dir,
+            result_s3_endpoint,
+            result_s3_bucket,
+            result_s3_region,
+            result_s3_access_key,
+            result_s3_secret_key,
+            result_s3_prefix,
+            result_ttl_secs,
+            result_sweep_interval_secs,
+            result_signing_keys,
+            result_url_ttl_secs,
+            transform_signing_keys,
+            transform_max_age_secs,
+        }
+    }
+
+    /// Returns the public TCP addresses to listen on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `LISTEN_ADDRS` is empty and `HOST`/`PORT` do not form a
+    /// valid socket address.
+    pub fn tcp_listen_addrs(&self) -> Vec<SocketAddr> {
+        if !self.listen_addrs.is_empty() {
+            return self.listen_addrs.clone();
         }
+        let addr_str = format!("{}:{}", self.host, self.port);
+        vec![addr_str.parse().expect("Invalid HOST or PORT config")]
+    }
+
+    /// Returns `true` unless the model is listed in `DISABLED_MODELS`.
+    pub fn model_enabled(&self, id: &str) -> bool {
+        !self.disabled_models.iter().any(|disabled| disabled == id)
+    }
+
+    /// Returns `true` if the model is listed in `HIDDEN_MODELS`.
+    pub fn model_hidden(&self, id: &str) -> bool {
+        self.hidden_models.iter().any(|hidden| hidden == id)
+    }
+
+    /// Returns the most images a `/batch` request from `tenant` may hold.
+    pub fn batch_max_items_for(&self, tenant: Option<&str>) -> usize {
+        tenant
+            .and_then(|tenant| self.batch_tenant_max_items.get(tenant))
+            .copied()
+            .unwrap_or(self.batch_max_items)
+    }
+
+    /// Returns how many batch images of `tenant` are processed at once.
+    pub fn batch_concurrency_for(&self, tenant: Option<&str>) -> usize {
+        tenant
+            .and_then(|tenant| self.batch_tenant_concurrency.get(tenant))
+            .copied()
+            .unwrap_or(self.batch_concurrency)
+    }
+
+    /// Returns the secret used to sign webhook events for `tenant`.
+    pub fn webhook_secret_for(&self, tenant: Option<&str>) -> Option<&str> {
+        tenant
+            .and_then(|tenant| self.webhook_tenant_secrets.get(tenant))
+            .or(self.webhook_secret.as_ref())
+            .map(String::as_str)
     }
+
+    /// Returns `true` when a certificate and key are configured.
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert_path.is_some() && self.tls_key_path.is_some()
+    }
+}
+
+/// Parses a comma-separated list of model IDs.
+///
+/// # Panics
+///
+/// Panics if an entry is not a known model ID.
+fn parse_models(name: &str, raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            let known =
+                id == REMOVEBG_MODEL_ID || UpscalerModel::ALL.iter().any(|model| model.id() == id);
+            if !known {
+                panic!("{} contains unknown model '{}'", name, id);
+            }
+            id.to_string()
+        })
+        .collect()
+}
+
+/// Parses a comma-separated list of `key=value` pairs, in order.
+///
+/// # Panics
+///
+/// Panics if an entry has no `=`.
+fn parse_pairs<C: FromIterator<(String, String)>>(name: &str, raw: &str) -> C {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (key, value) = entry
+                .split_once('=')
+                .unwrap_or_else(|| panic!("{} entries must look like key=value", name));
+            (key.trim().to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+/// Parses a comma-separated list of `key=count` pairs.
+///
+/// # Panics
+///
+/// Panics if an entry has no `=` or its count is not a positive integer.
+fn parse_counts(name: &str, raw: &str) -> HashMap<String, usize> {
+    parse_pairs::<Vec<_>>(name, raw)
+        .into_iter()
+        .map(|(key, value)| {
+            let count = value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .unwrap_or_else(|| panic!("{} counts must be positive integers", name));
+            (key, count)
+        })
+        .collect()
 }
@@ -0,0 +1,515 @@
+use super::pipeline::{self, PipelineError, Step, Timings};
+use super::respond::{self, RequestBody};
+use crate::imaging::{
+    self, ImageLimits, ImageRejection,
+    output::{self, Negotiated, OutputError},
+};
+use crate::models::{
+    BatchItem, BatchManifest, BatchRequest, OutputFormat, PipelineStep, RemoveBgStep,
+};
+use crate::state::AppState;
+use crate::tls::ClientIdentity;
+use crate::upload::{SpooledBody, UploadError, UploadLimits, read_text_field};
+use axum::{
+    body::{self, Bytes},
+    extract::{Json, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::StreamExt;
+use std::io::{Cursor, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::task::JoinError;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+/// Handler for processing many images in one request.
+///
+/// Accepts either:
+/// 1. `multipart/form-data` with any number of 'image' fields (file uploads), an optional 'archive' field holding a ZIP of images, and an optional 'steps' field.
+/// 2. `application/json` with a 'urls' array (image URLs, fetched by the gateway) and optional 'steps'.
+///
+/// Every image goes through the same steps as in `/pipeline`, background
+/// removal when none are given. Images are processed concurrently, at most
+/// `BATCH_CONCURRENCY` at a time. The bound is shared by all of a tenant's
+/// batches, and by all batches of clients not identified as a tenant by
+/// their certificate.
+/// An image that fails does not fail the batch: the result is a JSON
+/// manifest with the status of each image and the successful results
+/// base64-encoded, or a ZIP archive of the results plus `manifest.json` when
+/// the `Accept` header includes `application/zip`. Image types in `Accept`
+/// select the format of the results unless a `format` step is given.
+/// Results are held until the response is built, so once they total
+/// `BATCH_MAX_RESULT_BYTES` the images finishing later fail with
+/// `507 Insufficient Storage`.
+pub async fn batch(State(state): State<AppState>, request: Request) -> Response {
+    let config = state.config.clone();
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let zip = respond::accepts_zip(accept.as_deref());
+    let tenant = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .and_then(|identity| identity.tenant.clone());
+    let max_items = config.batch_max_items_for(tenant.as_deref());
+
+    let limits = UploadLimits::from_config(&config);
+    let mut inputs = Vec::new();
+    let steps;
+
+    let body = match respond::read_body(&state, request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    match body {
+        RequestBody::Json(body) => {
+            let payload: BatchRequest = match serde_json::from_value(body) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e))
+                        .into_response();
+                }
+            };
+            if payload.urls.len() > max_items {
+                return BatchError::TooManyItems(max_items).into_response();
+            }
+            steps = payload.steps;
+            inputs = payload
+                .urls
+                .into_iter()
+                .map(|url| Input {
+                    file_name: respond::url_file_name(&url),
+                    label: Some(url.clone()),
+                    source: Source::Url(url),
+                })
+                .collect();
+        }
+        RequestBody::Multipart(mut multipart) => {
+            let mut steps_text = None;
+            loop {
+                let field = match multipart.next_field().await {
+                    Ok(Some(field)) => field,
+                    Ok(None) => break,
+                    Err(e) => return UploadError::from(e).into_response(),
+                };
+                match field.name().unwrap_or("") {
+                    "image" => {
+                        if inputs.len() == max_items {
+                            return BatchError::TooManyItems(max_items).into_response();
+                        }
+                        let file_name = field.file_name().map(str::to_string);
+                        match SpooledBody::from_field(field, &limits).await {
+                            Ok(body) => inputs.push(Input {
+                                label: file_name.clone(),
+                                file_name,
+                                source: Source::Upload(body),
+                            }),
+                            Err(e) => return e.into_response(),
+                        }
+                    }
+                    "archive" => {
+                        let archive_limits = UploadLimits {
+                            max_image_bytes: config.max_body_bytes_batch,
+                            ..limits.clone()
+                        };
+                        let archive = match SpooledBody::from_field(field, &archive_limits).await {
+                            Ok(body) => body,
+                            Err(e) => return e.into_response(),
+                        };
+                        let remaining = max_items - inputs.len();
+                        match read_archive(archive, limits.clone(), remaining).await {
+                            Ok(entries) => inputs.extend(entries),
+                            Err(e) => return e.into_response(),
+                        }
+                    }
+                    "steps" => match read_text_field(field, &limits).await {
+                        Ok(text) => steps_text = Some(text),
+                        Err(e) => return e.into_response(),
+                    },
+                    _ => {}
+                }
+            }
+
+            steps = match steps_text
+                .map(|text| serde_json::from_str::<Vec<PipelineStep>>(&text))
+                .transpose()
+            {
+                Ok(steps) => steps,
+                Err(e) => {
+                    return (StatusCode::BAD_REQUEST, format!("Invalid steps: {}", e))
+                        .into_response();
+                }
+            };
+        }
+    }
+
+    if inputs.is_empty() {
+        return BatchError::NoItems.into_response();
+    }
+    let steps = steps.unwrap_or_else(|| vec![PipelineStep::Removebg(RemoveBgStep::default())]);
+    let (steps, output_options) = match pipeline::plan(&config, steps) {
+        Ok(plan) => plan,
+        Err(e) => return e.into_response(),
+    };
+    // The image types in `Accept` pick the format of every result;
+    // `application/zip` and `application/json` only select the envelope.
+    let negotiated = match output::negotiate(&output_options, accept.as_deref()) {
+        Ok(negotiated) => negotiated,
+        Err(e) => return e.into_response(),
+    };
+
+    let budget = ResultBudget::new(config.batch_max_result_bytes);
+    let concurrency = config.batch_concurrency_for(tenant.as_deref());
+    // Clients without a tenant share one budget.
+    let slots = state.batch_slots.get(tenant.as_deref().unwrap_or_default());
+    let results: Vec<(BatchItem, Option<Bytes>)> =
+        futures::stream::iter(inputs.into_iter().enumerate())
+            .map(|(index, input)| {
+                let (state, steps, limits, budget) = (&state, &steps, &limits, &budget);
+                let slots = slots.clone();
+                async move {
+                    let _permit = slots.acquire_owned().await.ok();
+                    process(state, steps, negotiated, limits, budget, index, input).await
+                }
+            })
+            .buffered(concurrency)
+            .collect()
+            .await;
+
+    if zip {
+        match archive(results) {
+            Ok(archive) => (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/zip"),
+                    ),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        HeaderValue::from_static("attachment; filename=\"batch.zip\""),
+                    ),
+                ],
+                archive,
+            )
+                .into_response(),
+            Err(e) => {
+                tracing::error!("Failed to build batch archive: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build archive").into_response()
+            }
+        }
+    } else {
+        let items = results
+            .into_iter()
+            .map(|(item, bytes)| BatchItem {
+                data: bytes.map(|bytes| STANDARD.encode(bytes)),
+                ..item
+            })
+            .collect();
+        Json(manifest(items)).into_response()
+    }
+}
+
+/// An image of the batch.
+struct Input {
+    /// File name, archive entry or URL, reported in the manifest.
+    label: Option<String>,
+    /// Name the result is derived from.
+    file_name: Option<String>,
+    source: Source,
+}
+
+/// Where a batch image comes from.
+enum Source {
+    /// A multipart file field or an archive entry.
+    Upload(SpooledBody),
+    /// A URL fetched by the gateway.
+    Url(String),
+    /// An archive entry that could not be extracted.
+    Unreadable(UploadError),
+}
+
+/// A batch that cannot be processed at all.
+enum BatchError {
+    /// The request held no images.
+    NoItems,
+    /// The request held more images than the client may send.
+    TooManyItems(usize),
+    /// The `archive` field was not a readable ZIP archive.
+    Archive(String),
+    /// A blocking task panicked.
+    Task(JoinError),
+}
+
+impl IntoResponse for BatchError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NoItems => {
+                (StatusCode::BAD_REQUEST, "No images found in the batch").into_response()
+            }
+            Self::TooManyItems(max) => (
+                StatusCode::BAD_REQUEST,
+                format!("A batch holds at most {} images", max),
+            )
+                .into_response(),
+            Self::Archive(e) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid archive: {}", e)).into_response()
+            }
+            Self::Task(e) => {
+                tracing::error!("Archive extraction task failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read archive").into_response()
+            }
+        }
+    }
+}
+
+/// Failure of a single image, reported in the manifest.
+enum ItemError {
+    /// The image could not be read or fetched.
+    Upload(UploadError),
+    /// The image was empty.
+    Empty,
+    /// The image was rejected.
+    Rejected(ImageRejection),
+    /// The step at this 1-based position failed.
+    Step(usize, PipelineError),
+    /// The output options do not fit the result.
+    Output(OutputError),
+    /// The result could not be encoded.
+    Encode(String),
+    /// The results of the batch already hold `BATCH_MAX_RESULT_BYTES`.
+    ResultsTooLarge(usize),
+}
+
+impl IntoResponse for ItemError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Upload(e) => e.into_response(),
+            Self::Empty => (StatusCode::BAD_REQUEST, "Image is empty").into_response(),
+            Self::Rejected(e) => e.into_response(),
+            Self::Step(_, e) => e.into_response(),
+            Self::Output(e) => e.into_response(),
+            Self::Encode(e) => {
+                tracing::error!("Failed to encode batch result: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response()
+            }
+            Self::ResultsTooLarge(limit) => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                format!("The batch results exceed {} bytes", limit),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Limits the total size of the results a batch holds in memory.
+struct ResultBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl ResultBudget {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserves `bytes` for a result, failing once the limit would be
+    /// crossed. Results that do not fit are discarded.
+    fn take(&self, bytes: usize) -> Result<(), ItemError> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|total| *total <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|_| ItemError::ResultsTooLarge(self.limit))
+    }
+}
+
+/// Extracts the images of a ZIP archive, skipping directories and hidden
+/// files. Each entry is read like an uploaded image field, spooled to disk
+/// above `limits.spool_threshold`; entries above `limits.max_image_bytes`
+/// are kept as failed items.
+async fn read_archive(
+    archive: SpooledBody,
+    limits: UploadLimits,
+    max_items: usize,
+) -> Result<Vec<Input>, BatchError> {
+    let reader = archive
+        .reader()
+        .await
+        .map_err(|e| BatchError::Archive(e.to_string()))?;
+    tokio::task::spawn_blocking(move || {
+        let mut archive =
+            ZipArchive::new(reader).map_err(|e| BatchError::Archive(e.to_string()))?;
+        let mut inputs = Vec::new();
+        for index in 0..archive.len() {
+            let entry = archive
+                .by_index(index)
+                .map_err(|e| BatchError::Archive(e.to_string()))?;
+            let name = entry.name().to_string();
+            let hidden = name.starts_with("__MACOSX/")
+                || name
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|base| base.starts_with('.'));
+            if entry.is_dir() || hidden {
+                continue;
+            }
+            if inputs.len() == max_items {
+                return Err(BatchError::TooManyItems(max_items));
+            }
+            let source = if entry.size() > limits.max_image_bytes as u64 {
+                Source::Unreadable(UploadError::TooLarge {
+                    field: name.clone(),
+                    limit: limits.max_image_bytes,
+                })
+            } else {
+                match SpooledBody::from_reader(entry, &name, &limits) {
+                    Ok(body) => Source::Upload(body),
+                    Err(e) => Source::Unreadable(e),
+                }
+            };
+            inputs.push(Input {
+                label: Some(name.clone()),
+                file_name: Some(name),
+                source,
+            });
+        }
+        Ok(inputs)
+    })
+    .await
+    .map_err(BatchError::Task)?
+}
+
+/// Processes one image and describes the outcome; the encoded result is
+/// returned alongside on success.
+async fn process(
+    state: &AppState,
+    steps: &[Step],
+    negotiated: Negotiated,
+    limits: &UploadLimits,
+    budget: &ResultBudget,
+    index: usize,
+    input: Input,
+) -> (BatchItem, Option<Bytes>) {
+    let mut item = BatchItem {
+        index: index + 1,
+        source: input.label,
+        status: StatusCode::OK.as_u16(),
+        file: None,
+        content_type: None,
+        width: None,
+        height: None,
+        data: None,
+        error: None,
+        failed_step: None,
+    };
+    let outcome = run(state, steps, negotiated, limits, input.source)
+        .await
+        .and_then(|result| budget.take(result.0.len()).map(|()| result));
+    match outcome {
+        Ok((bytes, format, width, height)) => {
+            item.file = Some(format!(
+                "{:03}-{}",
+                index + 1,
+                respond::file_name(input.file_name.as_deref(), "processed", format.extension())
+            ));
+            item.content_type = Some(format.mime_type().to_string());
+            item.width = Some(width);
+            item.height = Some(height);
+            (item, Some(bytes))
+        }
+        Err(e) => {
+            if let ItemError::Step(position, _) = &e {
+                item.failed_step = Some(*position);
+            }
+            let response = e.into_response();
+            item.status = response.status().as_u16();
+            let message = body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap_or_default();
+            item.error = Some(String::from_utf8_lossy(&message).into_owned());
+            (item, None)
+        }
+    }
+}
+
+/// Reads, checks and decodes an image, runs the steps and encodes the
+/// result.
+async fn run(
+    state: &AppState,
+    steps: &[Step],
+    negotiated: Negotiated,
+    limits: &UploadLimits,
+    source: Source,
+) -> Result<(Bytes, OutputFormat, u32, u32), ItemError> {
+    let mut body = match source {
+        Source::Upload(body) => body,
+        Source::Url(url) => SpooledBody::fetch(&state.fetch_http, &url, limits)
+            .await
+            .map_err(ItemError::Upload)?,
+        Source::Unreadable(e) => return Err(ItemError::Upload(e)),
+    };
+    if body.is_empty() {
+        return Err(ItemError::Empty);
+    }
+    let info = imaging::inspect(&mut body)
+        .await
+        .map_err(ItemError::Rejected)?;
+    ImageLimits::from_config(&state.config)
+        .check(&info)
+        .map_err(ItemError::Rejected)?;
+    let image = imaging::decode(&mut body)
+        .await
+        .map_err(ItemError::Rejected)?;
+    drop(body);
+
+    let image = pipeline::run_steps(state, steps, image, info, &mut Timings::default())
+        .await
+        .map_err(|(position, e)| ItemError::Step(position, e))?;
+    let rendition = negotiated
+        .resolve(pipeline::default_format(&image))
+        .map_err(ItemError::Output)?;
+    let (width, height) = (image.width(), image.height());
+    let encoded = tokio::task::spawn_blocking(move || output::encode(&image, &rendition))
+        .await
+        .map_err(|e| ItemError::Encode(e.to_string()))?
+        .map_err(|e| ItemError::Encode(e.to_string()))?;
+    Ok((Bytes::from(encoded), rendition.format, width, height))
+}
+
+/// Counts the outcomes of `items`.
+fn manifest(items: Vec<BatchItem>) -> BatchManifest {
+    let succeeded = items.iter().filter(|item| item.error.is_none()).count();
+    BatchManifest {
+        succeeded,
+        failed: items.len() - succeeded,
+        items,
+    }
+}
+
+/// Packs the successful results and `manifest.json` into a ZIP archive.
+fn archive(results: Vec<(BatchItem, Option<Bytes>)>) -> std::io::Result<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut items = Vec::with_capacity(results.len());
+    for (item, bytes) in results {
+        if let (Some(name), Some(bytes)) = (&item.file, bytes) {
+            archive
+                .start_file(name.as_str(), options)
+                .map_err(std::io::Error::from)?;
+            archive.write_all(&bytes)?;
+        }
+        items.push(item);
+    }
+    archive
+        .start_file("manifest.json", options)
+        .map_err(std::io::Error::from)?;
+    serde_json::to_writer_pretty(&mut archive, &manifest(items))?;
+    Ok(archive.finish().map_err(std::io::Error::from)?.into_inner())
+}
@@ -0,0 +1,142 @@
+use crate::models::{BuildInfo, DependencyStatus};
+use crate::state::AppState;
+use axum::{
+    Json,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use serde_json::json;
+
+/// Health check handler.
+///
+/// Returns a JSON response indicating the API status. This can be used
+/// by load balancers or monitoring tools to verify the service is running.
+///
+/// # Returns
+///
+/// * `200 OK` - Success, returns `{"status": "ok"}`
+pub async fn health_check() -> impl IntoResponse {
+    tracing::debug!("Health check requested");
+    Json(json!({ "status": "ok" }))
+}
+
+/// Liveness probe.
+///
+/// Succeeds as long as the process is able to serve requests.
+///
+/// # Returns
+///
+/// * `200 OK` - Always, returns `{"status": "alive"}`
+pub async fn livez() -> impl IntoResponse {
+    Json(json!({ "status": "alive" }))
+}
+
+/// Readiness probe.
+///
+/// The gateway is ready when its configuration is usable, it is not
+/// draining for shutdown, and not every worker failed its last health
+/// probe. Only cached probe results are consulted, so polling this
+/// endpoint never reaches the workers.
+///
+/// # Returns
+///
+/// * `200 OK` - Ready to receive traffic
+/// * `503 Service Unavailable` - Not ready; `checks` explains why
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let config_ok = [
+        &state.config.modal_removebg_url,
+        &state.config.modal_upscaler_url,
+    ]
+    .iter()
+    .all(|url| reqwest::Url::parse(url).is_ok());
+
+    let draining = state.is_draining();
+
+    let known = state.health.last_known().await;
+    let workers_available =
+        known.is_empty() || known.iter().any(|dep| dep.status == DependencyStatus::Up);
+
+    let ready = config_ok && !draining && workers_available;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(json!({
+            "status": if ready { "ready" } else { "not_ready" },
+            "checks": {
+                "config": config_ok,
+                "draining": draining,
+                "workers_available": workers_available,
+            }
+        })),
+    )
+}
+
+/// Detailed dependency health.
+///
+/// Probes each worker endpoint (results are cached for
+/// `HEALTH_CACHE_TTL_SECS`) and reports per-dependency status and latency
+/// along with the gateway's build information.
+///
+/// # Returns
+///
+/// * `200 OK` - All dependencies are up (`ok`) or some are down (`degraded`)
+/// * `503 Service Unavailable` - Every dependency is down (`down`)
+pub async fn health_details(State(state): State<AppState>) -> impl IntoResponse {
+    let dependencies = state.health.check_all().await;
+
+    let up = dependencies
+        .iter()
+        .filter(|dep| dep.status == DependencyStatus::Up)
+        .count();
+    let (status_code, status) = if up == dependencies.len() {
+        (StatusCode::OK, "ok")
+    } else if up > 0 {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "down")
+    };
+
+    let build = BuildInfo {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: option_env!("NIJIKA_GIT_SHA").map(str::to_string),
+        profile: if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        }
+        .to_string(),
+    };
+
+    (
+        status_code,
+        Json(json!({
+            "status": status,
+            "draining": state.is_draining(),
+            "uptime_secs": state.uptime().as_secs(),
+            "build": build,
+            "dependencies": dependencies,
+        })),
+    )
+}
+
+/// Metrics handler.
+///
+/// Reports request counters, job and tile usage and the cached worker
+/// status in the Prometheus text format.
+///
+/// # Returns
+///
+/// * `200 OK` - The metrics, as `text/plain; version=0.0.4`
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(&state).await,
+    )
+}
@@ -0,0 +1,131 @@
+use crate::jobs::Job;
+use crate::models::JobStatus;
+use crate::server::AdminListener;
+use crate::state::AppState;
+use crate::tls::ClientIdentity;
+use crate::webhooks;
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+
+/// Error returned by the job endpoints.
+#[derive(Debug)]
+pub enum JobError {
+    /// No job with this ID is visible to the client.
+    NotFound,
+    /// The job has not finished yet.
+    Pending,
+}
+
+impl IntoResponse for JobError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+            Self::Pending => (StatusCode::CONFLICT, "Job is still pending").into_response(),
+        }
+    }
+}
+
+/// Looks up job `id`, hiding jobs submitted by other tenants.
+fn find(
+    state: &AppState,
+    id: &str,
+    identity: Option<Extension<ClientIdentity>>,
+) -> Result<Job, JobError> {
+    let tenant = identity.and_then(|Extension(identity)| identity.tenant);
+    state
+        .jobs
+        .get(id)
+        .filter(|job| job.tenant == tenant)
+        .ok_or(JobError::NotFound)
+}
+
+/// Job status handler.
+///
+/// # Returns
+///
+/// * `200 OK` - The job, its outcome and its webhook delivery attempts.
+/// * `404 Not Found` - Unknown, expired, or another tenant's job.
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    identity: Option<Extension<ClientIdentity>>,
+) -> Result<impl IntoResponse, JobError> {
+    let job = find(&state, &id, identity)?;
+    Ok(Json(job.info()))
+}
+
+/// Job result handler.
+///
+/// Replays the response the job's request completed with, or redirects to
+/// the result storage when the result was persisted there, with a freshly
+/// signed URL when signing keys are configured.
+///
+/// # Returns
+///
+/// * The stored status, content type and body.
+/// * `303 See Other` - The result is in the result storage.
+/// * `404 Not Found` - Unknown, expired, or another tenant's job.
+/// * `409 Conflict` - The job is still pending.
+pub async fn get_result(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    identity: Option<Extension<ClientIdentity>>,
+) -> Result<Response, JobError> {
+    let job = find(&state, &id, identity)?;
+    if job.status == JobStatus::Pending {
+        return Err(JobError::Pending);
+    }
+    if let Some(stored) = &job.stored {
+        let url = match &state.storage {
+            Some(storage) => {
+                storage
+                    .download_url(&stored.key, stored.expires_at, None, false, None)
+                    .0
+            }
+            None => stored.url.clone(),
+        };
+        return Ok(Redirect::to(&url).into_response());
+    }
+    let status = job
+        .http_status
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let content_type = job
+        .content_type
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok((status, [(header::CONTENT_TYPE, content_type)], job.body).into_response())
+}
+
+/// Manual webhook redelivery handler.
+///
+/// Makes one delivery attempt right away, regardless of earlier attempts.
+/// Served with the admin routes; on the admin listener the jobs of every
+/// tenant are visible.
+///
+/// # Returns
+///
+/// * `200 OK` - The recorded delivery attempt.
+/// * `404 Not Found` - Unknown, expired, or another tenant's job.
+/// * `409 Conflict` - The job is still pending.
+pub async fn redeliver(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    identity: Option<Extension<ClientIdentity>>,
+    admin: Option<Extension<AdminListener>>,
+) -> Result<impl IntoResponse, JobError> {
+    let job = match admin {
+        Some(_) => state.jobs.get(&id).ok_or(JobError::NotFound)?,
+        None => find(&state, &id, identity)?,
+    };
+    if job.status == JobStatus::Pending {
+        return Err(JobError::Pending);
+    }
+    let attempt = webhooks::deliver(&state, &job.id, true)
+        .await
+        .ok_or(JobError::NotFound)?;
+    Ok(Json(attempt))
+}
@@ -4,21 +4,16 @@
 //! Handlers are responsible for processing requests and returning
 //! appropriate HTTP responses.
 
-use axum::{Json, response::IntoResponse};
-use serde_json::json;
-
+pub mod batch;
+pub mod health;
+pub mod jobs;
+pub mod models;
+pub mod pipeline;
+pub mod presets;
 pub mod removebg;
+mod respond;
+pub mod results;
+pub mod transform;
 pub mod upscaler;
 
-/// Health check handler.
-///
-/// Returns a JSON response indicating the API status. This can be used
-/// by load balancers or monitoring tools to verify the service is running.
-///
-/// # Returns
-///
-/// * `200 OK` - Success, returns `{"status": "ok"}`
-pub async fn health_check() -> impl IntoResponse {
-    tracing::debug!("Health check requested");
-    Json(json!({ "status": "ok" }))
-}
+pub use health::health_check;
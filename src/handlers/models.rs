@@ -0,0 +1,85 @@
+use crate::config::Config;
+use crate::imaging;
+use crate::models::{
+    DependencyHealth, DependencyStatus, ModelInfo, OutputFormat, REMOVEBG_MODEL_ID, UpscalerModel,
+};
+use crate::state::AppState;
+use axum::{Json, extract::State, response::IntoResponse};
+use serde_json::json;
+
+/// Parameters every upscaler model accepts.
+const UPSCALE_PARAMETERS: &[&str] = &["scale", "width", "height", "fit", "face_enhance"];
+
+/// Model catalog handler.
+///
+/// Lists the background removal model and every upscaler model, except those
+/// hidden through `HIDDEN_MODELS`. A model is reported as unavailable when it
+/// is disabled through `DISABLED_MODELS` or its worker failed the last health
+/// probe; only cached probe results are consulted.
+///
+/// # Returns
+///
+/// * `200 OK` - `{"models": [...]}`
+pub async fn list_models(State(state): State<AppState>) -> impl IntoResponse {
+    let known = state.health.last_known().await;
+    Json(json!({ "models": catalog(&state.config, &known) }))
+}
+
+/// Builds the catalog entries visible under `config`.
+fn catalog(config: &Config, known: &[DependencyHealth]) -> Vec<ModelInfo> {
+    let worker_up = |name: &str| {
+        known
+            .iter()
+            .find(|dep| dep.name == name)
+            .is_none_or(|dep| dep.status == DependencyStatus::Up)
+    };
+    let input_types: Vec<String> = imaging::supported_mime_types()
+        .map(str::to_string)
+        .collect();
+    let output_types: Vec<String> = OutputFormat::ALL
+        .iter()
+        .map(|format| format.mime_type().to_string())
+        .collect();
+
+    let removebg_enabled = config.model_enabled(REMOVEBG_MODEL_ID);
+    let mut models = vec![ModelInfo {
+        id: REMOVEBG_MODEL_ID.to_string(),
+        endpoint: "/removebg".to_string(),
+        description: "BiRefNet segmentation for background removal".to_string(),
+        native_scale: 1,
+        default: true,
+        parameters: Vec::new(),
+        input_types: input_types.clone(),
+        output_types: output_types.clone(),
+        default_output_type: OutputFormat::Png.mime_type().to_string(),
+        enabled: removebg_enabled,
+        available: removebg_enabled && worker_up("removebg"),
+    }];
+
+    let upscaler_up = worker_up("upscaler");
+    models.extend(UpscalerModel::ALL.iter().map(|model| {
+        let mut parameters: Vec<String> =
+            UPSCALE_PARAMETERS.iter().map(|p| p.to_string()).collect();
+        if model.supports_denoise_strength() {
+            parameters.push("denoise_strength".to_string());
+        }
+        let enabled = config.model_enabled(model.id());
+        ModelInfo {
+            id: model.id().to_string(),
+            endpoint: "/upscale".to_string(),
+            description: model.description().to_string(),
+            native_scale: model.native_scale(),
+            default: *model == UpscalerModel::default(),
+            parameters,
+            input_types: input_types.clone(),
+            output_types: output_types.clone(),
+            // Inputs with alpha are returned as PNG or WebP instead.
+            default_output_type: OutputFormat::Jpeg.mime_type().to_string(),
+            enabled,
+            available: enabled && upscaler_up,
+        }
+    }));
+
+    models.retain(|model| !config.model_hidden(&model.id));
+    models
+}
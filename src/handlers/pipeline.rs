@@ -0,0 +1,453 @@
+use super::removebg::{self, SegmentError};
+use super::respond::{self, Input, InputFields, Options};
+use super::upscaler::{self, UpscaleError, UpscaleOptions};
+use crate::config::Config;
+use crate::imaging::{
+    self, ImageInfo, ImageLimits, ImageRejection,
+    cutout::{self, Background, Color, ColorError},
+    output,
+    refine::{RefineError, Refinement},
+    resize::SizeRequest,
+    transform::{Transform, TransformError},
+};
+use crate::models::{
+    CropPadding, OutputFormat, OutputOptions, PipelineRequest, PipelineStep, PresetEndpoint,
+    REMOVEBG_MODEL_ID,
+};
+use crate::state::AppState;
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use image::DynamicImage;
+use std::fmt::Write;
+use std::time::Instant;
+use tokio::task::JoinError;
+
+/// Most steps accepted in one pipeline, `format` included.
+pub const MAX_STEPS: usize = 10;
+
+/// Handler for composed processing.
+///
+/// Accepts either:
+/// 1. `multipart/form-data` with an 'image' field (file upload) and a 'steps' field holding the steps as a JSON array.
+/// 2. `application/json` with a 'url' field (image URL, fetched by the gateway) and a 'steps' array.
+///
+/// Every step is validated before the image is touched. The image is then
+/// decoded once and passed through the steps in the gateway: `removebg` and
+/// `upscale` call their workers, `resize`, `crop`, `trim` and `pad` run
+/// locally, and only the final result is encoded, in the format of a trailing
+/// `format` step or the negotiated one. Intermediate results are held to
+/// `MAX_UPSCALE_OUTPUT_MEGAPIXELS`. The time spent in each step is reported
+/// in the `Server-Timing` header.
+///
+/// The steps can also come from a `pipeline` preset named by `preset`.
+pub async fn pipeline(State(state): State<AppState>, request: Request) -> Response {
+    let config = state.config.clone();
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let Input {
+        image: mut image_body,
+        source_name,
+        options: steps,
+    } = match respond::read_input(&state, request, &INPUT, parse_steps).await {
+        Ok(input) => input,
+        Err(response) => return response,
+    };
+
+    let (steps, output_options) = match plan(&config, steps) {
+        Ok(plan) => plan,
+        Err(e) => return e.into_response(),
+    };
+    let negotiated = match output::negotiate(&output_options, accept.as_deref()) {
+        Ok(negotiated) => negotiated,
+        Err(e) => return e.into_response(),
+    };
+
+    let info = match imaging::inspect(&mut image_body).await {
+        Ok(info) => info,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = ImageLimits::from_config(&config).check(&info) {
+        return e.into_response();
+    }
+
+    let mut timings = Timings::default();
+    let started = Instant::now();
+    let image = match imaging::decode(&mut image_body).await {
+        Ok(image) => image,
+        Err(e) => return e.into_response(),
+    };
+    drop(image_body);
+    timings.record("decode", None, started);
+
+    let image = match run_steps(&state, &steps, image, info, &mut timings).await {
+        Ok(image) => image,
+        Err((position, e)) => {
+            let mut response = timings.attach(e.into_response());
+            response
+                .headers_mut()
+                .insert("x-pipeline-failed-step", HeaderValue::from(position));
+            return response;
+        }
+    };
+
+    let rendition = match negotiated.resolve(default_format(&image)) {
+        Ok(rendition) => rendition,
+        Err(e) => return timings.attach(e.into_response()),
+    };
+    let started = Instant::now();
+    let encoded = tokio::task::spawn_blocking(move || output::encode(&image, &rendition)).await;
+    let encoded = match encoded {
+        Ok(Ok(encoded)) => encoded,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to encode pipeline result: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response();
+        }
+        Err(e) => {
+            tracing::error!("Encoding task failed: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response();
+        }
+    };
+    timings.record("encode", None, started);
+
+    timings.attach(respond::encoded_image(
+        Bytes::from(encoded),
+        rendition.format,
+        respond::content_disposition(source_name.as_deref(), "processed", rendition.format),
+    ))
+}
+
+/// Runs `steps` on `image`, described by `info`, recording each in
+/// `timings`.
+///
+/// A failure is returned with the 1-based position of the failed step.
+pub(crate) async fn run_steps(
+    state: &AppState,
+    steps: &[Step],
+    mut image: DynamicImage,
+    mut info: ImageInfo,
+    timings: &mut Timings,
+) -> Result<DynamicImage, (usize, PipelineError)> {
+    for (index, step) in steps.iter().enumerate() {
+        let started = Instant::now();
+        image = match step.run(state, image, &info).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::info!("Pipeline step {} ({}) failed", index + 1, step.name());
+                return Err((index + 1, e));
+            }
+        };
+        timings.record(step.name(), Some(index + 1), started);
+        info = ImageInfo {
+            width: image.width(),
+            height: image.height(),
+            has_alpha: image.color().has_alpha(),
+            ..info
+        };
+    }
+    Ok(image)
+}
+
+/// Format of a result without a `format` step: PNG if it is transparent,
+/// JPEG otherwise.
+pub(crate) fn default_format(image: &DynamicImage) -> OutputFormat {
+    if image.color().has_alpha() {
+        OutputFormat::Png
+    } else {
+        OutputFormat::Jpeg
+    }
+}
+
+/// A validated pipeline step.
+pub(crate) enum Step {
+    /// Background removal, with optional refinement and a flat background.
+    RemoveBg {
+        refine: Refinement,
+        background: Option<Color>,
+    },
+    /// Upscaling through the worker.
+    Upscale(UpscaleOptions),
+    /// A geometry step run in the gateway.
+    Transform(Transform),
+}
+
+/// Invalid steps, or a step that failed.
+pub(crate) enum PipelineError {
+    /// There were no steps, or more than [`MAX_STEPS`].
+    StepCount(usize),
+    /// A `format` step was not the last step.
+    FormatNotLast,
+    /// A `removebg` step was given while background removal is disabled.
+    RemoveBgDisabled,
+    /// A `removebg` step had an invalid refinement.
+    Refine(RefineError),
+    /// A `removebg` step had an invalid background color.
+    Color(ColorError),
+    /// An `upscale` step was invalid or failed.
+    Upscale(UpscaleError),
+    /// A geometry step was invalid or could not be applied.
+    Transform(TransformError),
+    /// A `removebg` step failed.
+    Segment(SegmentError),
+    /// A step would produce an image above the pixel limits.
+    Rejected(ImageRejection),
+    /// A blocking task panicked.
+    Task(JoinError),
+}
+
+impl IntoResponse for PipelineError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::StepCount(count) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "A pipeline needs between 1 and {} steps, got {}",
+                    MAX_STEPS, count
+                ),
+            )
+                .into_response(),
+            Self::FormatNotLast => (
+                StatusCode::BAD_REQUEST,
+                "The format step must be the last step",
+            )
+                .into_response(),
+            Self::RemoveBgDisabled => respond::model_disabled(REMOVEBG_MODEL_ID),
+            Self::Refine(e) => e.into_response(),
+            Self::Color(e) => e.into_response(),
+            Self::Upscale(e) => e.into_response(),
+            Self::Transform(e) => e.into_response(),
+            Self::Segment(e) => e.into_response(),
+            Self::Rejected(e) => e.into_response(),
+            Self::Task(e) => {
+                tracing::error!("Pipeline task failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process image").into_response()
+            }
+        }
+    }
+}
+
+/// Multipart fields read by [`pipeline`].
+const INPUT: InputFields = InputFields {
+    endpoint: PresetEndpoint::Pipeline,
+    text: &["steps"],
+    files: &[],
+};
+
+/// Reads the steps of a JSON payload or of the multipart `steps` field.
+#[allow(clippy::result_large_err)]
+fn parse_steps(options: Options<PipelineRequest>) -> Result<Vec<PipelineStep>, Response> {
+    let fields = match options {
+        Options::Json(payload) => return Ok(payload.steps),
+        Options::Multipart { fields, .. } => fields,
+    };
+    let steps_text = fields
+        .into_iter()
+        .rev()
+        .find_map(|(name, text)| (name == "steps").then_some(text));
+    let Some(text) = steps_text else {
+        return Err((StatusCode::BAD_REQUEST, "No steps found in 'steps' field").into_response());
+    };
+    serde_json::from_str(&text)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid steps: {}", e)).into_response())
+}
+
+/// Validates the requested steps and splits off the trailing `format` step.
+pub(crate) fn plan(
+    config: &Config,
+    steps: Vec<PipelineStep>,
+) -> Result<(Vec<Step>, OutputOptions), PipelineError> {
+    if steps.is_empty() || steps.len() > MAX_STEPS {
+        return Err(PipelineError::StepCount(steps.len()));
+    }
+    let count = steps.len();
+    let mut output_options = OutputOptions::default();
+    let mut planned = Vec::with_capacity(count);
+    for (index, step) in steps.into_iter().enumerate() {
+        let step = match step {
+            PipelineStep::Format(options) => {
+                if index + 1 != count {
+                    return Err(PipelineError::FormatNotLast);
+                }
+                output_options = options;
+                continue;
+            }
+            PipelineStep::Removebg(step) => {
+                if !config.model_enabled(REMOVEBG_MODEL_ID) {
+                    return Err(PipelineError::RemoveBgDisabled);
+                }
+                let refine = Refinement {
+                    threshold: step.mask_threshold,
+                    remove_islands: step.remove_islands.unwrap_or(false),
+                    erode: step.erode.unwrap_or(0),
+                    dilate: step.dilate.unwrap_or(0),
+                    feather: step.feather_radius.unwrap_or(0.0),
+                };
+                refine.validate().map_err(PipelineError::Refine)?;
+                let background = step
+                    .background_color
+                    .as_deref()
+                    .map(str::parse::<Color>)
+                    .transpose()
+                    .map_err(PipelineError::Color)?;
+                Step::RemoveBg { refine, background }
+            }
+            PipelineStep::Upscale(step) => {
+                let options = UpscaleOptions {
+                    model: step.model,
+                    size: SizeRequest {
+                        scale: step.scale,
+                        width: step.width,
+                        height: step.height,
+                        fit: step.fit,
+                    },
+                    face_enhance: step.face_enhance,
+                    denoise_strength: step.denoise_strength,
+                };
+                options.validate(config).map_err(PipelineError::Upscale)?;
+                Step::Upscale(options)
+            }
+            PipelineStep::Resize { width, height, fit } => Step::Transform(
+                Transform::resize(SizeRequest {
+                    scale: None,
+                    width,
+                    height,
+                    fit,
+                })
+                .map_err(PipelineError::Transform)?,
+            ),
+            PipelineStep::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => Step::Transform(
+                Transform::crop(x, y, width, height).map_err(PipelineError::Transform)?,
+            ),
+            PipelineStep::Trim { padding } => Step::Transform(
+                Transform::trim(
+                    padding.map(CropPadding::into_text).as_deref(),
+                    config.max_image_width,
+                    config.max_image_height,
+                )
+                .map_err(PipelineError::Transform)?,
+            ),
+            PipelineStep::Pad {
+                aspect,
+                padding,
+                color,
+            } => Step::Transform(
+                Transform::pad(aspect.as_deref(), padding, color.as_deref())
+                    .map_err(PipelineError::Transform)?,
+            ),
+        };
+        planned.push(step);
+    }
+    Ok((planned, output_options))
+}
+
+impl Step {
+    /// Name of the step, as given in the request.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::RemoveBg { .. } => "removebg",
+            Self::Upscale(_) => "upscale",
+            Self::Transform(transform) => transform.name(),
+        }
+    }
+
+    /// Runs the step on `image`, described by `info`.
+    async fn run(
+        &self,
+        state: &AppState,
+        image: DynamicImage,
+        info: &ImageInfo,
+    ) -> Result<DynamicImage, PipelineError> {
+        let limits = ImageLimits::from_config(&state.config);
+        match self {
+            Self::RemoveBg { refine, background } => {
+                limits.check(info).map_err(PipelineError::Rejected)?;
+                let mut cutout = removebg::segment(state, image)
+                    .await
+                    .map_err(PipelineError::Segment)?;
+                let (refine, background) = (*refine, *background);
+                tokio::task::spawn_blocking(move || {
+                    if !refine.is_noop() {
+                        refine.apply(&mut cutout);
+                    }
+                    match background {
+                        Some(color) => cutout::composite(&cutout, &Background::Color(color)),
+                        None => DynamicImage::ImageRgba8(cutout),
+                    }
+                })
+                .await
+                .map_err(PipelineError::Task)
+            }
+            Self::Upscale(options) => upscaler::upscale_image(state, image, info, options)
+                .await
+                .map_err(PipelineError::Upscale),
+            Self::Transform(transform) => {
+                if let Some((width, height)) = transform
+                    .output_size(info.width, info.height)
+                    .map_err(PipelineError::Transform)?
+                {
+                    check_result(&limits, width, height)?;
+                }
+                let transform = *transform;
+                let result = tokio::task::spawn_blocking(move || transform.apply(image))
+                    .await
+                    .map_err(PipelineError::Task)?
+                    .map_err(PipelineError::Transform)?;
+                check_result(&limits, result.width(), result.height())?;
+                Ok(result)
+            }
+        }
+    }
+}
+
+/// Holds an intermediate result to the largest output the gateway produces.
+fn check_result(limits: &ImageLimits, width: u32, height: u32) -> Result<(), PipelineError> {
+    if imaging::megapixels(width, height) > limits.max_output_megapixels {
+        return Err(PipelineError::Rejected(ImageRejection::TooManyPixels {
+            width,
+            height,
+            max: limits.max_output_megapixels,
+        }));
+    }
+    Ok(())
+}
+
+/// Durations of the completed stages, reported as `Server-Timing`.
+#[derive(Default)]
+pub(crate) struct Timings(String);
+
+impl Timings {
+    /// Records a stage that began at `started`; numbered steps carry their
+    /// position as the description.
+    fn record(&mut self, name: &str, step: Option<usize>, started: Instant) {
+        if !self.0.is_empty() {
+            self.0.push_str(", ");
+        }
+        let millis = started.elapsed().as_secs_f64() * 1000.0;
+        let _ = write!(self.0, "{};dur={:.1}", name, millis);
+        if let Some(step) = step {
+            let _ = write!(self.0, ";desc=\"step {}\"", step);
+        }
+    }
+
+    /// Adds the `Server-Timing` header to `response`.
+    fn attach(self, mut response: Response) -> Response {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            if !self.0.is_empty() {
+                response.headers_mut().insert("server-timing", value);
+            }
+        }
+        response
+    }
+}
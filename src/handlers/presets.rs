@@ -0,0 +1,25 @@
+use crate::models::PresetInfo;
+use crate::state::AppState;
+use axum::{Json, extract::State, response::IntoResponse};
+use serde_json::json;
+
+/// Preset listing handler.
+///
+/// Lists the presets defined in the `PRESETS_PATH` file, sorted by name,
+/// with their endpoint, parameters and overridable fields.
+///
+/// # Returns
+///
+/// * `200 OK` - `{"presets": [...]}`
+pub async fn list_presets(State(state): State<AppState>) -> impl IntoResponse {
+    let presets: Vec<PresetInfo> = state
+        .config
+        .presets
+        .iter()
+        .map(|(name, preset)| PresetInfo {
+            name: name.clone(),
+            preset: preset.clone(),
+        })
+        .collect();
+    Json(json!({ "presets": presets }))
+}
@@ -1,127 +1,824 @@
+use super::respond::{self, Input, InputFields, Options};
 use crate::config::Config;
-use crate::models::RemoveBgRequest;
+use crate::imaging::{
+    self, ImageLimits, ImageRejection, alpha,
+    crop::{self, BBox, CropError, CropSpec},
+    cutout::{self, Background, BackgroundError, Color},
+    effects::{EffectError, EffectOptions, Effects},
+    mask,
+    output::{self, Rendition},
+    refine::Refinement,
+};
+use crate::models::{
+    Crop, CropPadding, Fit, OutputFormat, OutputOptions, PresetEndpoint, REMOVEBG_MODEL_ID,
+    RemoveBgOutput, RemoveBgRequest,
+};
+use crate::state::AppState;
+use crate::upload::{SpooledBody, UploadError, UploadLimits};
 use axum::{
-    body::Body,
-    extract::{FromRequest, Json, Multipart, Request, State},
-    http::{HeaderMap, StatusCode, header},
+    body::Bytes,
+    extract::{Request, State},
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
-use std::sync::Arc;
+use image::{DynamicImage, ImageResult, RgbaImage, codecs::jpeg::JpegEncoder};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{Cursor, Write};
+use tokio::task::JoinError;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
 
 /// Handler for background removal.
 ///
 /// Accepts either:
-/// 1. `multipart/form-data` with an 'image' field (file upload).
-/// 2. `application/json` with a 'url' field (image URL).
+/// 1. `multipart/form-data` with an 'image' field (file upload) and optional output options.
+/// 2. `application/json` with a 'url' field (image URL, fetched by the gateway) and optional output options.
+///
+/// The image header is inspected and checked against the pixel limits before
+/// the image is forwarded to a Modal worker for processing. The worker's PNG
+/// is returned as is, or transcoded when another format is requested through
+/// the `format` option or the `Accept` header. Images above
+/// `REMOVEBG_MASK_THRESHOLD_MEGAPIXELS` take the low-resolution mask path.
+///
+/// The `output` option selects the cut-out (default), the mask, the subject
+/// on a solid `matte_color`, or both the cut-out and the mask. `both` is
+/// returned as `multipart/mixed`, or as a ZIP archive when the `Accept`
+/// header includes `application/zip`. The cut-out can be composited onto a
+/// `background_color`, a `background_image` (an upload or a URL) or, with
+/// `background_blur`, a blurred copy of the original background.
 ///
-/// Forwards the request to a Modal worker for processing and returns the result.
-pub async fn remove_bg(State(config): State<Arc<Config>>, request: Request) -> Response {
-    let content_type = request
+/// The alpha channel can be refined with `mask_threshold`, `remove_islands`,
+/// `erode`, `dilate` and `feather_radius` before anything else is applied.
+///
+/// With `crop: "subject"` the result is cropped to the subject's bounding
+/// box, padded and optionally framed, and the box is reported in the
+/// `X-Subject-BBox` header. Sticker effects (an outline and a drop shadow)
+/// can be drawn around a transparent cut-out on an expanded canvas.
+///
+/// Options can also come from a named preset selected with `preset`.
+pub async fn remove_bg(State(state): State<AppState>, request: Request) -> Response {
+    let config = state.config.clone();
+    if !config.model_enabled(REMOVEBG_MODEL_ID) {
+        return respond::model_disabled(REMOVEBG_MODEL_ID);
+    }
+    let accept = request
         .headers()
-        .get(header::CONTENT_TYPE)
+        .get(header::ACCEPT)
         .and_then(|value| value.to_str().ok())
-        .unwrap_or("");
+        .map(str::to_string);
 
-    let modal_url = &config.modal_removebg_url;
-    let client = reqwest::Client::new();
+    let limits = UploadLimits::from_config(&config);
+    let Input {
+        image: mut image_body,
+        source_name,
+        options,
+    } = match respond::read_input(&state, request, &INPUT, parse_options).await {
+        Ok(input) => input,
+        Err(response) => return response,
+    };
+    let RemoveBgOptions {
+        output: output_options,
+        mode,
+        matte_color,
+        background,
+        crop: crop_options,
+        refine,
+        effects: effect_options,
+    } = options;
 
-    if content_type.starts_with("application/json") {
-        let Json(payload) = match Json::<RemoveBgRequest>::from_request(request, &config).await {
-            Ok(j) => j,
-            Err(e) => {
-                return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response();
-            }
-        };
+    let rendition = match output::negotiate(&output_options, accept.as_deref())
+        .and_then(|negotiated| negotiated.resolve(OutputFormat::Png))
+    {
+        Ok(rendition) => rendition,
+        Err(e) => return e.into_response(),
+    };
 
-        let res = match client.post(modal_url).json(&payload).send().await {
-            Ok(res) => res,
-            Err(e) => {
-                tracing::error!("Failed to call Modal worker: {}", e);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to connect to processing worker",
-                )
-                    .into_response();
-            }
+    let info = match imaging::inspect(&mut image_body).await {
+        Ok(info) => info,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = ImageLimits::from_config(&config).check(&info) {
+        return e.into_response();
+    }
+
+    let matte_color = match matte_color.as_deref().map(str::parse::<Color>).transpose() {
+        Ok(color) => color.unwrap_or(Color::WHITE),
+        Err(e) => return e.into_response(),
+    };
+    let background = match load_background(&state, background, mode, &limits).await {
+        Ok(background) => background,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = refine.validate() {
+        return e.into_response();
+    }
+    let crop = match crop_options.spec(&config) {
+        Ok(crop) => crop,
+        Err(e) => return e.into_response(),
+    };
+    let effects = match effect_options.build() {
+        Ok(effects) => effects,
+        Err(e) => return e.into_response(),
+    };
+    if !effects.is_empty() {
+        if mode != RemoveBgOutput::Cutout || background.is_some() {
+            return EffectError::UnsupportedOutput.into_response();
+        }
+        if !matches!(rendition.format, OutputFormat::Png | OutputFormat::Webp) {
+            return EffectError::UnsupportedFormat(rendition.format).into_response();
+        }
+    }
+    let delivery = Delivery {
+        mode,
+        matte_color,
+        background,
+        refine,
+        crop,
+        effects,
+        rendition,
+        source_name,
+        zip: respond::accepts_zip(accept.as_deref()),
+    };
+
+    if uses_mask_path(&config, info.width, info.height) {
+        let image = match imaging::decode(&mut image_body).await {
+            Ok(image) => image,
+            Err(e) => return e.into_response(),
         };
+        drop(image_body);
+        return match segment_via_mask(&state, image).await {
+            Ok(cutout) => deliver(cutout, delivery).await,
+            Err(e) => e.into_response(),
+        };
+    }
 
-        handle_modal_response(res).await
-    } else if content_type.starts_with("multipart/form-data") {
-        let mut multipart = match Multipart::from_request(request, &config).await {
-            Ok(m) => m,
-            Err(e) => {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    format!("Invalid multipart request: {}", e),
-                )
-                    .into_response();
-            }
+    let res = match state
+        .http
+        .post(&config.modal_removebg_url)
+        .header("Content-Type", "application/octet-stream")
+        .header(header::CONTENT_LENGTH, image_body.len())
+        .body(image_body.into_reqwest_body())
+        .send()
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::error!("Failed to call Modal worker: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to connect to processing worker",
+            )
+                .into_response();
+        }
+    };
+
+    if delivery.needs_cutout() {
+        return match decode_cutout(res).await {
+            Ok(cutout) => deliver(cutout, delivery).await,
+            Err(e) => e.into_response(),
         };
+    }
+    let disposition =
+        respond::content_disposition(delivery.source_name.as_deref(), "nobg", rendition.format);
+    if rendition.is_passthrough(OutputFormat::Png) {
+        respond::stream(res, OutputFormat::Png.mime_type(), disposition).await
+    } else {
+        respond::transcode(res, rendition, disposition, |image| image).await
+    }
+}
+
+/// Multipart fields read by [`remove_bg`].
+const INPUT: InputFields = InputFields {
+    endpoint: PresetEndpoint::Removebg,
+    text: &[
+        "format",
+        "quality",
+        "lossless",
+        "output",
+        "matte_color",
+        "background_color",
+        "background_image",
+        "background_fit",
+        "background_blur",
+        "crop",
+        "crop_padding",
+        "crop_aspect",
+        "crop_size",
+        "mask_threshold",
+        "remove_islands",
+        "erode",
+        "dilate",
+        "feather_radius",
+        "outline_width",
+        "outline_color",
+        "shadow_offset_x",
+        "shadow_offset_y",
+        "shadow_blur",
+        "shadow_opacity",
+    ],
+    files: &["background_image"],
+};
+
+/// Options of a `/removebg` request, before validation.
+#[derive(Default)]
+struct RemoveBgOptions {
+    output: OutputOptions,
+    mode: RemoveBgOutput,
+    matte_color: Option<String>,
+    background: BackgroundOptions,
+    crop: CropOptions,
+    refine: Refinement,
+    effects: EffectOptions,
+}
+
+/// Reads the options of a JSON payload or of multipart fields.
+#[allow(clippy::result_large_err)]
+fn parse_options(options: Options<RemoveBgRequest>) -> Result<RemoveBgOptions, Response> {
+    let (fields, files) = match options {
+        Options::Json(payload) => {
+            return Ok(RemoveBgOptions {
+                output: payload.output,
+                mode: payload.mode.unwrap_or_default(),
+                matte_color: payload.matte_color,
+                background: BackgroundOptions {
+                    color: payload.background_color,
+                    image: payload.background_image.map(BackgroundSource::Url),
+                    fit: payload.background_fit,
+                    blur: payload.background_blur,
+                },
+                crop: CropOptions {
+                    crop: payload.crop,
+                    padding: payload.crop_padding.map(CropPadding::into_text),
+                    aspect: payload.crop_aspect,
+                    size: payload.crop_size,
+                },
+                refine: Refinement {
+                    threshold: payload.mask_threshold,
+                    remove_islands: payload.remove_islands.unwrap_or(false),
+                    erode: payload.erode.unwrap_or(0),
+                    dilate: payload.dilate.unwrap_or(0),
+                    feather: payload.feather_radius.unwrap_or(0.0),
+                },
+                effects: EffectOptions {
+                    outline_width: payload.outline_width,
+                    outline_color: payload.outline_color,
+                    shadow_offset_x: payload.shadow_offset_x,
+                    shadow_offset_y: payload.shadow_offset_y,
+                    shadow_blur: payload.shadow_blur,
+                    shadow_opacity: payload.shadow_opacity,
+                },
+            });
+        }
+        Options::Multipart { fields, files } => (fields, files),
+    };
 
-        let mut image_data = None;
-        while let Some(field) = multipart.next_field().await.unwrap_or(None) {
-            if field.name() == Some("image") {
-                let Ok(bytes) = field.bytes().await else {
-                    continue;
-                };
-                image_data = Some(bytes);
-                break;
+    let mut options = RemoveBgOptions::default();
+    options.background.image = files
+        .into_iter()
+        .next()
+        .map(|(_, body)| BackgroundSource::Upload(body));
+    for (name, text) in fields {
+        match name.as_str() {
+            "output" => {
+                options.mode =
+                    serde_json::from_value(serde_json::Value::String(text.to_lowercase()))
+                        .map_err(|_| {
+                            (
+                                StatusCode::BAD_REQUEST,
+                                format!(
+                                    "Output must be cutout, mask, matte or both, got '{}'",
+                                    text
+                                ),
+                            )
+                                .into_response()
+                        })?;
+            }
+            "matte_color" => options.matte_color = Some(text),
+            "background_color" => options.background.color = Some(text),
+            "background_image" => {
+                options.background.image = Some(BackgroundSource::Url(text));
+            }
+            "background_fit" => {
+                let fit = serde_json::from_value::<Fit>(serde_json::Value::String(text)).map_err(
+                    |_| {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            "Background fit must be contain, cover or exact",
+                        )
+                            .into_response()
+                    },
+                )?;
+                options.background.fit = Some(fit);
+            }
+            "background_blur" => match text.parse::<f32>() {
+                Ok(blur) => options.background.blur = Some(blur),
+                Err(_) => return Err(BackgroundError::InvalidBlur(text).into_response()),
+            },
+            "crop" => {
+                let crop = serde_json::from_value::<Crop>(serde_json::Value::String(text))
+                    .map_err(|_| {
+                        (StatusCode::BAD_REQUEST, "Crop must be subject").into_response()
+                    })?;
+                options.crop.crop = Some(crop);
+            }
+            "crop_padding" => options.crop.padding = Some(text),
+            "crop_aspect" => options.crop.aspect = Some(text),
+            "crop_size" => options.crop.size = Some(text),
+            "mask_threshold" | "remove_islands" | "erode" | "dilate" | "feather_radius" => {
+                options
+                    .refine
+                    .parse_field(&name, &text)
+                    .map_err(IntoResponse::into_response)?;
+            }
+            "outline_width" | "outline_color" | "shadow_offset_x" | "shadow_offset_y"
+            | "shadow_blur" | "shadow_opacity" => {
+                options
+                    .effects
+                    .parse_field(&name, &text)
+                    .map_err(IntoResponse::into_response)?;
+            }
+            _ => {
+                output::parse_field(&mut options.output, &name, &text)
+                    .map_err(IntoResponse::into_response)?;
             }
         }
+    }
+    Ok(options)
+}
 
-        let image_bytes = match image_data {
-            Some(data) => data,
-            None => {
-                return (StatusCode::BAD_REQUEST, "No image found in 'image' field")
-                    .into_response();
+/// Crop options as received, before validation.
+#[derive(Default)]
+struct CropOptions {
+    crop: Option<Crop>,
+    padding: Option<String>,
+    aspect: Option<String>,
+    size: Option<String>,
+}
+
+impl CropOptions {
+    /// Validates the options against the input limits.
+    fn spec(&self, config: &Config) -> Result<Option<CropSpec>, CropError> {
+        if self.crop.is_none() {
+            if self.padding.is_some() || self.aspect.is_some() || self.size.is_some() {
+                return Err(CropError::NotRequested);
             }
-        };
+            return Ok(None);
+        }
+        crop::spec(
+            self.padding.as_deref(),
+            self.aspect.as_deref(),
+            self.size.as_deref(),
+            config.max_image_width,
+            config.max_image_height,
+        )
+        .map(Some)
+    }
+}
+
+/// Where a `background_image` comes from.
+enum BackgroundSource {
+    /// A multipart file field.
+    Upload(SpooledBody),
+    /// A URL fetched by the gateway.
+    Url(String),
+}
+
+/// Background options as received, before validation.
+#[derive(Default)]
+struct BackgroundOptions {
+    color: Option<String>,
+    image: Option<BackgroundSource>,
+    fit: Option<Fit>,
+    blur: Option<f32>,
+}
+
+/// Failure while preparing the replacement background.
+enum BackgroundLoadError {
+    /// The options are invalid or conflicting.
+    Invalid(BackgroundError),
+    /// `background_color` could not be parsed.
+    Color(cutout::ColorError),
+    /// The background image could not be read or fetched.
+    Upload(UploadError),
+    /// The background image was rejected.
+    Image(ImageRejection),
+}
+
+impl IntoResponse for BackgroundLoadError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Invalid(e) => e.into_response(),
+            Self::Color(e) => e.into_response(),
+            Self::Upload(e) => e.into_response(),
+            Self::Image(e) => e.into_response(),
+        }
+    }
+}
+
+/// Validates the background options and loads the background image, which
+/// is subject to the same size and pixel limits as the input.
+async fn load_background(
+    state: &AppState,
+    options: BackgroundOptions,
+    mode: RemoveBgOutput,
+    limits: &UploadLimits,
+) -> Result<Option<Background>, BackgroundLoadError> {
+    let BackgroundOptions {
+        color,
+        image,
+        fit,
+        blur,
+    } = options;
+    if color.is_none() && image.is_none() && blur.is_none() {
+        return Ok(None);
+    }
+    if matches!(mode, RemoveBgOutput::Mask | RemoveBgOutput::Matte) {
+        return Err(BackgroundLoadError::Invalid(
+            BackgroundError::UnsupportedOutput,
+        ));
+    }
+    if let Some(blur) = blur {
+        if color.is_some() || image.is_some() {
+            return Err(BackgroundLoadError::Invalid(BackgroundError::Conflicting));
+        }
+        let sigma = cutout::check_blur(blur).map_err(BackgroundLoadError::Invalid)?;
+        return Ok(Some(Background::Blur { sigma }));
+    }
 
-        let res = match client
-            .post(modal_url)
-            .body(image_bytes)
-            .header("Content-Type", "application/octet-stream")
-            .send()
+    let color = color
+        .as_deref()
+        .map(str::parse::<Color>)
+        .transpose()
+        .map_err(BackgroundLoadError::Color)?;
+    let mut body = match image {
+        Some(BackgroundSource::Upload(body)) => body,
+        Some(BackgroundSource::Url(url)) => SpooledBody::fetch(&state.fetch_http, &url, limits)
             .await
-        {
-            Ok(res) => res,
-            Err(e) => {
+            .map_err(BackgroundLoadError::Upload)?,
+        None => return Ok(color.map(Background::Color)),
+    };
+    let info = imaging::inspect(&mut body)
+        .await
+        .map_err(BackgroundLoadError::Image)?;
+    ImageLimits::from_config(&state.config)
+        .check(&info)
+        .map_err(BackgroundLoadError::Image)?;
+    let image = imaging::decode(&mut body)
+        .await
+        .map_err(BackgroundLoadError::Image)?;
+    Ok(Some(Background::Image {
+        image,
+        fit: fit.unwrap_or(Fit::Cover),
+        fill: color,
+    }))
+}
+
+/// How the cut-out is turned into the response.
+struct Delivery {
+    mode: RemoveBgOutput,
+    matte_color: Color,
+    /// Replacement background for the cut-out.
+    background: Option<Background>,
+    /// Alpha refinement, applied first.
+    refine: Refinement,
+    /// Cropping to the subject, applied after refinement.
+    crop: Option<CropSpec>,
+    /// Sticker effects, drawn last around a transparent cut-out.
+    effects: Effects,
+    rendition: Rendition,
+    source_name: Option<String>,
+    /// Package `both` as a ZIP archive instead of `multipart/mixed`.
+    zip: bool,
+}
+
+/// Failure while obtaining the cut-out from the worker.
+pub(crate) enum SegmentError {
+    /// The input could not be decoded.
+    Rejected(ImageRejection),
+    /// The worker could not be reached.
+    Connect(reqwest::Error),
+    /// The worker answered with an error.
+    Worker(reqwest::StatusCode, String),
+    /// The worker's answer could not be read or decoded.
+    Unreadable(String),
+    /// A blocking task panicked.
+    Task(JoinError),
+}
+
+impl IntoResponse for SegmentError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Rejected(e) => e.into_response(),
+            Self::Connect(e) => {
                 tracing::error!("Failed to call Modal worker: {}", e);
-                return (
+                (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     "Failed to connect to processing worker",
                 )
-                    .into_response();
+                    .into_response()
+            }
+            Self::Worker(status, error_text) => {
+                tracing::error!("Modal worker returned error: {}", status);
+                tracing::error!("Modal worker error details: {}", error_text);
+                (
+                    StatusCode::BAD_GATEWAY,
+                    format!("Processing worker returned an error: {}", error_text),
+                )
+                    .into_response()
+            }
+            Self::Unreadable(e) => {
+                tracing::error!("Failed to read Modal worker response: {}", e);
+                (
+                    StatusCode::BAD_GATEWAY,
+                    "Processing worker returned an unreadable image",
+                )
+                    .into_response()
+            }
+            Self::Task(e) => {
+                tracing::error!("Segmentation task failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process image").into_response()
             }
+        }
+    }
+}
+
+/// Reads the worker's response body, failing on an error status.
+async fn worker_bytes(res: reqwest::Response) -> Result<Bytes, SegmentError> {
+    let status = res.status();
+    if !status.is_success() {
+        return Err(SegmentError::Worker(
+            status,
+            res.text().await.unwrap_or_default(),
+        ));
+    }
+    res.bytes()
+        .await
+        .map_err(|e| SegmentError::Unreadable(e.to_string()))
+}
+
+/// Decodes the cut-out returned by the worker.
+async fn decode_cutout(res: reqwest::Response) -> Result<RgbaImage, SegmentError> {
+    let bytes = worker_bytes(res).await?;
+    tokio::task::spawn_blocking(move || image::load_from_memory(&bytes).map(|i| i.into_rgba8()))
+        .await
+        .map_err(SegmentError::Task)?
+        .map_err(|e| SegmentError::Unreadable(e.to_string()))
+}
+
+/// Returns `true` if a `width`x`height` image takes the low-resolution mask
+/// path.
+fn uses_mask_path(config: &Config, width: u32, height: u32) -> bool {
+    imaging::megapixels(width, height) > config.removebg_mask_threshold_megapixels
+        && width.max(height) > config.removebg_mask_size
+}
+
+/// Removes the background of an image already decoded by the gateway, as the
+/// `removebg` step of `/pipeline` does. Large images take the low-resolution
+/// mask path.
+pub(crate) async fn segment(
+    state: &AppState,
+    image: DynamicImage,
+) -> Result<RgbaImage, SegmentError> {
+    if uses_mask_path(&state.config, image.width(), image.height()) {
+        return segment_via_mask(state, image).await;
+    }
+    let png = tokio::task::spawn_blocking(move || {
+        let mut png = Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageFormat::Png)
+            .map(|_| png.into_inner())
+    })
+    .await
+    .map_err(SegmentError::Task)?
+    .map_err(|e| SegmentError::Rejected(ImageRejection::Undecodable(e.to_string())))?;
+    let res = state
+        .http
+        .post(&state.config.modal_removebg_url)
+        .header("Content-Type", "application/octet-stream")
+        .header(header::CONTENT_LENGTH, png.len())
+        .body(png)
+        .send()
+        .await
+        .map_err(SegmentError::Connect)?;
+    decode_cutout(res).await
+}
+
+/// Removes the background of a large image through the low-resolution mask
+/// path: the worker segments a downsampled copy and returns only the mask,
+/// which is upsampled with a guided filter and applied to the original.
+async fn segment_via_mask(
+    state: &AppState,
+    image: DynamicImage,
+) -> Result<RgbaImage, SegmentError> {
+    let mask_size = state.config.removebg_mask_size;
+    let (image, guide_low, small) = tokio::task::spawn_blocking(move || {
+        let small = mask::downsample(&image, mask_size);
+        let mut jpeg = Cursor::new(Vec::new());
+        small
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, 95))
+            .map(|_| (image, small.to_luma8(), jpeg.into_inner()))
+    })
+    .await
+    .map_err(SegmentError::Task)?
+    .map_err(|e| SegmentError::Rejected(ImageRejection::Undecodable(e.to_string())))?;
+    tracing::debug!(
+        "Requesting {}x{} mask for {}x{} image",
+        guide_low.width(),
+        guide_low.height(),
+        image.width(),
+        image.height()
+    );
+
+    let res = state
+        .http
+        .post(&state.config.modal_removebg_url)
+        .header("Content-Type", "application/octet-stream")
+        .header("X-Output", "mask")
+        .header(header::CONTENT_LENGTH, small.len())
+        .body(small)
+        .send()
+        .await
+        .map_err(SegmentError::Connect)?;
+    let bytes = worker_bytes(res).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let low_mask = image::load_from_memory(&bytes)?.to_luma8();
+        let alpha = mask::upsample_guided(&low_mask, &guide_low, &image.to_luma8());
+        Ok(alpha::reattach(&image, &alpha))
+    })
+    .await
+    .map_err(SegmentError::Task)?
+    .map_err(|e: image::ImageError| SegmentError::Unreadable(e.to_string()))
+}
+
+/// Renders `cutout` according to `delivery` on a blocking thread.
+async fn deliver(mut cutout: RgbaImage, delivery: Delivery) -> Response {
+    let rendered = tokio::task::spawn_blocking(move || -> ImageResult<Response> {
+        let Delivery {
+            mode,
+            matte_color,
+            background,
+            refine,
+            crop,
+            effects,
+            rendition,
+            source_name,
+            zip,
+        } = delivery;
+        if !refine.is_noop() {
+            refine.apply(&mut cutout);
+        }
+        let (cutout, bbox) = match crop.map(|spec| crop::crop(&cutout, &spec)) {
+            Some(Ok((cropped, bbox))) => (cropped, Some(bbox)),
+            Some(Err(e)) => return Ok(e.into_response()),
+            None => (cutout, None),
         };
+        let source_name = source_name.as_deref();
+        let (image, suffix) = match mode {
+            RemoveBgOutput::Cutout => match &background {
+                Some(background) => (cutout::composite(&cutout, background), "nobg"),
+                None if !effects.is_empty() => {
+                    (DynamicImage::ImageRgba8(effects.apply(&cutout)), "nobg")
+                }
+                None => (DynamicImage::ImageRgba8(cutout), "nobg"),
+            },
+            RemoveBgOutput::Mask => (DynamicImage::ImageLuma8(cutout::mask(&cutout)), "mask"),
+            RemoveBgOutput::Matte => (cutout::matte(&cutout, matte_color), "matte"),
+            RemoveBgOutput::Both => {
+                let response = both(cutout, background.as_ref(), rendition, source_name, zip)?;
+                return Ok(with_bbox(response, bbox));
+            }
+        };
+        let encoded = output::encode(&image, &rendition)?;
+        let response = respond::encoded_image(
+            Bytes::from(encoded),
+            rendition.format,
+            respond::content_disposition(source_name, suffix, rendition.format),
+        );
+        Ok(with_bbox(response, bbox))
+    })
+    .await;
 
-        handle_modal_response(res).await
-    } else {
-        (
-            StatusCode::UNSUPPORTED_MEDIA_TYPE,
-            "Content-Type must be application/json or multipart/form-data",
-        )
-            .into_response()
+    match rendered {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to encode result: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response()
+        }
+        Err(e) => {
+            tracing::error!("Encoding task failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response()
+        }
     }
 }
 
-async fn handle_modal_response(res: reqwest::Response) -> Response {
-    if !res.status().is_success() {
-        tracing::error!("Modal worker returned error: {}", res.status());
-        let error_text = res.text().await.unwrap_or_default();
-        tracing::error!("Modal worker error details: {}", error_text);
-        return (
-            StatusCode::BAD_GATEWAY,
-            format!("Processing worker returned an error: {}", error_text),
-        )
-            .into_response();
+impl Delivery {
+    /// Returns `true` if the worker's cut-out must be decoded, rather than
+    /// streamed or transcoded as is.
+    fn needs_cutout(&self) -> bool {
+        self.mode != RemoveBgOutput::Cutout
+            || self.background.is_some()
+            || !self.refine.is_noop()
+            || self.crop.is_some()
+            || !self.effects.is_empty()
     }
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, "image/png".parse().unwrap());
+/// Reports the subject's bounding box in `X-Subject-BBox` as
+/// `x,y,width,height`.
+fn with_bbox(mut response: Response, bbox: Option<BBox>) -> Response {
+    if let Some(bbox) = bbox {
+        response
+            .headers_mut()
+            .insert("x-subject-bbox", bbox.to_string().parse().unwrap());
+    }
+    response
+}
+
+/// Packages the cut-out, encoded as `rendition` over the optional
+/// `background`, and its mask as a PNG.
+fn both(
+    cutout: RgbaImage,
+    background: Option<&Background>,
+    rendition: Rendition,
+    source_name: Option<&str>,
+    zip: bool,
+) -> ImageResult<Response> {
+    let mask_rendition = Rendition {
+        format: OutputFormat::Png,
+        quality: None,
+        lossless: false,
+    };
+    let mask = output::encode(
+        &DynamicImage::ImageLuma8(cutout::mask(&cutout)),
+        &mask_rendition,
+    )?;
+    let image = match background {
+        Some(background) => cutout::composite(&cutout, background),
+        None => DynamicImage::ImageRgba8(cutout),
+    };
+    let image = output::encode(&image, &rendition)?;
+    let parts = [
+        (
+            respond::file_name(source_name, "nobg", rendition.format.extension()),
+            rendition.format,
+            image,
+        ),
+        (
+            respond::file_name(source_name, "mask", "png"),
+            OutputFormat::Png,
+            mask,
+        ),
+    ];
 
-    let stream = res.bytes_stream();
-    let body = Body::from_stream(stream);
+    let (content_type, disposition, body) = if zip {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, _, bytes) in &parts {
+            archive
+                .start_file(name.as_str(), options)
+                .map_err(std::io::Error::from)?;
+            archive.write_all(bytes)?;
+        }
+        let archive = archive.finish().map_err(std::io::Error::from)?;
+        let disposition = format!(
+            "attachment; filename=\"{}\"",
+            respond::file_name(source_name, "nobg", "zip")
+        );
+        (
+            "application/zip".to_string(),
+            disposition,
+            archive.into_inner(),
+        )
+    } else {
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+        let mut body = Vec::new();
+        for (name, format, bytes) in &parts {
+            write!(
+                body,
+                "--{}\r\nContent-Type: {}\r\nContent-Disposition: inline; filename=\"{}\"\r\n\r\n",
+                boundary,
+                format.mime_type(),
+                name
+            )?;
+            body.extend_from_slice(bytes);
+            body.extend_from_slice(b"\r\n");
+        }
+        write!(body, "--{}--\r\n", boundary)?;
+        (
+            format!("multipart/mixed; boundary={}", boundary),
+            "inline".to_string(),
+            body,
+        )
+    };
 
-    (headers, body).into_response()
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::VARY, "Accept".to_string()),
+        ],
+        body,
+    )
+        .into_response())
 }
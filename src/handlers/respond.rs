@@ -0,0 +1,418 @@
+//! Helpers shared by the image handlers for reading their input and turning
+//! worker responses into client responses.
+
+use crate::imaging::output::{self, Rendition};
+use crate::models::{
+    OutputFormat, PipelineRequest, PresetEndpoint, RemoveBgRequest, UpscaleRequest,
+};
+use crate::presets;
+use crate::state::AppState;
+use crate::upload::{SpooledBody, UploadError, UploadLimits, read_text_field};
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Json, Multipart, Request},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, ImageResult};
+use serde::de::DeserializeOwned;
+use tokio::task::JoinError;
+
+/// A request body, by `Content-Type`.
+pub(crate) enum RequestBody {
+    /// An `application/json` body.
+    Json(serde_json::Value),
+    /// A `multipart/form-data` body, not read yet.
+    Multipart(Multipart),
+}
+
+/// Reads the JSON body or opens the multipart stream of `request`.
+///
+/// Bodies of any other type are refused with `415 Unsupported Media Type`.
+pub(crate) async fn read_body(state: &AppState, request: Request) -> Result<RequestBody, Response> {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    if content_type.starts_with("application/json") {
+        match Json::<serde_json::Value>::from_request(request, state).await {
+            Ok(Json(body)) => Ok(RequestBody::Json(body)),
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response())
+            }
+            Err(e) => {
+                Err((StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response())
+            }
+        }
+    } else if content_type.starts_with("multipart/form-data") {
+        match Multipart::from_request(request, state).await {
+            Ok(multipart) => Ok(RequestBody::Multipart(multipart)),
+            Err(e) => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Invalid multipart request: {}", e),
+            )
+                .into_response()),
+        }
+    } else {
+        Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/json or multipart/form-data",
+        )
+            .into_response())
+    }
+}
+
+/// JSON payload of a single-image endpoint, naming the image by URL.
+pub(crate) trait ImageRequest: DeserializeOwned {
+    /// URL of the image, fetched by the gateway.
+    fn url(&self) -> &str;
+}
+
+impl ImageRequest for RemoveBgRequest {
+    fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl ImageRequest for UpscaleRequest {
+    fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl ImageRequest for PipelineRequest {
+    fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Multipart fields a single-image endpoint reads besides `image` and
+/// `preset`.
+pub(crate) struct InputFields {
+    /// Endpoint whose presets apply.
+    pub endpoint: PresetEndpoint,
+    /// Text fields; other fields are skipped unread.
+    pub text: &'static [&'static str],
+    /// Fields read as files when they carry a file name, and as text
+    /// otherwise.
+    pub files: &'static [&'static str],
+}
+
+/// Options of a single-image request, before the handler parses them.
+pub(crate) enum Options<T> {
+    /// The JSON payload, with the preset filled in.
+    Json(T),
+    /// The multipart text fields, preceded by the preset's, and the file
+    /// fields named in [`InputFields::files`].
+    Multipart {
+        fields: Vec<(String, String)>,
+        files: Vec<(String, SpooledBody)>,
+    },
+}
+
+/// The image of a single-image request and its parsed options.
+pub(crate) struct Input<O> {
+    /// The image, spooled like an upload.
+    pub image: SpooledBody,
+    /// Name of the uploaded file, or the last segment of the URL.
+    pub source_name: Option<String>,
+    /// What the handler's `parse` made of the options.
+    pub options: O,
+}
+
+/// Reads a single-image request: an `application/json` payload with a
+/// `url`, or a `multipart/form-data` upload in an `image` field.
+///
+/// Presets are applied, then `parse` turns the options into the handler's
+/// own. It runs before the image URL is fetched, so invalid options are
+/// refused without reaching the image source.
+pub(crate) async fn read_input<T, O>(
+    state: &AppState,
+    request: Request,
+    input: &InputFields,
+    parse: impl FnOnce(Options<T>) -> Result<O, Response>,
+) -> Result<Input<O>, Response>
+where
+    T: ImageRequest,
+{
+    let config = &state.config;
+    let limits = UploadLimits::from_config(config);
+    let mut multipart = match read_body(state, request).await? {
+        RequestBody::Json(body) => {
+            let payload: T = presets::from_json(config, input.endpoint, body)
+                .map_err(IntoResponse::into_response)?;
+            let url = payload.url().to_string();
+            let options = parse(Options::Json(payload))?;
+            let image = SpooledBody::fetch(&state.fetch_http, &url, &limits)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(Input {
+                image,
+                source_name: url_file_name(&url),
+                options,
+            });
+        }
+        RequestBody::Multipart(multipart) => multipart,
+    };
+
+    let mut image = None;
+    let mut source_name = None;
+    let mut preset = None;
+    let mut fields = Vec::new();
+    let mut files = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return Err(UploadError::from(e).into_response()),
+        };
+        let name = field.name().unwrap_or("").to_string();
+        if name == "image" {
+            source_name = field.file_name().map(str::to_string);
+            image = Some(
+                SpooledBody::from_field(field, &limits)
+                    .await
+                    .map_err(IntoResponse::into_response)?,
+            );
+            continue;
+        }
+        if input.files.contains(&name.as_str()) && field.file_name().is_some() {
+            let body = SpooledBody::from_field(field, &limits)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            files.push((name, body));
+            continue;
+        }
+        if name != "preset" && !input.text.contains(&name.as_str()) {
+            continue;
+        }
+
+        let text = read_text_field(field, &limits)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if name == "preset" {
+            preset = Some(text);
+        } else {
+            fields.push((name, text));
+        }
+    }
+
+    let uploads: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    let fields = presets::merge_fields(config, input.endpoint, preset.as_deref(), fields, &uploads)
+        .map_err(IntoResponse::into_response)?;
+    let options = parse(Options::Multipart { fields, files })?;
+    match image {
+        Some(image) if !image.is_empty() => Ok(Input {
+            image,
+            source_name,
+            options,
+        }),
+        _ => Err((StatusCode::BAD_REQUEST, "No image found in 'image' field").into_response()),
+    }
+}
+
+/// Maps a failed worker response to `502 Bad Gateway`.
+pub(crate) async fn worker_error(res: reqwest::Response) -> Response {
+    tracing::error!("Modal worker returned error: {}", res.status());
+    let error_text = res.text().await.unwrap_or_default();
+    tracing::error!("Modal worker error details: {}", error_text);
+    (
+        StatusCode::BAD_GATEWAY,
+        format!("Processing worker returned an error: {}", error_text),
+    )
+        .into_response()
+}
+
+/// Rejects a request for a model disabled through `DISABLED_MODELS`.
+pub(crate) fn model_disabled(id: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Model '{}' is disabled", id),
+    )
+        .into_response()
+}
+
+/// Streams a successful worker response to the client unchanged.
+pub(crate) async fn stream(
+    res: reqwest::Response,
+    default_content_type: &str,
+    disposition: HeaderValue,
+) -> Response {
+    if !res.status().is_success() {
+        return worker_error(res).await;
+    }
+
+    let mut headers = HeaderMap::new();
+    let ct = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(default_content_type);
+    headers.insert(header::CONTENT_TYPE, ct.parse().unwrap());
+    if let Some(length) = res.headers().get(header::CONTENT_LENGTH) {
+        headers.insert(header::CONTENT_LENGTH, length.clone());
+    }
+    headers.insert(header::CONTENT_DISPOSITION, disposition);
+    headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+
+    let stream = res.bytes_stream();
+    let body = Body::from_stream(stream);
+
+    (headers, body).into_response()
+}
+
+/// Decodes the worker's output, applies `process` and encodes the result as
+/// `rendition` on a blocking thread.
+pub(crate) async fn transcode<F>(
+    res: reqwest::Response,
+    rendition: Rendition,
+    disposition: HeaderValue,
+    process: F,
+) -> Response
+where
+    F: FnOnce(DynamicImage) -> DynamicImage + Send + 'static,
+{
+    if !res.status().is_success() {
+        return worker_error(res).await;
+    }
+
+    let worker_output = match res.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read Modal worker response: {}", e);
+            return (
+                StatusCode::BAD_GATEWAY,
+                "Failed to read processing worker response",
+            )
+                .into_response();
+        }
+    };
+
+    let encoded = tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&worker_output)?;
+        output::encode(&process(image), &rendition)
+    })
+    .await;
+    encoded_result(encoded, rendition, disposition)
+}
+
+/// Applies `process` to an image assembled by the gateway and encodes the
+/// result as `rendition` on a blocking thread.
+pub(crate) async fn render<F>(
+    image: DynamicImage,
+    rendition: Rendition,
+    disposition: HeaderValue,
+    process: F,
+) -> Response
+where
+    F: FnOnce(DynamicImage) -> DynamicImage + Send + 'static,
+{
+    let encoded =
+        tokio::task::spawn_blocking(move || output::encode(&process(image), &rendition)).await;
+    encoded_result(encoded, rendition, disposition)
+}
+
+fn encoded_result(
+    encoded: Result<ImageResult<Vec<u8>>, JoinError>,
+    rendition: Rendition,
+    disposition: HeaderValue,
+) -> Response {
+    match encoded {
+        Ok(Ok(encoded)) => encoded_image(Bytes::from(encoded), rendition.format, disposition),
+        Ok(Err(e)) => {
+            tracing::error!("Failed to transcode worker output: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                "Processing worker returned an unreadable image",
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Transcoding task failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode image").into_response()
+        }
+    }
+}
+
+/// Builds the response for an image encoded by the gateway.
+pub(crate) fn encoded_image(
+    bytes: Bytes,
+    format: OutputFormat,
+    disposition: HeaderValue,
+) -> Response {
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.mime_type()),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::VARY, HeaderValue::from_static("Accept")),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+/// Returns `true` if the `Accept` header asks for `application/zip`.
+pub(crate) fn accepts_zip(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            range
+                .split(';')
+                .next()
+                .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/zip"))
+        })
+    })
+}
+
+/// Builds an inline `Content-Disposition` named after the source image,
+/// e.g. `photo-nobg.png` for `photo.jpg`.
+pub(crate) fn content_disposition(
+    source_name: Option<&str>,
+    suffix: &str,
+    format: OutputFormat,
+) -> HeaderValue {
+    format!(
+        "inline; filename=\"{}\"",
+        file_name(source_name, suffix, format.extension())
+    )
+    .parse()
+    .unwrap()
+}
+
+/// Names a result after the source image, e.g. `photo-nobg.png` for
+/// `photo.jpg`. The stem is reduced to characters safe in a header.
+pub(crate) fn file_name(source_name: Option<&str>, suffix: &str, extension: &str) -> String {
+    let stem = source_name
+        .map(|name| name.rsplit(['/', '\\']).next().unwrap_or(name))
+        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+        .map(|stem| {
+            stem.chars()
+                .take(100)
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "image".to_string());
+
+    format!("{}-{}.{}", stem, suffix, extension)
+}
+
+/// Returns the last path segment of `url`, used to name the result.
+pub(crate) fn url_file_name(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .path_segments()?
+        .next_back()
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,248 @@
+use crate::jobs::unix_now;
+use crate::models::{SignedUrl, SignedUrlRequest};
+use crate::state::AppState;
+use crate::storage::{SignatureError, SignedQuery, StorageError};
+use axum::{
+    Extension, Json,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+use std::ops::Range;
+
+/// Error returned by the result download endpoint.
+#[derive(Debug)]
+pub enum ResultError {
+    /// No result with this key, or it has expired.
+    NotFound,
+    /// The `Range` header lies outside a result of this size.
+    RangeNotSatisfiable(u64),
+    /// The download URL is unsigned, expired or otherwise refused.
+    Signature(SignatureError),
+    /// Signing keys are not configured.
+    SigningDisabled,
+    /// The storage backend failed.
+    Storage(StorageError),
+}
+
+impl From<StorageError> for ResultError {
+    fn from(e: StorageError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<SignatureError> for ResultError {
+    fn from(e: SignatureError) -> Self {
+        Self::Signature(e)
+    }
+}
+
+impl IntoResponse for ResultError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, "Result not found").into_response(),
+            Self::RangeNotSatisfiable(size) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                "Requested range not satisfiable",
+            )
+                .into_response(),
+            Self::Signature(e) => e.into_response(),
+            Self::SigningDisabled => {
+                (StatusCode::BAD_REQUEST, "Signed URLs are not configured").into_response()
+            }
+            Self::Storage(e) => e.into_response(),
+        }
+    }
+}
+
+/// Stored result download handler.
+///
+/// Serves a result persisted with `?store=true` or by a background job,
+/// with `ETag`, `If-None-Match` and single-range `Range` support. When
+/// signing keys are configured, only signed download URLs are accepted.
+///
+/// A single-use URL is used up only once the whole result has been read for
+/// it, so it ignores `If-None-Match` and `Range`: a revalidation or a byte
+/// range would otherwise either spend the URL without delivering the result
+/// or deliver it without spending the URL.
+///
+/// # Returns
+///
+/// * `200 OK` - The result.
+/// * `206 Partial Content` - The requested range of the result.
+/// * `304 Not Modified` - `If-None-Match` matches the result's `ETag`.
+/// * `403 Forbidden` - Unsigned, tampered, expired, used or misdirected URL.
+/// * `404 Not Found` - Unknown or expired result.
+/// * `416 Range Not Satisfiable` - The range starts past the end of the result.
+pub async fn get_result(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(query): Query<SignedQuery>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+) -> Result<Response, ResultError> {
+    let storage = state.storage.as_ref().ok_or(ResultError::NotFound)?;
+    let signer = storage.signer();
+    if let Some(signer) = signer {
+        signer.verify(
+            &key,
+            &query,
+            peer.map(|Extension(ConnectInfo(addr))| addr.ip()),
+        )?;
+    }
+    let meta = storage.head(&key).await?.ok_or(ResultError::NotFound)?;
+    let once = query.once.as_deref().filter(|_| signer.is_some());
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(etag) = HeaderValue::from_str(&meta.etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    let mut expires_at = meta.created_at + storage.ttl().as_secs();
+    if signer.is_some() {
+        expires_at = expires_at.min(query.expires.unwrap_or_default());
+    }
+    let cache_control = if query.once.is_some() {
+        "no-store".to_string()
+    } else {
+        format!("private, max-age={}", expires_at.saturating_sub(unix_now()))
+    };
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&cache_control).unwrap(),
+    );
+    let accept_ranges = if once.is_some() { "none" } else { "bytes" };
+    response_headers.insert(
+        header::ACCEPT_RANGES,
+        HeaderValue::from_static(accept_ranges),
+    );
+
+    let not_modified = once.is_none()
+        && headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|tag| tag.trim() == "*" || tag.trim() == meta.etag)
+            });
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    let range = headers
+        .get(header::RANGE)
+        .filter(|_| once.is_none())
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, meta.size))
+        .transpose()
+        .map_err(|_| ResultError::RangeNotSatisfiable(meta.size))?;
+    let status = match &range {
+        Some(range) => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!(
+                    "bytes {}-{}/{}",
+                    range.start,
+                    range.end - 1,
+                    meta.size
+                ))
+                .unwrap(),
+            );
+            StatusCode::PARTIAL_CONTENT
+        }
+        None => StatusCode::OK,
+    };
+    let body = storage.read(&key, range.unwrap_or(0..meta.size)).await?;
+    if let Some(once) = once {
+        if !storage.mark_used(once).await? {
+            return Err(SignatureError::AlreadyUsed.into());
+        }
+    }
+    if let Ok(content_type) = HeaderValue::from_str(&meta.content_type) {
+        response_headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    Ok((status, response_headers, body).into_response())
+}
+
+/// Signed download URL handler.
+///
+/// Mints a new download URL for a result, optionally single-use or bound to
+/// a client address. The request must itself carry a valid signed URL for
+/// the result, such as the one returned when it was stored; a single-use
+/// URL is used up by minting.
+///
+/// # Returns
+///
+/// * `200 OK` - The signed URL and its expiry, capped at the result's expiry.
+/// * `400 Bad Request` - Signing keys are not configured.
+/// * `403 Forbidden` - The request's own URL is not validly signed.
+/// * `404 Not Found` - Unknown or expired result.
+pub async fn create_url(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(query): Query<SignedQuery>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Json(request): Json<SignedUrlRequest>,
+) -> Result<Json<SignedUrl>, ResultError> {
+    let storage = state.storage.as_ref().ok_or(ResultError::SigningDisabled)?;
+    let signer = storage.signer().ok_or(ResultError::SigningDisabled)?;
+    signer.verify(
+        &key,
+        &query,
+        peer.map(|Extension(ConnectInfo(addr))| addr.ip()),
+    )?;
+    let meta = storage.head(&key).await?.ok_or(ResultError::NotFound)?;
+    if let Some(once) = &query.once {
+        if !storage.mark_used(once).await? {
+            return Err(SignatureError::AlreadyUsed.into());
+        }
+    }
+
+    let (url, expires_at) = storage.download_url(
+        &key,
+        meta.created_at + storage.ttl().as_secs(),
+        request.expires_in,
+        request.single_use,
+        request.ip,
+    );
+    Ok(Json(SignedUrl {
+        url,
+        expires_at: expires_at.unwrap_or_default(),
+    }))
+}
+
+/// Parses a single-range `Range` header against a result of `size` bytes.
+///
+/// Returns `None` for headers that should be ignored (other units, several
+/// ranges, malformed values) and `Some(Err(()))` for unsatisfiable ranges.
+fn parse_range(value: &str, size: u64) -> Option<Result<Range<u64>, ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        size.saturating_sub(suffix)..size
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => size,
+            end => end.parse::<u64>().ok()?.saturating_add(1).min(size),
+        };
+        if end <= start {
+            return if start >= size { Some(Err(())) } else { None };
+        }
+        start..end
+    };
+    if range.start >= size {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
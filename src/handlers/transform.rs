@@ -0,0 +1,231 @@
+use super::{removebg, upscaler};
+use crate::jobs::hex;
+use crate::state::AppState;
+use aws_lc_rs::hmac;
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde_json::{Map, Value};
+
+/// Options whose values are always passed on as text, even when they look
+/// like numbers (`background_color:000000`).
+const TEXT_OPTIONS: &[&str] = &[
+    "preset",
+    "format",
+    "output",
+    "model",
+    "fit",
+    "matte_color",
+    "background_color",
+    "background_fit",
+    "outline_color",
+    "crop",
+    "crop_padding",
+    "crop_aspect",
+    "crop_size",
+];
+
+/// Error returned by the transformation URL endpoint.
+#[derive(Debug)]
+pub enum TransformError {
+    /// `TRANSFORM_SIGNING_KEYS` is not set.
+    Disabled,
+    /// The signature does not match the options and source.
+    InvalidSignature,
+    /// The source URL is not base64url-encoded UTF-8.
+    InvalidSource,
+    /// The options are malformed.
+    InvalidOptions(String),
+}
+
+impl IntoResponse for TransformError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::Disabled => (
+                StatusCode::NOT_FOUND,
+                "Transformation URLs are not configured".to_string(),
+            ),
+            Self::InvalidSignature => (
+                StatusCode::FORBIDDEN,
+                "Invalid transformation URL signature".to_string(),
+            ),
+            Self::InvalidSource => (
+                StatusCode::BAD_REQUEST,
+                "Source URL must be base64url-encoded".to_string(),
+            ),
+            Self::InvalidOptions(message) => (StatusCode::BAD_REQUEST, message),
+        };
+        (status, [(header::CACHE_CONTROL, "no-store")], message).into_response()
+    }
+}
+
+/// Returns the signed transformation path for `options` and `source_url`.
+///
+/// The signature is the unpadded base64url HMAC-SHA256 of
+/// `{options}/{encoded_source_url}`, keyed with `secret`.
+pub fn signed_path(secret: &str, options: &str, source_url: &str) -> String {
+    let source = URL_SAFE_NO_PAD.encode(source_url);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}/{}", options, source).as_bytes());
+    format!("/t/{}/{}/{}", URL_SAFE_NO_PAD.encode(tag), options, source)
+}
+
+/// Transformation URL handler.
+///
+/// Serves `GET /t/{signature}/{options}/{encoded_source_url}` so that
+/// results can be cached by a CDN. `options` starts with the endpoint,
+/// `removebg` or `upscale`, followed by comma-separated `name:value` pairs
+/// taking the same parameters as the endpoint's JSON body (for example
+/// `upscale,scale:2,format:webp`). The request is handed to the endpoint's
+/// handler with the decoded source as `url`. The output format comes from
+/// the options only, never from the `Accept` header, so a URL always
+/// yields the same encoding.
+///
+/// The `ETag` is derived from the signature, so it is known before any
+/// work is done and a matching `If-None-Match` is answered without fetching
+/// the source or calling a model.
+///
+/// # Returns
+///
+/// * `200 OK` - The result, with `ETag` and `Cache-Control: public, max-age=TRANSFORM_MAX_AGE_SECS, immutable`.
+/// * `304 Not Modified` - `If-None-Match` matches the URL's `ETag`.
+/// * `400 Bad Request` - Malformed options or source URL.
+/// * `403 Forbidden` - The signature does not match.
+/// * `404 Not Found` - `TRANSFORM_SIGNING_KEYS` is not set.
+/// * Otherwise, the endpoint's error, with `Cache-Control: no-store`.
+pub async fn transform(
+    State(state): State<AppState>,
+    Path((signature, options, source)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, TransformError> {
+    let config = state.config.clone();
+    if config.transform_signing_keys.is_empty() {
+        return Err(TransformError::Disabled);
+    }
+    let tag = URL_SAFE_NO_PAD
+        .decode(&signature)
+        .map_err(|_| TransformError::InvalidSignature)?;
+    let message = format!("{}/{}", options, source);
+    let signed = config.transform_signing_keys.iter().any(|secret| {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        hmac::verify(&key, message.as_bytes(), &tag).is_ok()
+    });
+    if !signed {
+        return Err(TransformError::InvalidSignature);
+    }
+    let url = URL_SAFE_NO_PAD
+        .decode(&source)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(TransformError::InvalidSource)?;
+    let (endpoint, mut body) = parse_options(&options)?;
+
+    // The verified signature covers the options and source under one key.
+    let etag = format!("\"{}\"", hex(&tag[..16]));
+    let cache_control = format!(
+        "public, max-age={}, immutable",
+        config.transform_max_age_secs
+    );
+    if matches_etag(&headers, &etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+
+    body.insert("url".to_string(), Value::String(url));
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/{}", endpoint))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(Value::Object(body).to_string()))
+        .unwrap();
+    let response = match endpoint {
+        "removebg" => removebg::remove_bg(State(state), request).await,
+        _ => upscaler::upscale(State(state), request).await,
+    };
+    Ok(cacheable(response, &etag, &cache_control))
+}
+
+/// Splits `options` into the endpoint and its JSON parameters.
+fn parse_options(options: &str) -> Result<(&'static str, Map<String, Value>), TransformError> {
+    let mut parts = options.split(',');
+    let endpoint = match parts.next().unwrap_or_default() {
+        "removebg" => "removebg",
+        "upscale" => "upscale",
+        other => {
+            return Err(TransformError::InvalidOptions(format!(
+                "Options must start with removebg or upscale, got '{}'",
+                other
+            )));
+        }
+    };
+    let mut params = Map::new();
+    for part in parts {
+        let Some((name, text)) = part.split_once(':') else {
+            return Err(TransformError::InvalidOptions(format!(
+                "Option '{}' must look like name:value",
+                part
+            )));
+        };
+        if name == "url" || params.contains_key(name) {
+            return Err(TransformError::InvalidOptions(format!(
+                "Option '{}' cannot be set here",
+                name
+            )));
+        }
+        params.insert(name.to_string(), option_value(name, text));
+    }
+    Ok((endpoint, params))
+}
+
+/// Converts an option's text to the JSON value the endpoint expects.
+fn option_value(name: &str, text: &str) -> Value {
+    if !TEXT_OPTIONS.contains(&name) {
+        if let Ok(flag) = text.parse::<bool>() {
+            return Value::Bool(flag);
+        }
+        if let Ok(number) = text.parse::<serde_json::Number>() {
+            return Value::Number(number);
+        }
+    }
+    Value::String(text.to_string())
+}
+
+/// Returns whether `If-None-Match` in `headers` matches `etag`.
+fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == "*" || tag.trim() == etag)
+        })
+}
+
+/// Adds caching headers to the endpoint's `response`.
+///
+/// Successful results get `etag` and `cache_control`; anything else is
+/// marked as not cacheable.
+fn cacheable(mut response: Response, etag: &str, cache_control: &str) -> Response {
+    if response.status() != StatusCode::OK {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        return response;
+    }
+    let headers = response.headers_mut();
+    headers.remove(header::VARY);
+    headers.insert(header::ETAG, HeaderValue::from_str(etag).unwrap());
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(cache_control).unwrap(),
+    );
+    response
+}
@@ -1,172 +1,712 @@
+use super::respond::{self, Input, InputFields, Options};
 use crate::config::Config;
-use crate::models::{UpscaleRequest, UpscalerModel};
+use crate::imaging::{
+    self, ImageInfo, ImageLimits, ImageRejection, alpha, classify, output,
+    resize::{SizeError, SizeRequest},
+    tiling::{self, Tile},
+};
+use crate::models::{
+    Fit, OutputFormat, OutputOptions, PresetEndpoint, UpscaleRequest, UpscalerModel,
+};
+use crate::state::AppState;
 use axum::{
-    body::Body,
-    extract::{FromRequest, Json, Multipart, Request, State},
-    http::{HeaderMap, StatusCode, header},
+    body::Bytes,
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
+use futures::StreamExt;
+use image::{DynamicImage, ImageFormat, RgbImage, imageops::FilterType};
+use std::io::Cursor;
 use std::sync::Arc;
+use tokio::task::JoinError;
+
+/// Scale applied by the worker when none is requested.
+const DEFAULT_SCALE: u32 = 4;
+
+/// Invalid `denoise_strength` option.
+pub(crate) enum DenoiseError {
+    /// The strength is not a number between 0 and 1.
+    OutOfRange,
+    /// The selected model has no denoising variant.
+    UnsupportedModel,
+}
+
+impl IntoResponse for DenoiseError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::OutOfRange => (
+                StatusCode::BAD_REQUEST,
+                "Denoise strength must be between 0 and 1",
+            )
+                .into_response(),
+            Self::UnsupportedModel => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "denoise_strength is only supported by the {} model",
+                    UpscalerModel::RealEsrGeneralX4v3
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Checks that `denoise_strength` is between 0 and 1 and that `model`
+/// supports it.
+fn check_denoise_strength(
+    model: Option<UpscalerModel>,
+    denoise_strength: Option<f64>,
+) -> Result<(), DenoiseError> {
+    let Some(strength) = denoise_strength else {
+        return Ok(());
+    };
+    if !(0.0..=1.0).contains(&strength) {
+        return Err(DenoiseError::OutOfRange);
+    }
+    if !model.unwrap_or_default().supports_denoise_strength() {
+        return Err(DenoiseError::UnsupportedModel);
+    }
+    Ok(())
+}
 
 /// Handler for image upscaling.
 ///
 /// Accepts either:
 /// 1. `multipart/form-data` with an 'image' field (file upload) and optional parameters.
-/// 2. `application/json` with a 'url' field (image URL) and optional parameters.
+/// 2. `application/json` with a 'url' field (image URL, fetched by the gateway) and optional parameters.
+///
+/// The image header is inspected and the input and output sizes are checked
+/// against the pixel limits before the image is forwarded to a Modal worker.
+/// The worker only returns RGB, so the alpha channel of transparent inputs is
+/// kept by the gateway and reattached to the result, which is then returned
+/// as WebP for WebP inputs and as PNG otherwise. Other formats can be
+/// requested through the `format` option or the `Accept` header.
 ///
-/// Forwards the request to a Modal worker for processing and returns the result.
-pub async fn upscale(State(config): State<Arc<Config>>, request: Request) -> Response {
-    let content_type = request
+/// A `preset` field fills in the parameters of a named preset; the request
+/// may then only set the fields the preset marks as overridable.
+pub async fn upscale(State(state): State<AppState>, request: Request) -> Response {
+    let config = state.config.clone();
+    let accept = request
         .headers()
-        .get(header::CONTENT_TYPE)
+        .get(header::ACCEPT)
         .and_then(|value| value.to_str().ok())
-        .unwrap_or("");
+        .map(str::to_string);
 
-    let modal_url = &config.modal_upscaler_url;
-    let client = reqwest::Client::new();
+    let Input {
+        image: mut image_body,
+        source_name,
+        options,
+    } = match respond::read_input(&state, request, &INPUT, parse_options).await {
+        Ok(input) => input,
+        Err(response) => return response,
+    };
+    let UpscaleParams {
+        mut model,
+        size,
+        face_enhance,
+        denoise_strength,
+        output: output_options,
+    } = options;
 
-    if content_type.starts_with("application/json") {
-        let Json(payload) = match Json::<UpscaleRequest>::from_request(request, &config).await {
-            Ok(j) => j,
-            Err(e) => {
-                return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response();
-            }
-        };
+    let model_id = model.unwrap_or_default().id();
+    if !config.model_enabled(model_id) {
+        return respond::model_disabled(model_id);
+    }
 
-        if payload.scale.is_some_and(|scale| !(1..=6).contains(&scale)) {
-            return (StatusCode::BAD_REQUEST, "Scale must be between 1 and 6").into_response();
-        }
+    let negotiated = match output::negotiate(&output_options, accept.as_deref()) {
+        Ok(negotiated) => negotiated,
+        Err(e) => return e.into_response(),
+    };
 
-        let res = match client.post(modal_url).json(&payload).send().await {
-            Ok(res) => res,
+    let info = match imaging::inspect(&mut image_body).await {
+        Ok(info) => info,
+        Err(e) => return e.into_response(),
+    };
+    let plan = match size.plan(info.width, info.height, DEFAULT_SCALE) {
+        Ok(plan) => plan,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = ImageLimits::from_config(&config).check_upscale(&info, plan.worker_scale) {
+        return e.into_response();
+    }
+
+    let auto = model == Some(UpscalerModel::Auto);
+    let tiled = is_tiled(&config, &info);
+    if tiled && face_enhance == Some(true) {
+        return UpscaleError::FaceEnhanceTiled.into_response();
+    }
+    let (decoded, alpha, chosen) = if info.has_alpha || auto || tiled {
+        let image = match imaging::decode(&mut image_body).await {
+            Ok(image) => image,
+            Err(e) => return e.into_response(),
+        };
+        let analysis = tokio::task::spawn_blocking(move || {
+            let alpha = alpha::alpha_plane(&image);
+            let chosen = auto.then(|| classify::choose_model(&image));
+            (Some(image), alpha, chosen)
+        })
+        .await;
+        match analysis {
+            Ok(analysis) => analysis,
             Err(e) => {
-                tracing::error!("Failed to call Modal worker: {}", e);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to connect to processing worker",
-                )
+                tracing::error!("Image analysis task failed: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to analyze image")
                     .into_response();
             }
+        }
+    } else {
+        (None, None, None)
+    };
+    if let Some(preferred) = chosen {
+        match enabled_candidate(&config, preferred) {
+            Some(candidate) => model = Some(candidate),
+            None => return respond::model_disabled(preferred.id()),
+        }
+    }
+    let model_used = model.unwrap_or_default();
+    let default_format = match (&alpha, info.format) {
+        (None, _) => OutputFormat::Jpeg,
+        (Some(_), ImageFormat::WebP) => OutputFormat::Webp,
+        (Some(_), _) => OutputFormat::Png,
+    };
+    let rendition = match negotiated.resolve(default_format) {
+        Ok(rendition) => rendition,
+        Err(e) => return e.into_response(),
+    };
+
+    let params = WorkerParams {
+        model,
+        scale: plan.worker_scale,
+        face_enhance,
+        denoise_strength,
+    };
+    let disposition =
+        respond::content_disposition(source_name.as_deref(), "upscaled", rendition.format);
+    let exact = plan.is_exact((info.width, info.height));
+    let has_alpha = alpha.is_some();
+    let finish = move |image: DynamicImage| {
+        let image = match &alpha {
+            Some(alpha) => DynamicImage::ImageRgba8(alpha::reattach(&image, alpha)),
+            None => image,
         };
+        plan.apply(image)
+    };
 
-        handle_modal_response(res).await
-    } else if content_type.starts_with("multipart/form-data") {
-        let mut multipart = match Multipart::from_request(request, &config).await {
-            Ok(m) => m,
-            Err(e) => {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    format!("Invalid multipart request: {}", e),
+    let mut response = match decoded.filter(|_| tiled) {
+        Some(image) => match upscale_tiled(&state, image, &params).await {
+            Ok((upscaled, tiles)) => {
+                let mut response = respond::render(
+                    DynamicImage::ImageRgb8(upscaled),
+                    rendition,
+                    disposition,
+                    finish,
                 )
-                    .into_response();
+                .await;
+                response
+                    .headers_mut()
+                    .insert("x-upscale-tiles", HeaderValue::from(tiles));
+                response
             }
-        };
-
-        let mut image_data = None;
-        let mut model: Option<UpscalerModel> = None;
-        let mut scale = None;
-        let mut face_enhance = None;
-
-        while let Some(field) = multipart.next_field().await.unwrap_or(None) {
-            let name = field.name().unwrap_or("").to_string();
-            match name.as_str() {
-                "image" => {
-                    if let Ok(bytes) = field.bytes().await {
-                        image_data = Some(bytes);
-                    }
+            Err(e) => e.into_response(),
+        },
+        None => {
+            let res = match params
+                .request(&state)
+                .header(header::CONTENT_LENGTH, image_body.len())
+                .body(image_body.into_reqwest_body())
+                .send()
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    tracing::error!("Failed to call Modal worker: {}", e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to connect to processing worker",
+                    )
+                        .into_response();
                 }
-                "model" => {
-                    if let Ok(text) = field.text().await {
-                        model =
+            };
+
+            if !has_alpha && exact && rendition.is_passthrough(OutputFormat::Jpeg) {
+                respond::stream(res, OutputFormat::Jpeg.mime_type(), disposition).await
+            } else {
+                respond::transcode(res, rendition, disposition, finish).await
+            }
+        }
+    };
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert("x-model-used", HeaderValue::from_static(model_used.id()));
+    }
+    response
+}
+
+/// Returns `true` if `info` is large enough to be upscaled in tiles.
+///
+/// Face enhancement needs whole faces, so it is refused for tiled images.
+fn is_tiled(config: &Config, info: &ImageInfo) -> bool {
+    info.megapixels() > config.upscale_tile_threshold_megapixels
+        && (info.width > config.upscale_tile_size || info.height > config.upscale_tile_size)
+}
+
+/// Returns the automatically chosen `preferred` model, or the other
+/// candidate if it is disabled.
+fn enabled_candidate(config: &Config, preferred: UpscalerModel) -> Option<UpscalerModel> {
+    let other = if preferred == UpscalerModel::RealEsrganX4plusAnime6B {
+        UpscalerModel::RealEsrganX4plus
+    } else {
+        UpscalerModel::RealEsrganX4plusAnime6B
+    };
+    [preferred, other]
+        .into_iter()
+        .find(|candidate| config.model_enabled(candidate.id()))
+}
+
+/// Multipart fields read by [`upscale`].
+const INPUT: InputFields = InputFields {
+    endpoint: PresetEndpoint::Upscale,
+    text: &[
+        "model",
+        "scale",
+        "width",
+        "height",
+        "fit",
+        "face_enhance",
+        "denoise_strength",
+        "format",
+        "quality",
+        "lossless",
+    ],
+    files: &[],
+};
+
+/// Options of an `/upscale` request.
+#[derive(Default)]
+struct UpscaleParams {
+    model: Option<UpscalerModel>,
+    size: SizeRequest,
+    face_enhance: Option<bool>,
+    denoise_strength: Option<f64>,
+    output: OutputOptions,
+}
+
+/// Reads and validates the options of a JSON payload or of multipart fields.
+#[allow(clippy::result_large_err)]
+fn parse_options(options: Options<UpscaleRequest>) -> Result<UpscaleParams, Response> {
+    let params = match options {
+        Options::Json(payload) => UpscaleParams {
+            model: payload.model,
+            size: SizeRequest {
+                scale: payload.scale,
+                width: payload.width,
+                height: payload.height,
+                fit: payload.fit,
+            },
+            face_enhance: payload.face_enhance,
+            denoise_strength: payload.denoise_strength,
+            output: payload.output,
+        },
+        Options::Multipart { fields, .. } => {
+            let mut params = UpscaleParams::default();
+            for (name, text) in fields {
+                match name.as_str() {
+                    "model" => {
+                        params.model =
                             serde_json::from_str::<UpscalerModel>(&format!("\"{}\"", text)).ok();
                     }
-                }
-                "scale" => {
-                    if let Ok(text) = field.text().await {
-                        scale = text.parse::<u32>().ok();
+                    "scale" => {
+                        let Ok(scale) = text.parse::<f64>() else {
+                            return Err(SizeError::InvalidScale.into_response());
+                        };
+                        params.size.scale = Some(scale);
                     }
-                }
-                "face_enhance" => {
-                    if let Ok(text) = field.text().await {
-                        face_enhance = Some(text == "true" || text == "1");
+                    "width" | "height" => {
+                        let Ok(value) = text.parse::<u32>() else {
+                            return Err(SizeError::InvalidDimension.into_response());
+                        };
+                        if name == "width" {
+                            params.size.width = Some(value);
+                        } else {
+                            params.size.height = Some(value);
+                        }
+                    }
+                    "fit" => match serde_json::from_value::<Fit>(serde_json::Value::String(text)) {
+                        Ok(fit) => params.size.fit = Some(fit),
+                        Err(_) => {
+                            return Err((
+                                StatusCode::BAD_REQUEST,
+                                "Fit must be contain, cover or exact",
+                            )
+                                .into_response());
+                        }
+                    },
+                    "face_enhance" => {
+                        params.face_enhance = Some(text == "true" || text == "1");
+                    }
+                    "denoise_strength" => match text.parse::<f64>() {
+                        Ok(strength) if strength.is_finite() => {
+                            params.denoise_strength = Some(strength)
+                        }
+                        _ => return Err(DenoiseError::OutOfRange.into_response()),
+                    },
+                    _ => {
+                        output::parse_field(&mut params.output, &name, &text)
+                            .map_err(IntoResponse::into_response)?;
                     }
                 }
-                _ => {}
             }
+            params
         }
+    };
 
-        let image_bytes = match image_data {
-            Some(data) => data,
-            None => {
-                return (StatusCode::BAD_REQUEST, "No image found in 'image' field")
-                    .into_response();
-            }
-        };
+    params
+        .size
+        .validate()
+        .map_err(IntoResponse::into_response)?;
+    check_denoise_strength(params.model, params.denoise_strength)
+        .map_err(IntoResponse::into_response)?;
+    Ok(params)
+}
+
+/// Upscaling parameters of a `/pipeline` step.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct UpscaleOptions {
+    pub model: Option<UpscalerModel>,
+    pub size: SizeRequest,
+    pub face_enhance: Option<bool>,
+    pub denoise_strength: Option<f64>,
+}
 
-        if scale.is_some_and(|s| !(1..=6).contains(&s)) {
-            return (StatusCode::BAD_REQUEST, "Scale must be between 1 and 6").into_response();
+impl UpscaleOptions {
+    /// Validates the parameters before any image is processed.
+    pub fn validate(&self, config: &Config) -> Result<(), UpscaleError> {
+        self.size.validate().map_err(UpscaleError::Size)?;
+        check_denoise_strength(self.model, self.denoise_strength).map_err(UpscaleError::Denoise)?;
+        let model_id = self.model.unwrap_or_default().id();
+        if !config.model_enabled(model_id) {
+            return Err(UpscaleError::ModelDisabled(model_id));
         }
+        Ok(())
+    }
+}
 
-        let mut rb = client
-            .post(modal_url)
-            .body(image_bytes)
-            .header("Content-Type", "application/octet-stream");
+/// Failure while upscaling a decoded image.
+pub(crate) enum UpscaleError {
+    /// The size parameters are invalid or unreachable.
+    Size(SizeError),
+    /// The `denoise_strength` is invalid for the model.
+    Denoise(DenoiseError),
+    /// The result would exceed the pixel limits.
+    Rejected(ImageRejection),
+    /// The requested or automatically chosen model is disabled.
+    ModelDisabled(&'static str),
+    /// `face_enhance` was requested for an image upscaled in tiles.
+    FaceEnhanceTiled,
+    /// The worker failed.
+    Worker(TileError),
+}
 
-        if let Some(m) = model {
-            rb = rb.header("X-Model", m.to_string());
+impl IntoResponse for UpscaleError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Size(e) => e.into_response(),
+            Self::Denoise(e) => e.into_response(),
+            Self::Rejected(e) => e.into_response(),
+            Self::ModelDisabled(id) => respond::model_disabled(id),
+            Self::FaceEnhanceTiled => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "face_enhance is not supported for images large enough to be upscaled in tiles",
+            )
+                .into_response(),
+            Self::Worker(e) => e.into_response(),
         }
-        if let Some(s) = scale {
-            rb = rb.header("X-Scale", s.to_string());
+    }
+}
+
+impl From<TileError> for UpscaleError {
+    fn from(e: TileError) -> Self {
+        Self::Worker(e)
+    }
+}
+
+/// Upscales an image already decoded by the gateway, described by `info`.
+///
+/// As in [`upscale`], the alpha channel is kept, `auto` picks a model from
+/// the content and large images are upscaled in tiles. The result is
+/// resampled to the planned size.
+pub(crate) async fn upscale_image(
+    state: &AppState,
+    image: DynamicImage,
+    info: &ImageInfo,
+    options: &UpscaleOptions,
+) -> Result<DynamicImage, UpscaleError> {
+    let config = &state.config;
+    let plan = options
+        .size
+        .plan(info.width, info.height, DEFAULT_SCALE)
+        .map_err(UpscaleError::Size)?;
+    ImageLimits::from_config(config)
+        .check_upscale(info, plan.worker_scale)
+        .map_err(UpscaleError::Rejected)?;
+    let tiled = is_tiled(config, info);
+    if tiled && options.face_enhance == Some(true) {
+        return Err(UpscaleError::FaceEnhanceTiled);
+    }
+
+    let auto = options.model == Some(UpscalerModel::Auto);
+    let (image, alpha, chosen) = tokio::task::spawn_blocking(move || {
+        let alpha = alpha::alpha_plane(&image);
+        let chosen = auto.then(|| classify::choose_model(&image));
+        (image, alpha, chosen)
+    })
+    .await
+    .map_err(TileError::Task)?;
+    let model = match chosen {
+        Some(preferred) => Some(
+            enabled_candidate(config, preferred)
+                .ok_or(UpscaleError::ModelDisabled(preferred.id()))?,
+        ),
+        None => options.model,
+    };
+
+    let params = WorkerParams {
+        model,
+        scale: plan.worker_scale,
+        face_enhance: options.face_enhance,
+        denoise_strength: options.denoise_strength,
+    };
+    let upscaled = if tiled {
+        upscale_tiled(state, image, &params).await?.0
+    } else {
+        upscale_whole(state, image, &params).await?
+    };
+    let output = tokio::task::spawn_blocking(move || {
+        let upscaled = DynamicImage::ImageRgb8(upscaled);
+        let image = match &alpha {
+            Some(alpha) => DynamicImage::ImageRgba8(alpha::reattach(&upscaled, alpha)),
+            None => upscaled,
+        };
+        plan.apply(image)
+    })
+    .await
+    .map_err(TileError::Task)?;
+    Ok(output)
+}
+
+/// Parameters forwarded to the upscaler worker.
+struct WorkerParams {
+    model: Option<UpscalerModel>,
+    scale: u32,
+    face_enhance: Option<bool>,
+    denoise_strength: Option<f64>,
+}
+
+impl WorkerParams {
+    /// Starts a worker request carrying the parameters as headers.
+    fn request(&self, state: &AppState) -> reqwest::RequestBuilder {
+        let mut rb = state
+            .http
+            .post(&state.config.modal_upscaler_url)
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Scale", self.scale.to_string());
+        if let Some(m) = self.model {
+            rb = rb.header("X-Model", m.to_string());
         }
-        if let Some(f) = face_enhance {
+        if let Some(f) = self.face_enhance {
             rb = rb.header("X-Face-Enhance", f.to_string());
         }
+        if let Some(strength) = self.denoise_strength {
+            rb = rb.header("X-Denoise-Strength", strength.to_string());
+        }
+        rb
+    }
+}
 
-        let res = match rb.send().await {
-            Ok(res) => res,
-            Err(e) => {
+/// Failure while upscaling an image in tiles, or in one piece from
+/// [`upscale_image`].
+pub(crate) enum TileError {
+    /// The worker could not be reached.
+    Connect(reqwest::Error),
+    /// The worker answered a tile with an error.
+    Worker(String),
+    /// The worker's answer could not be read or decoded.
+    Unreadable(String),
+    /// A blocking task panicked.
+    Task(JoinError),
+}
+
+impl IntoResponse for TileError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Connect(e) => {
                 tracing::error!("Failed to call Modal worker: {}", e);
-                return (
+                (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     "Failed to connect to processing worker",
                 )
-                    .into_response();
+                    .into_response()
             }
-        };
+            Self::Worker(error_text) => (
+                StatusCode::BAD_GATEWAY,
+                format!("Processing worker returned an error: {}", error_text),
+            )
+                .into_response(),
+            Self::Unreadable(e) => {
+                tracing::error!("Failed to read upscaled tile: {}", e);
+                (
+                    StatusCode::BAD_GATEWAY,
+                    "Processing worker returned an unreadable image",
+                )
+                    .into_response()
+            }
+            Self::Task(e) => {
+                tracing::error!("Tiling task failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process image").into_response()
+            }
+        }
+    }
+}
 
-        handle_modal_response(res).await
-    } else {
-        (
-            StatusCode::UNSUPPORTED_MEDIA_TYPE,
-            "Content-Type must be application/json or multipart/form-data",
-        )
-            .into_response()
+/// Upscales `image` tile by tile and stitches the results.
+///
+/// Up to `UPSCALE_TILE_CONCURRENCY` tiles are in flight at once, across all
+/// requests. Returns the stitched image and the number of tiles.
+async fn upscale_tiled(
+    state: &AppState,
+    image: DynamicImage,
+    params: &WorkerParams,
+) -> Result<(RgbImage, usize), TileError> {
+    let config = &state.config;
+    let source = Arc::new(
+        tokio::task::spawn_blocking(move || image.into_rgb8())
+            .await
+            .map_err(TileError::Task)?,
+    );
+    let (width, height) = source.dimensions();
+    let tiles = tiling::split(
+        width,
+        height,
+        config.upscale_tile_size,
+        config.upscale_tile_overlap,
+    );
+    let count = tiles.len();
+    tracing::info!(
+        "Upscaling {}x{} image in {} tiles of {} px",
+        width,
+        height,
+        count,
+        config.upscale_tile_size
+    );
+
+    let mut results = futures::stream::iter(
+        tiles
+            .into_iter()
+            .map(|tile| upscale_tile(state, source.clone(), tile, params)),
+    )
+    .buffered(config.upscale_tile_concurrency.max(1));
+
+    let mut stitcher =
+        tiling::Stitcher::new(width, height, params.scale, config.upscale_tile_overlap);
+    while let Some(result) = results.next().await {
+        let (tile, upscaled) = result?;
+        stitcher = tokio::task::spawn_blocking(move || {
+            stitcher.add(&tile, &upscaled);
+            stitcher
+        })
+        .await
+        .map_err(TileError::Task)?;
     }
+    Ok((stitcher.finish(), count))
 }
 
-async fn handle_modal_response(res: reqwest::Response) -> Response {
-    if !res.status().is_success() {
-        tracing::error!("Modal worker returned error: {}", res.status());
-        let error_text = res.text().await.unwrap_or_default();
-        tracing::error!("Modal worker error details: {}", error_text);
-        return (
-            StatusCode::BAD_GATEWAY,
-            format!("Processing worker returned an error: {}", error_text),
+/// Sends one tile to the worker and returns its upscaled pixels.
+async fn upscale_tile(
+    state: &AppState,
+    source: Arc<RgbImage>,
+    tile: Tile,
+    params: &WorkerParams,
+) -> Result<(Tile, RgbImage), TileError> {
+    let png = tokio::task::spawn_blocking(move || {
+        encode_png(
+            &image::imageops::crop_imm(&*source, tile.x, tile.y, tile.width, tile.height)
+                .to_image(),
         )
-            .into_response();
-    }
+    })
+    .await
+    .map_err(TileError::Task)??;
 
-    let mut headers = HeaderMap::new();
-    let ct = res
-        .headers()
-        .get(header::CONTENT_TYPE)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("image/jpeg");
+    let bytes = {
+        let _permit = state
+            .upscale_tiles
+            .acquire()
+            .await
+            .expect("tile semaphore is never closed");
+        call_worker(state, png, params).await?
+    };
+    let expected = (tile.width * params.scale, tile.height * params.scale);
+    Ok((tile, decode_upscaled(bytes, expected).await?))
+}
 
-    headers.insert(header::CONTENT_TYPE, ct.parse().unwrap());
+/// Upscales `image` in a single worker call.
+async fn upscale_whole(
+    state: &AppState,
+    image: DynamicImage,
+    params: &WorkerParams,
+) -> Result<RgbImage, TileError> {
+    let expected = (image.width() * params.scale, image.height() * params.scale);
+    let png = tokio::task::spawn_blocking(move || encode_png(&image.into_rgb8()))
+        .await
+        .map_err(TileError::Task)??;
+    let bytes = call_worker(state, png, params).await?;
+    decode_upscaled(bytes, expected).await
+}
+
+/// Encodes the pixels sent to the worker as PNG.
+fn encode_png(image: &RgbImage) -> Result<Vec<u8>, TileError> {
+    let mut out = Cursor::new(Vec::new());
+    image
+        .write_to(&mut out, ImageFormat::Png)
+        .map(|_| out.into_inner())
+        .map_err(|e| TileError::Unreadable(e.to_string()))
+}
 
-    let stream = res.bytes_stream();
-    let body = Body::from_stream(stream);
+/// Sends a PNG to the worker and returns its answer.
+async fn call_worker(
+    state: &AppState,
+    png: Vec<u8>,
+    params: &WorkerParams,
+) -> Result<Bytes, TileError> {
+    let res = params
+        .request(state)
+        .header(header::CONTENT_LENGTH, png.len())
+        .body(png)
+        .send()
+        .await
+        .map_err(TileError::Connect)?;
+    if !res.status().is_success() {
+        tracing::error!("Modal worker returned error: {}", res.status());
+        return Err(TileError::Worker(res.text().await.unwrap_or_default()));
+    }
+    res.bytes()
+        .await
+        .map_err(|e| TileError::Unreadable(e.to_string()))
+}
 
-    (headers, body).into_response()
+/// Decodes the worker's answer, resampling it to `expected` if the worker
+/// did not produce exactly that size.
+async fn decode_upscaled(bytes: Bytes, expected: (u32, u32)) -> Result<RgbImage, TileError> {
+    tokio::task::spawn_blocking(move || {
+        let upscaled = image::load_from_memory(&bytes)?.into_rgb8();
+        Ok::<_, image::ImageError>(if upscaled.dimensions() == expected {
+            upscaled
+        } else {
+            image::imageops::resize(&upscaled, expected.0, expected.1, FilterType::Lanczos3)
+        })
+    })
+    .await
+    .map_err(TileError::Task)?
+    .map_err(|e| TileError::Unreadable(e.to_string()))
 }
@@ -0,0 +1,125 @@
+//! # Dependency Health
+//!
+//! Active probing of the Modal workers the gateway depends on. Probe results
+//! are cached for a short time so health endpoints can be polled frequently
+//! without flooding the workers.
+
+use crate::config::Config;
+use crate::models::{DependencyHealth, DependencyStatus};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// A single downstream dependency and its last probe result.
+struct Dependency {
+    name: &'static str,
+    url: String,
+    last: Mutex<Option<(Instant, DependencyHealth)>>,
+}
+
+/// Probes the configured workers and caches the results.
+pub struct HealthMonitor {
+    client: reqwest::Client,
+    timeout: Duration,
+    ttl: Duration,
+    dependencies: Vec<Dependency>,
+}
+
+impl HealthMonitor {
+    /// Creates a monitor for every worker endpoint in the configuration.
+    pub fn new(config: &Config, client: reqwest::Client) -> Self {
+        let dependency = |name, url: &str| Dependency {
+            name,
+            url: url.to_string(),
+            last: Mutex::new(None),
+        };
+
+        Self {
+            client,
+            timeout: Duration::from_millis(config.health_probe_timeout_ms),
+            ttl: Duration::from_secs(config.health_cache_ttl_secs),
+            dependencies: vec![
+                dependency("removebg", &config.modal_removebg_url),
+                dependency("upscaler", &config.modal_upscaler_url),
+            ],
+        }
+    }
+
+    /// Returns the health of every dependency, probing those whose cached
+    /// result is older than the configured TTL.
+    pub async fn check_all(&self) -> Vec<DependencyHealth> {
+        futures::future::join_all(self.dependencies.iter().map(|dep| self.check(dep))).await
+    }
+
+    /// Returns the cached results without probing.
+    ///
+    /// Dependencies that were never probed are omitted.
+    pub async fn last_known(&self) -> Vec<DependencyHealth> {
+        let mut results = Vec::with_capacity(self.dependencies.len());
+        for dep in &self.dependencies {
+            if let Some((_, health)) = dep.last.lock().await.as_ref() {
+                results.push(DependencyHealth {
+                    cached: true,
+                    ..health.clone()
+                });
+            }
+        }
+        results
+    }
+
+    async fn check(&self, dep: &Dependency) -> DependencyHealth {
+        // Holding the lock across the probe makes concurrent callers wait for
+        // and reuse a single in-flight probe.
+        let mut last = dep.last.lock().await;
+        let fresh = last.as_ref().filter(|(at, _)| at.elapsed() < self.ttl);
+        if let Some((_, health)) = fresh {
+            return DependencyHealth {
+                cached: true,
+                ..health.clone()
+            };
+        }
+
+        let health = self.probe(dep).await;
+        *last = Some((Instant::now(), health.clone()));
+        health
+    }
+
+    async fn probe(&self, dep: &Dependency) -> DependencyHealth {
+        let started = Instant::now();
+        let result = self.client.get(&dep.url).timeout(self.timeout).send().await;
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        // Worker endpoints only accept POST, so any non-5xx answer (usually
+        // 405) proves the worker is reachable.
+        let (status, latency_ms, http_status, error) = match result {
+            Ok(res) if res.status().is_server_error() => (
+                DependencyStatus::Down,
+                Some(latency_ms),
+                Some(res.status().as_u16()),
+                Some(format!("Worker returned {}", res.status())),
+            ),
+            Ok(res) => (
+                DependencyStatus::Up,
+                Some(latency_ms),
+                Some(res.status().as_u16()),
+                None,
+            ),
+            Err(e) => {
+                tracing::warn!("Health probe for {} failed: {}", dep.name, e);
+                (DependencyStatus::Down, None, None, Some(e.to_string()))
+            }
+        };
+
+        DependencyHealth {
+            name: dep.name.to_string(),
+            status,
+            latency_ms,
+            http_status,
+            checked_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            cached: false,
+            error,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Alpha channel handling for `/upscale`.
+//!
+//! The upscaler worker only returns RGB, so the gateway keeps the alpha plane
+//! of the input, resamples it to the output dimensions and puts it back.
+
+use image::{DynamicImage, GrayImage, RgbaImage, imageops::FilterType};
+
+/// Extracts the alpha plane of `image`.
+///
+/// Returns `None` when the image has no alpha channel or is fully opaque,
+/// since there is nothing to preserve in either case.
+pub fn alpha_plane(image: &DynamicImage) -> Option<GrayImage> {
+    if !image.color().has_alpha() {
+        return None;
+    }
+
+    let rgba = image.to_rgba8();
+    if rgba.pixels().all(|pixel| pixel[3] == u8::MAX) {
+        return None;
+    }
+
+    Some(GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        image::Luma([rgba.get_pixel(x, y)[3]])
+    }))
+}
+
+/// Recombines the worker's RGB output with the original alpha plane.
+///
+/// The alpha plane is resampled with a Lanczos filter when the output
+/// dimensions differ from the input.
+pub fn reattach(rgb: &DynamicImage, alpha: &GrayImage) -> RgbaImage {
+    let (width, height) = (rgb.width(), rgb.height());
+    let resized;
+    let alpha = if alpha.dimensions() == (width, height) {
+        alpha
+    } else {
+        resized = image::imageops::resize(alpha, width, height, FilterType::Lanczos3);
+        &resized
+    };
+
+    let mut output = rgb.to_rgba8();
+    for (pixel, a) in output.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = a[0];
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgba};
+
+    #[test]
+    fn test_opaque_images_have_no_alpha_plane() {
+        assert!(alpha_plane(&DynamicImage::new_rgb8(4, 4)).is_none());
+        let opaque = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+        assert!(alpha_plane(&DynamicImage::ImageRgba8(opaque)).is_none());
+
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 255]));
+        image.put_pixel(0, 0, Rgba([1, 2, 3, 7]));
+        let plane = alpha_plane(&DynamicImage::ImageRgba8(image)).unwrap();
+        assert_eq!(plane.dimensions(), (1, 1));
+        assert_eq!(plane.get_pixel(0, 0)[0], 7);
+    }
+
+    #[test]
+    fn test_reattach_resamples_the_plane_to_the_output() {
+        let alpha = GrayImage::from_pixel(2, 2, Luma([100]));
+        let rgb = DynamicImage::new_rgb8(8, 6);
+        let output = reattach(&rgb, &alpha);
+        assert_eq!(output.dimensions(), (8, 6));
+        assert!(output.pixels().all(|pixel| pixel[3].abs_diff(100) <= 1));
+
+        let alpha = GrayImage::from_fn(2, 1, |x, _| Luma([x as u8 * 255]));
+        let output = reattach(&DynamicImage::new_rgb8(2, 1), &alpha);
+        assert_eq!(
+            (output.get_pixel(0, 0)[3], output.get_pixel(1, 0)[3]),
+            (0, 255)
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Content analysis for `model: "auto"`.
+//!
+//! Illustrations tend to have a small palette, large flat regions and sharp
+//! outlines, while photos are textured throughout and often show skin. The
+//! statistics are measured on a thumbnail, so the cost does not grow with the
+//! input size.
+
+use crate::models::UpscalerModel;
+use image::DynamicImage;
+
+/// Longest side of the thumbnail the statistics are measured on.
+const ANALYSIS_SIZE: u32 = 256;
+/// Gradients at or below this are considered flat.
+const FLAT_GRADIENT: i32 = 6;
+/// Gradients at or above this are considered strong edges.
+const STRONG_GRADIENT: i32 = 64;
+
+/// Image statistics used to pick an upscaler model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContentStats {
+    /// Distinct colors (quantized to 5 bits per channel) per pixel.
+    pub palette_ratio: f64,
+    /// Share of pixels with almost no local gradient.
+    pub flat_ratio: f64,
+    /// Share of the non-flat pixels that are strong edges.
+    pub strong_edge_ratio: f64,
+    /// Share of pixels with a skin-like chroma.
+    pub skin_ratio: f64,
+}
+
+impl ContentStats {
+    /// Measures the statistics of `image`.
+    pub fn measure(image: &DynamicImage) -> Self {
+        let rgb = image.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_rgb8();
+        let (width, height) = rgb.dimensions();
+        let pixels = f64::from(width * height);
+
+        let mut palette = vec![false; 1 << 15];
+        let mut colors = 0usize;
+        let mut skin = 0usize;
+        for pixel in rgb.pixels() {
+            let [r, g, b] = pixel.0;
+            let key =
+                (usize::from(r >> 3) << 10) | (usize::from(g >> 3) << 5) | usize::from(b >> 3);
+            if !palette[key] {
+                palette[key] = true;
+                colors += 1;
+            }
+            if is_skin(r, g, b) {
+                skin += 1;
+            }
+        }
+
+        let luma: Vec<i32> = rgb
+            .pixels()
+            .map(|p| (299 * i32::from(p[0]) + 587 * i32::from(p[1]) + 114 * i32::from(p[2])) / 1000)
+            .collect();
+        let at = |x: u32, y: u32| luma[(y * width + x) as usize];
+        let (mut flat, mut strong, mut measured) = (0usize, 0usize, 0usize);
+        for y in 1..height.saturating_sub(1) {
+            for x in 1..width.saturating_sub(1) {
+                let gradient =
+                    (at(x + 1, y) - at(x - 1, y)).abs() + (at(x, y + 1) - at(x, y - 1)).abs();
+                measured += 1;
+                if gradient <= FLAT_GRADIENT {
+                    flat += 1;
+                } else if gradient >= STRONG_GRADIENT {
+                    strong += 1;
+                }
+            }
+        }
+
+        let ratio = |count: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 / total as f64
+            }
+        };
+        Self {
+            palette_ratio: colors as f64 / pixels.min(palette.len() as f64),
+            flat_ratio: ratio(flat, measured),
+            strong_edge_ratio: ratio(strong, measured - flat),
+            skin_ratio: ratio(skin, width as usize * height as usize),
+        }
+    }
+
+    /// Returns `true` if the statistics look like an illustration rather
+    /// than a photo.
+    pub fn is_illustration(&self) -> bool {
+        let mut score = 0;
+        if self.flat_ratio > 0.5 {
+            score += 2;
+        } else if self.flat_ratio > 0.35 {
+            score += 1;
+        }
+        if self.palette_ratio < 0.05 {
+            score += 2;
+        } else if self.palette_ratio < 0.15 {
+            score += 1;
+        }
+        if self.strong_edge_ratio > 0.25 {
+            score += 1;
+        }
+        // Large skin-toned areas without flat shading suggest a portrait.
+        if self.skin_ratio > 0.2 && self.flat_ratio < 0.5 {
+            score -= 2;
+        }
+        score >= 3
+    }
+}
+
+/// Picks the upscaler model for `image`: the anime model for illustrations
+/// and the general Real-ESRGAN model for photos.
+pub fn choose_model(image: &DynamicImage) -> UpscalerModel {
+    let stats = ContentStats::measure(image);
+    let model = if stats.is_illustration() {
+        UpscalerModel::RealEsrganX4plusAnime6B
+    } else {
+        UpscalerModel::RealEsrganX4plus
+    };
+    tracing::debug!("Automatic model selection chose {} from {:?}", model, stats);
+    model
+}
+
+/// Skin chroma range in YCbCr.
+fn is_skin(r: u8, g: u8, b: u8) -> bool {
+    let (r, g, b) = (f32::from(r), f32::from(g), f32::from(b));
+    let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Flat colored shapes with black outlines on white.
+    fn illustration() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(120, 80, |x, y| {
+            let (dx, dy) = (x as i32 - 40, y as i32 - 40);
+            let d = dx * dx + dy * dy;
+            if (576..=676).contains(&d) || x == 80 || y == 10 {
+                Rgb([0, 0, 0])
+            } else if d < 576 {
+                Rgb([250, 120, 160])
+            } else if x > 80 {
+                Rgb([90, 170, 240])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        }))
+    }
+
+    /// Textured throughout, like sensor noise on a gradient.
+    fn photo() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(120, 80, |x, y| {
+            let noise = (x.wrapping_mul(2_654_435_761) ^ y.wrapping_mul(40_503)) >> 7;
+            Rgb([
+                (x * 2 + noise % 61) as u8,
+                (y * 3 + (noise >> 6) % 53) as u8,
+                (60 + (noise >> 12) % 97) as u8,
+            ])
+        }))
+    }
+
+    #[test]
+    fn test_choose_model_by_content() {
+        assert_eq!(
+            choose_model(&illustration()),
+            UpscalerModel::RealEsrganX4plusAnime6B
+        );
+        assert_eq!(choose_model(&photo()), UpscalerModel::RealEsrganX4plus);
+    }
+
+    #[test]
+    fn test_tiny_images_are_measured_on_an_enlarged_thumbnail() {
+        // The thumbnail enlarges them, so a uniform pixel is all flat area.
+        for (width, height) in [(1, 1), (1, 40), (2, 2)] {
+            let image = DynamicImage::new_rgb8(width, height);
+            let stats = ContentStats::measure(&image);
+            assert_eq!(stats.flat_ratio, 1.0);
+            assert_eq!(stats.strong_edge_ratio, 0.0);
+            assert_eq!(choose_model(&image), UpscalerModel::RealEsrganX4plusAnime6B);
+        }
+    }
+
+    #[test]
+    fn test_skin_tones_outweigh_a_small_palette() {
+        let stats = ContentStats {
+            palette_ratio: 0.1,
+            flat_ratio: 0.4,
+            strong_edge_ratio: 0.3,
+            skin_ratio: 0.0,
+        };
+        assert!(stats.is_illustration());
+        assert!(
+            !ContentStats {
+                skin_ratio: 0.5,
+                ..stats
+            }
+            .is_illustration()
+        );
+        // Flat shading keeps skin-toned illustrations on the anime model.
+        assert!(
+            ContentStats {
+                flat_ratio: 0.6,
+                skin_ratio: 0.5,
+                ..stats
+            }
+            .is_illustration()
+        );
+    }
+}
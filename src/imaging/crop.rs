@@ -0,0 +1,296 @@
+//! Cropping `/removebg` cut-outs to their subject.
+//!
+//! The subject is the bounding box of the non-transparent pixels. It is
+//! padded, then optionally widened to an aspect ratio or scaled onto a fixed
+//! canvas, keeping the subject centered.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{RgbaImage, imageops::FilterType};
+use std::str::FromStr;
+
+/// Pixels with at least this alpha belong to the subject; fainter ones are
+/// segmentation noise.
+const SUBJECT_ALPHA: u8 = 16;
+/// Most elongated accepted `crop_aspect`, as long side over short side.
+pub const MAX_ASPECT: f64 = 10.0;
+
+/// Invalid crop options, or nothing to crop to.
+#[derive(Debug)]
+pub enum CropError {
+    /// `crop_padding` was not pixels or a percentage.
+    InvalidPadding(String),
+    /// `crop_aspect` was not a ratio such as `4:3`.
+    InvalidAspect(String),
+    /// `crop_size` was not a size such as `1000x1000`.
+    InvalidSize(String),
+    /// Both `crop_aspect` and `crop_size` were given.
+    Conflicting,
+    /// Crop options were given without `crop: "subject"`.
+    NotRequested,
+    /// The cut-out has no subject.
+    NoSubject,
+}
+
+impl IntoResponse for CropError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidPadding(value) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Crop padding must be pixels (e.g. 20) or a percentage of the subject (e.g. 10%), got '{}'",
+                    value
+                ),
+            )
+                .into_response(),
+            Self::InvalidAspect(value) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Crop aspect must be a ratio such as 4:3, between 1:{} and {}:1, got '{}'",
+                    MAX_ASPECT, MAX_ASPECT, value
+                ),
+            )
+                .into_response(),
+            Self::InvalidSize(value) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Crop size must be WIDTHxHEIGHT within the image limits, got '{}'",
+                    value
+                ),
+            )
+                .into_response(),
+            Self::Conflicting => (
+                StatusCode::BAD_REQUEST,
+                "crop_aspect cannot be combined with crop_size",
+            )
+                .into_response(),
+            Self::NotRequested => (
+                StatusCode::BAD_REQUEST,
+                "crop_padding, crop_aspect and crop_size require crop: subject",
+            )
+                .into_response(),
+            Self::NoSubject => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "No subject found to crop to",
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Margin added around the subject.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Padding {
+    /// A fixed number of pixels.
+    Pixels(u32),
+    /// A percentage of the subject's longer side.
+    Percent(f64),
+}
+
+impl Default for Padding {
+    fn default() -> Self {
+        Self::Pixels(0)
+    }
+}
+
+impl FromStr for Padding {
+    type Err = CropError;
+
+    /// Parses `20`, `20px` or `10%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || CropError::InvalidPadding(s.to_string());
+        let text = s.trim();
+        if let Some(percent) = text.strip_suffix('%') {
+            let percent: f64 = percent.trim().parse().map_err(|_| error())?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(error());
+            }
+            return Ok(Self::Percent(percent));
+        }
+        let pixels = text.strip_suffix("px").unwrap_or(text).trim();
+        pixels.parse().map(Self::Pixels).map_err(|_| error())
+    }
+}
+
+impl Padding {
+    /// Padding in pixels for a subject whose longer side is `side` pixels.
+    fn pixels(self, side: u32) -> u32 {
+        match self {
+            Self::Pixels(pixels) => pixels,
+            Self::Percent(percent) => (f64::from(side) * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+/// Shape the padded subject is brought to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frame {
+    /// Widen or heighten to this width-to-height ratio.
+    Aspect(f64),
+    /// Scale to fit and center on a canvas of this size.
+    Canvas(u32, u32),
+}
+
+/// Builds a crop spec from the `crop_padding`, `crop_aspect` and `crop_size`
+/// options. Pixel padding and canvas sizes are bounded by `max_width` and
+/// `max_height`, the input limits.
+pub fn spec(
+    padding: Option<&str>,
+    aspect: Option<&str>,
+    size: Option<&str>,
+    max_width: u32,
+    max_height: u32,
+) -> Result<CropSpec, CropError> {
+    let padding = padding.map(str::parse::<Padding>).transpose()?;
+    if let Some(Padding::Pixels(pixels)) = padding {
+        if pixels > max_width.max(max_height) {
+            return Err(CropError::InvalidPadding(pixels.to_string()));
+        }
+    }
+    let frame = match (aspect, size) {
+        (Some(_), Some(_)) => return Err(CropError::Conflicting),
+        (Some(aspect), None) => Some(parse_aspect(aspect)?),
+        (None, Some(size)) => Some(parse_size(size, max_width, max_height)?),
+        (None, None) => None,
+    };
+    Ok(CropSpec {
+        padding: padding.unwrap_or_default(),
+        frame,
+    })
+}
+
+/// Parses a `crop_aspect` such as `4:3`.
+fn parse_aspect(s: &str) -> Result<Frame, CropError> {
+    parse_ratio(s)
+        .map(Frame::Aspect)
+        .ok_or_else(|| CropError::InvalidAspect(s.to_string()))
+}
+
+/// Parses a width-to-height ratio such as `4:3`, between 1:10 and 10:1.
+pub fn parse_ratio(s: &str) -> Option<f64> {
+    let (width, height) = s.trim().split_once(':')?;
+    let width: f64 = width.trim().parse().ok()?;
+    let height: f64 = height.trim().parse().ok()?;
+    let ratio = width / height;
+    (ratio.is_finite() && ratio > 0.0 && ratio.max(1.0 / ratio) <= MAX_ASPECT).then_some(ratio)
+}
+
+/// Parses a `crop_size` such as `1000x1000`, at most `max_width` by
+/// `max_height`.
+fn parse_size(s: &str, max_width: u32, max_height: u32) -> Result<Frame, CropError> {
+    let error = || CropError::InvalidSize(s.to_string());
+    let (width, height) = s.trim().split_once(['x', 'X']).ok_or_else(error)?;
+    let width: u32 = width.trim().parse().map_err(|_| error())?;
+    let height: u32 = height.trim().parse().map_err(|_| error())?;
+    if width == 0 || height == 0 || width > max_width || height > max_height {
+        return Err(error());
+    }
+    Ok(Frame::Canvas(width, height))
+}
+
+/// How a cut-out is cropped to its subject.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CropSpec {
+    /// Margin around the subject.
+    pub padding: Padding,
+    /// Optional target shape.
+    pub frame: Option<Frame>,
+}
+
+/// A rectangle in input pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BBox {
+    /// Left edge.
+    pub x: u32,
+    /// Top edge.
+    pub y: u32,
+    /// Width.
+    pub width: u32,
+    /// Height.
+    pub height: u32,
+}
+
+impl std::fmt::Display for BBox {
+    /// Formats as `x,y,width,height`, the `X-Subject-BBox` syntax.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Returns the bounding box of the subject of `cutout`, or `None` if it is
+/// entirely transparent.
+pub fn subject_bbox(cutout: &RgbaImage) -> Option<BBox> {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in cutout.enumerate_pixels() {
+        if pixel[3] >= SUBJECT_ALPHA {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+    }
+    (left <= right).then(|| BBox {
+        x: left,
+        y: top,
+        width: right - left + 1,
+        height: bottom - top + 1,
+    })
+}
+
+/// Crops `cutout` to its subject according to `spec`.
+///
+/// Areas beyond the input are transparent, with the colors of the nearest
+/// edge pixel so that blurring or resampling does not darken the border.
+/// Returns the cropped image and the subject's bounding box in the input.
+pub fn crop(cutout: &RgbaImage, spec: &CropSpec) -> Result<(RgbaImage, BBox), CropError> {
+    let bbox = subject_bbox(cutout).ok_or(CropError::NoSubject)?;
+    let pad = i64::from(spec.padding.pixels(bbox.width.max(bbox.height)));
+    let (mut width, mut height) = (
+        i64::from(bbox.width) + 2 * pad,
+        i64::from(bbox.height) + 2 * pad,
+    );
+    if let Some(Frame::Aspect(ratio)) = spec.frame {
+        if (width as f64) / (height as f64) < ratio {
+            width = (height as f64 * ratio).round() as i64;
+        } else {
+            height = (width as f64 / ratio).round() as i64;
+        }
+    }
+    let left = i64::from(bbox.x) + i64::from(bbox.width) / 2 - width / 2;
+    let top = i64::from(bbox.y) + i64::from(bbox.height) / 2 - height / 2;
+
+    let (max_x, max_y) = (
+        i64::from(cutout.width()) - 1,
+        i64::from(cutout.height()) - 1,
+    );
+    let cropped = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        let (sx, sy) = (left + i64::from(x), top + i64::from(y));
+        let pixel = *cutout.get_pixel(sx.clamp(0, max_x) as u32, sy.clamp(0, max_y) as u32);
+        if (0..=max_x).contains(&sx) && (0..=max_y).contains(&sy) {
+            pixel
+        } else {
+            image::Rgba([pixel[0], pixel[1], pixel[2], 0])
+        }
+    });
+
+    let Some(Frame::Canvas(canvas_width, canvas_height)) = spec.frame else {
+        return Ok((cropped, bbox));
+    };
+    let scale =
+        (f64::from(canvas_width) / width as f64).min(f64::from(canvas_height) / height as f64);
+    let (fit_width, fit_height) = (
+        ((width as f64 * scale).round() as u32).clamp(1, canvas_width),
+        ((height as f64 * scale).round() as u32).clamp(1, canvas_height),
+    );
+    let fitted = image::imageops::resize(&cropped, fit_width, fit_height, FilterType::Lanczos3);
+    let mut canvas = RgbaImage::new(canvas_width, canvas_height);
+    image::imageops::replace(
+        &mut canvas,
+        &fitted,
+        i64::from((canvas_width - fit_width) / 2),
+        i64::from((canvas_height - fit_height) / 2),
+    );
+    Ok((canvas, bbox))
+}
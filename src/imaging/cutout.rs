@@ -0,0 +1,189 @@
+//! Post-processing of `/removebg` cut-outs.
+//!
+//! The worker returns the subject on a transparent background; the other
+//! outputs, and any replacement background, are derived from its alpha
+//! channel in the gateway.
+
+use crate::models::Fit;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, GrayImage, RgbaImage, imageops::FilterType};
+use std::str::FromStr;
+
+/// Largest accepted `background_blur` radius, in pixels.
+pub const MAX_BLUR: f32 = 100.0;
+
+/// An RGBA color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    /// Opaque white.
+    pub const WHITE: Self = Self([255, 255, 255, 255]);
+}
+
+/// A color that could not be parsed.
+#[derive(Debug)]
+pub struct ColorError(pub String);
+
+impl IntoResponse for ColorError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid color '{}'. Use #rgb, #rgba, #rrggbb or #rrggbbaa",
+                self.0
+            ),
+        )
+            .into_response()
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorError;
+
+    /// Parses a hex color, with or without the leading `#`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ColorError(s.to_string());
+        let hex = s.trim().trim_start_matches('#');
+        if !hex.is_ascii() {
+            return Err(error());
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).map_err(|_| error());
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| error());
+
+        match hex.len() {
+            3 | 4 => {
+                let mut rgba = [255; 4];
+                for (i, channel) in rgba.iter_mut().enumerate().take(hex.len()) {
+                    *channel = digit(i)? * 17;
+                }
+                Ok(Self(rgba))
+            }
+            6 | 8 => {
+                let mut rgba = [255; 4];
+                for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+                    *channel = byte(i * 2)?;
+                }
+                Ok(Self(rgba))
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Invalid combination of background options.
+#[derive(Debug)]
+pub enum BackgroundError {
+    /// `background_blur` was not a number in `(0, MAX_BLUR]`.
+    InvalidBlur(String),
+    /// `background_blur` was combined with another background.
+    Conflicting,
+    /// A background was requested for an output without one.
+    UnsupportedOutput,
+}
+
+impl IntoResponse for BackgroundError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::InvalidBlur(value) => format!(
+                "Background blur must be greater than 0 and at most {}, got '{}'",
+                MAX_BLUR, value
+            ),
+            Self::Conflicting => {
+                "background_blur cannot be combined with background_color or background_image"
+                    .to_string()
+            }
+            Self::UnsupportedOutput => {
+                "Background options only apply to the cutout and both outputs".to_string()
+            }
+        };
+        (StatusCode::BAD_REQUEST, message).into_response()
+    }
+}
+
+/// Validates a `background_blur` radius.
+pub fn check_blur(blur: f32) -> Result<f32, BackgroundError> {
+    if blur.is_finite() && blur > 0.0 && blur <= MAX_BLUR {
+        Ok(blur)
+    } else {
+        Err(BackgroundError::InvalidBlur(blur.to_string()))
+    }
+}
+
+/// What the cut-out is composited onto.
+pub enum Background {
+    /// A solid color.
+    Color(Color),
+    /// An image fitted to the cut-out, over `fill` where it leaves gaps.
+    Image {
+        image: DynamicImage,
+        fit: Fit,
+        fill: Option<Color>,
+    },
+    /// The original background, blurred with a radius of `sigma` pixels.
+    Blur { sigma: f32 },
+}
+
+impl Background {
+    /// Renders the background behind `cutout`.
+    fn render(&self, cutout: &RgbaImage) -> RgbaImage {
+        let (width, height) = cutout.dimensions();
+        match self {
+            Self::Color(color) => RgbaImage::from_pixel(width, height, image::Rgba(color.0)),
+            Self::Image { image, fit, fill } => {
+                let fitted = match fit {
+                    Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+                    Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+                    Fit::Exact => image.resize_exact(width, height, FilterType::Lanczos3),
+                };
+                let fill = fill.map_or([0; 4], |color| color.0);
+                let mut canvas = RgbaImage::from_pixel(width, height, image::Rgba(fill));
+                image::imageops::overlay(
+                    &mut canvas,
+                    &fitted.to_rgba8(),
+                    i64::from((width - fitted.width()) / 2),
+                    i64::from((height - fitted.height()) / 2),
+                );
+                canvas
+            }
+            Self::Blur { sigma } => {
+                // The worker applies the mask with `putalpha` and the mask
+                // path reattaches it to the original, so the color channels
+                // under transparent pixels are still the original's.
+                let mut original = cutout.clone();
+                for pixel in original.pixels_mut() {
+                    pixel[3] = u8::MAX;
+                }
+                image::imageops::fast_blur(&original, *sigma)
+            }
+        }
+    }
+}
+
+/// Extracts the alpha channel of `cutout` as a grayscale mask.
+pub fn mask(cutout: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(cutout.width(), cutout.height(), |x, y| {
+        image::Luma([cutout.get_pixel(x, y)[3]])
+    })
+}
+
+/// Composites `cutout` over a solid `color`.
+pub fn matte(cutout: &RgbaImage, color: Color) -> DynamicImage {
+    composite(cutout, &Background::Color(color))
+}
+
+/// Composites `cutout` over `background`.
+///
+/// The result is RGB when it is fully opaque and RGBA otherwise.
+pub fn composite(cutout: &RgbaImage, background: &Background) -> DynamicImage {
+    let mut output = background.render(cutout);
+    image::imageops::overlay(&mut output, cutout, 0, 0);
+    if output.pixels().all(|pixel| pixel[3] == u8::MAX) {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(output).into_rgb8())
+    } else {
+        DynamicImage::ImageRgba8(output)
+    }
+}
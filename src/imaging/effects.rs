@@ -0,0 +1,348 @@
+//! Sticker effects for `/removebg` cut-outs.
+//!
+//! An outline follows the alpha edge at a fixed distance, and a drop shadow
+//! is a blurred, offset copy of the silhouette. The canvas grows so that
+//! neither is clipped.
+
+use super::cutout::{Color, ColorError};
+use crate::models::OutputFormat;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{GrayImage, RgbaImage};
+
+/// Largest accepted `outline_width` and `shadow_blur`, in pixels.
+pub const MAX_EFFECT_RADIUS: f32 = 50.0;
+/// Largest accepted shadow offset along either axis, in pixels.
+pub const MAX_SHADOW_OFFSET: i32 = 100;
+/// Pixels with at least this alpha are inside the silhouette.
+const SILHOUETTE_ALPHA: u8 = 128;
+/// Shadow defaults, used when only some shadow parameters are given.
+const DEFAULT_SHADOW_OFFSET: (i32, i32) = (4, 4);
+const DEFAULT_SHADOW_BLUR: f32 = 6.0;
+const DEFAULT_SHADOW_OPACITY: f32 = 0.5;
+
+/// Invalid effect options.
+#[derive(Debug)]
+pub enum EffectError {
+    /// `outline_width` was out of range.
+    InvalidOutlineWidth(String),
+    /// `outline_color` could not be parsed.
+    InvalidOutlineColor(ColorError),
+    /// A shadow offset was out of range.
+    InvalidShadowOffset(String),
+    /// `shadow_blur` was out of range.
+    InvalidShadowBlur(String),
+    /// `shadow_opacity` was not between 0 and 1.
+    InvalidShadowOpacity(String),
+    /// Effects were requested for an output other than a plain cut-out.
+    UnsupportedOutput,
+    /// Effects were requested in a format without transparency.
+    UnsupportedFormat(OutputFormat),
+}
+
+impl IntoResponse for EffectError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::InvalidOutlineWidth(value) => format!(
+                "Outline width must be between 0 and {} pixels, got '{}'",
+                MAX_EFFECT_RADIUS, value
+            ),
+            Self::InvalidOutlineColor(e) => return e.into_response(),
+            Self::InvalidShadowOffset(value) => format!(
+                "Shadow offset must be between -{} and {} pixels, got '{}'",
+                MAX_SHADOW_OFFSET, MAX_SHADOW_OFFSET, value
+            ),
+            Self::InvalidShadowBlur(value) => format!(
+                "Shadow blur must be between 0 and {} pixels, got '{}'",
+                MAX_EFFECT_RADIUS, value
+            ),
+            Self::InvalidShadowOpacity(value) => {
+                format!("Shadow opacity must be between 0 and 1, got '{}'", value)
+            }
+            Self::UnsupportedOutput => {
+                "Outline and shadow effects only apply to the cutout output without a background"
+                    .to_string()
+            }
+            Self::UnsupportedFormat(format) => format!(
+                "Outline and shadow effects need transparency; use png or webp instead of {}",
+                format
+            ),
+        };
+        (StatusCode::BAD_REQUEST, message).into_response()
+    }
+}
+
+/// An outline stroke around the subject.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outline {
+    /// Stroke width, in pixels.
+    pub width: f32,
+    /// Stroke color.
+    pub color: Color,
+}
+
+/// A drop shadow behind the subject and its outline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shadow {
+    /// Horizontal offset, in pixels; positive is to the right.
+    pub offset_x: i32,
+    /// Vertical offset, in pixels; positive is down.
+    pub offset_y: i32,
+    /// Blur radius, in pixels.
+    pub blur: f32,
+    /// Opacity, from 0 to 1.
+    pub opacity: f32,
+}
+
+/// Effects applied to a transparent cut-out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Effects {
+    /// Optional outline.
+    pub outline: Option<Outline>,
+    /// Optional drop shadow.
+    pub shadow: Option<Shadow>,
+}
+
+/// Effect options as received, before validation.
+#[derive(Clone, Debug, Default)]
+pub struct EffectOptions {
+    /// Outline width, in pixels.
+    pub outline_width: Option<f32>,
+    /// Outline color, as hex.
+    pub outline_color: Option<String>,
+    /// Horizontal shadow offset, in pixels.
+    pub shadow_offset_x: Option<i32>,
+    /// Vertical shadow offset, in pixels.
+    pub shadow_offset_y: Option<i32>,
+    /// Shadow blur radius, in pixels.
+    pub shadow_blur: Option<f32>,
+    /// Shadow opacity, from 0 to 1.
+    pub shadow_opacity: Option<f32>,
+}
+
+impl EffectOptions {
+    /// Applies a multipart text field.
+    ///
+    /// Returns `Ok(false)` if `name` is not an effect option.
+    pub fn parse_field(&mut self, name: &str, text: &str) -> Result<bool, EffectError> {
+        let number = |error: fn(String) -> EffectError| {
+            text.parse::<f32>().map_err(|_| error(text.to_string()))
+        };
+        let offset = || {
+            text.parse::<i32>()
+                .map_err(|_| EffectError::InvalidShadowOffset(text.to_string()))
+        };
+        match name {
+            "outline_width" => self.outline_width = Some(number(EffectError::InvalidOutlineWidth)?),
+            "outline_color" => self.outline_color = Some(text.to_string()),
+            "shadow_offset_x" => self.shadow_offset_x = Some(offset()?),
+            "shadow_offset_y" => self.shadow_offset_y = Some(offset()?),
+            "shadow_blur" => self.shadow_blur = Some(number(EffectError::InvalidShadowBlur)?),
+            "shadow_opacity" => {
+                self.shadow_opacity = Some(number(EffectError::InvalidShadowOpacity)?)
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Validates the options. A shadow is drawn when any shadow option is
+    /// given, with defaults for the others; an outline when `outline_width`
+    /// is positive.
+    pub fn build(&self) -> Result<Effects, EffectError> {
+        let radius = 0.0..=MAX_EFFECT_RADIUS;
+        let outline = match self.outline_width {
+            Some(width) if !radius.contains(&width) => {
+                return Err(EffectError::InvalidOutlineWidth(width.to_string()));
+            }
+            Some(width) if width > 0.0 => Some(Outline {
+                width,
+                color: self
+                    .outline_color
+                    .as_deref()
+                    .map(str::parse::<Color>)
+                    .transpose()
+                    .map_err(EffectError::InvalidOutlineColor)?
+                    .unwrap_or(Color::WHITE),
+            }),
+            _ => None,
+        };
+
+        let shadow_requested = self.shadow_offset_x.is_some()
+            || self.shadow_offset_y.is_some()
+            || self.shadow_blur.is_some()
+            || self.shadow_opacity.is_some();
+        let shadow = if shadow_requested {
+            let offset_x = self.shadow_offset_x.unwrap_or(DEFAULT_SHADOW_OFFSET.0);
+            let offset_y = self.shadow_offset_y.unwrap_or(DEFAULT_SHADOW_OFFSET.1);
+            for offset in [offset_x, offset_y] {
+                if offset.abs() > MAX_SHADOW_OFFSET {
+                    return Err(EffectError::InvalidShadowOffset(offset.to_string()));
+                }
+            }
+            let blur = self.shadow_blur.unwrap_or(DEFAULT_SHADOW_BLUR);
+            if !radius.contains(&blur) {
+                return Err(EffectError::InvalidShadowBlur(blur.to_string()));
+            }
+            let opacity = self.shadow_opacity.unwrap_or(DEFAULT_SHADOW_OPACITY);
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err(EffectError::InvalidShadowOpacity(opacity.to_string()));
+            }
+            Some(Shadow {
+                offset_x,
+                offset_y,
+                blur,
+                opacity,
+            })
+        } else {
+            None
+        };
+
+        Ok(Effects { outline, shadow })
+    }
+}
+
+impl Effects {
+    /// Returns `true` if no effect is requested.
+    pub fn is_empty(&self) -> bool {
+        self.outline.is_none() && self.shadow.is_none()
+    }
+
+    /// Renders the effects around `cutout` on an expanded canvas.
+    pub fn apply(&self, cutout: &RgbaImage) -> RgbaImage {
+        let (width, height) = cutout.dimensions();
+        let stroke = self
+            .outline
+            .map_or(0, |outline| outline.width.ceil() as i64);
+        let (mut left, mut top, mut right, mut bottom) = (stroke, stroke, stroke, stroke);
+        if let Some(shadow) = self.shadow {
+            let spread = stroke + (shadow.blur * 2.0).ceil() as i64;
+            let (dx, dy) = (i64::from(shadow.offset_x), i64::from(shadow.offset_y));
+            left = left.max(spread - dx);
+            right = right.max(spread + dx);
+            top = top.max(spread - dy);
+            bottom = bottom.max(spread + dy);
+        }
+        let canvas_width = (i64::from(width) + left + right) as u32;
+        let canvas_height = (i64::from(height) + top + bottom) as u32;
+
+        let mut subject = RgbaImage::new(canvas_width, canvas_height);
+        image::imageops::replace(&mut subject, cutout, left, top);
+        let mut silhouette = GrayImage::from_fn(canvas_width, canvas_height, |x, y| {
+            image::Luma([subject.get_pixel(x, y)[3]])
+        });
+
+        let mut output = RgbaImage::new(canvas_width, canvas_height);
+        let outline = self.outline.map(|outline| {
+            let stroke = stroke_mask(&silhouette, outline.width);
+            for (out, value) in silhouette.iter_mut().zip(stroke.iter()) {
+                *out = (*out).max(*value);
+            }
+            (stroke, outline.color)
+        });
+
+        if let Some(shadow) = self.shadow {
+            let mut shifted = GrayImage::new(canvas_width, canvas_height);
+            image::imageops::replace(
+                &mut shifted,
+                &silhouette,
+                i64::from(shadow.offset_x),
+                i64::from(shadow.offset_y),
+            );
+            if shadow.blur > 0.0 {
+                shifted = image::imageops::fast_blur(&shifted, shadow.blur / 2.0);
+            }
+            for (pixel, value) in output.pixels_mut().zip(shifted.pixels()) {
+                *pixel = image::Rgba([0, 0, 0, (f32::from(value[0]) * shadow.opacity) as u8]);
+            }
+        }
+        if let Some((stroke, color)) = outline {
+            let [r, g, b, a] = color.0;
+            let layer = RgbaImage::from_fn(canvas_width, canvas_height, |x, y| {
+                let coverage = u16::from(stroke.get_pixel(x, y)[0]) * u16::from(a) / 255;
+                image::Rgba([r, g, b, coverage as u8])
+            });
+            image::imageops::overlay(&mut output, &layer, 0, 0);
+        }
+        image::imageops::overlay(&mut output, &subject, 0, 0);
+        output
+    }
+}
+
+/// Coverage of a stroke of `width` pixels around the silhouette, with an
+/// anti-aliased outer edge.
+fn stroke_mask(silhouette: &GrayImage, width: f32) -> GrayImage {
+    let distances = distance_transform(silhouette);
+    let (w, _) = silhouette.dimensions();
+    GrayImage::from_fn(silhouette.width(), silhouette.height(), |x, y| {
+        let distance = distances[(y * w + x) as usize].sqrt();
+        let coverage = (f64::from(width) + 0.5 - distance).clamp(0.0, 1.0);
+        image::Luma([(coverage * 255.0).round() as u8])
+    })
+}
+
+/// Squared Euclidean distance from every pixel to the nearest pixel inside
+/// the silhouette (Felzenszwalb and Huttenlocher).
+fn distance_transform(silhouette: &GrayImage) -> Vec<f64> {
+    let (width, height) = (silhouette.width() as usize, silhouette.height() as usize);
+    let far = ((width * width + height * height) as f64).max(1.0);
+    let mut grid: Vec<f64> = silhouette
+        .pixels()
+        .map(|p| if p[0] >= SILHOUETTE_ALPHA { 0.0 } else { far })
+        .collect();
+
+    let mut line = Vec::new();
+    let mut out = Vec::new();
+    for x in 0..width {
+        line.clear();
+        line.extend((0..height).map(|y| grid[y * width + x]));
+        distance_1d(&line, &mut out);
+        for (y, value) in out.iter().enumerate() {
+            grid[y * width + x] = *value;
+        }
+    }
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice(&grid[y * width..(y + 1) * width]);
+        distance_1d(&line, &mut out);
+        grid[y * width..(y + 1) * width].copy_from_slice(&out);
+    }
+    grid
+}
+
+/// One-dimensional squared distance transform: the lower envelope of the
+/// parabolas rooted at each sample.
+fn distance_1d(f: &[f64], out: &mut Vec<f64>) {
+    let n = f.len();
+    let mut roots = vec![0usize; n];
+    let mut bounds = vec![0f64; n + 1];
+    let mut k = 0;
+    bounds[0] = f64::NEG_INFINITY;
+    bounds[1] = f64::INFINITY;
+    let intersect = |q: usize, p: usize| {
+        ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * q as f64 - 2.0 * p as f64)
+    };
+    for q in 1..n {
+        let mut s = intersect(q, roots[k]);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersect(q, roots[k]);
+        }
+        k += 1;
+        roots[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f64::INFINITY;
+    }
+
+    out.clear();
+    k = 0;
+    for q in 0..n {
+        while bounds[k + 1] < q as f64 {
+            k += 1;
+        }
+        let d = q as f64 - roots[k] as f64;
+        out.push(d * d + f[roots[k]]);
+    }
+}
@@ -0,0 +1,171 @@
+//! Low-resolution mask path for `/removebg`.
+//!
+//! BiRefNet segments at 1024x1024 whatever the input size, so for large
+//! photos the gateway sends a downsampled copy, receives only the mask, and
+//! upsamples it with a fast guided filter that snaps the mask edges to the
+//! edges of the full-resolution original.
+
+use image::{DynamicImage, GrayImage, imageops::FilterType};
+
+/// Guided filter window radius, in low-resolution pixels.
+const GUIDED_RADIUS: u32 = 2;
+/// Guided filter regularization; smaller values follow the guide's edges
+/// more closely.
+const GUIDED_EPS: f32 = 1e-4;
+
+/// Downsamples `image` so that its longer side is at most `max_side`.
+pub fn downsample(image: &DynamicImage, max_side: u32) -> DynamicImage {
+    if image.width().max(image.height()) <= max_side {
+        return image.clone();
+    }
+    image.resize(max_side, max_side, FilterType::Triangle)
+}
+
+/// Upsamples a low-resolution `mask` to the size of `guide`, the luminance
+/// of the full-resolution original.
+///
+/// `guide_low` is the luminance of the image the mask was computed from.
+/// The filter coefficients are fitted at low resolution and interpolated,
+/// so the cost at full resolution is a few operations per pixel.
+pub fn upsample_guided(mask: &GrayImage, guide_low: &GrayImage, guide: &GrayImage) -> GrayImage {
+    let (w, h) = guide_low.dimensions();
+    let resized;
+    let mask = if mask.dimensions() == (w, h) {
+        mask
+    } else {
+        resized = image::imageops::resize(mask, w, h, FilterType::Triangle);
+        &resized
+    };
+
+    let i: Vec<f32> = guide_low
+        .pixels()
+        .map(|p| f32::from(p[0]) / 255.0)
+        .collect();
+    let p: Vec<f32> = mask.pixels().map(|p| f32::from(p[0]) / 255.0).collect();
+    let ii: Vec<f32> = i.iter().map(|v| v * v).collect();
+    let ip: Vec<f32> = i.iter().zip(&p).map(|(a, b)| a * b).collect();
+
+    let mean_i = box_filter(&i, w, h, GUIDED_RADIUS);
+    let mean_p = box_filter(&p, w, h, GUIDED_RADIUS);
+    let corr_i = box_filter(&ii, w, h, GUIDED_RADIUS);
+    let corr_ip = box_filter(&ip, w, h, GUIDED_RADIUS);
+
+    let mut a = vec![0.0; i.len()];
+    let mut b = vec![0.0; i.len()];
+    for k in 0..i.len() {
+        let var = corr_i[k] - mean_i[k] * mean_i[k];
+        let cov = corr_ip[k] - mean_i[k] * mean_p[k];
+        a[k] = cov / (var + GUIDED_EPS);
+        b[k] = mean_p[k] - a[k] * mean_i[k];
+    }
+    let mean_a = box_filter(&a, w, h, GUIDED_RADIUS);
+    let mean_b = box_filter(&b, w, h, GUIDED_RADIUS);
+
+    let (full_w, full_h) = guide.dimensions();
+    let (sx, sy) = (w as f32 / full_w as f32, h as f32 / full_h as f32);
+    GrayImage::from_fn(full_w, full_h, |x, y| {
+        let fx = ((x as f32 + 0.5) * sx - 0.5).clamp(0.0, (w - 1) as f32);
+        let fy = ((y as f32 + 0.5) * sy - 0.5).clamp(0.0, (h - 1) as f32);
+        let coeff_a = bilinear(&mean_a, w, fx, fy);
+        let coeff_b = bilinear(&mean_b, w, fx, fy);
+        let q = coeff_a * f32::from(guide.get_pixel(x, y)[0]) / 255.0 + coeff_b;
+        image::Luma([(q * 255.0).round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// Mean over a `(2r+1)`-pixel square window, clipped at the borders.
+fn box_filter(values: &[f32], width: u32, height: u32, radius: u32) -> Vec<f32> {
+    let (w, h, r) = (width as usize, height as usize, radius as usize);
+    let mut integral = vec![0.0f64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0.0f64;
+        for x in 0..w {
+            row += f64::from(values[y * w + x]);
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row;
+        }
+    }
+
+    let mut out = vec![0.0; w * h];
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(w));
+            let sum = integral[y1 * (w + 1) + x1]
+                - integral[y0 * (w + 1) + x1]
+                - integral[y1 * (w + 1) + x0]
+                + integral[y0 * (w + 1) + x0];
+            out[y * w + x] = (sum / ((x1 - x0) * (y1 - y0)) as f64) as f32;
+        }
+    }
+    out
+}
+
+fn bilinear(values: &[f32], width: u32, x: f32, y: f32) -> f32 {
+    let w = width as usize;
+    let height = values.len() / w;
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(height - 1));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let top = values[y0 * w + x0] * (1.0 - tx) + values[y0 * w + x1] * tx;
+    let bottom = values[y1 * w + x0] * (1.0 - tx) + values[y1 * w + x1] * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Black on the left half, white on the right half.
+    fn halves(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, _| {
+            Luma([if x < width / 2 { 0 } else { 255 }])
+        })
+    }
+
+    #[test]
+    fn test_upsample_guided_snaps_to_the_guide_edges() {
+        let output = upsample_guided(&halves(4, 4), &halves(4, 4), &halves(16, 16));
+        assert_eq!(output.dimensions(), (16, 16));
+        for y in [0, 8, 15] {
+            assert!(output.get_pixel(0, y)[0] < 10);
+            assert!(
+                output.get_pixel(7, y)[0] < 30,
+                "{:?}",
+                output.get_pixel(7, y)
+            );
+            assert!(
+                output.get_pixel(8, y)[0] > 225,
+                "{:?}",
+                output.get_pixel(8, y)
+            );
+            assert!(output.get_pixel(15, y)[0] > 245);
+        }
+    }
+
+    #[test]
+    fn test_upsample_guided_keeps_a_uniform_mask() {
+        let guide = GrayImage::from_fn(9, 5, |x, y| Luma([(x * 20 + y * 7) as u8]));
+        let mask = GrayImage::from_pixel(3, 2, Luma([255]));
+        let guide_low = image::imageops::resize(&guide, 4, 3, FilterType::Triangle);
+        // The mask is resized to the low-resolution guide first.
+        let output = upsample_guided(&mask, &guide_low, &guide);
+        assert_eq!(output.dimensions(), (9, 5));
+        assert!(output.pixels().all(|pixel| pixel[0] >= 254));
+    }
+
+    #[test]
+    fn test_upsample_guided_from_a_single_pixel() {
+        let mask = GrayImage::from_pixel(1, 1, Luma([128]));
+        let guide_low = GrayImage::from_pixel(1, 1, Luma([90]));
+        let guide = GrayImage::from_pixel(3, 2, Luma([90]));
+        let output = upsample_guided(&mask, &guide_low, &guide);
+        assert_eq!(output.dimensions(), (3, 2));
+        assert!(output.pixels().all(|pixel| pixel[0].abs_diff(128) <= 1));
+
+        let image = DynamicImage::new_luma8(1, 1);
+        assert_eq!(downsample(&image, 1).width(), 1);
+        let small = downsample(&DynamicImage::new_luma8(40, 10), 8);
+        assert_eq!((small.width(), small.height()), (8, 2));
+    }
+}
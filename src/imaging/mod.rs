@@ -0,0 +1,290 @@
+//! # Imaging
+//!
+//! Image handling done in the gateway itself. Every input is inspected
+//! before a worker is invoked: the header is sniffed to detect the format and
+//! dimensions, and oversized images are rejected without being decoded.
+
+pub mod alpha;
+pub mod classify;
+pub mod crop;
+pub mod cutout;
+pub mod effects;
+pub mod mask;
+pub mod output;
+pub mod refine;
+pub mod resize;
+pub mod tiling;
+pub mod transform;
+
+use crate::config::Config;
+use crate::upload::SpooledBody;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+/// Formats accepted as input.
+const SUPPORTED_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Tiff,
+];
+
+/// MIME types of the accepted input formats.
+pub fn supported_mime_types() -> impl Iterator<Item = &'static str> {
+    SUPPORTED_FORMATS.iter().map(|format| format.to_mime_type())
+}
+
+/// Format, dimensions and color layout read from an image header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// Detected container format.
+    pub format: ImageFormat,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Whether the image has an alpha channel.
+    pub has_alpha: bool,
+}
+
+impl ImageInfo {
+    /// Pixel count in megapixels.
+    pub fn megapixels(&self) -> f64 {
+        megapixels(self.width, self.height)
+    }
+}
+
+/// Reason an input image was rejected.
+#[derive(Debug)]
+pub enum ImageRejection {
+    /// The format could not be detected or is not supported.
+    UnsupportedFormat(Option<ImageFormat>),
+    /// The header could not be parsed.
+    Unreadable(String),
+    /// The header was valid but the pixel data could not be decoded.
+    Undecodable(String),
+    /// The image is wider than allowed.
+    TooWide { width: u32, max: u32 },
+    /// The image is taller than allowed.
+    TooTall { height: u32, max: u32 },
+    /// The image has more pixels than allowed.
+    TooManyPixels { width: u32, height: u32, max: f64 },
+    /// The upscaled result would have more pixels than allowed.
+    OutputTooLarge {
+        width: u32,
+        height: u32,
+        scale: u32,
+        max: f64,
+    },
+    /// The body could not be read back from memory or disk.
+    Io(std::io::Error),
+}
+
+impl IntoResponse for ImageRejection {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::UnsupportedFormat(Some(format)) => format!(
+                "Unsupported image format: {}. Supported formats: {}",
+                format_name(format),
+                supported_format_names()
+            ),
+            Self::UnsupportedFormat(None) => format!(
+                "Unrecognized image format. Supported formats: {}",
+                supported_format_names()
+            ),
+            Self::Unreadable(e) => format!("Could not read image header: {}", e),
+            Self::Undecodable(e) => format!("Could not decode image: {}", e),
+            Self::TooWide { width, max } => format!(
+                "Image width of {} px exceeds the limit of {} px",
+                width, max
+            ),
+            Self::TooTall { height, max } => format!(
+                "Image height of {} px exceeds the limit of {} px",
+                height, max
+            ),
+            Self::TooManyPixels { width, height, max } => format!(
+                "Image of {}x{} ({:.1} megapixels) exceeds the limit of {} megapixels",
+                width,
+                height,
+                megapixels(width, height),
+                max
+            ),
+            Self::OutputTooLarge {
+                width,
+                height,
+                scale,
+                max,
+            } => {
+                let (out_width, out_height) =
+                    (width as u64 * scale as u64, height as u64 * scale as u64);
+                format!(
+                    "Upscaling {}x{} by {}x would produce {}x{} ({:.1} megapixels), exceeding the limit of {} megapixels",
+                    width,
+                    height,
+                    scale,
+                    out_width,
+                    out_height,
+                    (out_width * out_height) as f64 / 1_000_000.0,
+                    max
+                )
+            }
+            Self::Io(e) => {
+                tracing::error!("Failed to read upload for inspection: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read upload")
+                    .into_response();
+            }
+        };
+
+        (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+    }
+}
+
+/// Pixel limits applied to input images.
+#[derive(Clone, Debug)]
+pub struct ImageLimits {
+    /// Maximum width, in pixels.
+    pub max_width: u32,
+    /// Maximum height, in pixels.
+    pub max_height: u32,
+    /// Maximum pixel count, in megapixels.
+    pub max_megapixels: f64,
+    /// Maximum pixel count of an upscaled result, in megapixels.
+    pub max_output_megapixels: f64,
+}
+
+impl ImageLimits {
+    /// Reads the image limits from the configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_width: config.max_image_width,
+            max_height: config.max_image_height,
+            max_megapixels: config.max_image_megapixels,
+            max_output_megapixels: config.max_upscale_output_megapixels,
+        }
+    }
+
+    /// Checks the input dimensions.
+    pub fn check(&self, info: &ImageInfo) -> Result<(), ImageRejection> {
+        if info.width > self.max_width {
+            return Err(ImageRejection::TooWide {
+                width: info.width,
+                max: self.max_width,
+            });
+        }
+        if info.height > self.max_height {
+            return Err(ImageRejection::TooTall {
+                height: info.height,
+                max: self.max_height,
+            });
+        }
+        if info.megapixels() > self.max_megapixels {
+            return Err(ImageRejection::TooManyPixels {
+                width: info.width,
+                height: info.height,
+                max: self.max_megapixels,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the input dimensions and the size of the result of upscaling
+    /// it by `scale`.
+    pub fn check_upscale(&self, info: &ImageInfo, scale: u32) -> Result<(), ImageRejection> {
+        self.check(info)?;
+        let output = info.megapixels() * f64::from(scale) * f64::from(scale);
+        if output > self.max_output_megapixels {
+            return Err(ImageRejection::OutputTooLarge {
+                width: info.width,
+                height: info.height,
+                scale,
+                max: self.max_output_megapixels,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Sniffs the format and dimensions of `body` without decoding its pixels.
+pub async fn inspect(body: &mut SpooledBody) -> Result<ImageInfo, ImageRejection> {
+    let reader = body.reader().await.map_err(ImageRejection::Io)?;
+    let info = tokio::task::spawn_blocking(move || {
+        let mut reader = ImageReader::new(reader)
+            .with_guessed_format()
+            .map_err(ImageRejection::Io)?;
+        reader.no_limits();
+
+        let format = reader
+            .format()
+            .ok_or(ImageRejection::UnsupportedFormat(None))?;
+        if !SUPPORTED_FORMATS.contains(&format) {
+            return Err(ImageRejection::UnsupportedFormat(Some(format)));
+        }
+
+        let decoder = reader
+            .into_decoder()
+            .map_err(|e| ImageRejection::Unreadable(e.to_string()))?;
+        let (width, height) = decoder.dimensions();
+        Ok(ImageInfo {
+            format,
+            width,
+            height,
+            has_alpha: decoder.color_type().has_alpha(),
+        })
+    })
+    .await
+    .map_err(|e| ImageRejection::Io(std::io::Error::other(e)))?;
+
+    body.rewind().await.map_err(ImageRejection::Io)?;
+    info
+}
+
+/// Decodes the full image held in `body`.
+///
+/// Call only after [`inspect`] and the pixel limits have accepted the image.
+pub async fn decode(body: &mut SpooledBody) -> Result<DynamicImage, ImageRejection> {
+    let reader = body.reader().await.map_err(ImageRejection::Io)?;
+    let image = tokio::task::spawn_blocking(move || {
+        let mut reader = ImageReader::new(reader)
+            .with_guessed_format()
+            .map_err(ImageRejection::Io)?;
+        reader.no_limits();
+        reader
+            .decode()
+            .map_err(|e| ImageRejection::Undecodable(e.to_string()))
+    })
+    .await
+    .map_err(|e| ImageRejection::Io(std::io::Error::other(e)))?;
+
+    body.rewind().await.map_err(ImageRejection::Io)?;
+    image
+}
+
+/// Pixel count of a `width`x`height` image in megapixels.
+pub fn megapixels(width: u32, height: u32) -> f64 {
+    (u64::from(width) * u64::from(height)) as f64 / 1_000_000.0
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "PNG",
+        ImageFormat::Jpeg => "JPEG",
+        ImageFormat::WebP => "WebP",
+        ImageFormat::Gif => "GIF",
+        ImageFormat::Bmp => "BMP",
+        ImageFormat::Tiff => "TIFF",
+        other => other.extensions_str().first().copied().unwrap_or("unknown"),
+    }
+}
+
+fn supported_format_names() -> String {
+    SUPPORTED_FORMATS
+        .iter()
+        .map(|format| format_name(*format))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
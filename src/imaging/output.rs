@@ -0,0 +1,305 @@
+//! Output format negotiation and encoding.
+//!
+//! The format comes from the request's `format` field or, failing that, from
+//! the `Accept` header. Results that need a different format than the worker
+//! produced are transcoded in the gateway.
+
+use crate::models::{OutputFormat, OutputOptions};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{
+    DynamicImage, ImageResult, RgbImage,
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder},
+};
+use std::io::Cursor;
+
+/// JPEG quality used when none is requested.
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+/// Lossy WebP quality used when none is requested.
+pub const DEFAULT_WEBP_QUALITY: u8 = 80;
+/// AVIF quality used when none is requested.
+pub const DEFAULT_AVIF_QUALITY: u8 = 70;
+/// AVIF encoder speed (1 = slowest, 10 = fastest).
+const AVIF_SPEED: u8 = 8;
+
+/// Invalid or unsatisfiable output options.
+#[derive(Debug)]
+pub enum OutputError {
+    /// The `format` field named an unknown format.
+    InvalidFormat(String),
+    /// The `quality` field was not between 1 and 100.
+    InvalidQuality(String),
+    /// The `lossless` field was not a boolean.
+    InvalidLossless(String),
+    /// Lossless encoding was requested for a lossy-only format.
+    LosslessUnsupported(OutputFormat),
+    /// None of the types in the `Accept` header can be produced.
+    NotAcceptable(String),
+}
+
+impl IntoResponse for OutputError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidFormat(format) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Unsupported output format '{}'. Supported formats: png, jpeg, webp, avif",
+                    format
+                ),
+            )
+                .into_response(),
+            Self::InvalidQuality(quality) => (
+                StatusCode::BAD_REQUEST,
+                format!("Quality must be between 1 and 100, got '{}'", quality),
+            )
+                .into_response(),
+            Self::InvalidLossless(value) => (
+                StatusCode::BAD_REQUEST,
+                format!("Lossless must be true or false, got '{}'", value),
+            )
+                .into_response(),
+            Self::LosslessUnsupported(format) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Lossless encoding is not supported for {}; use png or webp",
+                    format
+                ),
+            )
+                .into_response(),
+            Self::NotAcceptable(accept) => (
+                StatusCode::NOT_ACCEPTABLE,
+                format!(
+                    "None of the accepted types ({}) can be produced. Available: image/png, image/jpeg, image/webp, image/avif",
+                    accept
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// A fully resolved output encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rendition {
+    /// Output format.
+    pub format: OutputFormat,
+    /// Requested quality, if any.
+    pub quality: Option<u8>,
+    /// Whether lossless encoding was requested.
+    pub lossless: bool,
+}
+
+impl Rendition {
+    /// Returns `true` if a worker result already in `format` can be returned
+    /// unchanged.
+    pub fn is_passthrough(&self, format: OutputFormat) -> bool {
+        self.format == format && self.quality.is_none() && !self.lossless
+    }
+}
+
+/// Output options after negotiation; the format is `None` when the client
+/// expressed no preference.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Negotiated {
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    lossless: Option<bool>,
+}
+
+impl Negotiated {
+    /// Resolves the encoding, using `default` when no format was chosen.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if lossless encoding was requested for JPEG or AVIF.
+    pub fn resolve(&self, default: OutputFormat) -> Result<Rendition, OutputError> {
+        let format = self.format.unwrap_or(default);
+        let lossless = self.lossless.unwrap_or(false);
+        if lossless && matches!(format, OutputFormat::Jpeg | OutputFormat::Avif) {
+            return Err(OutputError::LosslessUnsupported(format));
+        }
+        Ok(Rendition {
+            format,
+            quality: self.quality,
+            lossless,
+        })
+    }
+}
+
+/// Applies a multipart text field to `options`.
+///
+/// Returns `Ok(false)` if `name` is not an output option.
+pub fn parse_field(
+    options: &mut OutputOptions,
+    name: &str,
+    text: &str,
+) -> Result<bool, OutputError> {
+    match name {
+        "format" => {
+            let format = serde_json::from_value(serde_json::Value::String(text.to_lowercase()))
+                .map_err(|_| OutputError::InvalidFormat(text.to_string()))?;
+            options.format = Some(format);
+        }
+        "quality" => {
+            let quality = text
+                .parse::<u8>()
+                .map_err(|_| OutputError::InvalidQuality(text.to_string()))?;
+            options.quality = Some(quality);
+        }
+        "lossless" => {
+            options.lossless = Some(match text {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(OutputError::InvalidLossless(text.to_string())),
+            });
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Validates `options` and negotiates the format against `accept`.
+///
+/// An explicit `format` wins over the `Accept` header. Wildcards express no
+/// preference, so the endpoint's default format is used.
+///
+/// # Errors
+///
+/// Returns an error for an out-of-range quality or when the `Accept` header
+/// rules out every format.
+pub fn negotiate(options: &OutputOptions, accept: Option<&str>) -> Result<Negotiated, OutputError> {
+    if let Some(quality) = options.quality.filter(|q| !(1..=100).contains(q)) {
+        return Err(OutputError::InvalidQuality(quality.to_string()));
+    }
+
+    let format = match (
+        options.format,
+        accept.map(str::trim).filter(|a| !a.is_empty()),
+    ) {
+        (Some(format), _) => Some(format),
+        (None, Some(accept)) => match parse_accept(accept) {
+            Preference::Format(format) => Some(format),
+            Preference::Any => None,
+            Preference::Nothing => return Err(OutputError::NotAcceptable(accept.to_string())),
+        },
+        (None, None) => None,
+    };
+
+    Ok(Negotiated {
+        format,
+        quality: options.quality,
+        lossless: options.lossless,
+    })
+}
+
+enum Preference {
+    Format(OutputFormat),
+    Any,
+    Nothing,
+}
+
+/// Picks the most preferred producible format from an `Accept` header.
+///
+/// Ties keep header order; a wildcard preferred over every concrete type
+/// means any format will do. Headers that name no image type at all (such as
+/// `application/json`) are treated as having no preference.
+fn parse_accept(accept: &str) -> Preference {
+    let mut best: Option<(OutputFormat, f32)> = None;
+    let mut wildcard: f32 = 0.0;
+    let mut names_image = false;
+
+    for range in accept.split(',') {
+        let mut parts = range.split(';');
+        let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        names_image |= media_type.starts_with("image/");
+        if q <= 0.0 {
+            continue;
+        }
+
+        let format = match media_type.as_str() {
+            "image/png" => OutputFormat::Png,
+            "image/jpeg" | "image/jpg" => OutputFormat::Jpeg,
+            "image/webp" => OutputFormat::Webp,
+            "image/avif" => OutputFormat::Avif,
+            "image/*" | "*/*" => {
+                wildcard = wildcard.max(q);
+                continue;
+            }
+            _ => continue,
+        };
+        if best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((format, q));
+        }
+    }
+
+    match best {
+        Some((format, q)) if q >= wildcard => Preference::Format(format),
+        _ if wildcard > 0.0 || !names_image => Preference::Any,
+        _ => Preference::Nothing,
+    }
+}
+
+/// Encodes `image` according to `rendition`.
+///
+/// JPEG has no alpha channel, so transparent images are flattened onto white.
+pub fn encode(image: &DynamicImage, rendition: &Rendition) -> ImageResult<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    match rendition.format {
+        OutputFormat::Png => image.write_to(&mut out, image::ImageFormat::Png)?,
+        OutputFormat::Jpeg => {
+            let quality = rendition.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            flatten(image).write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))?;
+        }
+        OutputFormat::Webp => return encode_webp(image, rendition),
+        OutputFormat::Avif => {
+            let quality = rendition.quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+            let encoder = AvifEncoder::new_with_speed_quality(&mut out, AVIF_SPEED, quality);
+            if image.color().has_alpha() {
+                image.to_rgba8().write_with_encoder(encoder)?;
+            } else {
+                image.to_rgb8().write_with_encoder(encoder)?;
+            }
+        }
+    }
+    Ok(out.into_inner())
+}
+
+fn encode_webp(image: &DynamicImage, rendition: &Rendition) -> ImageResult<Vec<u8>> {
+    let quality = f32::from(rendition.quality.unwrap_or(DEFAULT_WEBP_QUALITY));
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+            .encode_simple(rendition.lossless, quality)
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height())
+            .encode_simple(rendition.lossless, quality)
+    };
+    encoded.map(|webp| webp.to_vec()).map_err(|e| {
+        image::ImageError::Encoding(image::error::EncodingError::new(
+            image::error::ImageFormatHint::Exact(image::ImageFormat::WebP),
+            format!("{:?}", e),
+        ))
+    })
+}
+
+/// Composites a transparent image onto white.
+fn flatten(image: &DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend =
+            |c: u8| ((u16::from(c) * u16::from(a) + 255 * (255 - u16::from(a))) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
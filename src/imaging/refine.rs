@@ -0,0 +1,246 @@
+//! Alpha post-processing for `/removebg`.
+//!
+//! BiRefNet masks can leave semi-transparent halos and stray specks. The
+//! steps here are applied in the gateway, in a fixed order: threshold,
+//! island removal, erosion, dilation and feathering.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{GrayImage, RgbaImage};
+use std::collections::VecDeque;
+
+/// Largest accepted `erode`, `dilate` and `feather_radius`, in pixels.
+pub const MAX_RADIUS: u32 = 50;
+/// Islands smaller than this share of the largest one are removed.
+const ISLAND_RATIO: f64 = 0.01;
+
+/// An out-of-range refinement parameter.
+#[derive(Debug)]
+pub enum RefineError {
+    /// `mask_threshold` was not between 0 and 1.
+    InvalidThreshold(String),
+    /// A radius (`erode`, `dilate` or `feather_radius`) was out of range.
+    InvalidRadius(&'static str, String),
+}
+
+impl IntoResponse for RefineError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::InvalidThreshold(value) => {
+                format!("Mask threshold must be between 0 and 1, got '{}'", value)
+            }
+            Self::InvalidRadius(name, value) => format!(
+                "{} must be between 0 and {} pixels, got '{}'",
+                name, MAX_RADIUS, value
+            ),
+        };
+        (StatusCode::BAD_REQUEST, message).into_response()
+    }
+}
+
+/// Mask refinement parameters. The default changes nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Refinement {
+    /// Alpha at or above this fraction becomes opaque, the rest transparent.
+    pub threshold: Option<f32>,
+    /// Remove specks disconnected from the subject.
+    pub remove_islands: bool,
+    /// Shrink the mask by this many pixels.
+    pub erode: u32,
+    /// Grow the mask by this many pixels.
+    pub dilate: u32,
+    /// Soften the mask edge over about this many pixels.
+    pub feather: f32,
+}
+
+impl Refinement {
+    /// Returns `true` if applying the refinement would change nothing.
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks that every parameter is in range.
+    pub fn validate(&self) -> Result<(), RefineError> {
+        if let Some(threshold) = self.threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(RefineError::InvalidThreshold(threshold.to_string()));
+            }
+        }
+        for (name, radius) in [("erode", self.erode), ("dilate", self.dilate)] {
+            if radius > MAX_RADIUS {
+                return Err(RefineError::InvalidRadius(name, radius.to_string()));
+            }
+        }
+        if !(0.0..=MAX_RADIUS as f32).contains(&self.feather) {
+            return Err(RefineError::InvalidRadius(
+                "feather_radius",
+                self.feather.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies a multipart text field.
+    ///
+    /// Returns `Ok(false)` if `name` is not a refinement parameter.
+    pub fn parse_field(&mut self, name: &str, text: &str) -> Result<bool, RefineError> {
+        let radius = |name: &'static str| {
+            text.parse::<u32>()
+                .map_err(|_| RefineError::InvalidRadius(name, text.to_string()))
+        };
+        match name {
+            "mask_threshold" => {
+                let threshold = text
+                    .parse::<f32>()
+                    .map_err(|_| RefineError::InvalidThreshold(text.to_string()))?;
+                self.threshold = Some(threshold);
+            }
+            "feather_radius" => {
+                self.feather = text
+                    .parse::<f32>()
+                    .map_err(|_| RefineError::InvalidRadius("feather_radius", text.to_string()))?;
+            }
+            "erode" => self.erode = radius("erode")?,
+            "dilate" => self.dilate = radius("dilate")?,
+            "remove_islands" => self.remove_islands = text == "true" || text == "1",
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Refines the alpha channel of `cutout` in place.
+    pub fn apply(&self, cutout: &mut RgbaImage) {
+        let (width, height) = cutout.dimensions();
+        let mut alpha = GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([cutout.get_pixel(x, y)[3]])
+        });
+        self.apply_to_mask(&mut alpha);
+        for (pixel, value) in cutout.pixels_mut().zip(alpha.pixels()) {
+            pixel[3] = value[0];
+        }
+    }
+
+    /// Refines a grayscale mask in place.
+    fn apply_to_mask(&self, mask: &mut GrayImage) {
+        if let Some(threshold) = self.threshold {
+            let cutoff = (threshold * 255.0).round() as u8;
+            for pixel in mask.pixels_mut() {
+                pixel[0] = if pixel[0] >= cutoff.max(1) { 255 } else { 0 };
+            }
+        }
+        if self.remove_islands {
+            remove_islands(mask);
+        }
+        if self.erode > 0 {
+            morph(mask, self.erode, u8::min, u8::MAX);
+        }
+        if self.dilate > 0 {
+            morph(mask, self.dilate, u8::max, 0);
+        }
+        if self.feather > 0.0 {
+            *mask = image::imageops::fast_blur(mask, self.feather / 2.0);
+        }
+    }
+}
+
+/// Clears 8-connected regions of non-transparent pixels that are much
+/// smaller than the largest one.
+fn remove_islands(mask: &mut GrayImage) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let mut labels = vec![0u32; width * height];
+    let mut sizes = vec![0usize];
+    let mut queue = VecDeque::new();
+
+    for start in 0..labels.len() {
+        if labels[start] != 0 || mask.as_raw()[start] == 0 {
+            continue;
+        }
+        let label = sizes.len() as u32;
+        let mut size = 0;
+        labels[start] = label;
+        queue.push_back(start);
+        while let Some(index) = queue.pop_front() {
+            size += 1;
+            let (x, y) = (index % width, index / width);
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = ny * width + nx;
+                    if labels[neighbour] == 0 && mask.as_raw()[neighbour] != 0 {
+                        labels[neighbour] = label;
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+        sizes.push(size);
+    }
+
+    let largest = sizes.iter().copied().max().unwrap_or(0);
+    let keep: Vec<bool> = sizes
+        .iter()
+        .map(|&size| size as f64 >= largest as f64 * ISLAND_RATIO)
+        .collect();
+    for (value, label) in mask.iter_mut().zip(&labels) {
+        if *label != 0 && !keep[*label as usize] {
+            *value = 0;
+        }
+    }
+}
+
+/// Applies a square min (erosion) or max (dilation) filter of the given
+/// radius, as two separable passes. Pixels beyond the border count as `pad`.
+fn morph(mask: &mut GrayImage, radius: u32, pick: fn(u8, u8) -> u8, pad: u8) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let radius = radius as usize;
+    let mut line = Vec::new();
+    let mut out = Vec::new();
+
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice(&mask.as_raw()[y * width..(y + 1) * width]);
+        extremum(&line, radius, pick, pad, &mut out);
+        mask.as_mut()[y * width..(y + 1) * width].copy_from_slice(&out);
+    }
+    for x in 0..width {
+        line.clear();
+        line.extend((0..height).map(|y| mask.as_raw()[y * width + x]));
+        extremum(&line, radius, pick, pad, &mut out);
+        for (y, value) in out.iter().enumerate() {
+            mask.as_mut()[y * width + x] = *value;
+        }
+    }
+}
+
+/// Sliding-window extremum over `2 * radius + 1` values in linear time
+/// (van Herk/Gil-Werman): block-wise prefix and suffix extrema, combined at
+/// each window.
+fn extremum(line: &[u8], radius: usize, pick: fn(u8, u8) -> u8, pad: u8, out: &mut Vec<u8>) {
+    let window = 2 * radius + 1;
+    let len = (line.len() + 2 * radius).div_ceil(window) * window;
+    let padded: Vec<u8> = (0..len)
+        .map(|i| {
+            i.checked_sub(radius)
+                .and_then(|i| line.get(i))
+                .copied()
+                .unwrap_or(pad)
+        })
+        .collect();
+
+    let mut prefix = padded.clone();
+    let mut suffix = padded;
+    for i in 1..len {
+        if i % window != 0 {
+            prefix[i] = pick(prefix[i - 1], prefix[i]);
+        }
+    }
+    for i in (0..len - 1).rev() {
+        if i % window != window - 1 {
+            suffix[i] = pick(suffix[i + 1], suffix[i]);
+        }
+    }
+
+    out.clear();
+    out.extend((0..line.len()).map(|i| pick(suffix[i], prefix[i + window - 1])));
+}
@@ -0,0 +1,323 @@
+//! Output sizing for `/upscale`.
+//!
+//! The worker only upscales by whole factors, so the gateway asks it for the
+//! smallest sufficient factor and resamples the result to the exact size.
+
+use crate::models::Fit;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, imageops::FilterType};
+
+/// Largest upscale factor the worker supports.
+pub const MAX_SCALE: f64 = 6.0;
+
+/// Requested output size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeRequest {
+    /// Upscale factor.
+    pub scale: Option<f64>,
+    /// Target width in pixels.
+    pub width: Option<u32>,
+    /// Target height in pixels.
+    pub height: Option<u32>,
+    /// Fit mode when both dimensions are given.
+    pub fit: Option<Fit>,
+}
+
+/// Invalid size parameters.
+#[derive(Debug)]
+pub enum SizeError {
+    /// The scale is outside 1-6.
+    InvalidScale,
+    /// A target dimension is zero.
+    InvalidDimension,
+    /// `scale` was combined with `width` or `height`.
+    Conflicting,
+    /// `fit` was given without both `width` and `height`.
+    FitWithoutBothDimensions,
+    /// The target needs a larger factor than the worker supports.
+    TooLarge {
+        width: u32,
+        height: u32,
+        factor: f64,
+    },
+}
+
+impl IntoResponse for SizeError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidScale => {
+                (StatusCode::BAD_REQUEST, "Scale must be between 1 and 6").into_response()
+            }
+            Self::InvalidDimension => (
+                StatusCode::BAD_REQUEST,
+                "Width and height must be positive integers",
+            )
+                .into_response(),
+            Self::Conflicting => (
+                StatusCode::BAD_REQUEST,
+                "Use either scale or width/height, not both",
+            )
+                .into_response(),
+            Self::FitWithoutBothDimensions => (
+                StatusCode::BAD_REQUEST,
+                "Fit requires both width and height",
+            )
+                .into_response(),
+            Self::TooLarge {
+                width,
+                height,
+                factor,
+            } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Reaching the requested size from {}x{} needs a scale of {:.2}x, above the maximum of {}x",
+                    width, height, factor, MAX_SCALE
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// How an input is turned into the requested output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizePlan {
+    /// Whole factor the worker upscales by.
+    pub worker_scale: u32,
+    /// Dimensions the worker output is resampled to.
+    pub resize: (u32, u32),
+    /// Centered crop applied after resampling (`cover` only).
+    pub crop: Option<(u32, u32)>,
+}
+
+impl SizePlan {
+    /// Final output dimensions.
+    pub fn output(&self) -> (u32, u32) {
+        self.crop.unwrap_or(self.resize)
+    }
+
+    /// Returns `true` if the worker output is already the final size.
+    pub fn is_exact(&self, input: (u32, u32)) -> bool {
+        self.crop.is_none()
+            && self.resize == (input.0 * self.worker_scale, input.1 * self.worker_scale)
+    }
+
+    /// Resamples the worker output to the planned size.
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let (width, height) = self.resize;
+        let image = if (image.width(), image.height()) == (width, height) {
+            image
+        } else {
+            image.resize_exact(width, height, FilterType::Lanczos3)
+        };
+
+        match self.crop {
+            Some((crop_width, crop_height)) => image.crop_imm(
+                (width - crop_width) / 2,
+                (height - crop_height) / 2,
+                crop_width,
+                crop_height,
+            ),
+            None => image,
+        }
+    }
+}
+
+impl SizeRequest {
+    /// Validates the parameters on their own, before the input is known.
+    pub fn validate(&self) -> Result<(), SizeError> {
+        if self
+            .scale
+            .is_some_and(|scale| !(1.0..=MAX_SCALE).contains(&scale))
+        {
+            return Err(SizeError::InvalidScale);
+        }
+        if self.width == Some(0) || self.height == Some(0) {
+            return Err(SizeError::InvalidDimension);
+        }
+        if self.scale.is_some() && (self.width.is_some() || self.height.is_some()) {
+            return Err(SizeError::Conflicting);
+        }
+        if self.fit.is_some() && (self.width.is_none() || self.height.is_none()) {
+            return Err(SizeError::FitWithoutBothDimensions);
+        }
+        Ok(())
+    }
+
+    /// Plans the output for an input of `width`x`height`, using
+    /// `default_scale` when no size was requested.
+    pub fn plan(&self, width: u32, height: u32, default_scale: u32) -> Result<SizePlan, SizeError> {
+        self.validate()?;
+        let (w, h) = (f64::from(width), f64::from(height));
+        let scaled = |factor: f64| (round(w * factor), round(h * factor));
+
+        let (factor, resize, crop) = match (self.width, self.height) {
+            (None, None) => {
+                let factor = self.scale.unwrap_or(f64::from(default_scale));
+                (factor, scaled(factor), None)
+            }
+            (Some(target), None) => {
+                let factor = f64::from(target) / w;
+                (factor, (target, round(h * factor)), None)
+            }
+            (None, Some(target)) => {
+                let factor = f64::from(target) / h;
+                (factor, (round(w * factor), target), None)
+            }
+            (Some(target_w), Some(target_h)) => {
+                let (fx, fy) = (f64::from(target_w) / w, f64::from(target_h) / h);
+                match self.fit.unwrap_or_default() {
+                    Fit::Contain => {
+                        let factor = fx.min(fy);
+                        let (rw, rh) = scaled(factor);
+                        (factor, (rw.min(target_w), rh.min(target_h)), None)
+                    }
+                    Fit::Cover => {
+                        let factor = fx.max(fy);
+                        let (rw, rh) = scaled(factor);
+                        let resize = (rw.max(target_w), rh.max(target_h));
+                        (factor, resize, Some((target_w, target_h)))
+                    }
+                    Fit::Exact => (fx.max(fy), (target_w, target_h), None),
+                }
+            }
+        };
+
+        if factor > MAX_SCALE + f64::EPSILON {
+            return Err(SizeError::TooLarge {
+                width,
+                height,
+                factor,
+            });
+        }
+
+        Ok(SizePlan {
+            worker_scale: ((factor - 1e-9).ceil() as u32).clamp(1, MAX_SCALE as u32),
+            resize,
+            crop: crop.filter(|crop| *crop != resize),
+        })
+    }
+}
+
+fn round(value: f64) -> u32 {
+    (value.round() as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn plan_for(width: u32, height: u32, request: SizeRequest) -> Result<SizePlan, SizeError> {
+        request.plan(width, height, 4)
+    }
+
+    fn target(width: Option<u32>, height: Option<u32>, fit: Option<Fit>) -> SizeRequest {
+        SizeRequest {
+            width,
+            height,
+            fit,
+            ..SizeRequest::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_rounds_and_picks_the_smallest_whole_factor() {
+        let plan = plan_for(3, 2, target(Some(5), None, None)).unwrap();
+        assert_eq!(plan.resize, (5, 3));
+        assert_eq!(plan.worker_scale, 2);
+        assert!(!plan.is_exact((3, 2)));
+
+        // A factor just above a whole one needs the next worker scale.
+        let plan = plan_for(100, 100, target(Some(201), None, None)).unwrap();
+        assert_eq!(plan.worker_scale, 3);
+
+        // Downscaling still goes through the worker once, and no side
+        // rounds to zero.
+        let plan = plan_for(100, 1, target(Some(10), None, None)).unwrap();
+        assert_eq!((plan.worker_scale, plan.resize), (1, (10, 1)));
+
+        // Exact multiples need no resampling.
+        let plan = plan_for(100, 50, target(Some(200), None, None)).unwrap();
+        assert_eq!((plan.worker_scale, plan.resize), (2, (200, 100)));
+        assert!(plan.is_exact((100, 50)));
+        let plan = plan_for(100, 50, SizeRequest::default()).unwrap();
+        assert_eq!((plan.worker_scale, plan.resize), (4, (400, 200)));
+        assert!(plan.is_exact((100, 50)));
+    }
+
+    #[test]
+    fn test_plan_enforces_max_scale() {
+        let plan = plan_for(1, 1, target(Some(6), None, None)).unwrap();
+        assert_eq!((plan.worker_scale, plan.resize), (6, (6, 6)));
+        assert!(matches!(
+            plan_for(1, 1, target(Some(7), None, None)),
+            Err(SizeError::TooLarge { factor, .. }) if factor == 7.0
+        ));
+        assert!(plan_for(100, 100, target(None, Some(600), None)).is_ok());
+        assert!(plan_for(100, 100, target(None, Some(601), None)).is_err());
+
+        let scaled = |scale| {
+            plan_for(
+                10,
+                10,
+                SizeRequest {
+                    scale: Some(scale),
+                    ..SizeRequest::default()
+                },
+            )
+        };
+        assert_eq!(scaled(MAX_SCALE).unwrap().worker_scale, 6);
+        assert_eq!(scaled(1.5).unwrap().resize, (15, 15));
+        assert!(matches!(scaled(6.5), Err(SizeError::InvalidScale)));
+        assert!(matches!(scaled(0.5), Err(SizeError::InvalidScale)));
+    }
+
+    #[test]
+    fn test_plan_fit_modes() {
+        let (w, h) = (Some(300), Some(300));
+        let contain = plan_for(100, 50, target(w, h, Some(Fit::Contain))).unwrap();
+        assert_eq!((contain.worker_scale, contain.resize), (3, (300, 150)));
+        assert_eq!(contain.crop, None);
+        assert_eq!(contain.output(), (300, 150));
+
+        let cover = plan_for(100, 50, target(w, h, Some(Fit::Cover))).unwrap();
+        assert_eq!((cover.worker_scale, cover.resize), (6, (600, 300)));
+        assert_eq!(cover.output(), (300, 300));
+
+        let exact = plan_for(100, 50, target(w, h, Some(Fit::Exact))).unwrap();
+        assert_eq!((exact.worker_scale, exact.resize), (6, (300, 300)));
+        assert_eq!(exact.crop, None);
+
+        // A cover target with the input's aspect ratio needs no crop.
+        let cover = plan_for(100, 50, target(Some(200), Some(100), Some(Fit::Cover))).unwrap();
+        assert_eq!(cover.crop, None);
+        assert!(cover.is_exact((100, 50)));
+
+        assert!(matches!(
+            plan_for(100, 50, target(w, None, Some(Fit::Cover))),
+            Err(SizeError::FitWithoutBothDimensions)
+        ));
+        assert!(matches!(
+            plan_for(100, 50, target(Some(0), None, None)),
+            Err(SizeError::InvalidDimension)
+        ));
+    }
+
+    #[test]
+    fn test_apply_crops_around_the_center() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(4, 2, |x, _| Rgb([x as u8, 0, 0])));
+        let plan = SizePlan {
+            worker_scale: 1,
+            resize: (4, 2),
+            crop: Some((2, 2)),
+        };
+        let output = plan.apply(image).to_rgb8();
+        assert_eq!(output.dimensions(), (2, 2));
+        assert_eq!(output.get_pixel(0, 0)[0], 1);
+        assert_eq!(output.get_pixel(1, 1)[0], 2);
+    }
+}
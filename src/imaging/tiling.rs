@@ -0,0 +1,158 @@
+//! Tiled upscaling of large inputs.
+//!
+//! Large images are split into overlapping tiles that are upscaled one by
+//! one, then stitched back together. Each tile fades in over the overlap with
+//! the tiles above and to the left of it, hiding the seams.
+
+use image::RgbImage;
+
+/// A region of the input image, in input pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    /// Left edge.
+    pub x: u32,
+    /// Top edge.
+    pub y: u32,
+    /// Width.
+    pub width: u32,
+    /// Height.
+    pub height: u32,
+}
+
+/// Splits a `width`x`height` image into tiles of at most `tile_size` pixels
+/// per side that overlap their neighbours by at least `overlap` pixels.
+///
+/// Tiles are returned in raster order, as [`Stitcher::add`] expects them.
+pub fn split(width: u32, height: u32, tile_size: u32, overlap: u32) -> Vec<Tile> {
+    let columns = offsets(width, tile_size, overlap);
+    let rows = offsets(height, tile_size, overlap);
+    rows.iter()
+        .flat_map(|&y| {
+            columns.iter().map(move |&x| Tile {
+                x,
+                y,
+                width: tile_size.min(width),
+                height: tile_size.min(height),
+            })
+        })
+        .collect()
+}
+
+/// Start offsets along one axis; the last tile is aligned with the far edge.
+fn offsets(length: u32, tile_size: u32, overlap: u32) -> Vec<u32> {
+    if length <= tile_size {
+        return vec![0];
+    }
+    let step = tile_size - overlap;
+    let mut offsets: Vec<u32> = (0..length - tile_size).step_by(step as usize).collect();
+    offsets.push(length - tile_size);
+    offsets
+}
+
+/// Assembles upscaled tiles into the full output.
+pub struct Stitcher {
+    output: RgbImage,
+    scale: u32,
+    feather: f32,
+}
+
+impl Stitcher {
+    /// Creates a stitcher for an input of `width`x`height` upscaled by
+    /// `scale`, blending over `overlap` input pixels.
+    pub fn new(width: u32, height: u32, scale: u32, overlap: u32) -> Self {
+        Self {
+            output: RgbImage::new(width * scale, height * scale),
+            scale,
+            feather: (overlap * scale).max(1) as f32,
+        }
+    }
+
+    /// Adds the upscaled version of `tile`.
+    ///
+    /// Tiles must be added in the order returned by [`split`].
+    pub fn add(&mut self, tile: &Tile, upscaled: &RgbImage) {
+        let (left, top) = (tile.x * self.scale, tile.y * self.scale);
+        let ramp = |offset: u32, interior: bool| {
+            if interior {
+                ((offset as f32 + 0.5) / self.feather).min(1.0)
+            } else {
+                1.0
+            }
+        };
+
+        for (x, y, pixel) in upscaled.enumerate_pixels() {
+            let (ox, oy) = (left + x, top + y);
+            if ox >= self.output.width() || oy >= self.output.height() {
+                continue;
+            }
+            let weight = ramp(x, tile.x > 0) * ramp(y, tile.y > 0);
+            let target = self.output.get_pixel_mut(ox, oy);
+            for (out, value) in target.0.iter_mut().zip(pixel.0) {
+                let blended = f32::from(*out) * (1.0 - weight) + f32::from(value) * weight;
+                *out = blended.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    /// Returns the stitched image.
+    pub fn finish(self) -> RgbImage {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_offsets_cover_the_axis_with_the_overlap() {
+        assert_eq!(offsets(10, 4, 1), vec![0, 3, 6]);
+        assert_eq!(offsets(7, 4, 1), vec![0, 3]);
+        // Tiles that fit exactly are not repeated at the far edge.
+        assert_eq!(offsets(8, 4, 0), vec![0, 4]);
+        assert_eq!(offsets(4, 4, 1), vec![0]);
+        // A tile larger than the image covers it alone.
+        assert_eq!(offsets(10, 16, 2), vec![0]);
+        assert_eq!(offsets(1, 1, 0), vec![0]);
+    }
+
+    #[test]
+    fn test_split_clamps_tiles_to_the_image() {
+        assert_eq!(
+            split(10, 5, 16, 2),
+            vec![Tile {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 5,
+            }]
+        );
+
+        let tiles = split(10, 7, 4, 1);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!((tiles[1].x, tiles[1].y), (3, 0));
+        assert_eq!((tiles[3].x, tiles[3].y), (0, 3));
+        assert!(tiles.iter().all(|tile| tile.width == 4 && tile.height == 4));
+    }
+
+    #[test]
+    fn test_stitcher_feathers_over_the_overlap() {
+        let tiles = split(6, 1, 4, 2);
+        assert_eq!(tiles.len(), 2);
+        let mut stitcher = Stitcher::new(6, 1, 1, 2);
+        stitcher.add(&tiles[0], &RgbImage::from_pixel(4, 1, Rgb([0, 0, 0])));
+        stitcher.add(&tiles[1], &RgbImage::from_pixel(4, 1, Rgb([200, 200, 200])));
+        let output = stitcher.finish();
+        let row: Vec<u8> = (0..6).map(|x| output.get_pixel(x, 0)[0]).collect();
+        assert_eq!(row, vec![0, 0, 50, 150, 200, 200]);
+
+        // Upscaled tiles land at scaled offsets; a single pixel is copied.
+        let mut stitcher = Stitcher::new(1, 1, 2, 0);
+        let tile = split(1, 1, 4, 0)[0];
+        stitcher.add(&tile, &RgbImage::from_pixel(2, 2, Rgb([9, 8, 7])));
+        let output = stitcher.finish();
+        assert_eq!(output.dimensions(), (2, 2));
+        assert!(output.pixels().all(|pixel| pixel.0 == [9, 8, 7]));
+    }
+}
@@ -0,0 +1,268 @@
+//! Local geometry steps of `/pipeline`.
+//!
+//! These run in the gateway between worker steps: resizing, cropping to a
+//! rectangle or to the subject, and padding onto a larger canvas.
+
+use super::crop::{self, CropError, CropSpec, MAX_ASPECT};
+use super::cutout::{Color, ColorError};
+use super::resize::{SizeError, SizeRequest};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use image::{DynamicImage, RgbaImage};
+
+/// An invalid or inapplicable geometry step.
+#[derive(Debug)]
+pub enum TransformError {
+    /// A step was given without any of its required parameters.
+    Incomplete(&'static str),
+    /// `resize` had invalid dimensions or needs too large a factor.
+    Size(SizeError),
+    /// `crop` had a zero width or height.
+    EmptyCrop,
+    /// The `crop` rectangle does not fit the image.
+    OutOfBounds {
+        /// Requested rectangle, as `(x, y, width, height)`.
+        rect: (u32, u32, u32, u32),
+        /// Dimensions of the image.
+        image: (u32, u32),
+    },
+    /// `trim` had an invalid padding, or the image has no subject.
+    Trim(CropError),
+    /// `pad` had an invalid aspect ratio.
+    InvalidAspect(String),
+    /// `pad` had an invalid color.
+    Color(ColorError),
+}
+
+impl IntoResponse for TransformError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Incomplete(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+            Self::Size(e) => e.into_response(),
+            Self::EmptyCrop => (
+                StatusCode::BAD_REQUEST,
+                "Crop width and height must be positive integers",
+            )
+                .into_response(),
+            Self::OutOfBounds {
+                rect: (x, y, width, height),
+                image: (image_width, image_height),
+            } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Crop of {}x{} at ({}, {}) does not fit the {}x{} image",
+                    width, height, x, y, image_width, image_height
+                ),
+            )
+                .into_response(),
+            Self::Trim(e) => e.into_response(),
+            Self::InvalidAspect(value) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Pad aspect must be a ratio such as 1:1, between 1:{} and {}:1, got '{}'",
+                    MAX_ASPECT, MAX_ASPECT, value
+                ),
+            )
+                .into_response(),
+            Self::Color(e) => e.into_response(),
+        }
+    }
+}
+
+/// A geometry step applied in the gateway.
+#[derive(Clone, Copy, Debug)]
+pub enum Transform {
+    /// Resample to a width, a height or both, with a fit mode.
+    Resize(SizeRequest),
+    /// Cut out a rectangle.
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Crop to the bounding box of the subject.
+    Trim(CropSpec),
+    /// Center on a larger canvas filled with `color`.
+    Pad {
+        /// Width-to-height ratio the canvas is widened or heightened to.
+        aspect: Option<f64>,
+        /// Margin added on every side before the aspect is applied.
+        padding: u32,
+        color: Color,
+    },
+}
+
+impl Transform {
+    /// Builds a `resize` step.
+    pub fn resize(size: SizeRequest) -> Result<Self, TransformError> {
+        if size.width.is_none() && size.height.is_none() {
+            return Err(TransformError::Incomplete(
+                "The resize step requires a width or a height",
+            ));
+        }
+        size.validate().map_err(TransformError::Size)?;
+        Ok(Self::Resize(size))
+    }
+
+    /// Builds a `crop` step.
+    pub fn crop(x: u32, y: u32, width: u32, height: u32) -> Result<Self, TransformError> {
+        if width == 0 || height == 0 {
+            return Err(TransformError::EmptyCrop);
+        }
+        Ok(Self::Crop {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Builds a `trim` step; `padding` is bounded by `max_width` and
+    /// `max_height`, the input limits.
+    pub fn trim(
+        padding: Option<&str>,
+        max_width: u32,
+        max_height: u32,
+    ) -> Result<Self, TransformError> {
+        crop::spec(padding, None, None, max_width, max_height)
+            .map(Self::Trim)
+            .map_err(TransformError::Trim)
+    }
+
+    /// Builds a `pad` step. The canvas is transparent unless a `color` is
+    /// given.
+    pub fn pad(
+        aspect: Option<&str>,
+        padding: Option<u32>,
+        color: Option<&str>,
+    ) -> Result<Self, TransformError> {
+        if aspect.is_none() && padding.is_none() {
+            return Err(TransformError::Incomplete(
+                "The pad step requires an aspect or a padding",
+            ));
+        }
+        let aspect = aspect
+            .map(|aspect| {
+                crop::parse_ratio(aspect)
+                    .ok_or_else(|| TransformError::InvalidAspect(aspect.to_string()))
+            })
+            .transpose()?;
+        let color = color
+            .map(str::parse::<Color>)
+            .transpose()
+            .map_err(TransformError::Color)?
+            .unwrap_or(Color([0, 0, 0, 0]));
+        Ok(Self::Pad {
+            aspect,
+            padding: padding.unwrap_or(0),
+            color,
+        })
+    }
+
+    /// Name of the step, as given in the request.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Resize(_) => "resize",
+            Self::Crop { .. } => "crop",
+            Self::Trim(_) => "trim",
+            Self::Pad { .. } => "pad",
+        }
+    }
+
+    /// Dimensions of the result for a `width`x`height` input, or `None` if
+    /// they depend on the pixels (`trim`).
+    pub fn output_size(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<(u32, u32)>, TransformError> {
+        match *self {
+            Self::Resize(size) => size
+                .plan(width, height, 1)
+                .map(|plan| Some(plan.output()))
+                .map_err(TransformError::Size),
+            Self::Crop {
+                x,
+                y,
+                width: crop_width,
+                height: crop_height,
+            } => {
+                if u64::from(x) + u64::from(crop_width) > u64::from(width)
+                    || u64::from(y) + u64::from(crop_height) > u64::from(height)
+                {
+                    return Err(TransformError::OutOfBounds {
+                        rect: (x, y, crop_width, crop_height),
+                        image: (width, height),
+                    });
+                }
+                Ok(Some((crop_width, crop_height)))
+            }
+            Self::Trim(_) => Ok(None),
+            Self::Pad {
+                aspect, padding, ..
+            } => Ok(Some(padded_size(width, height, padding, aspect))),
+        }
+    }
+
+    /// Applies the step to `image`.
+    pub fn apply(&self, image: DynamicImage) -> Result<DynamicImage, TransformError> {
+        match *self {
+            Self::Resize(size) => {
+                let plan = size
+                    .plan(image.width(), image.height(), 1)
+                    .map_err(TransformError::Size)?;
+                Ok(plan.apply(image))
+            }
+            Self::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                self.output_size(image.width(), image.height())?;
+                Ok(image.crop_imm(x, y, width, height))
+            }
+            Self::Trim(spec) => crop::crop(&image.to_rgba8(), &spec)
+                .map(|(trimmed, _)| DynamicImage::ImageRgba8(trimmed))
+                .map_err(TransformError::Trim),
+            Self::Pad {
+                aspect,
+                padding,
+                color,
+            } => {
+                let (width, height) = padded_size(image.width(), image.height(), padding, aspect);
+                let mut canvas = RgbaImage::from_pixel(width, height, image::Rgba(color.0));
+                image::imageops::overlay(
+                    &mut canvas,
+                    &image.to_rgba8(),
+                    i64::from((width - image.width()) / 2),
+                    i64::from((height - image.height()) / 2),
+                );
+                Ok(if color.0[3] == u8::MAX {
+                    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).into_rgb8())
+                } else {
+                    DynamicImage::ImageRgba8(canvas)
+                })
+            }
+        }
+    }
+}
+
+/// Size of a `width`x`height` image after adding `padding` on every side and
+/// widening or heightening it to `aspect`.
+fn padded_size(width: u32, height: u32, padding: u32, aspect: Option<f64>) -> (u32, u32) {
+    let width = f64::from(width) + 2.0 * f64::from(padding);
+    let height = f64::from(height) + 2.0 * f64::from(padding);
+    let (width, height) = match aspect {
+        Some(ratio) if width / height < ratio => ((height * ratio).round(), height),
+        Some(ratio) => (width, (width / ratio).round()),
+        None => (width, height),
+    };
+    (
+        width.min(f64::from(u32::MAX)) as u32,
+        height.min(f64::from(u32::MAX)) as u32,
+    )
+}
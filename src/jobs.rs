@@ -0,0 +1,403 @@
+//! # Jobs
+//!
+//! In-memory store of the requests accepted with a `callback_url`. A job
+//! keeps the outcome of its request and the webhook delivery attempts made
+//! for it; finished jobs are dropped after `JOB_TTL_SECS`, or earlier when
+//! the store is full.
+
+use crate::config::Config;
+use crate::models::{DeliveryAttempt, JobInfo, JobStatus, StoredResult};
+use crate::state::TenantSlots;
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// A request processed in the background.
+#[derive(Clone, Debug)]
+pub struct Job {
+    /// Job identifier.
+    pub id: String,
+    /// Endpoint that processes the job (e.g., "/removebg").
+    pub endpoint: String,
+    /// Tenant of the client that submitted the job.
+    pub tenant: Option<String>,
+    /// Where webhook events are delivered.
+    pub callback_url: String,
+    /// Current state.
+    pub status: JobStatus,
+    /// Unix timestamp (seconds) at which the job was accepted.
+    pub created_at: u64,
+    /// Unix timestamp (seconds) at which processing finished.
+    pub completed_at: Option<u64>,
+    /// Processing time, in milliseconds.
+    pub duration_ms: Option<u64>,
+    /// HTTP status the request completed with.
+    pub http_status: Option<u16>,
+    /// MIME type of `body`.
+    pub content_type: Option<String>,
+    /// Response body: the result, or the error message.
+    ///
+    /// Empty when the result was persisted to the result storage.
+    pub body: Bytes,
+    /// The result, when persisted to the result storage.
+    pub stored: Option<StoredResult>,
+    /// Webhook delivery attempts, oldest first.
+    pub deliveries: Vec<DeliveryAttempt>,
+    /// When processing finished, for expiry.
+    finished: Option<Instant>,
+    /// When processing started.
+    started: Instant,
+}
+
+impl Job {
+    /// Creates a pending job.
+    pub fn new(id: String, endpoint: String, tenant: Option<String>, callback_url: String) -> Self {
+        Self {
+            id,
+            endpoint,
+            tenant,
+            callback_url,
+            status: JobStatus::Pending,
+            created_at: unix_now(),
+            completed_at: None,
+            duration_ms: None,
+            http_status: None,
+            content_type: None,
+            body: Bytes::new(),
+            stored: None,
+            deliveries: Vec::new(),
+            finished: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Path the result can be downloaded from.
+    pub fn result_url(&self) -> String {
+        match &self.stored {
+            Some(stored) => stored.url.clone(),
+            None => format!("/jobs/{}/result", self.id),
+        }
+    }
+
+    /// Size of the result, in bytes.
+    pub fn result_size(&self) -> u64 {
+        self.stored
+            .as_ref()
+            .map_or(self.body.len() as u64, |stored| stored.size)
+    }
+
+    /// Error message of a failed job.
+    pub fn error(&self) -> Option<String> {
+        (self.status == JobStatus::Failed).then(|| String::from_utf8_lossy(&self.body).into_owned())
+    }
+
+    /// Describes the job for `GET /jobs/{id}`.
+    pub fn info(&self) -> JobInfo {
+        JobInfo {
+            id: self.id.clone(),
+            endpoint: self.endpoint.clone(),
+            status: self.status,
+            created_at: self.created_at,
+            completed_at: self.completed_at,
+            duration_ms: self.duration_ms,
+            http_status: self.http_status,
+            result_url: (self.status == JobStatus::Succeeded).then(|| self.result_url()),
+            error: self.error(),
+            deliveries: self.deliveries.clone(),
+        }
+    }
+}
+
+/// Why a job was not accepted.
+#[derive(Debug)]
+pub enum JobRejection {
+    /// `JOB_MAX_RUNNING` jobs are already running.
+    Busy,
+    /// The client's tenant already runs `JOB_TENANT_MAX_RUNNING` jobs.
+    TenantBusy,
+    /// The store holds `JOB_MAX_STORED` jobs that cannot be dropped yet.
+    Full,
+    /// The server is shutting down.
+    Draining,
+}
+
+impl IntoResponse for JobRejection {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::Busy => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many background jobs are running",
+            ),
+            Self::TenantBusy => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many background jobs are running for this client",
+            ),
+            Self::Full => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many background jobs are pending",
+            ),
+            Self::Draining => (StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down"),
+        };
+        (status, [(header::RETRY_AFTER, "1")], message).into_response()
+    }
+}
+
+/// Permission to run an accepted job, held until its task ends.
+///
+/// Webhook deliveries run after the task and do not hold it.
+pub struct Admission {
+    id: String,
+    _running: OwnedSemaphorePermit,
+    _tenant_running: OwnedSemaphorePermit,
+}
+
+/// All jobs known to this instance.
+///
+/// Running jobs are limited globally and per tenant, and the store keeps at
+/// most `JOB_MAX_STORED` jobs and `JOB_MAX_STORED_BYTES` of results,
+/// dropping the oldest finished jobs first.
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    ttl: Duration,
+    max_stored: usize,
+    max_stored_bytes: usize,
+    running: Arc<Semaphore>,
+    max_running: usize,
+    tenant_running: TenantSlots,
+    tasks: TaskTracker,
+    cancel: CancellationToken,
+}
+
+impl JobStore {
+    /// Creates the store with the limits from the configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(config.job_ttl_secs),
+            max_stored: config.job_max_stored.max(1),
+            max_stored_bytes: config.job_max_stored_bytes,
+            running: Arc::new(Semaphore::new(config.job_max_running.max(1))),
+            max_running: config.job_max_running.max(1),
+            tenant_running: TenantSlots::new(config.job_tenant_max_running, HashMap::new()),
+            tasks: TaskTracker::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Adds a pending job if the limits allow it.
+    pub fn admit(&self, job: Job) -> Result<Admission, JobRejection> {
+        if self.tasks.is_closed() {
+            return Err(JobRejection::Draining);
+        }
+        let running = self
+            .running
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| JobRejection::Busy)?;
+        let tenant_running = self
+            .tenant_running
+            .get(job.tenant.as_deref().unwrap_or_default())
+            .try_acquire_owned()
+            .map_err(|_| JobRejection::TenantBusy)?;
+
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let ttl = self.ttl;
+        jobs.retain(|_, job| job.finished.is_none_or(|at| at.elapsed() < ttl));
+        self.evict(&mut jobs, None, 1);
+        if jobs.len() >= self.max_stored {
+            return Err(JobRejection::Full);
+        }
+        let id = job.id.clone();
+        jobs.insert(id.clone(), job);
+        Ok(Admission {
+            id,
+            _running: running,
+            _tenant_running: tenant_running,
+        })
+    }
+
+    /// Runs `task` for an admitted job.
+    ///
+    /// The task is cancelled if shutdown outlasts `JOB_DRAIN_TIMEOUT_SECS`.
+    /// A job whose task ends without an outcome, because it was cancelled
+    /// or panicked, is marked as failed.
+    pub fn spawn<F>(self: &Arc<Self>, admission: Admission, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let guard = PendingGuard {
+            store: self.clone(),
+            admission,
+        };
+        let cancel = self.cancel.clone();
+        self.tasks.spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => {}
+                _ = task => {}
+            }
+            drop(guard);
+        });
+    }
+
+    /// Runs the webhook delivery of a finished job.
+    ///
+    /// Deliveries do not count against the running limits, but shutdown
+    /// waits for them like for jobs. `task` is given a token cancelled once
+    /// the drain times out, after which it should make one last attempt
+    /// instead of waiting to retry.
+    pub fn spawn_delivery<F>(&self, task: impl FnOnce(CancellationToken) -> F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task(self.cancel.clone()));
+    }
+
+    /// Stops accepting jobs and waits for the running ones and their
+    /// deliveries, cancelling them once `timeout` has passed.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.tasks.close();
+        if tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "Cancelling {} background jobs and deliveries still running",
+                self.tasks.len()
+            );
+            self.cancel.cancel();
+            self.tasks.wait().await;
+        }
+    }
+
+    /// Returns a copy of the job `id`.
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.get(id)
+            .filter(|job| job.finished.is_none_or(|at| at.elapsed() < self.ttl))
+            .cloned()
+    }
+
+    /// Number of jobs currently running.
+    pub fn running(&self) -> usize {
+        self.max_running - self.running.available_permits()
+    }
+
+    /// Number of jobs kept, pending or finished.
+    pub fn stored(&self) -> usize {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Records the outcome of the job `id`.
+    pub fn finish(
+        &self,
+        id: &str,
+        http_status: u16,
+        content_type: Option<String>,
+        body: Bytes,
+        stored: Option<StoredResult>,
+    ) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(job) = jobs.get_mut(id) {
+            job.status = if (200..300).contains(&http_status) {
+                JobStatus::Succeeded
+            } else {
+                JobStatus::Failed
+            };
+            job.completed_at = Some(unix_now());
+            job.duration_ms = Some(job.started.elapsed().as_millis() as u64);
+            job.http_status = Some(http_status);
+            job.content_type = content_type;
+            job.body = body;
+            job.stored = stored;
+            job.finished = Some(Instant::now());
+        }
+        self.evict(&mut jobs, Some(id), 0);
+    }
+
+    /// Appends a delivery attempt to the job `id`, numbering it.
+    pub fn record(&self, id: &str, mut attempt: DeliveryAttempt) -> DeliveryAttempt {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(job) = jobs.get_mut(id) {
+            attempt.attempt = job.deliveries.len() as u32 + 1;
+            job.deliveries.push(attempt.clone());
+        }
+        attempt
+    }
+
+    /// Drops the oldest finished jobs other than `keep` until `room` more
+    /// jobs fit and the results are within `JOB_MAX_STORED_BYTES`.
+    fn evict(&self, jobs: &mut HashMap<String, Job>, keep: Option<&str>, room: usize) {
+        let mut bytes: usize = jobs.values().map(|job| job.body.len()).sum();
+        if jobs.len() + room <= self.max_stored && bytes <= self.max_stored_bytes {
+            return;
+        }
+        let mut finished: Vec<(Instant, String)> = jobs
+            .values()
+            .filter(|job| Some(job.id.as_str()) != keep)
+            .filter_map(|job| Some((job.finished?, job.id.clone())))
+            .collect();
+        finished.sort();
+        for (_, id) in finished {
+            if jobs.len() + room <= self.max_stored && bytes <= self.max_stored_bytes {
+                break;
+            }
+            if let Some(job) = jobs.remove(&id) {
+                bytes -= job.body.len();
+            }
+        }
+    }
+}
+
+/// Marks a job whose task ended without an outcome as failed.
+struct PendingGuard {
+    store: Arc<JobStore>,
+    admission: Admission,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let id = &self.admission.id;
+        let pending = self
+            .store
+            .get(id)
+            .is_some_and(|job| job.status == JobStatus::Pending);
+        if pending {
+            self.store.finish(
+                id,
+                StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+                Some("text/plain; charset=utf-8".to_string()),
+                Bytes::from_static(b"Job was cancelled"),
+                None,
+            );
+        }
+    }
+}
+
+/// Returns a random 128-bit identifier, hex encoded.
+pub(crate) fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    aws_lc_rs::rand::fill(&mut bytes).expect("system random number generator failed");
+    hex(&bytes)
+}
+
+/// Lowercase hex encoding of `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Current Unix time, in seconds.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
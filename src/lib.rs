@@ -6,7 +6,18 @@
 
 pub mod config;
 pub mod handlers;
+pub mod health;
+pub mod imaging;
+pub mod jobs;
+pub mod metrics;
 pub mod models;
+pub mod presets;
 pub mod routes;
+pub mod server;
+pub mod state;
+pub mod storage;
+pub mod tls;
+pub mod upload;
+pub mod webhooks;
 
-pub use routes::create_router;
+pub use routes::{create_admin_router, create_router, create_router_with_state};
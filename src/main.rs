@@ -1,12 +1,12 @@
-use nijika_api::{config::Config, create_router};
-use std::net::SocketAddr;
+use nijika_api::{config::Config, server, state::AppState};
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 /// Application entry point.
 ///
 /// Initializes the environment, sets up tracing, creates the router,
-/// and starts the Axum server.
+/// and starts the configured listeners.
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
@@ -16,18 +16,47 @@ async fn main() {
         .init();
 
     let config = Arc::new(Config::from_env());
-    let app = create_router(config.clone());
+    let state = AppState::new(config);
 
-    let addr_str = format!("{}:{}", config.host, config.port);
-    let addr: SocketAddr = addr_str.parse().expect("Invalid HOST or PORT config");
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_signal(state.clone(), shutdown.clone()));
 
-    tracing::info!("Listening on {}", addr);
+    server::run(state, shutdown)
+        .await
+        .expect("Failed to start server");
+}
+
+/// Cancels `shutdown` once the process receives Ctrl+C or SIGTERM.
+///
+/// The server is first marked as draining so `/readyz` fails, then kept
+/// serving for `SHUTDOWN_GRACE_PERIOD_SECS` to let load balancers react
+/// before in-flight requests are finished and the listeners close.
+async fn shutdown_signal(state: AppState, shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 
-    let listener = TcpListener::bind(addr).await.unwrap();
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .unwrap();
+    state.begin_drain();
+    let grace = Duration::from_secs(state.config.shutdown_grace_period_secs);
+    tracing::info!("Shutdown requested, draining for {:?}", grace);
+    tokio::time::sleep(grace).await;
+    shutdown.cancel();
 }
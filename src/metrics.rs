@@ -0,0 +1,123 @@
+//! # Metrics
+//!
+//! Request counters and the Prometheus text exposition served on
+//! `GET /metrics`.
+
+use crate::models::DependencyStatus;
+use crate::state::AppState;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the requests served by the public router.
+#[derive(Default)]
+pub struct Metrics {
+    /// Requests currently being handled.
+    in_flight: AtomicU64,
+    /// Finished requests, indexed by status class (`1xx` to `5xx`).
+    responses: [AtomicU64; 5],
+}
+
+impl Metrics {
+    /// Number of finished requests whose status is in `class` (1 to 5).
+    pub fn responses(&self, class: usize) -> u64 {
+        self.responses[class - 1].load(Ordering::Relaxed)
+    }
+}
+
+/// Middleware counting the requests handled by the rest of the stack.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let metrics = &state.metrics;
+    metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+    let response = next.run(request).await;
+    metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    let class = (response.status().as_u16() / 100).clamp(1, 5) as usize;
+    metrics.responses[class - 1].fetch_add(1, Ordering::Relaxed);
+    response
+}
+
+/// Renders the metrics in the Prometheus text format.
+///
+/// Worker status comes from the cached health probes only, so scraping
+/// never reaches the workers.
+pub async fn render(state: &AppState) -> String {
+    let mut out = String::new();
+    let metrics = &state.metrics;
+
+    gauge(
+        &mut out,
+        "nijika_uptime_seconds",
+        "Seconds since the gateway started.",
+        state.uptime().as_secs(),
+    );
+    gauge(
+        &mut out,
+        "nijika_draining",
+        "1 once shutdown has begun.",
+        state.is_draining() as u64,
+    );
+    gauge(
+        &mut out,
+        "nijika_http_requests_in_flight",
+        "Requests currently being handled.",
+        metrics.in_flight.load(Ordering::Relaxed),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP nijika_http_responses_total Responses sent, by status class."
+    );
+    let _ = writeln!(out, "# TYPE nijika_http_responses_total counter");
+    for class in 1..=5 {
+        let _ = writeln!(
+            out,
+            "nijika_http_responses_total{{class=\"{class}xx\"}} {}",
+            metrics.responses(class)
+        );
+    }
+
+    gauge(
+        &mut out,
+        "nijika_jobs_running",
+        "Background jobs currently running.",
+        state.jobs.running() as u64,
+    );
+    gauge(
+        &mut out,
+        "nijika_jobs_stored",
+        "Background jobs kept by the job store.",
+        state.jobs.stored() as u64,
+    );
+    let tiles = state.config.upscale_tile_concurrency.max(1);
+    gauge(
+        &mut out,
+        "nijika_upscale_tiles_in_flight",
+        "Upscale tiles currently sent to the worker.",
+        tiles.saturating_sub(state.upscale_tiles.available_permits()) as u64,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP nijika_dependency_up 1 if the last health probe of the worker succeeded."
+    );
+    let _ = writeln!(out, "# TYPE nijika_dependency_up gauge");
+    for dep in state.health.last_known().await {
+        let _ = writeln!(
+            out,
+            "nijika_dependency_up{{dependency=\"{}\"}} {}",
+            dep.name,
+            (dep.status == DependencyStatus::Up) as u8
+        );
+    }
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
@@ -1,14 +1,171 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 /// Request payload for background removal via URL.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RemoveBgRequest {
     /// URL of the image to process.
     pub url: String,
+    /// What to return; the cut-out when omitted.
+    #[serde(rename = "output")]
+    pub mode: Option<RemoveBgOutput>,
+    /// Background color for the `matte` output (hex, e.g. `#ffffff`).
+    pub matte_color: Option<String>,
+    /// Color to composite the cut-out onto (hex, e.g. `#ff8800`).
+    pub background_color: Option<String>,
+    /// URL of an image to composite the cut-out onto.
+    pub background_image: Option<String>,
+    /// How `background_image` is fitted to the cut-out; `cover` when omitted.
+    pub background_fit: Option<Fit>,
+    /// Blur radius, in pixels, for keeping a blurred original background.
+    pub background_blur: Option<f32>,
+    /// Alpha at or above this fraction (0-1) becomes opaque, the rest
+    /// transparent.
+    pub mask_threshold: Option<f32>,
+    /// Remove specks disconnected from the subject.
+    pub remove_islands: Option<bool>,
+    /// Shrink the mask by this many pixels.
+    pub erode: Option<u32>,
+    /// Grow the mask by this many pixels.
+    pub dilate: Option<u32>,
+    /// Soften the mask edge over about this many pixels.
+    pub feather_radius: Option<f32>,
+    /// Crop the result to the subject.
+    pub crop: Option<Crop>,
+    /// Margin around the cropped subject.
+    pub crop_padding: Option<CropPadding>,
+    /// Aspect ratio to pad the cropped subject to, e.g. `4:3`.
+    pub crop_aspect: Option<String>,
+    /// Canvas size to fit the cropped subject into, e.g. `1000x1000`.
+    pub crop_size: Option<String>,
+    /// Width of a sticker outline, in pixels.
+    pub outline_width: Option<f32>,
+    /// Color of the sticker outline (hex); white when omitted.
+    pub outline_color: Option<String>,
+    /// Horizontal drop shadow offset, in pixels.
+    pub shadow_offset_x: Option<i32>,
+    /// Vertical drop shadow offset, in pixels.
+    pub shadow_offset_y: Option<i32>,
+    /// Drop shadow blur radius, in pixels.
+    pub shadow_blur: Option<f32>,
+    /// Drop shadow opacity, from 0 to 1.
+    pub shadow_opacity: Option<f32>,
+    /// Encoding of the result.
+    #[serde(flatten)]
+    pub output: OutputOptions,
 }
 
+/// Result returned by `/removebg`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoveBgOutput {
+    /// The subject on a transparent background.
+    #[default]
+    Cutout,
+    /// The grayscale segmentation mask.
+    Mask,
+    /// The subject composited over a solid color.
+    Matte,
+    /// The cut-out and the mask together.
+    Both,
+}
+
+/// Cropping applied to the `/removebg` result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Crop {
+    /// Crop to the bounding box of the subject.
+    Subject,
+}
+
+/// Margin around a cropped subject: a number of pixels, or a string such as
+/// `"20px"` or `"10%"`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CropPadding {
+    /// Pixels.
+    Pixels(u32),
+    /// Pixels or a percentage, as text.
+    Text(String),
+}
+
+impl CropPadding {
+    /// Returns the padding as text.
+    pub fn into_text(self) -> String {
+        match self {
+            Self::Pixels(pixels) => pixels.to_string(),
+            Self::Text(text) => text,
+        }
+    }
+}
+
+/// Image formats the gateway can return.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Lossless PNG.
+    Png,
+    /// JPEG; transparency is flattened onto white.
+    #[serde(alias = "jpg")]
+    Jpeg,
+    /// WebP, lossy or lossless.
+    Webp,
+    /// AVIF.
+    Avif,
+}
+
+impl OutputFormat {
+    /// Every output format.
+    pub const ALL: [Self; 4] = [Self::Png, Self::Jpeg, Self::Webp, Self::Avif];
+
+    /// MIME type of the format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    /// File extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Png => write!(f, "png"),
+            Self::Jpeg => write!(f, "jpeg"),
+            Self::Webp => write!(f, "webp"),
+            Self::Avif => write!(f, "avif"),
+        }
+    }
+}
+
+/// Output encoding options shared by the image endpoints.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OutputOptions {
+    /// Output format; negotiated from the `Accept` header when omitted.
+    pub format: Option<OutputFormat>,
+    /// Encoding quality (1-100) for JPEG, WebP and AVIF.
+    pub quality: Option<u8>,
+    /// Lossless encoding (PNG and WebP only).
+    pub lossless: Option<bool>,
+}
+
+/// Identifier of the background removal model in the model catalog.
+pub const REMOVEBG_MODEL_ID: &str = "birefnet";
+
 /// Supported models for image upscaling.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum UpscalerModel {
     /// Standard Real-ESRGAN model for high-quality upscaling.
@@ -19,7 +176,8 @@ pub enum UpscalerModel {
     #[serde(rename = "RealESRNet_x4plus")]
     RealEsrnetX4plus,
 
-    /// Specialized model for anime-style images.
+    /// Specialized model for anime-style images; used when no model is given.
+    #[default]
     #[serde(rename = "RealESRGAN_x4plus_anime_6B")]
     RealEsrganX4plusAnime6B,
 
@@ -30,18 +188,83 @@ pub enum UpscalerModel {
     /// Versatile general-purpose model.
     #[serde(rename = "realesr-general-x4v3")]
     RealEsrGeneralX4v3,
+
+    /// Chosen by the gateway from the image content.
+    #[serde(rename = "auto")]
+    Auto,
 }
 
-impl std::fmt::Display for UpscalerModel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl UpscalerModel {
+    /// Every upscaler model, in catalog order.
+    pub const ALL: [Self; 6] = [
+        Self::RealEsrganX4plus,
+        Self::RealEsrnetX4plus,
+        Self::RealEsrganX4plusAnime6B,
+        Self::RealEsrganX2plus,
+        Self::RealEsrGeneralX4v3,
+        Self::Auto,
+    ];
+
+    /// Identifier used in requests and in the model catalog.
+    pub fn id(&self) -> &'static str {
         match self {
-            Self::RealEsrganX4plus => write!(f, "RealESRGAN_x4plus"),
-            Self::RealEsrnetX4plus => write!(f, "RealESRNet_x4plus"),
-            Self::RealEsrganX4plusAnime6B => write!(f, "RealESRGAN_x4plus_anime_6B"),
-            Self::RealEsrganX2plus => write!(f, "RealESRGAN_x2plus"),
-            Self::RealEsrGeneralX4v3 => write!(f, "realesr-general-x4v3"),
+            Self::RealEsrganX4plus => "RealESRGAN_x4plus",
+            Self::RealEsrnetX4plus => "RealESRNet_x4plus",
+            Self::RealEsrganX4plusAnime6B => "RealESRGAN_x4plus_anime_6B",
+            Self::RealEsrganX2plus => "RealESRGAN_x2plus",
+            Self::RealEsrGeneralX4v3 => "realesr-general-x4v3",
+            Self::Auto => "auto",
         }
     }
+
+    /// Short human-readable description.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::RealEsrganX4plus => "Real-ESRGAN for general photos, with texture restoration",
+            Self::RealEsrnetX4plus => "Real-ESRNet for general photos, smoother and less sharpened",
+            Self::RealEsrganX4plusAnime6B => "Real-ESRGAN optimized for anime and illustrations",
+            Self::RealEsrganX2plus => "Real-ESRGAN with a native 2x factor",
+            Self::RealEsrGeneralX4v3 => {
+                "Lightweight general-purpose model with adjustable denoising"
+            }
+            Self::Auto => {
+                "Picks RealESRGAN_x4plus_anime_6B for illustrations and RealESRGAN_x4plus for photos"
+            }
+        }
+    }
+
+    /// Factor the network upscales by; other scales are resampled by the worker.
+    pub fn native_scale(&self) -> u32 {
+        match self {
+            Self::RealEsrganX2plus => 2,
+            _ => 4,
+        }
+    }
+
+    /// Returns `true` if the model accepts a `denoise_strength`, which blends
+    /// it with its denoising variant.
+    pub fn supports_denoise_strength(&self) -> bool {
+        matches!(self, Self::RealEsrGeneralX4v3)
+    }
+}
+
+impl std::fmt::Display for UpscalerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// How an image is fitted into a target width and height.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Fit inside the target, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Fill the target, keeping the aspect ratio and cropping the overflow.
+    Cover,
+    /// Stretch to exactly the target dimensions.
+    Exact,
 }
 
 /// Request payload for image upscaling.
@@ -53,6 +276,423 @@ pub struct UpscaleRequest {
     pub model: Option<UpscalerModel>,
     /// Whether to apply face enhancement (GFPGAN).
     pub face_enhance: Option<bool>,
+    /// Denoising strength (0-1), only for `realesr-general-x4v3`.
+    pub denoise_strength: Option<f64>,
+    /// Desired upscale factor (1-6); fractional values such as 1.5 are allowed.
+    pub scale: Option<f64>,
+    /// Target width in pixels.
+    pub width: Option<u32>,
+    /// Target height in pixels.
+    pub height: Option<u32>,
+    /// How the image is fitted when both `width` and `height` are given.
+    pub fit: Option<Fit>,
+    /// Encoding of the result.
+    #[serde(flatten)]
+    pub output: OutputOptions,
+}
+
+/// Request payload for `/pipeline` via URL.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PipelineRequest {
+    /// URL of the image to process.
+    pub url: String,
+    /// Steps to run, in order.
+    pub steps: Vec<PipelineStep>,
+}
+
+/// A step of a `/pipeline` request, selected by its `op` field.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PipelineStep {
+    /// Background removal through the worker.
+    Removebg(RemoveBgStep),
+    /// Upscaling through the worker.
+    Upscale(UpscaleStep),
+    /// Resampling to a width, a height or both.
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+        fit: Option<Fit>,
+    },
+    /// Cropping to a rectangle.
+    Crop {
+        #[serde(default)]
+        x: u32,
+        #[serde(default)]
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Cropping to the subject's bounding box.
+    Trim { padding: Option<CropPadding> },
+    /// Centering on a larger canvas.
+    Pad {
+        /// Aspect ratio of the canvas, e.g. `1:1`.
+        aspect: Option<String>,
+        /// Margin added on every side, in pixels.
+        padding: Option<u32>,
+        /// Canvas color (hex); transparent when omitted.
+        color: Option<String>,
+    },
+    /// Encoding of the result; only allowed as the last step.
+    Format(OutputOptions),
+}
+
+/// Parameters of a `removebg` pipeline step.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RemoveBgStep {
+    /// Alpha at or above this fraction (0-1) becomes opaque, the rest
+    /// transparent.
+    pub mask_threshold: Option<f32>,
+    /// Remove specks disconnected from the subject.
+    pub remove_islands: Option<bool>,
+    /// Shrink the mask by this many pixels.
+    pub erode: Option<u32>,
+    /// Grow the mask by this many pixels.
+    pub dilate: Option<u32>,
+    /// Soften the mask edge over about this many pixels.
+    pub feather_radius: Option<f32>,
+    /// Color to composite the cut-out onto (hex).
+    pub background_color: Option<String>,
+}
+
+/// Parameters of an `upscale` pipeline step, as for `/upscale`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpscaleStep {
+    /// Optional model selection.
+    pub model: Option<UpscalerModel>,
+    /// Whether to apply face enhancement (GFPGAN).
+    pub face_enhance: Option<bool>,
+    /// Denoising strength (0-1), only for `realesr-general-x4v3`.
+    pub denoise_strength: Option<f64>,
     /// Desired upscale factor (1-6).
-    pub scale: Option<u32>,
+    pub scale: Option<f64>,
+    /// Target width in pixels.
+    pub width: Option<u32>,
+    /// Target height in pixels.
+    pub height: Option<u32>,
+    /// How the image is fitted when both `width` and `height` are given.
+    pub fit: Option<Fit>,
+}
+
+/// Request payload for `/batch` via URLs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchRequest {
+    /// URLs of the images to process.
+    pub urls: Vec<String>,
+    /// Steps run on every image, as for `/pipeline`; background removal
+    /// when omitted.
+    pub steps: Option<Vec<PipelineStep>>,
+}
+
+/// Outcome of one image of a `/batch` request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchItem {
+    /// 1-based position of the image in the request.
+    pub index: usize,
+    /// File name, archive entry or URL the image came from.
+    pub source: Option<String>,
+    /// HTTP status the image would have had on its own.
+    pub status: u16,
+    /// Name of the result, in the ZIP archive if one is returned.
+    pub file: Option<String>,
+    /// MIME type of the result.
+    pub content_type: Option<String>,
+    /// Width of the result, in pixels.
+    pub width: Option<u32>,
+    /// Height of the result, in pixels.
+    pub height: Option<u32>,
+    /// Base64-encoded result, in the JSON manifest only.
+    pub data: Option<String>,
+    /// Error message when the image failed.
+    pub error: Option<String>,
+    /// 1-based position of the step that failed, if any.
+    pub failed_step: Option<usize>,
+}
+
+/// Result of a `/batch` request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchManifest {
+    /// Number of images processed successfully.
+    pub succeeded: usize,
+    /// Number of images that failed.
+    pub failed: usize,
+    /// One entry per image, in request order.
+    pub items: Vec<BatchItem>,
+}
+
+/// State of a background job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// The request is still being processed.
+    Pending,
+    /// The request completed with a success status.
+    Succeeded,
+    /// The request completed with an error status.
+    Failed,
+}
+
+/// One attempt at delivering a job's webhook.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    /// 1-based attempt number, counted over all deliveries of the job.
+    pub attempt: u32,
+    /// Unix timestamp (seconds) of the attempt.
+    pub sent_at: u64,
+    /// HTTP status returned by the callback URL, if a response was received.
+    pub http_status: Option<u16>,
+    /// Whether the callback URL accepted the event (2xx).
+    pub delivered: bool,
+    /// Round-trip time of the attempt, in milliseconds.
+    pub duration_ms: u64,
+    /// Error description when no response was received.
+    pub error: Option<String>,
+    /// Whether the attempt was requested through the redelivery endpoint.
+    pub manual: bool,
+}
+
+/// A result persisted in the result storage.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredResult {
+    /// Key of the result in the result storage.
+    pub key: String,
+    /// Where the result can be downloaded; signed when signing keys are configured.
+    pub url: String,
+    /// Unix timestamp (seconds) after which a signed `url` stops working.
+    pub url_expires_at: Option<u64>,
+    /// MIME type of the result.
+    pub content_type: String,
+    /// Size of the result, in bytes.
+    pub size: u64,
+    /// Unix timestamp (seconds) after which the result is no longer served.
+    pub expires_at: u64,
+}
+
+/// Request body of `POST /results/{key}/url`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SignedUrlRequest {
+    /// Lifetime of the URL, in seconds. Defaults to `RESULT_URL_TTL_SECS`.
+    pub expires_in: Option<u64>,
+    /// Whether the URL stops working after its first download.
+    #[serde(default)]
+    pub single_use: bool,
+    /// Only accept downloads from this client address.
+    pub ip: Option<IpAddr>,
+}
+
+/// A signed result download URL.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedUrl {
+    /// The signed URL.
+    pub url: String,
+    /// Unix timestamp (seconds) after which the URL stops working.
+    pub expires_at: u64,
+}
+
+/// Response to a request that was accepted as a background job.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobAccepted {
+    /// Job identifier.
+    pub job_id: String,
+    /// Always `pending`.
+    pub status: JobStatus,
+    /// Where the job can be polled.
+    pub status_url: String,
+}
+
+/// A background job, as returned by `GET /jobs/{id}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobInfo {
+    /// Job identifier.
+    pub id: String,
+    /// Endpoint that processes the job (e.g., "/removebg").
+    pub endpoint: String,
+    /// Current state.
+    pub status: JobStatus,
+    /// Unix timestamp (seconds) at which the job was accepted.
+    pub created_at: u64,
+    /// Unix timestamp (seconds) at which processing finished.
+    pub completed_at: Option<u64>,
+    /// Processing time, in milliseconds.
+    pub duration_ms: Option<u64>,
+    /// HTTP status the request completed with.
+    pub http_status: Option<u16>,
+    /// Path of the result, once the job succeeded.
+    pub result_url: Option<String>,
+    /// Error message when the job failed.
+    pub error: Option<String>,
+    /// Webhook delivery attempts, oldest first.
+    pub deliveries: Vec<DeliveryAttempt>,
+}
+
+/// The result of a completed job, as sent in a webhook event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookOutput {
+    /// Where the result can be downloaded.
+    pub url: String,
+    /// MIME type of the result.
+    pub content_type: String,
+    /// Size of the result, in bytes.
+    pub size: u64,
+}
+
+/// Why a job failed, as sent in a webhook event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookError {
+    /// HTTP status the request failed with.
+    pub code: u16,
+    /// Error message.
+    pub message: String,
+}
+
+/// Timing of a job, as sent in a webhook event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookTiming {
+    /// Unix timestamp (seconds) at which the job was accepted.
+    pub created_at: u64,
+    /// Unix timestamp (seconds) at which processing finished.
+    pub completed_at: u64,
+    /// Processing time, in milliseconds.
+    pub duration_ms: u64,
+}
+
+/// Body of a webhook request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// `job.succeeded` or `job.failed`.
+    pub event: String,
+    /// Job identifier.
+    pub job_id: String,
+    /// Endpoint that processed the job.
+    pub endpoint: String,
+    /// Final state of the job.
+    pub status: JobStatus,
+    /// The result, when the job succeeded.
+    pub output: Option<WebhookOutput>,
+    /// The error, when the job failed.
+    pub error: Option<WebhookError>,
+    /// When the job was accepted and finished.
+    pub timing: WebhookTiming,
+}
+
+/// Endpoint a preset applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresetEndpoint {
+    /// `POST /upscale`.
+    Upscale,
+    /// `POST /removebg`.
+    Removebg,
+    /// `POST /pipeline`.
+    Pipeline,
+}
+
+impl PresetEndpoint {
+    /// Request path of the endpoint.
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Upscale => "/upscale",
+            Self::Removebg => "/removebg",
+            Self::Pipeline => "/pipeline",
+        }
+    }
+}
+
+/// A named parameter set defined in the presets file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Preset {
+    /// Endpoint the preset can be used with.
+    pub endpoint: PresetEndpoint,
+    /// Short human-readable description.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Request fields set by the preset, as in a JSON request.
+    #[serde(default)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+    /// Fields a request may set alongside the preset.
+    #[serde(default)]
+    pub overridable: Vec<String>,
+}
+
+/// An entry of the `GET /presets` listing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PresetInfo {
+    /// Name referenced by the `preset` field.
+    pub name: String,
+    /// The preset itself.
+    #[serde(flatten)]
+    pub preset: Preset,
+}
+
+/// An entry of the `GET /models` catalog.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Model identifier, as accepted by the `model` field.
+    pub id: String,
+    /// Endpoint serving the model (e.g., "/upscale").
+    pub endpoint: String,
+    /// Short human-readable description.
+    pub description: String,
+    /// Factor the network upscales by (1 for background removal).
+    pub native_scale: u32,
+    /// Whether the endpoint uses this model when none is requested.
+    pub default: bool,
+    /// Request parameters the model accepts besides the output options.
+    pub parameters: Vec<String>,
+    /// Accepted input MIME types.
+    pub input_types: Vec<String>,
+    /// MIME types the result can be returned as.
+    pub output_types: Vec<String>,
+    /// MIME type returned for opaque inputs when no format is negotiated.
+    ///
+    /// Upscalers keep transparency, so inputs with an alpha channel come
+    /// back as PNG, or as WebP for WebP inputs, instead.
+    pub default_output_type: String,
+    /// Whether the model is enabled in the configuration.
+    pub enabled: bool,
+    /// Whether the model is enabled and its worker was not last seen down.
+    pub available: bool,
+}
+
+/// Reachability of a downstream dependency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    /// The dependency answered the probe.
+    Up,
+    /// The dependency could not be reached or returned a server error.
+    Down,
+}
+
+/// Result of probing a single downstream dependency.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DependencyHealth {
+    /// Dependency name (e.g., "removebg").
+    pub name: String,
+    /// Outcome of the last probe.
+    pub status: DependencyStatus,
+    /// Round-trip time of the last probe, if a response was received.
+    pub latency_ms: Option<u64>,
+    /// HTTP status returned by the dependency, if any.
+    pub http_status: Option<u16>,
+    /// Unix timestamp (seconds) of the last probe.
+    pub checked_at: u64,
+    /// Whether this result was served from the probe cache.
+    pub cached: bool,
+    /// Error description when the probe failed.
+    pub error: Option<String>,
+}
+
+/// Version and build information of the gateway.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Crate name.
+    pub name: String,
+    /// Crate version.
+    pub version: String,
+    /// Git commit the binary was built from, if provided at build time.
+    pub git_sha: Option<String>,
+    /// Build profile ("debug" or "release").
+    pub profile: String,
 }
@@ -0,0 +1,210 @@
+//! # Presets
+//!
+//! Named parameter sets defined by the administrator in the file pointed to
+//! by `PRESETS_PATH`. A request selects one through its `preset` field; the
+//! preset's parameters are filled in and the request may only set the
+//! fields the preset lists as overridable.
+
+use crate::config::Config;
+use crate::models::{PipelineRequest, Preset, PresetEndpoint, RemoveBgRequest, UpscaleRequest};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Fields a request may always set alongside a preset.
+const ALWAYS_ALLOWED: &[&str] = &["url", "image", "preset"];
+
+/// Error raised while applying a preset to a request.
+#[derive(Debug)]
+pub enum PresetError {
+    /// No preset has this name.
+    Unknown(String),
+    /// The preset belongs to another endpoint.
+    WrongEndpoint {
+        /// Name of the preset.
+        name: String,
+        /// Endpoint the preset is defined for.
+        endpoint: PresetEndpoint,
+    },
+    /// The request set a field the preset does not mark as overridable.
+    NotOverridable {
+        /// Name of the preset.
+        name: String,
+        /// The offending field.
+        field: String,
+    },
+    /// The merged request did not match the endpoint's payload.
+    Json(serde_json::Error),
+}
+
+impl IntoResponse for PresetError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Unknown(name) => (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown preset '{}'", name),
+            )
+                .into_response(),
+            Self::WrongEndpoint { name, endpoint } => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Preset '{}' can only be used with {}",
+                    name,
+                    endpoint.path()
+                ),
+            )
+                .into_response(),
+            Self::NotOverridable { name, field } => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Field '{}' cannot be overridden with preset '{}'",
+                    field, name
+                ),
+            )
+                .into_response(),
+            Self::Json(e) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+            }
+        }
+    }
+}
+
+/// Loads and validates the presets file.
+///
+/// The file is a JSON object mapping preset names to presets.
+pub fn load(path: &str) -> Result<BTreeMap<String, Preset>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let presets: BTreeMap<String, Preset> =
+        serde_json::from_str(&text).map_err(|e| format!("invalid presets file: {}", e))?;
+    for (name, preset) in &presets {
+        validate(preset).map_err(|e| format!("preset '{}' {}", name, e))?;
+    }
+    Ok(presets)
+}
+
+/// Checks that the parameters of `preset` form a valid request payload.
+fn validate(preset: &Preset) -> Result<(), String> {
+    if let Some(field) = ALWAYS_ALLOWED
+        .iter()
+        .find(|field| preset.params.contains_key(**field))
+    {
+        return Err(format!("cannot set '{}'", field));
+    }
+    let mut payload = preset.params.clone();
+    payload.insert("url".to_string(), Value::String(String::new()));
+    let payload = Value::Object(payload);
+    let checked = match preset.endpoint {
+        PresetEndpoint::Upscale => serde_json::from_value::<UpscaleRequest>(payload).map(drop),
+        PresetEndpoint::Removebg => serde_json::from_value::<RemoveBgRequest>(payload).map(drop),
+        PresetEndpoint::Pipeline => {
+            let mut payload = payload;
+            if let Value::Object(fields) = &mut payload {
+                fields.entry("steps").or_insert(Value::Array(Vec::new()));
+            }
+            serde_json::from_value::<PipelineRequest>(payload).map(drop)
+        }
+    };
+    checked.map_err(|e| format!("has invalid params: {}", e))
+}
+
+/// Looks up the preset `name` for `endpoint` and checks that every field in
+/// `fields` may be set alongside it.
+pub fn resolve<'a>(
+    config: &'a Config,
+    endpoint: PresetEndpoint,
+    name: &str,
+    fields: &[&str],
+) -> Result<&'a Preset, PresetError> {
+    let preset = config
+        .presets
+        .get(name)
+        .ok_or_else(|| PresetError::Unknown(name.to_string()))?;
+    if preset.endpoint != endpoint {
+        return Err(PresetError::WrongEndpoint {
+            name: name.to_string(),
+            endpoint: preset.endpoint,
+        });
+    }
+    let fixed = fields.iter().find(|field| {
+        !ALWAYS_ALLOWED.contains(field) && !preset.overridable.iter().any(|o| o == *field)
+    });
+    if let Some(field) = fixed {
+        return Err(PresetError::NotOverridable {
+            name: name.to_string(),
+            field: field.to_string(),
+        });
+    }
+    Ok(preset)
+}
+
+/// Deserializes a JSON request body, filling in the preset named by its
+/// `preset` field, if any.
+pub fn from_json<T: DeserializeOwned>(
+    config: &Config,
+    endpoint: PresetEndpoint,
+    body: Value,
+) -> Result<T, PresetError> {
+    let Value::Object(mut fields) = body else {
+        return serde_json::from_value(body).map_err(PresetError::Json);
+    };
+    if let Some(name) = fields.remove("preset") {
+        let name = match name {
+            Value::String(name) => name,
+            other => return Err(PresetError::Unknown(other.to_string())),
+        };
+        let names: Vec<&str> = fields.keys().map(String::as_str).collect();
+        let preset = resolve(config, endpoint, &name, &names)?;
+        fill(&mut fields, &preset.params);
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(PresetError::Json)
+}
+
+/// Adds the preset `params` the request did not set to `fields`.
+fn fill(fields: &mut Map<String, Value>, params: &Map<String, Value>) {
+    for (key, value) in params {
+        fields.entry(key.as_str()).or_insert_with(|| value.clone());
+    }
+}
+
+/// Prepends the parameters of the preset `name` to the text fields of a
+/// multipart request, as text.
+///
+/// `uploads` names the file fields of the request other than `image`; they
+/// are checked like text fields. Parameters the request set are left out.
+pub fn merge_fields(
+    config: &Config,
+    endpoint: PresetEndpoint,
+    name: Option<&str>,
+    fields: Vec<(String, String)>,
+    uploads: &[&str],
+) -> Result<Vec<(String, String)>, PresetError> {
+    let Some(name) = name else {
+        return Ok(fields);
+    };
+    let names: Vec<&str> = fields
+        .iter()
+        .map(|(field, _)| field.as_str())
+        .chain(uploads.iter().copied())
+        .collect();
+    let preset = resolve(config, endpoint, name, &names)?;
+    let mut merged: Vec<(String, String)> = preset
+        .params
+        .iter()
+        .filter(|(key, _)| !names.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), field_text(value)))
+        .collect();
+    merged.extend(fields);
+    Ok(merged)
+}
+
+/// Renders a JSON parameter as a multipart text field.
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
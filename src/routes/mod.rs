@@ -1,16 +1,27 @@
 use axum::{
-    Router,
-    extract::Request,
-    routing::{get, post},
+    Extension, Router,
+    extract::{DefaultBodyLimit, Request},
+    middleware,
+    routing::{MethodRouter, get, post},
 };
+use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_governor::{GovernorLayer, governor::GovernorConfigBuilder};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 
 use crate::config::Config;
-use crate::handlers::{health_check, removebg, upscaler};
+use crate::handlers::{
+    batch, health, health_check, jobs, models, pipeline, presets, removebg, results, transform,
+    upscaler,
+};
+use crate::metrics;
+use crate::server::{AdminListener, forward_peer_info};
+use crate::state::AppState;
+use crate::storage;
+use crate::webhooks;
 
 /// Creates the main application router.
 ///
@@ -20,6 +31,17 @@ use crate::handlers::{health_check, removebg, upscaler};
 ///
 /// A `Router` instance configured with all application routes.
 pub fn create_router(config: Arc<Config>) -> Router {
+    create_router_with_state(AppState::new(config))
+}
+
+/// Creates the main application router around an existing [`AppState`].
+///
+/// Use this when the caller needs to keep a handle on the state, e.g. to
+/// start draining on shutdown. When an admin listener is configured, the
+/// health, metrics and admin endpoints are left out of this router and
+/// served by [`create_admin_router`] instead.
+pub fn create_router_with_state(state: AppState) -> Router {
+    let config = state.config.clone();
     let governor_conf = Arc::new(
         GovernorConfigBuilder::default()
             .per_second(config.rate_limit_per_second)
@@ -28,29 +50,129 @@ pub fn create_router(config: Arc<Config>) -> Router {
             .unwrap(),
     );
 
+    let mut router = Router::new()
+        .route(
+            "/removebg",
+            body_limit(
+                processing(post(removebg::remove_bg), &state),
+                config.max_body_bytes_removebg,
+            ),
+        )
+        .route(
+            "/upscale",
+            body_limit(
+                processing(post(upscaler::upscale), &state),
+                config.max_body_bytes_upscale,
+            ),
+        )
+        .route(
+            "/pipeline",
+            body_limit(
+                processing(post(pipeline::pipeline), &state),
+                config
+                    .max_body_bytes_removebg
+                    .max(config.max_body_bytes_upscale),
+            ),
+        )
+        .route(
+            "/batch",
+            body_limit(
+                processing(post(batch::batch), &state),
+                config.max_body_bytes_batch,
+            ),
+        )
+        .route("/models", get(models::list_models))
+        .route("/presets", get(presets::list_presets))
+        .route("/jobs/{id}", get(jobs::get_job))
+        .route("/jobs/{id}/result", get(jobs::get_result))
+        .route("/results/{key}", get(results::get_result))
+        .route("/results/{key}/url", post(results::create_url))
+        .route(
+            "/t/{signature}/{options}/{source}",
+            get(transform::transform),
+        );
+    if config.admin_listen_addr.is_none() {
+        router = router.merge(admin_routes());
+    }
+
+    with_tracing(router.layer(GovernorLayer::new(governor_conf)))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .layer(middleware::from_fn(forward_peer_info))
+        .with_state(state)
+}
+
+/// Creates the router served on the admin listener.
+///
+/// It exposes only the health, metrics and admin endpoints and is not rate
+/// limited. Admin actions taken here see the jobs of every tenant.
+pub fn create_admin_router(state: AppState) -> Router {
+    with_tracing(admin_routes())
+        .layer(Extension(AdminListener))
+        .with_state(state)
+}
+
+/// Limits a route's request body to `max_bytes`.
+///
+/// Requests declaring a larger `Content-Length` are rejected with
+/// `413 Payload Too Large` before the body is read; streamed bodies are cut
+/// off once they exceed the limit.
+fn body_limit(route: MethodRouter<AppState>, max_bytes: usize) -> MethodRouter<AppState> {
+    route
+        .layer::<_, Infallible>(RequestBodyLimitLayer::new(max_bytes))
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// Lets a processing `route` accept a `callback_url`, to run as a
+/// background job, and `?store=true`, to persist its result.
+///
+/// Applied inside [`body_limit`] so the buffered body is still limited.
+/// Results of background jobs are persisted by the job itself, so the
+/// `202 Accepted` answer is never stored.
+fn processing(route: MethodRouter<AppState>, state: &AppState) -> MethodRouter<AppState> {
+    route
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            webhooks::accept_callback,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            storage::store_response,
+        ))
+}
+
+/// Health, metrics and operational routes.
+fn admin_routes() -> Router<AppState> {
     Router::new()
         .route("/health", get(health_check))
-        .route("/removebg", post(removebg::remove_bg))
-        .route("/upscale", post(upscaler::upscale))
-        .layer(GovernorLayer::new(governor_conf))
-        .layer(
-            TraceLayer::new_for_http()
-                .on_request(|request: &Request<_>, _span: &Span| {
+        .route("/livez", get(health::livez))
+        .route("/readyz", get(health::readyz))
+        .route("/health/details", get(health::health_details))
+        .route("/metrics", get(health::metrics))
+        .route("/jobs/{id}/redeliver", post(jobs::redeliver))
+}
+
+/// Adds request/response logging to `router`.
+fn with_tracing(router: Router<AppState>) -> Router<AppState> {
+    router.layer(
+        TraceLayer::new_for_http()
+            .on_request(|request: &Request<_>, _span: &Span| {
+                tracing::info!(
+                    "started processing request: method={} uri={}",
+                    request.method(),
+                    request.uri()
+                );
+            })
+            .on_response(
+                |response: &axum::response::Response, latency: Duration, _span: &Span| {
                     tracing::info!(
-                        "started processing request: method={} uri={}",
-                        request.method(),
-                        request.uri()
+                        "finished processing request: status={} latency={:?}",
+                        response.status(),
+                        latency
                     );
-                })
-                .on_response(
-                    |response: &axum::response::Response, latency: Duration, _span: &Span| {
-                        tracing::info!(
-                            "finished processing request: status={} latency={:?}",
-                            response.status(),
-                            latency
-                        );
-                    },
-                ),
-        )
-        .with_state(config)
+                },
+            ),
+    )
 }
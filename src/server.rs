@@ -0,0 +1,209 @@
+//! # Server
+//!
+//! Listener setup and connection-level plumbing: binding the public TCP,
+//! TLS and Unix socket listeners plus the admin listener, the per-connection
+//! peer information, and the middleware that exposes it to handlers.
+
+use crate::routes::{create_admin_router, create_router_with_state};
+use crate::state::AppState;
+use crate::tls::{ClientIdentity, ReloadableTlsConfig, TlsListener};
+use axum::{
+    extract::{ConnectInfo, Request, connect_info::Connected},
+    middleware::Next,
+    response::Response,
+    serve::IncomingStream,
+};
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Information about the peer of an accepted connection.
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    /// Remote address of the client.
+    ///
+    /// Unix socket clients have no address and are reported as
+    /// `127.0.0.1:0`, so they share a single rate limit bucket.
+    pub addr: SocketAddr,
+    /// Verified client certificate identity (mutual TLS only).
+    pub identity: Option<ClientIdentity>,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self {
+            addr: *stream.remote_addr(),
+            identity: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, tokio::net::UnixListener>> for PeerInfo {
+    fn connect_info(_stream: IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            identity: None,
+        }
+    }
+}
+
+/// Marks requests received on the admin listener.
+#[derive(Clone, Copy, Debug)]
+pub struct AdminListener;
+
+/// Middleware exposing [`PeerInfo`] to the rest of the stack.
+///
+/// Inserts `ConnectInfo<SocketAddr>` (used by the rate limiter) and, when a
+/// client certificate was verified, the [`ClientIdentity`].
+pub async fn forward_peer_info(mut request: Request, next: Next) -> Response {
+    if let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<PeerInfo>>().cloned() {
+        request.extensions_mut().insert(ConnectInfo(peer.addr));
+        if let Some(identity) = peer.identity {
+            request.extensions_mut().insert(identity);
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Binds every configured listener and serves until `shutdown` is cancelled.
+///
+/// The public API is served on each TCP address (over TLS when configured)
+/// and on the Unix socket; health, metrics and admin endpoints move to the
+/// admin listener when one is configured.
+///
+/// # Errors
+///
+/// Returns an error if a listener cannot be bound or the TLS material
+/// cannot be loaded.
+pub async fn run(state: AppState, shutdown: CancellationToken) -> io::Result<()> {
+    let config = state.config.clone();
+    let public =
+        create_router_with_state(state.clone()).into_make_service_with_connect_info::<PeerInfo>();
+    let mut servers = JoinSet::new();
+
+    let tls = if config.tls_enabled() {
+        let tls = ReloadableTlsConfig::load(&config).map_err(io::Error::other)?;
+        tls.spawn_reloader(config.clone());
+        Some(tls)
+    } else {
+        None
+    };
+    let tenants = Arc::new(config.tls_client_tenants.clone());
+    if let Some(storage) = state.storage.clone() {
+        storage.spawn_sweeper(
+            Duration::from_secs(config.result_sweep_interval_secs),
+            shutdown.clone(),
+        );
+    }
+
+    for addr in config.tcp_listen_addrs() {
+        let listener = bind_tcp(addr)?;
+        let shutdown = shutdown.clone().cancelled_owned();
+        match &tls {
+            Some(tls) => {
+                let listener = TlsListener::new(listener, tls.clone(), tenants.clone())?;
+                tracing::info!("Listening on https://{}", addr);
+                servers.spawn(
+                    axum::serve(listener, public.clone())
+                        .with_graceful_shutdown(shutdown)
+                        .into_future(),
+                );
+            }
+            None => {
+                tracing::info!("Listening on http://{}", addr);
+                servers.spawn(
+                    axum::serve(listener, public.clone())
+                        .with_graceful_shutdown(shutdown)
+                        .into_future(),
+                );
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket_path {
+        let listener = bind_unix(path, config.unix_socket_mode)?;
+        tracing::info!("Listening on unix:{}", path);
+        let path = path.clone();
+        let server = axum::serve(listener, public.clone())
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+        servers.spawn(async move {
+            let result = server.await;
+            let _ = std::fs::remove_file(&path);
+            result
+        });
+    }
+
+    if let Some(addr) = config.admin_listen_addr {
+        let listener = bind_tcp(addr)?;
+        let admin =
+            create_admin_router(state.clone()).into_make_service_with_connect_info::<PeerInfo>();
+        tracing::info!("Admin listener on http://{}", addr);
+        servers.spawn(
+            axum::serve(listener, admin)
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                .into_future(),
+        );
+    }
+
+    while let Some(result) = servers.join_next().await {
+        result.map_err(io::Error::other)??;
+    }
+    state
+        .jobs
+        .shutdown(Duration::from_secs(config.job_drain_timeout_secs))
+        .await;
+    Ok(())
+}
+
+/// Binds a TCP listener.
+///
+/// IPv6 sockets are bound as IPv6-only so that `0.0.0.0` and `[::]` on the
+/// same port can be listed side by side for dual-stack setups.
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a Unix domain socket and applies `mode` to it.
+///
+/// A stale socket file left behind by a previous run is removed first.
+#[cfg(unix)]
+fn bind_unix(path: &str, mode: u32) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
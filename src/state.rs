@@ -0,0 +1,177 @@
+//! # Application State
+//!
+//! Shared state handed to every handler through Axum's `State` extractor.
+
+use crate::config::Config;
+use crate::health::HealthMonitor;
+use crate::jobs::JobStore;
+use crate::metrics::Metrics;
+use crate::storage::Storage;
+use crate::upload;
+use axum::extract::FromRef;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// State shared by all handlers.
+///
+/// Cloning is cheap; every field is reference counted.
+#[derive(Clone)]
+pub struct AppState {
+    /// Application configuration.
+    pub config: Arc<Config>,
+    /// Shared HTTP client used to reach the workers.
+    pub http: reqwest::Client,
+    /// HTTP client used to fetch image URLs and deliver webhooks; see
+    /// [`upload::fetch_client`].
+    pub fetch_http: reqwest::Client,
+    /// Worker health probes.
+    pub health: Arc<HealthMonitor>,
+    /// Limits the upscale tiles in flight across all requests.
+    pub upscale_tiles: Arc<Semaphore>,
+    /// Limits the batch images in flight per tenant; clients without a
+    /// tenant share the `""` entry.
+    pub batch_slots: Arc<TenantSlots>,
+    /// Requests accepted with a callback URL.
+    pub jobs: Arc<JobStore>,
+    /// Result storage, when `RESULT_STORAGE` selects a backend.
+    pub storage: Option<Arc<Storage>>,
+    /// Request counters reported on `GET /metrics`.
+    pub metrics: Arc<Metrics>,
+    /// Set once shutdown has begun.
+    draining: Arc<AtomicBool>,
+    /// Process start time.
+    started_at: Instant,
+}
+
+impl AppState {
+    /// Builds the state for the given configuration.
+    pub fn new(config: Arc<Config>) -> Self {
+        let http = reqwest::Client::new();
+        let fetch_http = upload::fetch_client(config.fetch_deny_private_networks);
+        let health = Arc::new(HealthMonitor::new(&config, http.clone()));
+        let upscale_tiles = Arc::new(Semaphore::new(config.upscale_tile_concurrency.max(1)));
+        let storage = Storage::from_config(&config, http.clone()).map(Arc::new);
+        let jobs = Arc::new(JobStore::from_config(&config));
+        let batch_slots = Arc::new(TenantSlots::new(
+            config.batch_concurrency,
+            config.batch_tenant_concurrency.clone(),
+        ));
+
+        Self {
+            config,
+            http,
+            fetch_http,
+            health,
+            upscale_tiles,
+            batch_slots,
+            jobs,
+            storage,
+            metrics: Arc::new(Metrics::default()),
+            draining: Arc::new(AtomicBool::new(false)),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Marks the server as draining so readiness checks start failing.
+    pub fn begin_drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` once shutdown has begun.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Time elapsed since the state was created.
+    pub fn uptime(&self) -> std::time::Duration {
+        self.started_at.elapsed()
+    }
+}
+
+/// One semaphore per tenant, created on first use.
+///
+/// Entries are dropped once no request waits on or holds one of their
+/// permits, so tenants seen once do not accumulate.
+pub struct TenantSlots {
+    permits: usize,
+    tenant_permits: HashMap<String, usize>,
+    slots: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl TenantSlots {
+    /// Creates the slots, allowing `permits` at once per tenant, or the
+    /// tenant's entry in `tenant_permits`.
+    pub fn new(permits: usize, tenant_permits: HashMap<String, usize>) -> Self {
+        Self {
+            permits: permits.max(1),
+            tenant_permits,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the semaphore of `tenant`.
+    ///
+    /// Callers keep the entry alive by holding the returned `Arc` or an
+    /// owned permit acquired from it.
+    pub fn get(&self, tenant: &str) -> Arc<Semaphore> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.retain(|_, slot| Arc::strong_count(slot) > 1);
+        let permits = self
+            .tenant_permits
+            .get(tenant)
+            .copied()
+            .unwrap_or(self.permits)
+            .max(1);
+        slots
+            .entry(tenant.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(permits)))
+            .clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_slots_use_the_configured_limits() {
+        let slots = TenantSlots::new(2, HashMap::from([("acme".to_string(), 5)]));
+        assert_eq!(slots.get("acme").available_permits(), 5);
+        assert_eq!(slots.get("other").available_permits(), 2);
+        assert_eq!(slots.get("").available_permits(), 2);
+        assert_eq!(
+            TenantSlots::new(0, HashMap::new())
+                .get("")
+                .available_permits(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_tenant_slots_drop_idle_entries() {
+        let slots = TenantSlots::new(2, HashMap::new());
+        let permit = slots.get("acme").try_acquire_owned().unwrap();
+        for tenant in 0..100 {
+            drop(slots.get(&tenant.to_string()));
+        }
+        slots.get("other");
+        {
+            let held = slots.slots.lock().unwrap();
+            assert_eq!(held.len(), 2);
+            assert!(held.contains_key("acme"));
+        }
+        // The held permit keeps counting against the tenant.
+        assert_eq!(slots.get("acme").available_permits(), 1);
+        drop(permit);
+        assert_eq!(slots.get("acme").available_permits(), 2);
+    }
+}
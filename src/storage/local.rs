@@ -0,0 +1,130 @@
+//! Local filesystem driver: one file per result in a directory.
+
+use super::{ObjectMeta, StorageError, content_type, valid_key};
+use axum::body::Bytes;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Age after which a partially written result is considered abandoned, in
+/// seconds.
+const STALE_PARTIAL_SECS: u64 = 3600;
+
+/// Results stored as files in a directory.
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    /// Stores results in `dir`, created on first write.
+    pub fn new(dir: &str) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Writes `body` to `key`, atomically.
+    pub async fn put(&self, key: &str, body: Bytes) -> Result<(), StorageError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let partial = self.dir.join(format!(".{}.partial", key));
+        tokio::fs::write(&partial, &body).await?;
+        tokio::fs::rename(&partial, self.dir.join(key)).await?;
+        Ok(())
+    }
+
+    /// Creates an empty `key` unless it exists, returning whether it was
+    /// created.
+    pub async fn put_new(&self, key: &str) -> Result<bool, StorageError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let created = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.dir.join(key))
+            .await;
+        match created {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the metadata of `key`, or `None` if it does not exist.
+    pub async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, StorageError> {
+        let metadata = match tokio::fs::metadata(self.dir.join(key)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Some(ObjectMeta {
+            size: metadata.len(),
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos()),
+            content_type: content_type(key).to_string(),
+            created_at: modified.as_secs(),
+        }))
+    }
+
+    /// Reads the bytes of `key` in `range`.
+    pub async fn read(&self, key: &str, range: Range<u64>) -> Result<Bytes, StorageError> {
+        let mut file = tokio::fs::File::open(self.dir.join(key)).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let mut bytes = Vec::with_capacity((range.end - range.start) as usize);
+        file.take(range.end - range.start)
+            .read_to_end(&mut bytes)
+            .await?;
+        Ok(Bytes::from(bytes))
+    }
+
+    /// Deletes `key`; missing files are ignored.
+    pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes the partially written results left behind by writes that
+    /// were interrupted more than an hour before `now`, returning how many
+    /// were deleted.
+    pub async fn delete_stale_partials(&self, now: u64) -> Result<usize, StorageError> {
+        let mut deleted = 0;
+        for (name, modified) in self.list().await? {
+            let partial = name
+                .strip_prefix('.')
+                .and_then(|name| name.strip_suffix(".partial"))
+                .is_some_and(valid_key);
+            if partial && modified + STALE_PARTIAL_SECS <= now {
+                self.delete(&name).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Lists the stored keys with the Unix time they were stored at.
+    pub async fn list(&self) -> Result<Vec<(String, u64)>, StorageError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut objects = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let modified = entry
+                .metadata()
+                .await?
+                .modified()
+                .unwrap_or(SystemTime::now())
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            objects.push((name, modified.as_secs()));
+        }
+        Ok(objects)
+    }
+}
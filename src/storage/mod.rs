@@ -0,0 +1,381 @@
+//! # Result Storage
+//!
+//! Persists processing results so they can be downloaded later from
+//! `GET /results/{key}`. Results are kept on the local filesystem or in an
+//! S3-compatible bucket and expire `RESULT_TTL_SECS` after they were
+//! stored; a background sweeper deletes expired results. When signing keys
+//! are configured, results are only served through signed URLs.
+
+mod local;
+mod s3;
+mod signing;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+pub use signing::{SignatureError, SignedQuery, UrlSigner};
+
+use crate::config::{Config, StorageBackend};
+use crate::jobs::{random_id, unix_now};
+use crate::models::StoredResult;
+use crate::state::AppState;
+use axum::{
+    Json,
+    body::{Bytes, to_bytes},
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// File extensions of the content types that can be stored.
+///
+/// The content type is served back from the key's extension, so results
+/// of other types, such as `multipart/mixed` whose boundary would be lost,
+/// are refused.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/webp", "webp"),
+    ("image/avif", "avif"),
+    ("application/zip", "zip"),
+    ("application/json", "json"),
+];
+
+/// Extension of the markers recording used single-use nonces.
+const USED_EXTENSION: &str = "used";
+
+/// Metadata of a stored result.
+#[derive(Clone, Debug)]
+pub struct ObjectMeta {
+    /// Size of the result, in bytes.
+    pub size: u64,
+    /// Entity tag, quoted.
+    pub etag: String,
+    /// MIME type of the result.
+    pub content_type: String,
+    /// Unix timestamp (seconds) at which the result was stored.
+    pub created_at: u64,
+}
+
+/// Error raised by a storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The local filesystem failed.
+    Io(io::Error),
+    /// The S3 endpoint failed or answered with an error.
+    Backend(String),
+    /// Results of this content type cannot be stored.
+    Unsupported(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Backend(message) => write!(f, "{}", message),
+            Self::Unsupported(content_type) => {
+                write!(f, "results of type {} cannot be stored", content_type)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl IntoResponse for StorageError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Backend(_) => StatusCode::BAD_GATEWAY,
+            Self::Unsupported(content_type) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Results of type {} cannot be stored", content_type),
+                )
+                    .into_response();
+            }
+        };
+        tracing::error!("Result storage failed: {}", self);
+        (status, format!("Result storage failed: {}", self)).into_response()
+    }
+}
+
+/// A storage driver.
+enum Backend {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+/// Result storage with expiry.
+pub struct Storage {
+    backend: Backend,
+    ttl: Duration,
+    signer: Option<UrlSigner>,
+    url_ttl: Duration,
+}
+
+impl Storage {
+    /// Creates the storage selected by `RESULT_STORAGE`, if any.
+    pub fn from_config(config: &Config, http: reqwest::Client) -> Option<Self> {
+        let backend = match config.result_storage {
+            StorageBackend::None => return None,
+            StorageBackend::Local => Backend::Local(LocalStorage::new(&config.result_storage_dir)),
+            StorageBackend::S3 => Backend::S3(S3Storage::new(config, http)),
+        };
+        Some(Self {
+            backend,
+            ttl: Duration::from_secs(config.result_ttl_secs),
+            signer: UrlSigner::new(&config.result_signing_keys),
+            url_ttl: Duration::from_secs(config.result_url_ttl_secs),
+        })
+    }
+
+    /// Stores `body` under a new key.
+    ///
+    /// Only the content types listed in [`EXTENSIONS`] can be stored; their
+    /// parameters are dropped.
+    pub async fn put(&self, content_type: &str, body: Bytes) -> Result<StoredResult, StorageError> {
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let Some(extension) = extension(&content_type) else {
+            return Err(StorageError::Unsupported(content_type));
+        };
+        let key = format!("{}.{}", random_id(), extension);
+        let size = body.len() as u64;
+        let created_at = unix_now();
+        match &self.backend {
+            Backend::Local(local) => local.put(&key, body).await?,
+            Backend::S3(s3) => s3.put(&key, &content_type, body, created_at).await?,
+        }
+        let expires_at = created_at + self.ttl.as_secs();
+        let (url, url_expires_at) = self.download_url(&key, expires_at, None, false, None);
+        Ok(StoredResult {
+            key,
+            url,
+            url_expires_at,
+            content_type,
+            size,
+            expires_at,
+        })
+    }
+
+    /// Returns the download URL of `key`, which expires at `expires_at`.
+    ///
+    /// With signing keys, the URL is signed and valid for `expires_in`
+    /// seconds (`RESULT_URL_TTL_SECS` by default), but never past the
+    /// result's expiry; its own expiry is returned alongside.
+    pub fn download_url(
+        &self,
+        key: &str,
+        expires_at: u64,
+        expires_in: Option<u64>,
+        single_use: bool,
+        ip: Option<IpAddr>,
+    ) -> (String, Option<u64>) {
+        let Some(signer) = &self.signer else {
+            return (format!("/results/{}", key), None);
+        };
+        let expires_in = expires_in.unwrap_or(self.url_ttl.as_secs());
+        let expires = unix_now().saturating_add(expires_in).min(expires_at);
+        (signer.sign(key, expires, single_use, ip), Some(expires))
+    }
+
+    /// Returns the signer of download URLs, when signing keys are configured.
+    pub fn signer(&self) -> Option<&UrlSigner> {
+        self.signer.as_ref()
+    }
+
+    /// Records the single-use nonce `once` as used, returning `false` if it
+    /// already was.
+    ///
+    /// The nonce is stored as a marker object next to the results, created
+    /// only if it does not exist yet, so a single-use URL works once across
+    /// restarts and every instance sharing the storage. Markers are swept
+    /// like results: a URL never outlives its result.
+    pub async fn mark_used(&self, once: &str) -> Result<bool, StorageError> {
+        if !valid_id(once) {
+            return Ok(false);
+        }
+        let marker = format!("{}.{}", once, USED_EXTENSION);
+        match &self.backend {
+            Backend::Local(local) => local.put_new(&marker).await,
+            Backend::S3(s3) => s3.put_new(&marker, unix_now()).await,
+        }
+    }
+
+    /// Returns the metadata of `key`, or `None` if it does not exist or has
+    /// expired.
+    pub async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, StorageError> {
+        if !valid_key(key) {
+            return Ok(None);
+        }
+        let meta = match &self.backend {
+            Backend::Local(local) => local.head(key).await?,
+            Backend::S3(s3) => s3.head(key).await?,
+        };
+        Ok(meta.filter(|meta| meta.created_at + self.ttl.as_secs() > unix_now()))
+    }
+
+    /// Reads the bytes of `key` in `range`.
+    pub async fn read(&self, key: &str, range: Range<u64>) -> Result<Bytes, StorageError> {
+        match &self.backend {
+            Backend::Local(local) => local.read(key, range).await,
+            Backend::S3(s3) => s3.read(key, range).await,
+        }
+    }
+
+    /// Returns how long results are served after they were stored.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Deletes the expired results and used-nonce markers, returning how
+    /// many were deleted.
+    ///
+    /// With the local backend, files left behind by interrupted writes are
+    /// deleted as well.
+    pub async fn sweep(&self) -> Result<usize, StorageError> {
+        let now = unix_now();
+        let (objects, mut deleted) = match &self.backend {
+            Backend::Local(local) => (local.list().await?, local.delete_stale_partials(now).await?),
+            Backend::S3(s3) => (s3.list().await?, 0),
+        };
+        for (key, created_at) in objects {
+            let marker = key
+                .strip_suffix(USED_EXTENSION)
+                .and_then(|id| id.strip_suffix('.'))
+                .is_some_and(valid_id);
+            if !(valid_key(&key) || marker) || created_at + self.ttl.as_secs() > now {
+                continue;
+            }
+            match &self.backend {
+                Backend::Local(local) => local.delete(&key).await?,
+                Backend::S3(s3) => s3.delete(&key).await?,
+            }
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Runs [`Storage::sweep`] every `interval` until `shutdown` is cancelled.
+    pub fn spawn_sweeper(self: Arc<Self>, interval: Duration, shutdown: CancellationToken) {
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                match self.sweep().await {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::info!("Deleted {} expired results", deleted),
+                    Err(e) => tracing::warn!("Sweeping expired results failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Returns `true` for keys the storage could have generated.
+///
+/// Anything else is rejected before it reaches a backend, so keys can be
+/// used as file names and object keys as they are.
+pub fn valid_key(key: &str) -> bool {
+    key.split_once('.')
+        .is_some_and(|(id, ext)| valid_id(id) && EXTENSIONS.iter().any(|(_, known)| *known == ext))
+}
+
+/// Returns `true` for IDs generated by [`random_id`].
+fn valid_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// File extension for `content_type`, or `None` if it cannot be stored.
+fn extension(content_type: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == content_type)
+        .map(|(_, ext)| *ext)
+}
+
+/// MIME type of a key's extension.
+fn content_type(key: &str) -> &'static str {
+    let ext = key.rsplit('.').next().unwrap_or_default();
+    EXTENSIONS
+        .iter()
+        .find(|(_, known)| *known == ext)
+        .map(|(content_type, _)| *content_type)
+        .unwrap_or("application/octet-stream")
+}
+
+#[derive(Deserialize)]
+struct StoreQuery {
+    store: Option<bool>,
+}
+
+/// Middleware persisting the response when the request asks for it with
+/// `?store=true`.
+///
+/// A successful response is replaced with `201 Created` describing the
+/// stored result; other responses are returned unchanged.
+pub async fn store_response(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let store = Query::<StoreQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.store)
+        .unwrap_or(false);
+    if !store {
+        return next.run(request).await;
+    }
+    let Some(storage) = state.storage.clone() else {
+        return (StatusCode::BAD_REQUEST, "Result storage is not configured").into_response();
+    };
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let body = match to_bytes(response.into_body(), usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read the result: {}", e),
+            )
+                .into_response();
+        }
+    };
+    match storage.put(&content_type, body).await {
+        Ok(stored) => (
+            StatusCode::CREATED,
+            [(header::LOCATION, stored.url.clone())],
+            Json(stored),
+        )
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
@@ -0,0 +1,389 @@
+//! S3-compatible driver: path-style requests signed with AWS Signature
+//! Version 4, so it works with AWS S3 as well as MinIO and similar servers.
+
+use super::{ObjectMeta, StorageError};
+use crate::config::Config;
+use crate::jobs::{hex, unix_now};
+use aws_lc_rs::{digest, hmac};
+use axum::body::Bytes;
+use reqwest::{Method, StatusCode, header};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Results stored as objects in an S3 bucket.
+pub struct S3Storage {
+    http: reqwest::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    prefix: String,
+}
+
+/// Object metadata header holding the Unix time the result was stored at.
+const CREATED_HEADER: &str = "x-amz-meta-created";
+
+impl S3Storage {
+    /// Creates the driver from the `RESULT_S3_*` settings.
+    pub fn new(config: &Config, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            endpoint: config.result_s3_endpoint.trim_end_matches('/').to_string(),
+            bucket: config.result_s3_bucket.clone(),
+            region: config.result_s3_region.clone(),
+            access_key: config.result_s3_access_key.clone(),
+            secret_key: config.result_s3_secret_key.clone(),
+            prefix: config.result_s3_prefix.clone(),
+        }
+    }
+
+    /// Uploads `body` to `key`.
+    pub async fn put(
+        &self,
+        key: &str,
+        content_type: &str,
+        body: Bytes,
+        created_at: u64,
+    ) -> Result<(), StorageError> {
+        let headers = [
+            ("content-type", content_type.to_string()),
+            (CREATED_HEADER, created_at.to_string()),
+        ];
+        let res = self
+            .send(Method::PUT, &self.object_path(key), &[], &headers, body)
+            .await?;
+        check(res).await.map(drop)
+    }
+
+    /// Creates an empty `key` unless it exists, returning whether it was
+    /// created.
+    ///
+    /// Relies on conditional writes (`If-None-Match: *`), supported by AWS
+    /// S3 and MinIO.
+    pub async fn put_new(&self, key: &str, created_at: u64) -> Result<bool, StorageError> {
+        let headers = [
+            ("if-none-match", "*".to_string()),
+            (CREATED_HEADER, created_at.to_string()),
+        ];
+        let res = self
+            .send(
+                Method::PUT,
+                &self.object_path(key),
+                &[],
+                &headers,
+                Bytes::new(),
+            )
+            .await?;
+        // 409 is returned while a concurrent conditional write is pending.
+        if matches!(
+            res.status(),
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT
+        ) {
+            return Ok(false);
+        }
+        check(res).await.map(|_| true)
+    }
+
+    /// Returns the metadata of `key`, or `None` if it does not exist.
+    pub async fn head(&self, key: &str) -> Result<Option<ObjectMeta>, StorageError> {
+        let res = self
+            .send(Method::HEAD, &self.object_path(key), &[], &[], Bytes::new())
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check(res).await?;
+        let text = |name: header::HeaderName| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(Some(ObjectMeta {
+            size: text(header::CONTENT_LENGTH)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or_default(),
+            etag: text(header::ETAG).unwrap_or_default(),
+            content_type: text(header::CONTENT_TYPE)
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+            created_at: res
+                .headers()
+                .get(CREATED_HEADER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .unwrap_or_else(unix_now),
+        }))
+    }
+
+    /// Downloads the bytes of `key` in `range`.
+    pub async fn read(&self, key: &str, range: Range<u64>) -> Result<Bytes, StorageError> {
+        let path = self.object_path(key);
+        let mut request = self.request(Method::GET, &path, &[], &[], Bytes::new());
+        if range.start < range.end {
+            request = request.header(
+                header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            );
+        }
+        let res = request
+            .send()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        let partial = res.status() == StatusCode::PARTIAL_CONTENT;
+        let bytes = check(res)
+            .await?
+            .bytes()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        if partial {
+            return Ok(bytes);
+        }
+        // The server ignored the range and sent the whole object.
+        let end = (range.end as usize).min(bytes.len());
+        Ok(bytes.slice((range.start as usize).min(end)..end))
+    }
+
+    /// Deletes `key`.
+    pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let res = self
+            .send(
+                Method::DELETE,
+                &self.object_path(key),
+                &[],
+                &[],
+                Bytes::new(),
+            )
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check(res).await.map(drop)
+    }
+
+    /// Lists the stored keys with the Unix time they were last modified.
+    pub async fn list(&self) -> Result<Vec<(String, u64)>, StorageError> {
+        let path = format!("/{}", self.bucket);
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let res = self
+                .send(Method::GET, &path, &query, &[], Bytes::new())
+                .await?;
+            let xml = check(res)
+                .await?
+                .text()
+                .await
+                .map_err(|e| StorageError::Backend(e.to_string()))?;
+            for entry in xml.split("<Contents>").skip(1) {
+                let key = tag(entry, "Key").and_then(|key| key.strip_prefix(self.prefix.as_str()));
+                let modified = tag(entry, "LastModified").and_then(parse_timestamp);
+                if let (Some(key), Some(modified)) = (key, modified) {
+                    objects.push((key.to_string(), modified));
+                }
+            }
+            token = match tag(&xml, "IsTruncated") {
+                Some("true") => tag(&xml, "NextContinuationToken").map(str::to_string),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    /// Path of `key`, including the bucket.
+    fn object_path(&self, key: &str) -> String {
+        format!("/{}/{}{}", self.bucket, self.prefix, key)
+    }
+
+    /// Sends a signed request.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Bytes,
+    ) -> Result<reqwest::Response, StorageError> {
+        self.request(method, path, query, headers, body)
+            .send()
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))
+    }
+
+    /// Builds a request signed with AWS Signature Version 4.
+    ///
+    /// `headers` are sent and signed along with `host` and the `x-amz-*`
+    /// headers the signature requires.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Bytes,
+    ) -> reqwest::RequestBuilder {
+        let now = unix_now();
+        let (date, time) = timestamp(now);
+        let amz_date = format!("{}T{}Z", date, time);
+        let payload_hash = hex(digest::digest(&digest::SHA256, &body).as_ref());
+        let host = self
+            .endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut signed: BTreeMap<String, String> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        signed.insert("host".to_string(), host);
+        signed.insert("x-amz-content-sha256".to_string(), payload_hash.clone());
+        signed.insert("x-amz-date".to_string(), amz_date.clone());
+
+        let canonical_uri = encode(path, false);
+        let mut pairs: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (encode(name, true), encode(value, true)))
+            .collect();
+        pairs.sort();
+        let canonical_query = pairs
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = signed.keys().cloned().collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, canonical_uri, canonical_query, canonical_headers, signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+        );
+        let mut key = format!("AWS4{}", self.secret_key).into_bytes();
+        for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+            key = sign(&key, part.as_bytes());
+        }
+        let signature = hex(&sign(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+
+        let mut url = format!("{}{}", self.endpoint, canonical_uri);
+        if !canonical_query.is_empty() {
+            url = format!("{}?{}", url, canonical_query);
+        }
+        let mut request = self
+            .http
+            .request(method, url)
+            .header(header::AUTHORIZATION, authorization);
+        for (name, value) in signed.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request.body(body)
+    }
+}
+
+/// Turns an error status into [`StorageError::Backend`].
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, StorageError> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    Err(StorageError::Backend(format!(
+        "S3 returned {}: {}",
+        status,
+        tag(&text, "Message").unwrap_or(&text)
+    )))
+}
+
+/// HMAC-SHA256 of `data` keyed with `key`.
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+/// URI-encodes `value` as required by Signature Version 4.
+fn encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Text of the first `<name>` element in `xml`.
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..start + end])
+}
+
+/// Formats a Unix time as `YYYYMMDD` and `HHMMSS`, in UTC.
+fn timestamp(secs: u64) -> (String, String) {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!("{:02}{:02}{:02}", rem / 3600, rem % 3600 / 60, rem % 60),
+    )
+}
+
+/// Parses an ISO 8601 UTC timestamp such as `2026-10-18T12:00:00.000Z`.
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let number = |range: Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let days = days_from_civil(number(0..4)?, number(5..7)?, number(8..10)?);
+    let secs = days * 86400 + number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+    u64::try_from(secs).ok()
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the Unix epoch.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
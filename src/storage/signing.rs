@@ -0,0 +1,162 @@
+//! Signed, time-limited download URLs.
+//!
+//! A signed URL carries its expiry, the ID of the signing key and an
+//! HMAC-SHA256 signature over the result key and every restriction it
+//! carries: a single-use nonce and a client address. Several keys can be
+//! active at once so they can be rotated; the first one signs new URLs.
+//!
+//! Used single-use nonces are recorded in the result storage, see
+//! [`Storage::mark_used`](super::Storage::mark_used), so they hold across
+//! restarts and instances sharing the storage.
+
+use crate::jobs::{hex, random_id, unix_now};
+use aws_lc_rs::hmac;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Why a download URL was refused.
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The URL carries no signature.
+    Missing,
+    /// The signature does not match, or its key is unknown.
+    Invalid,
+    /// The URL has expired.
+    Expired,
+    /// The URL is bound to another client address.
+    WrongAddress,
+    /// The single-use URL was already used.
+    AlreadyUsed,
+}
+
+impl IntoResponse for SignatureError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::Missing => "Download URL must be signed",
+            Self::Invalid => "Invalid download URL signature",
+            Self::Expired => "Download URL has expired",
+            Self::WrongAddress => "Download URL is not valid from this address",
+            Self::AlreadyUsed => "Download URL has already been used",
+        };
+        (StatusCode::FORBIDDEN, message).into_response()
+    }
+}
+
+/// Query parameters of a signed download URL.
+#[derive(Debug, Default, Deserialize)]
+pub struct SignedQuery {
+    /// Unix timestamp (seconds) after which the URL stops working.
+    pub expires: Option<u64>,
+    /// Nonce of a single-use URL.
+    pub once: Option<String>,
+    /// Client address the URL is bound to.
+    pub ip: Option<IpAddr>,
+    /// ID of the signing key.
+    pub kid: Option<String>,
+    /// Hex-encoded signature.
+    pub sig: Option<String>,
+}
+
+/// Signs and verifies download URLs.
+pub struct UrlSigner {
+    /// Active keys by ID; the first one signs new URLs.
+    keys: Vec<(String, hmac::Key)>,
+}
+
+impl UrlSigner {
+    /// Creates a signer from `(key ID, secret)` pairs, or `None` without keys.
+    pub fn new(keys: &[(String, String)]) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Self {
+            keys: keys
+                .iter()
+                .map(|(id, secret)| {
+                    (
+                        id.clone(),
+                        hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns the signed download URL of `key`.
+    pub fn sign(&self, key: &str, expires: u64, single_use: bool, ip: Option<IpAddr>) -> String {
+        let (kid, signing_key) = &self.keys[0];
+        let once = single_use.then(random_id);
+        let tag = hmac::sign(
+            signing_key,
+            message(key, expires, once.as_deref(), ip).as_bytes(),
+        );
+        let mut url = format!("/results/{}?expires={}", key, expires);
+        if let Some(once) = &once {
+            url.push_str(&format!("&once={}", once));
+        }
+        if let Some(ip) = ip {
+            url.push_str(&format!("&ip={}", ip));
+        }
+        url.push_str(&format!("&kid={}&sig={}", kid, hex(tag.as_ref())));
+        url
+    }
+
+    /// Checks the signature, expiry and address binding of a download URL.
+    ///
+    /// Single-use URLs are not marked as used; see
+    /// [`Storage::mark_used`](super::Storage::mark_used).
+    pub fn verify(
+        &self,
+        key: &str,
+        query: &SignedQuery,
+        peer: Option<IpAddr>,
+    ) -> Result<(), SignatureError> {
+        let (Some(expires), Some(kid), Some(sig)) = (query.expires, &query.kid, &query.sig) else {
+            return Err(SignatureError::Missing);
+        };
+        let signing_key = self
+            .keys
+            .iter()
+            .find(|(id, _)| id == kid)
+            .map(|(_, signing_key)| signing_key)
+            .ok_or(SignatureError::Invalid)?;
+        let tag = from_hex(sig).ok_or(SignatureError::Invalid)?;
+        let message = message(key, expires, query.once.as_deref(), query.ip);
+        hmac::verify(signing_key, message.as_bytes(), &tag).map_err(|_| SignatureError::Invalid)?;
+        if expires <= unix_now() {
+            return Err(SignatureError::Expired);
+        }
+        if let Some(ip) = query.ip {
+            if peer.map(|peer| peer.to_canonical()) != Some(ip.to_canonical()) {
+                return Err(SignatureError::WrongAddress);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The signed message: the result key and the URL's restrictions.
+fn message(key: &str, expires: u64, once: Option<&str>, ip: Option<IpAddr>) -> String {
+    format!(
+        "{}\n{}\n{}\n{}",
+        key,
+        expires,
+        once.unwrap_or_default(),
+        ip.map(|ip| ip.to_string()).unwrap_or_default()
+    )
+}
+
+/// Decodes a hex string.
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,298 @@
+//! # TLS Termination
+//!
+//! Optional rustls-based HTTPS listener. Certificates are reloaded when their
+//! files change, and client certificates can be verified against a CA bundle
+//! (mutual TLS), with the certificate's common name mapped to a tenant.
+
+use crate::config::{Config, TlsVersion};
+use crate::server::PeerInfo;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+
+/// Maximum time a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error raised while loading TLS material.
+#[derive(Debug)]
+pub enum TlsError {
+    /// A PEM file could not be read or parsed.
+    Pem(String, rustls::pki_types::pem::Error),
+    /// A PEM file contained no certificate.
+    NoCertificates(String),
+    /// rustls rejected the configuration.
+    Rustls(rustls::Error),
+    /// The client certificate verifier could not be built.
+    Verifier(rustls::server::VerifierBuilderError),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pem(path, e) => write!(f, "failed to read {}: {}", path, e),
+            Self::NoCertificates(path) => write!(f, "no certificates found in {}", path),
+            Self::Rustls(e) => write!(f, "invalid TLS configuration: {}", e),
+            Self::Verifier(e) => write!(f, "invalid client CA: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> Self {
+        Self::Rustls(e)
+    }
+}
+
+/// Identity of a client that presented a verified certificate.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    /// Full certificate subject (e.g., "CN=backend, O=Acme").
+    pub subject: String,
+    /// Subject common name, if present.
+    pub common_name: Option<String>,
+    /// Tenant mapped from the common name via `TLS_CLIENT_TENANTS`.
+    pub tenant: Option<String>,
+}
+
+/// Builds a rustls server configuration from the TLS settings in `config`.
+pub fn load_server_config(config: &Config) -> Result<ServerConfig, TlsError> {
+    let cert_path = config.tls_cert_path.as_deref().unwrap_or_default();
+    let key_path = config.tls_key_path.as_deref().unwrap_or_default();
+
+    let certs = load_certs(cert_path)?;
+    let key =
+        PrivateKeyDer::from_pem_file(key_path).map_err(|e| TlsError::Pem(key_path.into(), e))?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let versions: &[&rustls::SupportedProtocolVersion] = match config.tls_min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let builder =
+        ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+
+    let builder = match &config.tls_client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots.add(cert)?;
+            }
+            let mut verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            if !config.tls_client_auth_required {
+                verifier = verifier.allow_unauthenticated();
+            }
+            builder.with_client_cert_verifier(verifier.build().map_err(TlsError::Verifier)?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    server_config.alpn_protocols = if config.tls_alpn_h2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    Ok(server_config)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Pem(path.into(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.into()));
+    }
+    Ok(certs)
+}
+
+/// A TLS configuration that can be swapped while the server is running.
+#[derive(Clone)]
+pub struct ReloadableTlsConfig {
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl ReloadableTlsConfig {
+    /// Loads the initial configuration.
+    pub fn load(config: &Config) -> Result<Self, TlsError> {
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(load_server_config(config)?))),
+        })
+    }
+
+    /// Returns the configuration used for new connections.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn replace(&self, server_config: ServerConfig) {
+        *self.current.write().unwrap() = Arc::new(server_config);
+    }
+
+    /// Spawns a task that polls the certificate, key and client CA files
+    /// every `TLS_RELOAD_INTERVAL_SECS` and reloads them when any of their
+    /// modification times change.
+    ///
+    /// A failed reload is logged and the previous configuration stays active.
+    pub fn spawn_reloader(&self, config: Arc<Config>) {
+        let reloadable = self.clone();
+        let interval = Duration::from_secs(config.tls_reload_interval_secs.max(1));
+
+        tokio::spawn(async move {
+            let mut last_seen = file_stamps(&config);
+            loop {
+                tokio::time::sleep(interval).await;
+                let stamps = file_stamps(&config);
+                if stamps == last_seen {
+                    continue;
+                }
+                last_seen = stamps;
+
+                match load_server_config(&config) {
+                    Ok(server_config) => {
+                        reloadable.replace(server_config);
+                        tracing::info!("Reloaded TLS certificates");
+                    }
+                    Err(e) => tracing::error!("Failed to reload TLS certificates: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn file_stamps(config: &Config) -> Vec<Option<SystemTime>> {
+    [
+        &config.tls_cert_path,
+        &config.tls_key_path,
+        &config.tls_client_ca_path,
+    ]
+    .iter()
+    .map(|path| {
+        path.as_ref()
+            .and_then(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+    })
+    .collect()
+}
+
+/// Accepts TCP connections and completes TLS handshakes in the background.
+///
+/// Handshakes run in their own tasks so a slow client cannot hold up the
+/// accept loop.
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, PeerInfo)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Starts accepting TLS connections on `listener`.
+    pub fn new(
+        listener: TcpListener,
+        tls: ReloadableTlsConfig,
+        tenants: Arc<HashMap<String, String>>,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    _ = tx.closed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            tracing::error!("Failed to accept connection: {}", e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+
+                let acceptor = TlsAcceptor::from(tls.current());
+                let tx = tx.clone();
+                let tenants = tenants.clone();
+                tokio::spawn(async move {
+                    let stream = match tokio::time::timeout(
+                        HANDSHAKE_TIMEOUT,
+                        acceptor.accept(stream),
+                    )
+                    .await
+                    {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            tracing::debug!("TLS handshake with {} failed: {}", addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::debug!("TLS handshake with {} timed out", addr);
+                            return;
+                        }
+                    };
+
+                    let identity = client_identity(&stream, &tenants);
+                    let _ = tx.send((stream, PeerInfo { addr, identity })).await;
+                });
+            }
+        });
+
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = PeerInfo;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(connection) => connection,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(PeerInfo {
+            addr: self.local_addr,
+            identity: None,
+        })
+    }
+}
+
+/// Extracts the identity from a verified client certificate, if any.
+fn client_identity(
+    stream: &TlsStream<TcpStream>,
+    tenants: &HashMap<String, String>,
+) -> Option<ClientIdentity> {
+    let der = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+
+    let common_name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(str::to_string);
+    let tenant = common_name.as_ref().and_then(|cn| tenants.get(cn)).cloned();
+
+    Some(ClientIdentity {
+        subject: cert.subject().to_string(),
+        common_name,
+        tenant,
+    })
+}
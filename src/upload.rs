@@ -0,0 +1,588 @@
+//! # Uploads
+//!
+//! Size-limited reading of multipart fields and of images fetched by URL.
+//! Small images stay in memory; larger ones are spooled to an anonymous
+//! temporary file and streamed to the worker from disk, so concurrent large
+//! uploads do not exhaust memory.
+//!
+//! URLs given by clients are fetched with the client built by
+//! [`fetch_client`], which does not follow redirects and, when private
+//! networks are denied, only connects to public addresses.
+
+use crate::config::Config;
+use axum::{
+    extract::multipart::{Field, MultipartError},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr};
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+/// Limits applied while reading multipart fields.
+#[derive(Clone, Debug)]
+pub struct UploadLimits {
+    /// Maximum size of an image field, in bytes.
+    pub max_image_bytes: usize,
+    /// Maximum size of a text field, in bytes.
+    pub max_text_bytes: usize,
+    /// Image fields larger than this are spooled to disk, in bytes.
+    pub spool_threshold: usize,
+    /// Directory for spooled files; the system temp directory when `None`.
+    pub temp_dir: Option<String>,
+    /// Reject URLs whose host resolves to a private or local address.
+    pub deny_private_networks: bool,
+}
+
+impl UploadLimits {
+    /// Reads the upload limits from the configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_image_bytes: config.max_image_field_bytes,
+            max_text_bytes: config.max_text_field_bytes,
+            spool_threshold: config.upload_spool_threshold_bytes,
+            temp_dir: config.upload_temp_dir.clone(),
+            deny_private_networks: config.fetch_deny_private_networks,
+        }
+    }
+}
+
+/// Error raised while reading an upload.
+#[derive(Debug)]
+pub enum UploadError {
+    /// A field exceeded its size limit.
+    TooLarge {
+        /// Name of the offending field.
+        field: String,
+        /// Limit that was exceeded, in bytes.
+        limit: usize,
+    },
+    /// The multipart stream was malformed or exceeded the body limit.
+    Multipart(MultipartError),
+    /// A text field was not valid UTF-8.
+    InvalidText(String),
+    /// The image URL was invalid or could not be fetched.
+    Fetch(String),
+    /// Spooling to disk failed.
+    Io(io::Error),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::TooLarge { field, limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Field '{}' exceeds the limit of {} bytes", field, limit),
+            )
+                .into_response(),
+            Self::Multipart(e) => {
+                (e.status(), format!("Invalid multipart request: {}", e)).into_response()
+            }
+            Self::InvalidText(field) => (
+                StatusCode::BAD_REQUEST,
+                format!("Field '{}' must be valid UTF-8", field),
+            )
+                .into_response(),
+            Self::Fetch(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to fetch image: {}", reason),
+            )
+                .into_response(),
+            Self::Io(e) => {
+                tracing::error!("Failed to spool upload: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store upload").into_response()
+            }
+        }
+    }
+}
+
+impl From<MultipartError> for UploadError {
+    fn from(e: MultipartError) -> Self {
+        Self::Multipart(e)
+    }
+}
+
+impl From<reqwest::Error> for UploadError {
+    fn from(e: reqwest::Error) -> Self {
+        tracing::warn!("Fetching an image failed: {}", e.without_url());
+        Self::Fetch("the source could not be reached".to_string())
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+enum Storage {
+    Memory(Bytes),
+    File(File),
+}
+
+/// An uploaded body held in memory or in a temporary file.
+///
+/// Temporary files are unlinked on creation and disappear once dropped.
+pub struct SpooledBody {
+    storage: Storage,
+    len: u64,
+}
+
+impl SpooledBody {
+    /// Wraps bytes that are already in memory.
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Self {
+            len: bytes.len() as u64,
+            storage: Storage::Memory(bytes),
+        }
+    }
+
+    /// Reads an image field, enforcing `limits.max_image_bytes` and spooling
+    /// to disk once `limits.spool_threshold` is exceeded.
+    pub async fn from_field(field: Field<'_>, limits: &UploadLimits) -> Result<Self, UploadError> {
+        let name = field.name().unwrap_or_default().to_string();
+        Self::spool(field, name, limits).await
+    }
+
+    /// Reads `reader` with the same limits as an upload, on a blocking
+    /// thread; a body above `limits.max_image_bytes` is reported as `name`.
+    pub fn from_reader(
+        reader: impl Read,
+        name: &str,
+        limits: &UploadLimits,
+    ) -> Result<Self, UploadError> {
+        let mut reader = reader.take(limits.max_image_bytes as u64 + 1);
+        let mut buffer = Vec::new();
+        let mut len = (&mut reader)
+            .take(limits.spool_threshold as u64 + 1)
+            .read_to_end(&mut buffer)?;
+        let storage = if len > limits.spool_threshold {
+            let mut file = temp_file(limits)?;
+            file.write_all(&buffer)?;
+            len += io::copy(&mut reader, &mut file)? as usize;
+            file.flush()?;
+            file.seek(SeekFrom::Start(0))?;
+            Storage::File(File::from_std(file))
+        } else {
+            Storage::Memory(Bytes::from(buffer))
+        };
+        if len > limits.max_image_bytes {
+            return Err(UploadError::TooLarge {
+                field: name.to_string(),
+                limit: limits.max_image_bytes,
+            });
+        }
+        Ok(Self {
+            storage,
+            len: len as u64,
+        })
+    }
+
+    /// Downloads the image at `url` with the same limits as an upload.
+    ///
+    /// The URL must pass [`check_url`], and `client` should come from
+    /// [`fetch_client`]. Upstream statuses and connection errors are not
+    /// passed on to the caller, so the gateway cannot be used to probe
+    /// other hosts.
+    pub async fn fetch(
+        client: &reqwest::Client,
+        url: &str,
+        limits: &UploadLimits,
+    ) -> Result<Self, UploadError> {
+        let url = check_url(url, limits.deny_private_networks)
+            .await
+            .map_err(UploadError::Fetch)?;
+
+        let res = client.get(url).send().await?;
+        if !res.status().is_success() {
+            tracing::warn!("Image source returned {}", res.status());
+            return Err(UploadError::Fetch(
+                "the source returned an error".to_string(),
+            ));
+        }
+        if res
+            .content_length()
+            .is_some_and(|len| len > limits.max_image_bytes as u64)
+        {
+            return Err(UploadError::TooLarge {
+                field: "url".to_string(),
+                limit: limits.max_image_bytes,
+            });
+        }
+
+        Self::spool(res.bytes_stream(), "url".to_string(), limits).await
+    }
+
+    async fn spool<S, E>(
+        stream: S,
+        name: String,
+        limits: &UploadLimits,
+    ) -> Result<Self, UploadError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        UploadError: From<E>,
+    {
+        let mut stream = std::pin::pin!(stream);
+        let mut buffer = BytesMut::new();
+        let mut file: Option<File> = None;
+        let mut len = 0usize;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            len += chunk.len();
+            if len > limits.max_image_bytes {
+                return Err(UploadError::TooLarge {
+                    field: name,
+                    limit: limits.max_image_bytes,
+                });
+            }
+
+            match file.as_mut() {
+                Some(file) => file.write_all(&chunk).await?,
+                None if len > limits.spool_threshold => {
+                    let mut spooled = File::from_std(temp_file(limits)?);
+                    spooled.write_all(&buffer).await?;
+                    spooled.write_all(&chunk).await?;
+                    buffer = BytesMut::new();
+                    file = Some(spooled);
+                }
+                None => buffer.extend_from_slice(&chunk),
+            }
+        }
+
+        let storage = match file {
+            Some(mut file) => {
+                file.flush().await?;
+                file.seek(SeekFrom::Start(0)).await?;
+                Storage::File(file)
+            }
+            None => Storage::Memory(buffer.freeze()),
+        };
+
+        Ok(Self {
+            storage,
+            len: len as u64,
+        })
+    }
+
+    /// Size of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the body was spooled to disk.
+    pub fn is_spooled(&self) -> bool {
+        matches!(self.storage, Storage::File(_))
+    }
+
+    /// Opens a blocking reader over the body, for use on a blocking thread.
+    ///
+    /// Reading from a spooled body moves the shared file position; call
+    /// [`SpooledBody::rewind`] before streaming it.
+    pub async fn reader(&self) -> io::Result<SpooledReader> {
+        match &self.storage {
+            Storage::Memory(bytes) => Ok(SpooledReader::Memory(Cursor::new(bytes.clone()))),
+            Storage::File(file) => {
+                let mut file = file.try_clone().await?.into_std().await;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(SpooledReader::File(BufReader::new(file)))
+            }
+        }
+    }
+
+    /// Moves a spooled body back to its start.
+    pub async fn rewind(&mut self) -> io::Result<()> {
+        if let Storage::File(file) = &mut self.storage {
+            file.seek(SeekFrom::Start(0)).await?;
+        }
+        Ok(())
+    }
+
+    /// Converts the body into a request body for the worker, streaming from
+    /// disk when spooled.
+    pub fn into_reqwest_body(self) -> reqwest::Body {
+        match self.storage {
+            Storage::Memory(bytes) => reqwest::Body::from(bytes),
+            Storage::File(file) => reqwest::Body::wrap_stream(ReaderStream::new(file)),
+        }
+    }
+}
+
+/// Blocking reader returned by [`SpooledBody::reader`].
+pub enum SpooledReader {
+    /// Reader over an in-memory body.
+    Memory(Cursor<Bytes>),
+    /// Reader over a spooled file.
+    File(BufReader<std::fs::File>),
+}
+
+impl Read for SpooledReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(reader) => reader.read(buf),
+            Self::File(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl BufRead for SpooledReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::Memory(reader) => reader.fill_buf(),
+            Self::File(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Self::Memory(reader) => reader.consume(amount),
+            Self::File(reader) => reader.consume(amount),
+        }
+    }
+}
+
+impl Seek for SpooledReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Memory(reader) => Seek::seek(reader, pos),
+            Self::File(reader) => Seek::seek(reader, pos),
+        }
+    }
+}
+
+/// Checks that the gateway may fetch `url`, returning the reason if not.
+///
+/// Only `http` and `https` URLs are accepted. With `deny_private`, hosts
+/// resolving to loopback, private, link-local or otherwise non-public
+/// addresses are rejected as well. Host names are resolved again when the
+/// client from [`fetch_client`] connects, and checked once more then, so a
+/// name that changes its address after this check is still refused.
+pub async fn check_url(url: &str, deny_private: bool) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported URL scheme '{}'", url.scheme()));
+    }
+    if !deny_private {
+        return Ok(url);
+    }
+    let host = url.host_str().ok_or("URL has no host")?;
+    let addrs: Vec<IpAddr> = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(80);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("cannot resolve '{}': {}", host, e))?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    if addrs.iter().any(|ip| !is_public(*ip)) {
+        return Err("URL points to a private or local address".to_string());
+    }
+    Ok(url)
+}
+
+/// Builds the HTTP client used for URLs given by clients: image URLs and
+/// webhook callbacks.
+///
+/// It never follows redirects. With `deny_private`, host names are resolved
+/// by a resolver that refuses non-public addresses, so the client connects
+/// to the address that was checked, and proxies are bypassed since they
+/// would resolve names themselves.
+pub fn fetch_client(deny_private: bool) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if deny_private {
+        builder = builder.dns_resolver(PublicResolver).no_proxy();
+    }
+    builder
+        .build()
+        .expect("failed to build the fetch HTTP client")
+}
+
+/// DNS resolver refusing names with a non-public address.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("'{}' resolves to a private or local address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Returns `false` for loopback, private, link-local, shared, documentation,
+/// benchmarking, multicast, reserved and unspecified addresses.
+///
+/// IPv6 addresses embedding an IPv4 address (mapped, IPv4-compatible, NAT64
+/// and 6to4) are judged by that address; Teredo addresses, whose IPv4 server
+/// and client cannot be checked, are refused.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 192 && b == 88 && c == 99))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                is_public(IpAddr::from([a, b, c, d]))
+            };
+            // IPv4-compatible addresses, other than `::` and `::1`.
+            if segments[..6] == [0; 6] && !ip.is_loopback() && !ip.is_unspecified() {
+                return embedded(segments[6], segments[7]);
+            }
+            // NAT64 addresses embed the IPv4 address they translate to.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return embedded(segments[6], segments[7]);
+            }
+            // 6to4 addresses embed the IPv4 address of their gateway.
+            if segments[0] == 0x2002 {
+                return embedded(segments[1], segments[2]);
+            }
+            let first = segments[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && segments[1] == 0x0db8)
+                || (first == 0x2001 && segments[1] == 0)
+                || (first == 0x64 && segments[1] == 0xff9b && segments[2] == 1)
+                || (first == 0x100 && segments[1..4] == [0, 0, 0]))
+        }
+    }
+}
+
+/// Reads a text field, enforcing `limits.max_text_bytes`.
+pub async fn read_text_field(
+    mut field: Field<'_>,
+    limits: &UploadLimits,
+) -> Result<String, UploadError> {
+    let name = field.name().unwrap_or_default().to_string();
+    let mut buffer = Vec::new();
+
+    while let Some(chunk) = field.chunk().await? {
+        if buffer.len() + chunk.len() > limits.max_text_bytes {
+            return Err(UploadError::TooLarge {
+                field: name,
+                limit: limits.max_text_bytes,
+            });
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(buffer).map_err(|_| UploadError::InvalidText(name))
+}
+
+fn temp_file(limits: &UploadLimits) -> io::Result<std::fs::File> {
+    match &limits.temp_dir {
+        Some(dir) => tempfile::tempfile_in(dir),
+        None => tempfile::tempfile(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public;
+    use std::net::IpAddr;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn test_non_public_ipv4_ranges_are_blocked() {
+        for ip in [
+            "0.1.2.3",         // 0.0.0.0/8
+            "10.0.0.1",        // private
+            "100.64.0.1",      // shared address space
+            "127.0.0.1",       // loopback
+            "169.254.169.254", // link-local
+            "172.16.0.1",      // private
+            "192.0.0.1",       // IETF protocol assignments
+            "192.0.2.1",       // documentation
+            "192.88.99.1",     // 6to4 relay anycast
+            "192.168.1.1",     // private
+            "198.18.0.1",      // benchmarking
+            "203.0.113.1",     // documentation
+            "224.0.0.1",       // multicast
+            "240.0.0.1",       // reserved
+            "255.255.255.255", // broadcast
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+        assert!(public("93.184.216.34"));
+        assert!(public("192.0.1.1"));
+    }
+
+    #[test]
+    fn test_non_public_ipv6_ranges_are_blocked() {
+        for ip in [
+            "::",                  // unspecified
+            "::1",                 // loopback
+            "::ffff:10.0.0.1",     // IPv4-mapped private
+            "::7f00:1",            // IPv4-compatible loopback
+            "::a9fe:a9fe",         // IPv4-compatible link-local
+            "64:ff9b::a00:1",      // NAT64 of a private address
+            "64:ff9b:1::1",        // local-use NAT64
+            "100::1",              // discard-only
+            "2001::1",             // Teredo
+            "2001:0:4136:e378::1", // Teredo
+            "2001:db8::1",         // documentation
+            "2002:7f00:1::",       // 6to4 of a loopback address
+            "2002:c0a8:101::1",    // 6to4 of a private address
+            "fc00::1",             // unique local
+            "fe80::1",             // link-local
+            "ff02::1",             // multicast
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_embedded_ipv4_addresses_are_judged_by_their_address() {
+        for ip in [
+            "2606:4700::1111",
+            "::ffff:93.184.216.34",
+            "::5db8:d822",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(public(ip), "{}", ip);
+        }
+    }
+}
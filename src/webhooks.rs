@@ -0,0 +1,311 @@
+//! # Webhooks
+//!
+//! Processing requests carrying a `callback_url` are answered right away
+//! with `202 Accepted` and run in the background as a [`Job`]. Once the job
+//! finishes, a JSON event is POSTed to the callback URL, signed with the
+//! tenant's webhook secret, and retried with exponential backoff until the
+//! receiver answers with a 2xx status.
+
+use crate::jobs::{Job, hex, random_id, unix_now};
+use crate::models::{
+    DeliveryAttempt, JobAccepted, JobStatus, WebhookError, WebhookEvent, WebhookOutput,
+    WebhookTiming,
+};
+use crate::state::AppState;
+use crate::tls::ClientIdentity;
+use crate::upload::check_url;
+use aws_lc_rs::hmac;
+use axum::{
+    Json,
+    body::{Body, Bytes, to_bytes},
+    extract::{FromRequest, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Header carrying the event name.
+pub const EVENT_HEADER: &str = "x-nijika-event";
+/// Header carrying the event signature, `t=<unix time>,v1=<hex HMAC>`.
+pub const SIGNATURE_HEADER: &str = "x-nijika-signature";
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    callback_url: Option<String>,
+}
+
+/// Middleware turning requests with a `callback_url` into background jobs.
+///
+/// The callback URL is read from the `callback_url` query parameter or,
+/// for JSON requests, from the body field of the same name, which is
+/// removed before the request reaches the handler. Requests with a
+/// callback URL are buffered in full so they can outlive the connection.
+pub async fn accept_callback(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let query_url = Query::<CallbackQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.callback_url);
+    let is_json = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if query_url.is_none() && !is_json {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = match Bytes::from_request(Request::new(body), &state).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+        }
+        Err(e) => return e.into_response(),
+    };
+    let (bytes, callback_url) = if is_json {
+        match take_callback(bytes) {
+            Ok((bytes, body_url)) => (bytes, body_url.or(query_url)),
+            Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+        }
+    } else {
+        (bytes, query_url)
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let request = Request::from_parts(parts, Body::from(bytes));
+    let Some(callback_url) = callback_url else {
+        return next.run(request).await;
+    };
+
+    let config = &state.config;
+    let url = match check_url(&callback_url, config.fetch_deny_private_networks).await {
+        Ok(url) => url,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid callback URL: {}", e),
+            )
+                .into_response();
+        }
+    };
+    let tenant = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .and_then(|identity| identity.tenant.clone());
+    if config.webhook_secret_for(tenant.as_deref()).is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Callbacks are not enabled for this client",
+        )
+            .into_response();
+    }
+
+    let job = Job::new(
+        random_id(),
+        request.uri().path().to_string(),
+        tenant,
+        url.to_string(),
+    );
+    let id = job.id.clone();
+    let admission = match state.jobs.admit(job) {
+        Ok(admission) => admission,
+        Err(e) => return e.into_response(),
+    };
+    state
+        .jobs
+        .spawn(admission, run_job(state.clone(), id.clone(), request, next));
+
+    let status_url = format!("/jobs/{}", id);
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, status_url.clone())],
+        Json(JobAccepted {
+            job_id: id,
+            status: JobStatus::Pending,
+            status_url,
+        }),
+    )
+        .into_response()
+}
+
+/// Removes the `callback_url` field from a JSON body.
+///
+/// Bodies that are not JSON objects are passed through for the handler to
+/// reject.
+fn take_callback(bytes: Bytes) -> Result<(Bytes, Option<String>), &'static str> {
+    let Ok(Value::Object(mut fields)) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok((bytes, None));
+    };
+    match fields.remove("callback_url") {
+        None => Ok((bytes, None)),
+        Some(Value::Null) => Ok((Bytes::from(Value::Object(fields).to_string()), None)),
+        Some(Value::String(url)) => Ok((Bytes::from(Value::Object(fields).to_string()), Some(url))),
+        Some(_) => Err("callback_url must be a string"),
+    }
+}
+
+/// Processes the request of job `id`, stores its outcome and hands the
+/// webhook event over to a delivery task, releasing the job's running slot.
+async fn run_job(state: AppState, id: String, request: Request, next: Next) {
+    let response = next.run(request).await;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let mut stored = None;
+    if let (Some(storage), 200) = (&state.storage, status) {
+        let content_type = content_type
+            .as_deref()
+            .unwrap_or("application/octet-stream");
+        match storage.put(content_type, body.clone()).await {
+            Ok(result) => {
+                stored = Some(result);
+                body = Bytes::new();
+            }
+            Err(e) => tracing::warn!("Storing the result of job {} failed: {}", id, e),
+        }
+    }
+    state.jobs.finish(&id, status, content_type, body, stored);
+
+    let delivery = state.clone();
+    state
+        .jobs
+        .spawn_delivery(|cancel| deliver_with_retries(delivery, id, cancel));
+}
+
+/// Delivers the event of the finished job `id`, retrying with exponential
+/// backoff.
+///
+/// Once `cancel` fires at shutdown, the pending retry is made right away
+/// and is the last one.
+async fn deliver_with_retries(state: AppState, id: String, cancel: CancellationToken) {
+    let config = &state.config;
+    let base = Duration::from_millis(config.webhook_retry_base_ms);
+    for retry in 0..config.webhook_max_attempts {
+        if retry > 0 {
+            tokio::select! {
+                _ = cancel.cancelled() => {}
+                _ = tokio::time::sleep(base.saturating_mul(1 << (retry - 1).min(16))) => {}
+            }
+        }
+        match deliver(&state, &id, false).await {
+            Some(attempt) if !attempt.delivered => {
+                tracing::warn!(
+                    "webhook delivery {} for job {} failed: {}",
+                    attempt.attempt,
+                    id,
+                    attempt
+                        .error
+                        .unwrap_or_else(|| format!("HTTP {}", attempt.http_status.unwrap_or(0)))
+                );
+                if cancel.is_cancelled() {
+                    return;
+                }
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Makes one delivery attempt for the finished job `id` and records it.
+///
+/// Returns `None` if the job is unknown or still pending.
+pub async fn deliver(state: &AppState, id: &str, manual: bool) -> Option<DeliveryAttempt> {
+    let job = state.jobs.get(id)?;
+    if job.status == JobStatus::Pending {
+        return None;
+    }
+    let secret = state.config.webhook_secret_for(job.tenant.as_deref())?;
+    let event = event(&job);
+    let payload = serde_json::to_vec(&event).unwrap_or_default();
+    let sent_at = unix_now();
+    let started = Instant::now();
+
+    let outcome = async {
+        // Checked again on every attempt: the host may resolve differently now.
+        // The client checks the address it connects to as well.
+        let url = check_url(&job.callback_url, state.config.fetch_deny_private_networks).await?;
+        let response = state
+            .fetch_http
+            .post(url)
+            .timeout(Duration::from_millis(state.config.webhook_timeout_ms))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &event.event)
+            .header(SIGNATURE_HEADER, sign(secret, sent_at, &payload))
+            .body(payload)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok::<_, String>(response.status().as_u16())
+    }
+    .await;
+
+    let (http_status, error) = match outcome {
+        Ok(status) => (Some(status), None),
+        Err(e) => (None, Some(e)),
+    };
+    let attempt = DeliveryAttempt {
+        attempt: 0,
+        sent_at,
+        http_status,
+        delivered: http_status.is_some_and(|status| (200..300).contains(&status)),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+        manual,
+    };
+    Some(state.jobs.record(id, attempt))
+}
+
+/// Builds the event describing the finished `job`.
+fn event(job: &Job) -> WebhookEvent {
+    let succeeded = job.status == JobStatus::Succeeded;
+    WebhookEvent {
+        event: if succeeded {
+            "job.succeeded"
+        } else {
+            "job.failed"
+        }
+        .to_string(),
+        job_id: job.id.clone(),
+        endpoint: job.endpoint.clone(),
+        status: job.status,
+        output: succeeded.then(|| WebhookOutput {
+            url: job.result_url(),
+            content_type: job.content_type.clone().unwrap_or_default(),
+            size: job.result_size(),
+        }),
+        error: job.error().map(|message| WebhookError {
+            code: job.http_status.unwrap_or_default(),
+            message,
+        }),
+        timing: WebhookTiming {
+            created_at: job.created_at,
+            completed_at: job.completed_at.unwrap_or_default(),
+            duration_ms: job.duration_ms.unwrap_or_default(),
+        },
+    }
+}
+
+/// Computes the signature header for `payload` sent at `timestamp`.
+///
+/// The signature is the hex-encoded HMAC-SHA256 of `<timestamp>.<payload>`
+/// keyed with `secret`.
+pub fn sign(secret: &str, timestamp: u64, payload: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let mut context = hmac::Context::with_key(&key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(payload);
+    format!("t={},v1={}", timestamp, hex(context.sign().as_ref()))
+}
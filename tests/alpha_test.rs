@@ -0,0 +1,70 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+/// Left half transparent, right half opaque.
+fn half_transparent(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, _| {
+        let a = if x < width / 2 { 0 } else { 255 };
+        image::Rgba([200, 40, 90, a])
+    }))
+}
+
+async fn upscale(gateway: &str, image: Vec<u8>, scale: &str) -> reqwest::Response {
+    let form = Form::new()
+        .part("image", Part::bytes(image).file_name("image"))
+        .text("scale", scale.to_string());
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_alpha_survives_upscaling() {
+    let worker = common::spawn_upscale_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await;
+
+    let input = common::encode(&half_transparent(32, 16), ImageFormat::Png);
+    let res = upscale(&gateway, input, "2").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(output.dimensions(), (64, 32));
+    assert_eq!(output.get_pixel(2, 16)[3], 0);
+    assert_eq!(output.get_pixel(61, 16)[3], 255);
+
+    let input = common::encode(&half_transparent(32, 16), ImageFormat::WebP);
+    let res = upscale(&gateway, input, "3").await;
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    let output = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert!(output.color().has_alpha());
+    assert_eq!(output.width(), 96);
+}
+
+#[tokio::test]
+async fn test_opaque_input_is_passed_through() {
+    let worker = common::spawn_upscale_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await;
+
+    let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, image::Rgba([1, 2, 3, 255])));
+    let res = upscale(&gateway, common::encode(&opaque, ImageFormat::Png), "2").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/jpeg");
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::post,
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use nijika_api::imaging::classify;
+use nijika_api::models::UpscalerModel;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use std::sync::{Arc, Mutex};
+
+type Seen = Arc<Mutex<Option<String>>>;
+
+/// A worker that records the `X-Model` header it receives and returns the
+/// image unchanged.
+async fn spawn_recording_worker() -> (String, Seen) {
+    async fn handler(
+        State(seen): State<Seen>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        *seen.lock().unwrap() = headers
+            .get("x-model")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        ([(header::CONTENT_TYPE, "image/png")], body)
+    }
+
+    let seen = Seen::default();
+    let app = Router::new()
+        .route("/", post(handler))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(seen.clone());
+    (common::spawn(app).await, seen)
+}
+
+/// Flat-shaded shapes with dark outlines, like a cel-shaded drawing.
+fn illustration() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(200, 160, |x, y| {
+        let (dx, dy) = (x as i32 - 100, y as i32 - 80);
+        let d = dx * dx + dy * dy;
+        if (3300..3700).contains(&d) {
+            Rgb([20, 20, 30])
+        } else if d < 3300 {
+            Rgb([250, 200, 60])
+        } else if y > 130 {
+            Rgb([90, 170, 90])
+        } else {
+            Rgb([140, 200, 250])
+        }
+    }))
+}
+
+/// A textured gradient with sensor-like noise.
+fn photo() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(200, 160, |x, y| {
+        let noise = (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)) % 48;
+        let v = |base: u32| (base + noise).min(255) as u8;
+        Rgb([v(x / 2 + 40), v(y / 2 + 60), v((x + y) / 4 + 30)])
+    }))
+}
+
+async fn upscale(gateway: &str, image: &DynamicImage, model: Option<&str>) -> reqwest::Response {
+    let mut form = Form::new()
+        .part(
+            "image",
+            Part::bytes(common::encode(image, ImageFormat::Png)).file_name("a.png"),
+        )
+        .text("scale", "1");
+    if let Some(model) = model {
+        form = form.text("model", model.to_string());
+    }
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[test]
+fn test_classifier_separates_illustrations_and_photos() {
+    assert_eq!(
+        classify::choose_model(&illustration()),
+        UpscalerModel::RealEsrganX4plusAnime6B
+    );
+    assert_eq!(
+        classify::choose_model(&photo()),
+        UpscalerModel::RealEsrganX4plus
+    );
+}
+
+#[tokio::test]
+async fn test_auto_model_is_resolved_by_the_gateway() {
+    let (worker, seen) = spawn_recording_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await;
+
+    let res = upscale(&gateway, &illustration(), Some("auto")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-model-used"], "RealESRGAN_x4plus_anime_6B");
+    assert_eq!(
+        seen.lock().unwrap().as_deref(),
+        Some("RealESRGAN_x4plus_anime_6B")
+    );
+
+    let res = upscale(&gateway, &photo(), Some("auto")).await;
+    assert_eq!(res.headers()["x-model-used"], "RealESRGAN_x4plus");
+    assert_eq!(seen.lock().unwrap().as_deref(), Some("RealESRGAN_x4plus"));
+
+    let res = upscale(&gateway, &photo(), Some("RealESRGAN_x2plus")).await;
+    assert_eq!(res.headers()["x-model-used"], "RealESRGAN_x2plus");
+
+    let res = upscale(&gateway, &photo(), None).await;
+    assert_eq!(res.headers()["x-model-used"], "RealESRGAN_x4plus_anime_6B");
+    assert_eq!(seen.lock().unwrap().as_deref(), None);
+}
+
+#[tokio::test]
+async fn test_auto_model_skips_disabled_models() {
+    let (worker, seen) = spawn_recording_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        disabled_models: vec!["RealESRGAN_x4plus_anime_6B".to_string()],
+        ..Config::default()
+    })
+    .await;
+
+    let res = upscale(&gateway, &illustration(), Some("auto")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-model-used"], "RealESRGAN_x4plus");
+    assert_eq!(seen.lock().unwrap().as_deref(), Some("RealESRGAN_x4plus"));
+}
@@ -0,0 +1,295 @@
+mod common;
+
+use axum::{Router, body::Bytes, http::header, response::IntoResponse, routing::post};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+fn photo(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbImage::from_pixel(width, height, Rgb([200, 100, 50]));
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway(config: Config) -> String {
+    common::spawn_gateway(Config {
+        modal_removebg_url: common::spawn_half_removebg_worker().await,
+        ..config
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_partial_failures_are_reported_in_manifest() {
+    let gateway = gateway(Config::default()).await;
+
+    let form = Form::new()
+        .part("image", Part::bytes(photo(20, 10)).file_name("a.png"))
+        .part(
+            "image",
+            Part::bytes(b"not an image".to_vec()).file_name("b.png"),
+        )
+        .part("image", Part::bytes(photo(8, 8)).file_name("c.png"));
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let manifest: Value = res.json().await.unwrap();
+    assert_eq!(manifest["succeeded"], 2);
+    assert_eq!(manifest["failed"], 1);
+
+    let items = manifest["items"].as_array().unwrap();
+    assert_eq!(items[0]["source"], "a.png");
+    assert_eq!(items[0]["status"], 200);
+    assert_eq!(items[0]["file"], "001-a-processed.png");
+    assert_eq!(items[0]["content_type"], "image/png");
+    let data = STANDARD.decode(items[0]["data"].as_str().unwrap()).unwrap();
+    let cutout = image::load_from_memory(&data).unwrap().to_rgba8();
+    assert_eq!(cutout.dimensions(), (20, 10));
+    assert_eq!(cutout.get_pixel(2, 5)[3], 255);
+    assert_eq!(cutout.get_pixel(17, 5)[3], 0);
+
+    assert_eq!(items[1]["status"], 422);
+    assert!(items[1]["data"].is_null());
+    assert!(!items[1]["error"].as_str().unwrap().is_empty());
+    assert_eq!(items[2]["index"], 3);
+    assert_eq!(items[2]["width"], 8);
+}
+
+#[tokio::test]
+async fn test_zip_archive_in_and_out() {
+    // Entries are spooled to disk like uploads, with the same size limit.
+    let gateway = gateway(Config {
+        upload_spool_threshold_bytes: 64,
+        max_image_field_bytes: 2048,
+        ..Config::default()
+    })
+    .await;
+
+    let mut upload = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, bytes) in [
+        ("photos/one.png", photo(40, 20)),
+        ("photos/.DS_Store", b"junk".to_vec()),
+        ("two.png", photo(10, 10)),
+        ("big.png", vec![0; 4096]),
+    ] {
+        upload
+            .start_file(name, SimpleFileOptions::default())
+            .unwrap();
+        upload.write_all(&bytes).unwrap();
+    }
+    let upload = upload.finish().unwrap().into_inner();
+
+    let steps = json!([{"op": "resize", "width": 5}, {"op": "format", "format": "jpeg"}]);
+    let form = Form::new()
+        .part("archive", Part::bytes(upload).file_name("photos.zip"))
+        .text("steps", steps.to_string());
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .header("accept", "application/zip")
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/zip");
+
+    let mut archive = ZipArchive::new(Cursor::new(res.bytes().await.unwrap())).unwrap();
+    let mut files = HashMap::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).unwrap();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).unwrap();
+        files.insert(entry.name().to_string(), bytes);
+    }
+    assert_eq!(files.len(), 3);
+    let one = image::load_from_memory(&files["001-one-processed.jpg"]).unwrap();
+    assert_eq!((one.width(), one.height()), (5, 3));
+    let two = image::load_from_memory(&files["002-two-processed.jpg"]).unwrap();
+    assert_eq!((two.width(), two.height()), (5, 5));
+
+    let manifest: Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+    assert_eq!(manifest["succeeded"], 2);
+    assert_eq!(manifest["items"][0]["source"], "photos/one.png");
+    assert!(manifest["items"][0]["data"].is_null());
+    assert_eq!(manifest["items"][2]["status"], 413);
+}
+
+#[tokio::test]
+async fn test_accept_selects_result_format() {
+    let gateway = gateway(Config::default()).await;
+    let url = common::spawn_image_source(photo(10, 10)).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .header("accept", "application/json, image/webp")
+        .json(&json!({"urls": [url]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let manifest: Value = res.json().await.unwrap();
+    let item = &manifest["items"][0];
+    assert_eq!(item["content_type"], "image/webp");
+    assert!(item["file"].as_str().unwrap().ends_with(".webp"));
+    let data = STANDARD.decode(item["data"].as_str().unwrap()).unwrap();
+    assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::WebP);
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .header("accept", "application/zip, image/png;q=0.5, image/jpeg")
+        .json(&json!({"urls": [url]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/zip");
+    let archive = ZipArchive::new(Cursor::new(res.bytes().await.unwrap())).unwrap();
+    assert!(archive.file_names().any(|name| name.ends_with(".jpg")));
+
+    // A header ruling out every image type is refused up front.
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .header("accept", "image/gif")
+        .json(&json!({"urls": [url]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn test_results_beyond_the_size_limit_fail() {
+    let url = common::spawn_image_source(photo(16, 16)).await;
+    let send = |gateway: String| {
+        let url = url.clone();
+        async move {
+            let res = reqwest::Client::new()
+                .post(format!("{}/batch", gateway))
+                .json(&json!({"urls": [url, url, url]}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            res.json::<Value>().await.unwrap()
+        }
+    };
+
+    let manifest = send(gateway(Config::default()).await).await;
+    assert_eq!(manifest["succeeded"], 3);
+    let size = STANDARD
+        .decode(manifest["items"][0]["data"].as_str().unwrap())
+        .unwrap()
+        .len();
+
+    // Room for two results: the last image processed fails on its own.
+    let manifest = send(
+        gateway(Config {
+            batch_max_result_bytes: size * 2 + 1,
+            batch_concurrency: 1,
+            ..Config::default()
+        })
+        .await,
+    )
+    .await;
+    assert_eq!(manifest["succeeded"], 2);
+    let item = &manifest["items"][2];
+    assert_eq!(item["status"], 507);
+    assert!(item["data"].is_null());
+    assert_eq!(
+        item["error"],
+        format!("The batch results exceed {} bytes", size * 2 + 1)
+    );
+}
+
+#[tokio::test]
+async fn test_item_limits() {
+    let gateway = gateway(Config {
+        batch_max_items: 2,
+        ..Config::default()
+    })
+    .await;
+    let url = common::spawn_image_source(photo(10, 10)).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .json(&json!({"urls": [url, url, url]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.text().await.unwrap(), "A batch holds at most 2 images");
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .json(&json!({"urls": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // A URL that cannot be fetched fails only its own item.
+    let res = reqwest::Client::new()
+        .post(format!("{}/batch", gateway))
+        .json(&json!({"urls": [url, "ftp://example.com/a.png"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let manifest: Value = res.json().await.unwrap();
+    assert_eq!(manifest["succeeded"], 1);
+    assert_eq!(manifest["items"][1]["status"], 400);
+
+    // Clients without a tenant share one concurrency budget.
+    let in_flight = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+    let counter = in_flight.clone();
+    let worker = common::spawn(Router::new().route(
+        "/",
+        post(move |body: Bytes| {
+            let counter = counter.clone();
+            async move {
+                let now = counter.0.fetch_add(1, Ordering::SeqCst) + 1;
+                counter.1.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                counter.0.fetch_sub(1, Ordering::SeqCst);
+                ([(header::CONTENT_TYPE, "image/png")], body).into_response()
+            }
+        }),
+    ))
+    .await;
+    let gateway = common::spawn_gateway(Config {
+        modal_removebg_url: worker,
+        batch_concurrency: 1,
+        ..Config::default()
+    })
+    .await;
+    let send = || {
+        reqwest::Client::new()
+            .post(format!("{}/batch", gateway))
+            .json(&json!({"urls": [url, url]}))
+            .send()
+    };
+    let (first, second) = tokio::join!(send(), send());
+    assert_eq!(first.unwrap().status(), StatusCode::OK);
+    assert_eq!(second.unwrap().status(), StatusCode::OK);
+    assert_eq!(in_flight.1.load(Ordering::SeqCst), 1);
+
+    let config = Config {
+        batch_tenant_max_items: HashMap::from([("acme".to_string(), 50)]),
+        ..Config::default()
+    };
+    assert_eq!(config.batch_max_items_for(Some("acme")), 50);
+    assert_eq!(config.batch_max_items_for(Some("other")), 20);
+    assert_eq!(config.batch_max_items_for(None), 20);
+}
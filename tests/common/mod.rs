@@ -0,0 +1,254 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::DefaultBodyLimit,
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use image::{ImageFormat, RgbImage};
+use nijika_api::config::Config;
+use nijika_api::create_router;
+use reqwest::multipart::{Form, Part};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Serves `app` on an ephemeral local port and returns its base URL.
+pub async fn spawn(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap()
+    });
+    format!("http://{}", addr)
+}
+
+/// Starts the gateway with `config` and returns its base URL.
+///
+/// The stand-in image sources and webhook receivers listen on loopback, so
+/// private networks are allowed; see [`spawn_guarded_gateway`].
+pub async fn spawn_gateway(config: Config) -> String {
+    spawn_guarded_gateway(Config {
+        fetch_deny_private_networks: false,
+        ..config
+    })
+    .await
+}
+
+/// Starts the gateway with `config` as is, keeping
+/// `fetch_deny_private_networks`, and returns its base URL.
+pub async fn spawn_guarded_gateway(config: Config) -> String {
+    spawn(create_router(Arc::new(config))).await
+}
+
+/// Starts the gateway with its background removal worker at `worker` and
+/// returns its base URL.
+pub async fn spawn_removebg_gateway(worker: String) -> String {
+    spawn_gateway(Config {
+        modal_removebg_url: worker,
+        ..Config::default()
+    })
+    .await
+}
+
+/// Uploads `image` to the gateway's `/removebg` with the text `fields`.
+pub async fn remove_bg(
+    gateway: &str,
+    image: Vec<u8>,
+    fields: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut form = Form::new();
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    remove_bg_form(gateway, image, form).await
+}
+
+/// Uploads `image` to the gateway's `/removebg` along with the parts of
+/// `form`.
+pub async fn remove_bg_form(gateway: &str, image: Vec<u8>, form: Form) -> reqwest::Response {
+    let form = form.part("image", Part::bytes(image).file_name("image.png"));
+    reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Checks that `res` succeeded and decodes the image it holds.
+pub async fn decode(res: reqwest::Response) -> image::DynamicImage {
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    image::load_from_memory(&res.bytes().await.unwrap()).unwrap()
+}
+
+/// A stand-in worker that echoes the request body back as `image/png`.
+pub async fn spawn_echo_worker() -> String {
+    async fn echo(body: Bytes) -> impl IntoResponse {
+        ([(header::CONTENT_TYPE, "image/png")], body)
+    }
+
+    spawn(
+        Router::new()
+            .route("/", post(echo))
+            .layer(DefaultBodyLimit::disable()),
+    )
+    .await
+}
+
+/// A stand-in upscaler worker that behaves like the real one: it resizes the
+/// body by `X-Scale` (4 by default), drops any alpha channel and answers with
+/// a JPEG.
+pub async fn spawn_upscale_worker() -> String {
+    async fn upscale(headers: HeaderMap, body: Bytes) -> impl IntoResponse {
+        let scale: u32 = headers
+            .get("x-scale")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4);
+        let input = image::load_from_memory(&body).unwrap();
+        let output = input
+            .resize_exact(
+                input.width() * scale,
+                input.height() * scale,
+                image::imageops::FilterType::Triangle,
+            )
+            .to_rgb8();
+        let mut out = Cursor::new(Vec::new());
+        output.write_to(&mut out, ImageFormat::Jpeg).unwrap();
+        ([(header::CONTENT_TYPE, "image/jpeg")], out.into_inner())
+    }
+
+    spawn(
+        Router::new()
+            .route("/", post(upscale))
+            .layer(DefaultBodyLimit::disable()),
+    )
+    .await
+}
+
+/// A stand-in background removal worker that treats the left half of the
+/// image as the subject. Like the real one, it keeps the original colors and
+/// only replaces the alpha channel.
+pub async fn spawn_half_removebg_worker() -> String {
+    async fn remove(body: Bytes) -> impl IntoResponse {
+        let mut output = image::load_from_memory(&body).unwrap().to_rgba8();
+        let width = output.width();
+        for (x, _, pixel) in output.enumerate_pixels_mut() {
+            pixel[3] = if x < width / 2 { 255 } else { 0 };
+        }
+        (
+            [(header::CONTENT_TYPE, "image/png")],
+            encode(&image::DynamicImage::ImageRgba8(output), ImageFormat::Png),
+        )
+    }
+
+    spawn(
+        Router::new()
+            .route("/", post(remove))
+            .layer(DefaultBodyLimit::disable()),
+    )
+    .await
+}
+
+/// A stand-in background removal worker that treats every non-black pixel
+/// as the subject.
+pub async fn spawn_subject_removebg_worker() -> String {
+    async fn remove(body: Bytes) -> impl IntoResponse {
+        let mut output = image::load_from_memory(&body).unwrap().to_rgba8();
+        for pixel in output.pixels_mut() {
+            pixel[3] = if pixel.0[..3] == [0, 0, 0] { 0 } else { 255 };
+        }
+        (
+            [(header::CONTENT_TYPE, "image/png")],
+            encode(&image::DynamicImage::ImageRgba8(output), ImageFormat::Png),
+        )
+    }
+
+    spawn(
+        Router::new()
+            .route("/", post(remove))
+            .layer(DefaultBodyLimit::disable()),
+    )
+    .await
+}
+
+/// Serves `body` at `/image` and returns its full URL.
+pub async fn spawn_image_source(body: Vec<u8>) -> String {
+    let app = Router::new().route(
+        "/image",
+        get(move || async move { ([(header::CONTENT_TYPE, "image/png")], body) }),
+    );
+    format!("{}/image", spawn(app).await)
+}
+
+/// Encodes a `width`x`height` PNG filled with noise, so it does not compress.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let v = (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)) as u8;
+        image::Rgb([v, v.wrapping_mul(3), v.wrapping_mul(7)])
+    });
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+/// Builds a tiny PNG whose header declares `width`x`height` pixels but that
+/// carries almost no pixel data, like a decompression bomb.
+pub fn png_header_only(width: u32, height: u32) -> Vec<u8> {
+    fn chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let crc = crc32(&out[start..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(
+        &mut out,
+        b"IDAT",
+        &[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01],
+    );
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Encodes `image` as `format`.
+pub fn encode(image: &image::DynamicImage, format: ImageFormat) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, format).unwrap();
+    out.into_inner()
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::post,
+};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use std::sync::{Arc, Mutex};
+
+type Seen = Arc<Mutex<Option<String>>>;
+
+/// A worker that records the `X-Denoise-Strength` header it receives and
+/// returns the image unchanged.
+async fn spawn_recording_worker() -> (String, Seen) {
+    async fn handler(
+        State(seen): State<Seen>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        *seen.lock().unwrap() = headers
+            .get("x-denoise-strength")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        ([(header::CONTENT_TYPE, "image/png")], body)
+    }
+
+    let seen = Seen::default();
+    let app = Router::new()
+        .route("/", post(handler))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(seen.clone());
+    (common::spawn(app).await, seen)
+}
+
+async fn upscale(gateway: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+    let mut form = Form::new().part("image", Part::bytes(common::png(8, 8)).file_name("a.png"));
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_denoise_strength_is_forwarded() {
+    let (worker, seen) = spawn_recording_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await;
+
+    let res = upscale(
+        &gateway,
+        &[
+            ("model", "realesr-general-x4v3"),
+            ("denoise_strength", "0.2"),
+            ("scale", "1"),
+        ],
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(seen.lock().unwrap().as_deref(), Some("0.2"));
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&serde_json::json!({
+            "url": common::spawn_image_source(common::png(8, 8)).await,
+            "model": "realesr-general-x4v3",
+            "denoise_strength": 1.0,
+            "scale": 1
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(seen.lock().unwrap().as_deref(), Some("1"));
+
+    upscale(&gateway, &[("scale", "1")]).await;
+    assert_eq!(seen.lock().unwrap().as_deref(), None);
+}
+
+#[tokio::test]
+async fn test_invalid_denoise_strength() {
+    let (worker, seen) = spawn_recording_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await;
+
+    for value in ["1.5", "-0.1", "strong", "NaN"] {
+        let res = upscale(
+            &gateway,
+            &[
+                ("model", "realesr-general-x4v3"),
+                ("denoise_strength", value),
+            ],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", value);
+        assert_eq!(
+            res.text().await.unwrap(),
+            "Denoise strength must be between 0 and 1"
+        );
+    }
+
+    let res = upscale(
+        &gateway,
+        &[("model", "RealESRGAN_x4plus"), ("denoise_strength", "0.5")],
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "denoise_strength is only supported by the realesr-general-x4v3 model"
+    );
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&serde_json::json!({ "url": "http://127.0.0.1:1/a.png", "denoise_strength": 0.5 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "denoise_strength is only supported by the realesr-general-x4v3 model"
+    );
+
+    assert_eq!(*seen.lock().unwrap(), None);
+}
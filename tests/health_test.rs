@@ -0,0 +1,121 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+    routing::post,
+};
+use nijika_api::config::Config;
+use nijika_api::{create_router_with_state, state::AppState};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .uri(uri)
+        .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+/// Starts a stand-in worker that only accepts POST, like the Modal endpoints.
+async fn spawn_worker() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/", post(|| async { "ok" }));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+#[tokio::test]
+async fn test_liveness_and_readiness_before_any_probe() {
+    let config = Arc::new(Config {
+        modal_removebg_url: "http://127.0.0.1:1".to_string(),
+        modal_upscaler_url: "http://127.0.0.1:1".to_string(),
+        ..Config::default()
+    });
+    let app = create_router_with_state(AppState::new(config));
+
+    let (status, body) = get_json(&app, "/livez").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "alive");
+
+    // Nothing has been probed yet, so workers are assumed available.
+    let (status, body) = get_json(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+}
+
+#[tokio::test]
+async fn test_unreachable_workers_fail_details_and_readiness() {
+    let config = Arc::new(Config {
+        modal_removebg_url: "http://127.0.0.1:1".to_string(),
+        modal_upscaler_url: "http://127.0.0.1:1".to_string(),
+        health_probe_timeout_ms: 500,
+        ..Config::default()
+    });
+    let app = create_router_with_state(AppState::new(config));
+
+    let (status, body) = get_json(&app, "/health/details").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["build"]["version"], env!("CARGO_PKG_VERSION"));
+    let deps = body["dependencies"].as_array().unwrap();
+    assert_eq!(deps.len(), 2);
+    assert!(deps.iter().all(|d| d["status"] == "down"));
+
+    let (status, body) = get_json(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["workers_available"], false);
+}
+
+#[tokio::test]
+async fn test_details_reports_reachable_worker_and_caches() {
+    let worker = spawn_worker().await;
+    let config = Arc::new(Config {
+        modal_removebg_url: worker,
+        modal_upscaler_url: "http://127.0.0.1:1".to_string(),
+        health_probe_timeout_ms: 500,
+        ..Config::default()
+    });
+    let app = create_router_with_state(AppState::new(config));
+
+    let (status, body) = get_json(&app, "/health/details").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "degraded");
+    let removebg = &body["dependencies"][0];
+    assert_eq!(removebg["name"], "removebg");
+    assert_eq!(removebg["status"], "up");
+    assert_eq!(removebg["http_status"], 405);
+    assert_eq!(removebg["cached"], false);
+
+    let (_, body) = get_json(&app, "/health/details").await;
+    assert_eq!(body["dependencies"][0]["cached"], true);
+
+    let (status, _) = get_json(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_draining_fails_readiness() {
+    let state = AppState::new(Arc::new(Config::default()));
+    let app = create_router_with_state(state.clone());
+
+    state.begin_drain();
+
+    let (status, body) = get_json(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["draining"], true);
+
+    let (status, _) = get_json(&app, "/livez").await;
+    assert_eq!(status, StatusCode::OK);
+}
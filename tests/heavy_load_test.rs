@@ -15,12 +15,9 @@ async fn test_heavy_load_health_check() {
     // Configure with a known limit to predict behavior
     // 100 requests per second, burst of 50
     let config = Arc::new(Config {
-        host: "127.0.0.1".to_string(),
-        port: 3000,
-        modal_removebg_url: "http://localhost:8000".to_string(),
-        modal_upscaler_url: "http://localhost:8001".to_string(),
         rate_limit_per_second: 100,
         rate_limit_burst: 50,
+        ..Config::default()
     });
 
     let app = create_router(config);
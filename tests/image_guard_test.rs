@@ -0,0 +1,108 @@
+mod common;
+
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+async fn post_image(gateway: &str, path: &str, form: Form) -> (StatusCode, String) {
+    let res = reqwest::Client::new()
+        .post(format!("{}{}", gateway, path))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    (res.status(), res.text().await.unwrap())
+}
+
+fn image_form(image: Vec<u8>) -> Form {
+    Form::new().part("image", Part::bytes(image).file_name("image.png"))
+}
+
+#[tokio::test]
+async fn test_declared_dimensions_are_rejected_before_the_worker() {
+    let gateway = common::spawn_gateway(Config::default()).await;
+
+    let bomb = common::png_header_only(50000, 50000);
+    assert!(bomb.len() < 100);
+    let (status, body) = post_image(&gateway, "/removebg", image_form(bomb)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body, "Image width of 50000 px exceeds the limit of 8192 px");
+
+    let (status, body) = post_image(
+        &gateway,
+        "/upscale",
+        image_form(common::png_header_only(100, 9000)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body, "Image height of 9000 px exceeds the limit of 8192 px");
+
+    let (status, body) = post_image(&gateway, "/removebg", image_form(b"GIF00".to_vec())).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body.starts_with("Unrecognized image format"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_megapixel_limits() {
+    let gateway = common::spawn_gateway(Config {
+        max_image_megapixels: 1.0,
+        max_upscale_output_megapixels: 4.0,
+        ..Config::default()
+    })
+    .await;
+
+    let (status, body) = post_image(
+        &gateway,
+        "/removebg",
+        image_form(common::png_header_only(1200, 1000)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body,
+        "Image of 1200x1000 (1.2 megapixels) exceeds the limit of 1 megapixels"
+    );
+
+    let form = image_form(common::png_header_only(600, 500)).text("scale", "4");
+    let (status, body) = post_image(&gateway, "/upscale", form).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body,
+        "Upscaling 600x500 by 4x would produce 2400x2000 (4.8 megapixels), exceeding the limit of 4 megapixels"
+    );
+}
+
+#[tokio::test]
+async fn test_fetched_urls_are_inspected() {
+    let worker = common::spawn_echo_worker().await;
+    let gateway = common::spawn_removebg_gateway(worker).await;
+    let client = reqwest::Client::new();
+
+    let bomb = common::spawn_image_source(common::png_header_only(50000, 50000)).await;
+    let res = client
+        .post(format!("{}/removebg", gateway))
+        .json(&serde_json::json!({ "url": bomb }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let image = common::png(32, 32);
+    let source = common::spawn_image_source(image.clone()).await;
+    let res = client
+        .post(format!("{}/removebg", gateway))
+        .json(&serde_json::json!({ "url": source }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.bytes().await.unwrap().as_ref(), image.as_slice());
+
+    let res = client
+        .post(format!("{}/removebg", gateway))
+        .json(&serde_json::json!({ "url": "file:///etc/passwd" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use axum::{Router, routing::post};
+use nijika_api::config::Config;
+use nijika_api::create_router_with_state;
+use nijika_api::state::AppState;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A stand-in worker that never answers, keeping jobs running.
+async fn spawn_stuck_worker() -> String {
+    common::spawn(Router::new().route(
+        "/",
+        post(|| async {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }),
+    ))
+    .await
+}
+
+async fn config() -> Config {
+    Config {
+        modal_upscaler_url: spawn_stuck_worker().await,
+        webhook_secret: Some("s3cret".to_string()),
+        webhook_max_attempts: 1,
+        fetch_deny_private_networks: false,
+        ..Config::default()
+    }
+}
+
+async fn submit(gateway: &str, image: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&json!({"url": image, "callback_url": "http://127.0.0.1:9/hook"}))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_running_jobs_are_limited() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+
+    let gateway = common::spawn_gateway(Config {
+        job_max_running: 1,
+        ..config().await
+    })
+    .await;
+    assert_eq!(
+        submit(&gateway, &image).await.status(),
+        StatusCode::ACCEPTED
+    );
+    let res = submit(&gateway, &image).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()["retry-after"], "1");
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Too many background jobs are running"
+    );
+
+    // Clients without a tenant share one per-tenant limit.
+    let gateway = common::spawn_gateway(Config {
+        job_tenant_max_running: 2,
+        ..config().await
+    })
+    .await;
+    for _ in 0..2 {
+        assert_eq!(
+            submit(&gateway, &image).await.status(),
+            StatusCode::ACCEPTED
+        );
+    }
+    let res = submit(&gateway, &image).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Too many background jobs are running for this client"
+    );
+
+    // Synchronous requests are not affected.
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&json!({"url": image, "scale": 9}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_store_drops_oldest_finished_jobs() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        job_max_stored: 2,
+        ..config().await
+    })
+    .await;
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let res = submit(&gateway, &image).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let accepted: Value = res.json().await.unwrap();
+        let id = accepted["job_id"].as_str().unwrap().to_string();
+        for _ in 0..200 {
+            let job: Value = reqwest::get(format!("{}/jobs/{}", gateway, id))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if job["deliveries"].as_array().is_some_and(|d| !d.is_empty()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        ids.push(id);
+    }
+
+    let statuses: Vec<StatusCode> = futures::future::join_all(
+        ids.iter()
+            .map(|id| reqwest::get(format!("{}/jobs/{}", gateway, id))),
+    )
+    .await
+    .into_iter()
+    .map(|res| res.unwrap().status())
+    .collect();
+    assert_eq!(
+        statuses,
+        [StatusCode::NOT_FOUND, StatusCode::OK, StatusCode::OK]
+    );
+}
+
+#[tokio::test]
+async fn test_shutdown_cancels_jobs_after_the_drain_timeout() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+    let state = AppState::new(Arc::new(config().await));
+    let gateway = common::spawn(create_router_with_state(state.clone())).await;
+
+    let accepted: Value = submit(&gateway, &image).await.json().await.unwrap();
+    let id = accepted["job_id"].as_str().unwrap();
+
+    state.jobs.shutdown(Duration::from_millis(100)).await;
+    let job = state.jobs.get(id).unwrap().info();
+    assert_eq!(job.http_status, Some(503));
+    assert_eq!(job.error.as_deref(), Some("Job was cancelled"));
+
+    let res = submit(&gateway, &image).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.text().await.unwrap(), "Server is shutting down");
+}
+
+#[tokio::test]
+async fn test_deliveries_do_not_hold_running_slots() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+    let state = AppState::new(Arc::new(Config {
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        job_max_running: 1,
+        webhook_max_attempts: 3,
+        webhook_retry_base_ms: 60_000,
+        ..config().await
+    }));
+    let gateway = common::spawn(create_router_with_state(state.clone())).await;
+
+    let accepted: Value = submit(&gateway, &image).await.json().await.unwrap();
+    let id = accepted["job_id"].as_str().unwrap();
+    for _ in 0..200 {
+        if !state.jobs.get(id).unwrap().deliveries.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // The receiver is down and the job waits to retry, but its slot is free.
+    let res = submit(&gateway, &image).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    // At shutdown, the pending retry is made right away instead of dropped.
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        state.jobs.shutdown(Duration::from_millis(100)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(state.jobs.get(id).unwrap().deliveries.len(), 2);
+}
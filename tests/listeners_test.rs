@@ -0,0 +1,143 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Method, Request, StatusCode, header},
+};
+use nijika_api::config::Config;
+use nijika_api::state::AppState;
+use nijika_api::{create_admin_router, create_router_with_state, server};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn request(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 6000))))
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_admin_listener_takes_over_health_routes() {
+    let config = Arc::new(Config {
+        admin_listen_addr: Some(free_addr()),
+        rate_limit_per_second: 1,
+        rate_limit_burst: 1,
+        ..Config::default()
+    });
+    let state = AppState::new(config);
+    let public = create_router_with_state(state.clone());
+    let admin = create_admin_router(state);
+
+    let response = public.oneshot(request("/livez")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The admin router is not rate limited.
+    for _ in 0..5 {
+        let response = admin.clone().oneshot(request("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_listener_serves_metrics_and_redelivery() {
+    let config = Arc::new(Config {
+        admin_listen_addr: Some(free_addr()),
+        ..Config::default()
+    });
+    let state = AppState::new(config);
+    let public = create_router_with_state(state.clone());
+    let admin = create_admin_router(state);
+
+    // Redelivery is an admin route and is not served publicly.
+    let mut redeliver = request("/jobs/0123/redeliver");
+    *redeliver.method_mut() = Method::POST;
+    let response = public.oneshot(redeliver).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = admin.clone().oneshot(request("/metrics")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("nijika_http_responses_total{class=\"4xx\"} 1\n"));
+    assert!(text.contains("nijika_jobs_running 0\n"));
+
+    let mut redeliver = request("/jobs/0123/redeliver");
+    *redeliver.method_mut() = Method::POST;
+    let response = admin.oneshot(redeliver).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_serves_tcp_unix_and_admin_listeners() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let socket_path = std::env::temp_dir().join(format!("nijika-{}.sock", std::process::id()));
+    let public_addr = free_addr();
+    let admin_addr = free_addr();
+    let config = Arc::new(Config {
+        listen_addrs: vec![public_addr],
+        unix_socket_path: Some(socket_path.to_string_lossy().into_owned()),
+        unix_socket_mode: 0o600,
+        admin_listen_addr: Some(admin_addr),
+        ..Config::default()
+    });
+
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(server::run(AppState::new(config), shutdown.clone()));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let client = reqwest::Client::new();
+    let res = client
+        .get(format!("http://{}/livez", admin_addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{}/livez", public_addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // GET on a POST-only API route proves the request reached the public router.
+    let unix_client = reqwest::Client::builder()
+        .unix_socket(socket_path.clone())
+        .build()
+        .unwrap();
+    let res = unix_client
+        .get("http://localhost/removebg")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    shutdown.cancel();
+    server.await.unwrap().unwrap();
+    assert!(!socket_path.exists());
+}
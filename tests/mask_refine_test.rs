@@ -0,0 +1,131 @@
+mod common;
+
+use axum::{Router, body::Bytes, http::header, response::IntoResponse, routing::post};
+use image::{DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage};
+use reqwest::StatusCode;
+
+/// A worker whose mask is the red channel of the input.
+async fn spawn_red_mask_worker() -> String {
+    async fn handler(body: Bytes) -> impl IntoResponse {
+        let mut output = image::load_from_memory(&body).unwrap().to_rgba8();
+        for pixel in output.pixels_mut() {
+            pixel[3] = pixel[0];
+        }
+        (
+            [(header::CONTENT_TYPE, "image/png")],
+            common::encode(&DynamicImage::ImageRgba8(output), ImageFormat::Png),
+        )
+    }
+
+    common::spawn(Router::new().route("/", post(handler))).await
+}
+
+/// An opaque square at 10..30 with a faint 2 px halo, and a one-pixel speck
+/// at (2, 2).
+fn subject() -> Vec<u8> {
+    let image = RgbImage::from_fn(40, 40, |x, y| {
+        let inside = |lo, hi| (lo..hi).contains(&x) && (lo..hi).contains(&y);
+        if inside(10, 30) || (x, y) == (2, 2) {
+            Rgb([255, 0, 0])
+        } else if inside(8, 32) {
+            Rgb([100, 0, 0])
+        } else {
+            Rgb([0, 0, 0])
+        }
+    });
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway() -> String {
+    common::spawn_removebg_gateway(spawn_red_mask_worker().await).await
+}
+
+async fn mask(gateway: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+    common::remove_bg(
+        gateway,
+        subject(),
+        &[&[("output", "mask")], fields].concat(),
+    )
+    .await
+}
+
+/// Decodes the mask in `res`.
+async fn decode(res: reqwest::Response) -> GrayImage {
+    common::decode(res).await.to_luma8()
+}
+
+#[tokio::test]
+async fn test_threshold_and_island_removal() {
+    let gateway = gateway().await;
+
+    let output = decode(mask(&gateway, &[]).await).await;
+    assert_eq!(output.get_pixel(9, 20)[0], 100);
+
+    let output = decode(mask(&gateway, &[("mask_threshold", "0.5")]).await).await;
+    assert_eq!(output.get_pixel(9, 20)[0], 0);
+    assert_eq!(output.get_pixel(10, 20)[0], 255);
+    assert_eq!(output.get_pixel(2, 2)[0], 255);
+
+    let output = decode(
+        mask(
+            &gateway,
+            &[("mask_threshold", "0.5"), ("remove_islands", "true")],
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(output.get_pixel(2, 2)[0], 0);
+    assert_eq!(output.get_pixel(20, 20)[0], 255);
+}
+
+#[tokio::test]
+async fn test_erode_dilate_and_feather() {
+    let gateway = gateway().await;
+
+    let output = decode(mask(&gateway, &[("mask_threshold", "0.5"), ("erode", "2")]).await).await;
+    assert_eq!(output.get_pixel(11, 20)[0], 0);
+    assert_eq!(output.get_pixel(12, 20)[0], 255);
+    assert_eq!(output.get_pixel(27, 20)[0], 255);
+    assert_eq!(output.get_pixel(28, 20)[0], 0);
+
+    let output = decode(mask(&gateway, &[("mask_threshold", "0.5"), ("dilate", "3")]).await).await;
+    assert_eq!(output.get_pixel(6, 20)[0], 0);
+    assert_eq!(output.get_pixel(7, 20)[0], 255);
+    assert_eq!(output.get_pixel(32, 20)[0], 255);
+    assert_eq!(output.get_pixel(33, 20)[0], 0);
+
+    let output = decode(
+        mask(
+            &gateway,
+            &[("mask_threshold", "0.5"), ("feather_radius", "6")],
+        )
+        .await,
+    )
+    .await;
+    let edge = output.get_pixel(10, 20)[0];
+    assert!((1..255).contains(&edge), "edge={}", edge);
+    assert_eq!(output.get_pixel(20, 20)[0], 255);
+}
+
+#[tokio::test]
+async fn test_out_of_range_parameters_are_rejected() {
+    let gateway = gateway().await;
+
+    for (name, value, message) in [
+        (
+            "mask_threshold",
+            "1.5",
+            "Mask threshold must be between 0 and 1",
+        ),
+        ("erode", "51", "erode must be between 0 and 50 pixels"),
+        (
+            "feather_radius",
+            "-1",
+            "feather_radius must be between 0 and 50 pixels",
+        ),
+    ] {
+        let res = mask(&gateway, &[(name, value)]).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.text().await.unwrap().contains(message), "{}", name);
+    }
+}
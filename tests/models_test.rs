@@ -0,0 +1,154 @@
+mod common;
+
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use serde_json::Value;
+
+async fn models(gateway: &str) -> Vec<Value> {
+    let res = reqwest::get(format!("{}/models", gateway)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = res.json().await.unwrap();
+    body["models"].as_array().unwrap().clone()
+}
+
+fn find<'a>(models: &'a [Value], id: &str) -> Option<&'a Value> {
+    models.iter().find(|model| model["id"] == id)
+}
+
+#[tokio::test]
+async fn test_catalog_lists_every_model() {
+    let gateway = common::spawn_gateway(Config::default()).await;
+    let models = models(&gateway).await;
+
+    let ids: Vec<&str> = models.iter().map(|m| m["id"].as_str().unwrap()).collect();
+    assert_eq!(
+        ids,
+        [
+            "birefnet",
+            "RealESRGAN_x4plus",
+            "RealESRNet_x4plus",
+            "RealESRGAN_x4plus_anime_6B",
+            "RealESRGAN_x2plus",
+            "realesr-general-x4v3",
+            "auto"
+        ]
+    );
+
+    let removebg = find(&models, "birefnet").unwrap();
+    assert_eq!(removebg["endpoint"], "/removebg");
+    assert_eq!(removebg["default_output_type"], "image/png");
+    assert_eq!(removebg["available"], true);
+
+    let x2 = find(&models, "RealESRGAN_x2plus").unwrap();
+    assert_eq!(x2["native_scale"], 2);
+    assert_eq!(x2["default"], false);
+    assert!(
+        !x2["parameters"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("denoise_strength"))
+    );
+
+    let general = find(&models, "realesr-general-x4v3").unwrap();
+    assert_eq!(general["native_scale"], 4);
+    assert!(
+        general["parameters"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("denoise_strength"))
+    );
+    assert!(
+        general["input_types"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("image/webp"))
+    );
+    assert!(
+        general["output_types"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("image/avif"))
+    );
+
+    assert_eq!(
+        find(&models, "RealESRGAN_x4plus_anime_6B").unwrap()["default"],
+        true
+    );
+}
+
+#[tokio::test]
+async fn test_disabled_and_hidden_models() {
+    let worker = common::spawn_upscale_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        disabled_models: vec!["RealESRGAN_x4plus".to_string(), "birefnet".to_string()],
+        hidden_models: vec!["RealESRNet_x4plus".to_string()],
+        ..Config::default()
+    })
+    .await;
+
+    let models = models(&gateway).await;
+    assert!(find(&models, "RealESRNet_x4plus").is_none());
+    let disabled = find(&models, "RealESRGAN_x4plus").unwrap();
+    assert_eq!(disabled["enabled"], false);
+    assert_eq!(disabled["available"], false);
+    assert_eq!(find(&models, "birefnet").unwrap()["available"], false);
+
+    let upscale = |model: &'static str| {
+        let gateway = gateway.clone();
+        async move {
+            let form = Form::new()
+                .part("image", Part::bytes(common::png(8, 8)).file_name("a.png"))
+                .text("model", model);
+            reqwest::Client::new()
+                .post(format!("{}/upscale", gateway))
+                .multipart(form)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+
+    let res = upscale("RealESRGAN_x4plus").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Model 'RealESRGAN_x4plus' is disabled"
+    );
+
+    // Hidden models are still usable.
+    let res = upscale("RealESRNet_x4plus").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let form = Form::new().part("image", Part::bytes(common::png(8, 8)).file_name("a.png"));
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.text().await.unwrap(), "Model 'birefnet' is disabled");
+}
+
+#[tokio::test]
+async fn test_models_of_unreachable_worker_are_unavailable() {
+    let removebg = common::spawn_echo_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_removebg_url: removebg,
+        modal_upscaler_url: "http://127.0.0.1:1".to_string(),
+        ..Config::default()
+    })
+    .await;
+
+    reqwest::get(format!("{}/health/details", gateway))
+        .await
+        .unwrap();
+
+    let models = models(&gateway).await;
+    assert_eq!(find(&models, "birefnet").unwrap()["available"], true);
+    let general = find(&models, "realesr-general-x4v3").unwrap();
+    assert_eq!(general["enabled"], true);
+    assert_eq!(general["available"], false);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+async fn gateway() -> String {
+    let worker = common::spawn_echo_worker().await;
+    common::spawn_removebg_gateway(worker).await
+}
+
+fn form(fields: &[(&str, &str)]) -> Form {
+    let mut form = Form::new().part(
+        "image",
+        Part::bytes(common::png(24, 16)).file_name("holiday photo.jpg"),
+    );
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    form
+}
+
+async fn remove_bg(gateway: &str, form: Form, accept: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(form);
+    if let Some(accept) = accept {
+        request = request.header("Accept", accept);
+    }
+    request.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_default_output_is_passed_through() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, form(&[]), None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "inline; filename=\"holiday_photo-nobg.png\""
+    );
+    assert_eq!(
+        res.bytes().await.unwrap().as_ref(),
+        common::png(24, 16).as_slice()
+    );
+}
+
+#[tokio::test]
+async fn test_format_field_and_accept_header_select_the_encoding() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(
+        &gateway,
+        form(&[("format", "webp"), ("quality", "60")]),
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    let length: usize = res.headers()["content-length"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let body = res.bytes().await.unwrap();
+    assert_eq!(body.len(), length);
+    assert_eq!(image::load_from_memory(&body).unwrap().width(), 24);
+
+    let res = remove_bg(
+        &gateway,
+        form(&[]),
+        Some("image/avif,image/webp;q=0.9,*/*;q=0.8"),
+    )
+    .await;
+    assert_eq!(res.headers()["content-type"], "image/avif");
+    assert_eq!(res.headers()["vary"], "Accept");
+    assert_eq!(&res.bytes().await.unwrap()[4..12], b"ftypavif");
+
+    let res = remove_bg(&gateway, form(&[("format", "jpeg")]), Some("image/webp")).await;
+    assert_eq!(res.headers()["content-type"], "image/jpeg");
+    assert!(
+        res.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .ends_with("-nobg.jpg\"")
+    );
+
+    let source = common::spawn_image_source(common::png(8, 8)).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .json(&serde_json::json!({ "url": source, "format": "webp", "lossless": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "inline; filename=\"image-nobg.webp\""
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_output_options_are_rejected() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, form(&[("format", "gif")]), None).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = remove_bg(
+        &gateway,
+        form(&[("format", "jpeg"), ("lossless", "true")]),
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = remove_bg(&gateway, form(&[("quality", "0")]), None).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = remove_bg(&gateway, form(&[]), Some("image/gif")).await;
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+
+    let res = remove_bg(&gateway, form(&[]), Some("application/json")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+}
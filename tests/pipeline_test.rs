@@ -0,0 +1,157 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use serde_json::json;
+
+/// Red on the left, blue on the right.
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_fn(40, 20, |x, _| {
+        if x < 20 {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 255])
+        }
+    });
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway() -> String {
+    common::spawn_gateway(Config {
+        modal_removebg_url: common::spawn_half_removebg_worker().await,
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        ..Config::default()
+    })
+    .await
+}
+
+async fn run(gateway: &str, steps: serde_json::Value) -> reqwest::Response {
+    let form = Form::new()
+        .part("image", Part::bytes(photo()).file_name("photo.png"))
+        .text("steps", steps.to_string());
+    reqwest::Client::new()
+        .post(format!("{}/pipeline", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_removebg_upscale_pad_and_format() {
+    let gateway = gateway().await;
+    let url = common::spawn_image_source(photo()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline", gateway))
+        .json(&json!({
+            "url": url,
+            "steps": [
+                {"op": "removebg"},
+                {"op": "upscale", "scale": 2},
+                {"op": "pad", "aspect": "1:1"},
+                {"op": "format", "format": "webp", "lossless": true}
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    let timing = res.headers()["server-timing"].to_str().unwrap().to_string();
+    for stage in [
+        "decode;dur=",
+        "removebg;dur=",
+        "upscale;dur=",
+        "pad;dur=",
+        "encode;dur=",
+    ] {
+        assert!(timing.contains(stage), "{}", timing);
+    }
+    assert!(timing.contains(";desc=\"step 3\""), "{}", timing);
+
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(output.dimensions(), (80, 80));
+    // The subject survives the upscale with its alpha; the padding and the
+    // removed half are transparent.
+    assert_eq!(output.get_pixel(10, 40)[3], 255);
+    assert_eq!(output.get_pixel(70, 40)[3], 0);
+    assert_eq!(output.get_pixel(10, 5)[3], 0);
+}
+
+#[tokio::test]
+async fn test_local_steps_default_to_jpeg_for_opaque_results() {
+    let gateway = gateway().await;
+
+    let res = run(
+        &gateway,
+        json!([
+            {"op": "crop", "width": 20, "height": 20},
+            {"op": "resize", "width": 10},
+            {"op": "pad", "padding": 5, "color": "#00ff00"}
+        ]),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/jpeg");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "inline; filename=\"photo-processed.jpg\""
+    );
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgb8();
+    assert_eq!(output.dimensions(), (20, 20));
+    let [r, g, b] = output.get_pixel(10, 10).0;
+    assert!(r > 200 && g < 60 && b < 60, "{:?}", (r, g, b));
+    let [r, g, b] = output.get_pixel(1, 1).0;
+    assert!(r < 60 && g > 200 && b < 60, "{:?}", (r, g, b));
+}
+
+#[tokio::test]
+async fn test_invalid_pipelines_are_rejected() {
+    let gateway = gateway().await;
+
+    for (steps, message) in [
+        (json!([]), "between 1 and 10 steps"),
+        (
+            json!([{"op": "format", "format": "png"}, {"op": "removebg"}]),
+            "format step must be the last step",
+        ),
+        (json!([{"op": "rotate"}]), "Invalid steps"),
+        (
+            json!([{"op": "upscale", "denoise_strength": 0.5}]),
+            "denoise_strength is only supported",
+        ),
+        (json!([{"op": "pad"}]), "requires an aspect or a padding"),
+    ] {
+        let res = run(&gateway, steps).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.text().await.unwrap().contains(message), "{}", message);
+    }
+
+    let res = run(
+        &gateway,
+        json!([
+            {"op": "resize", "width": 20},
+            {"op": "crop", "x": 10, "width": 20, "height": 10}
+        ]),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.headers()["x-pipeline-failed-step"], "2");
+    assert!(
+        res.headers()["server-timing"]
+            .to_str()
+            .unwrap()
+            .contains("resize;dur=")
+    );
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Crop of 20x10 at (10, 0) does not fit the 20x10 image"
+    );
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use nijika_api::models::Preset;
+use nijika_api::presets;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// Writes `presets` to a temporary file and loads it like `PRESETS_PATH`.
+fn load(name: &str, presets: Value) -> Result<BTreeMap<String, Preset>, String> {
+    let path = std::env::temp_dir().join(format!(
+        "nijika-presets-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, presets.to_string()).unwrap();
+    let loaded = presets::load(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    loaded
+}
+
+fn config(name: &str) -> Config {
+    Config {
+        presets: load(name, definitions()).unwrap(),
+        ..Config::default()
+    }
+}
+
+fn definitions() -> Value {
+    json!({
+        "avatar": {
+            "endpoint": "upscale",
+            "description": "Profile pictures",
+            "params": {"model": "RealESRGAN_x4plus", "scale": 2, "face_enhance": false, "format": "webp"},
+            "overridable": ["format", "quality"]
+        },
+        "thumbnail": {
+            "endpoint": "pipeline",
+            "params": {"steps": [{"op": "resize", "width": 10}, {"op": "format", "format": "png"}]}
+        }
+    })
+}
+
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_pixel(20, 10, Rgb([200, 100, 50]));
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+#[tokio::test]
+async fn test_presets_are_listed_and_applied() {
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        ..config("applied")
+    })
+    .await;
+
+    let res = reqwest::get(format!("{}/presets", gateway)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = res.json().await.unwrap();
+    let listed = body["presets"].as_array().unwrap();
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed[0]["name"], "avatar");
+    assert_eq!(listed[0]["endpoint"], "upscale");
+    assert_eq!(listed[0]["params"]["scale"], 2);
+    assert_eq!(listed[0]["overridable"], json!(["format", "quality"]));
+    assert_eq!(listed[1]["name"], "thumbnail");
+
+    let url = common::spawn_image_source(photo()).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&json!({"url": url, "preset": "avatar"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    let output = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((output.width(), output.height()), (40, 20));
+
+    // Overridable fields win over the preset, in multipart requests too.
+    let form = Form::new()
+        .part("image", Part::bytes(photo()).file_name("photo.png"))
+        .text("preset", "avatar")
+        .text("format", "jpeg");
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/jpeg");
+}
+
+#[tokio::test]
+async fn test_pipeline_preset_supplies_steps() {
+    let gateway = common::spawn_gateway(config("pipeline")).await;
+
+    let form = Form::new()
+        .part("image", Part::bytes(photo()).file_name("photo.png"))
+        .text("preset", "thumbnail");
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let output = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((output.width(), output.height()), (10, 5));
+}
+
+#[tokio::test]
+async fn test_preset_misuse_is_rejected() {
+    let gateway = common::spawn_gateway(config("misuse")).await;
+    let url = common::spawn_image_source(photo()).await;
+
+    for (endpoint, body, message) in [
+        (
+            "upscale",
+            json!({"url": url, "preset": "avatar", "scale": 4}),
+            "Field 'scale' cannot be overridden with preset 'avatar'",
+        ),
+        (
+            "upscale",
+            json!({"url": url, "preset": "missing"}),
+            "Unknown preset 'missing'",
+        ),
+        (
+            "removebg",
+            json!({"url": url, "preset": "avatar"}),
+            "Preset 'avatar' can only be used with /upscale",
+        ),
+        (
+            "pipeline",
+            json!({"url": url, "preset": "thumbnail", "steps": []}),
+            "Field 'steps' cannot be overridden with preset 'thumbnail'",
+        ),
+    ] {
+        let res = reqwest::Client::new()
+            .post(format!("{}/{}", gateway, endpoint))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.text().await.unwrap(), message);
+    }
+
+    let error = load(
+        "invalid",
+        json!({"broken": {"endpoint": "upscale", "params": {"scale": "big"}}}),
+    )
+    .unwrap_err();
+    assert!(
+        error.starts_with("preset 'broken' has invalid params"),
+        "{}",
+        error
+    );
+}
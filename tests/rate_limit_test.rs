@@ -13,12 +13,9 @@ use tower::ServiceExt;
 async fn test_rate_limiting() {
     // Setup config with very low rate limits for testing
     let config = Arc::new(Config {
-        host: "127.0.0.1".to_string(),
-        port: 3000,
-        modal_removebg_url: "http://localhost:8000".to_string(),
-        modal_upscaler_url: "http://localhost:8001".to_string(),
         rate_limit_per_second: 1,
         rate_limit_burst: 1,
+        ..Config::default()
     });
 
     let app = create_router(config);
@@ -0,0 +1,116 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, RgbaImage};
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+/// Red on the left, black and white stripes on the right.
+fn striped() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(40, 20, |x, _| {
+        if x < 20 {
+            Rgb([255, 0, 0])
+        } else if x % 2 == 0 {
+            Rgb([255, 255, 255])
+        } else {
+            Rgb([0, 0, 0])
+        }
+    }))
+}
+
+async fn gateway() -> String {
+    common::spawn_removebg_gateway(common::spawn_half_removebg_worker().await).await
+}
+
+async fn remove_bg(gateway: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+    common::remove_bg(
+        gateway,
+        common::encode(&striped(), ImageFormat::Png),
+        fields,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_background_color_and_image() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, &[("background_color", "#0000ff")]).await;
+    let output = common::decode(res).await;
+    assert!(!output.color().has_alpha());
+    let output = output.to_rgb8();
+    assert_eq!(output.get_pixel(5, 5).0, [255, 0, 0]);
+    assert_eq!(output.get_pixel(30, 5).0, [0, 0, 255]);
+
+    // A square green backdrop, contained in the 40x20 canvas over yellow.
+    let green = RgbaImage::from_pixel(10, 10, image::Rgba([0, 255, 0, 255]));
+    let form = Form::new()
+        .part(
+            "background_image",
+            Part::bytes(common::encode(
+                &DynamicImage::ImageRgba8(green),
+                ImageFormat::Png,
+            ))
+            .file_name("backdrop.png"),
+        )
+        .text("background_fit", "contain")
+        .text("background_color", "#ffff00");
+    let image = common::encode(&striped(), ImageFormat::Png);
+    let res = common::remove_bg_form(&gateway, image, form).await;
+    let output = common::decode(res).await.to_rgb8();
+    assert_eq!(output.get_pixel(5, 5).0, [255, 0, 0]);
+    assert_eq!(output.get_pixel(25, 10).0, [0, 255, 0]);
+    assert_eq!(output.get_pixel(35, 10).0, [255, 255, 0]);
+
+    // The same backdrop from a URL, covering the canvas.
+    let url = common::spawn_image_source(common::encode(
+        &DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb([0, 255, 0]))),
+        ImageFormat::Png,
+    ))
+    .await;
+    let res = remove_bg(&gateway, &[("background_image", &url)]).await;
+    let output = common::decode(res).await.to_rgb8();
+    assert_eq!(output.get_pixel(35, 10).0, [0, 255, 0]);
+}
+
+#[tokio::test]
+async fn test_background_blur_keeps_a_blurred_original() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, &[("background_blur", "4")]).await;
+    let output = common::decode(res).await.to_rgb8();
+    assert_eq!(output.get_pixel(5, 5).0, [255, 0, 0]);
+    for x in [32u32, 33] {
+        let [r, g, b] = output.get_pixel(x, 10).0;
+        assert!((100..=155).contains(&r), "x={} r={}", x, r);
+        assert_eq!((r, g, b), (r, r, r));
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_background_options_are_rejected() {
+    let gateway = gateway().await;
+
+    let cases: [(&[(&str, &str)], &str); 4] = [
+        (
+            &[("background_blur", "4"), ("background_color", "#fff")],
+            "cannot be combined",
+        ),
+        (
+            &[("background_blur", "0")],
+            "Background blur must be greater than 0",
+        ),
+        (
+            &[("output", "mask"), ("background_color", "#fff")],
+            "only apply to the cutout and both outputs",
+        ),
+        (
+            &[("background_fit", "tile")],
+            "Background fit must be contain, cover or exact",
+        ),
+    ];
+    for (fields, message) in cases {
+        let res = remove_bg(&gateway, fields).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.text().await.unwrap().contains(message), "{}", message);
+    }
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use reqwest::StatusCode;
+use serde_json::json;
+
+/// A 20x10 red product at (30, 20) on a black 100x80 image.
+fn product() -> Vec<u8> {
+    let image = RgbImage::from_fn(100, 80, |x, y| {
+        if (30..50).contains(&x) && (20..30).contains(&y) {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 0])
+        }
+    });
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway() -> String {
+    common::spawn_removebg_gateway(common::spawn_subject_removebg_worker().await).await
+}
+
+#[tokio::test]
+async fn test_crop_to_subject_with_padding_and_aspect() {
+    let gateway = gateway().await;
+
+    let res = common::remove_bg(
+        &gateway,
+        product(),
+        &[("crop", "subject"), ("crop_padding", "5")],
+    )
+    .await;
+    assert_eq!(res.headers()["x-subject-bbox"], "30,20,20,10");
+    let output = common::decode(res).await.to_rgba8();
+    assert_eq!(output.dimensions(), (30, 20));
+    assert_eq!(output.get_pixel(5, 5).0, [255, 0, 0, 255]);
+    assert_eq!(output.get_pixel(4, 5)[3], 0);
+
+    let res = common::remove_bg(
+        &gateway,
+        product(),
+        &[("crop", "subject"), ("crop_padding", "10%")],
+    )
+    .await;
+    assert_eq!(common::decode(res).await.to_rgba8().dimensions(), (24, 14));
+
+    let res = common::remove_bg(
+        &gateway,
+        product(),
+        &[
+            ("crop", "subject"),
+            ("crop_padding", "5px"),
+            ("crop_aspect", "1:1"),
+        ],
+    )
+    .await;
+    let output = common::decode(res).await.to_rgba8();
+    assert_eq!(output.dimensions(), (30, 30));
+    assert_eq!(output.get_pixel(15, 2)[3], 0);
+    assert_eq!(output.get_pixel(15, 15).0, [255, 0, 0, 255]);
+}
+
+#[tokio::test]
+async fn test_crop_to_fixed_canvas_from_json() {
+    let gateway = gateway().await;
+    let url = common::spawn_image_source(product()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .json(&json!({
+            "url": url,
+            "crop": "subject",
+            "crop_padding": 0,
+            "crop_size": "200x200"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["x-subject-bbox"], "30,20,20,10");
+    let output = common::decode(res).await.to_rgba8();
+    assert_eq!(output.dimensions(), (200, 200));
+    assert_eq!(output.get_pixel(100, 20)[3], 0);
+    assert_eq!(output.get_pixel(100, 100).0, [255, 0, 0, 255]);
+    assert_eq!(output.get_pixel(100, 180)[3], 0);
+}
+
+#[tokio::test]
+async fn test_invalid_crops_are_rejected() {
+    let gateway = gateway().await;
+
+    let res = common::remove_bg(&gateway, product(), &[("crop_padding", "5")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(res.text().await.unwrap().contains("require crop: subject"));
+
+    let res = common::remove_bg(
+        &gateway,
+        product(),
+        &[
+            ("crop", "subject"),
+            ("crop_aspect", "1:1"),
+            ("crop_size", "100x100"),
+        ],
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let empty = common::encode(
+        &DynamicImage::ImageRgb8(RgbImage::new(16, 16)),
+        ImageFormat::Png,
+    );
+    let res = common::remove_bg(&gateway, empty, &[("crop", "subject")]).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.text().await.unwrap(), "No subject found to crop to");
+}
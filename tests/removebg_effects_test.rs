@@ -0,0 +1,101 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use reqwest::StatusCode;
+
+/// An 8x8 red square at (6, 6) on a black 20x20 image.
+fn square() -> Vec<u8> {
+    let image = RgbImage::from_fn(20, 20, |x, y| {
+        if (6..14).contains(&x) && (6..14).contains(&y) {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 0])
+        }
+    });
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway() -> String {
+    common::spawn_removebg_gateway(common::spawn_subject_removebg_worker().await).await
+}
+
+async fn remove_bg(gateway: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+    common::remove_bg(gateway, square(), fields).await
+}
+
+#[tokio::test]
+async fn test_outline_expands_the_canvas() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(
+        &gateway,
+        &[("outline_width", "3"), ("outline_color", "#00ff00")],
+    )
+    .await;
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let output = common::decode(res).await.to_rgba8();
+    assert_eq!(output.dimensions(), (26, 26));
+    assert_eq!(output.get_pixel(13, 13).0, [255, 0, 0, 255]);
+    assert_eq!(output.get_pixel(7, 13).0, [0, 255, 0, 255]);
+    assert_eq!(output.get_pixel(1, 13)[3], 0);
+    // The stroke is rounded at the corners.
+    assert_eq!(output.get_pixel(6, 6)[3], 0);
+
+    let res = remove_bg(&gateway, &[("outline_width", "2"), ("format", "webp")]).await;
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    assert_eq!(common::decode(res).await.to_rgba8().dimensions(), (24, 24));
+}
+
+#[tokio::test]
+async fn test_drop_shadow_is_offset_and_translucent() {
+    let gateway = gateway().await;
+
+    let output = common::decode(
+        remove_bg(
+            &gateway,
+            &[
+                ("shadow_offset_x", "5"),
+                ("shadow_offset_y", "0"),
+                ("shadow_blur", "0"),
+                ("shadow_opacity", "0.5"),
+            ],
+        )
+        .await,
+    )
+    .await
+    .to_rgba8();
+    assert_eq!(output.dimensions(), (25, 20));
+    assert_eq!(output.get_pixel(10, 10).0, [255, 0, 0, 255]);
+    let [r, g, b, a] = output.get_pixel(17, 10).0;
+    assert_eq!((r, g, b), (0, 0, 0));
+    assert!((120..=135).contains(&a), "alpha={}", a);
+    assert_eq!(output.get_pixel(3, 10)[3], 0);
+}
+
+#[tokio::test]
+async fn test_effects_need_a_transparent_cutout() {
+    let gateway = gateway().await;
+
+    for (fields, message) in [
+        (
+            vec![("outline_width", "3"), ("format", "jpeg")],
+            "use png or webp",
+        ),
+        (
+            vec![("outline_width", "3"), ("output", "mask")],
+            "only apply to the cutout output",
+        ),
+        (
+            vec![("shadow_blur", "2"), ("background_color", "#fff")],
+            "only apply to the cutout output",
+        ),
+        (
+            vec![("shadow_opacity", "2")],
+            "Shadow opacity must be between 0 and 1",
+        ),
+    ] {
+        let res = remove_bg(&gateway, &fields).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(res.text().await.unwrap().contains(message), "{}", message);
+    }
+}
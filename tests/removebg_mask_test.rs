@@ -0,0 +1,140 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::post,
+};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma, Rgb, RgbImage};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use std::sync::{Arc, Mutex};
+
+/// Dimensions and `X-Output` header of the last request.
+type Seen = Arc<Mutex<Option<((u32, u32), Option<String>)>>>;
+
+/// A worker that segments bright pixels: it returns a mask when asked for
+/// one and a cut-out otherwise.
+async fn spawn_segmenting_worker() -> (String, Seen) {
+    async fn handler(
+        State(seen): State<Seen>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        let input = image::load_from_memory(&body).unwrap().to_rgb8();
+        let output = headers
+            .get("x-output")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        *seen.lock().unwrap() = Some((input.dimensions(), output.clone()));
+
+        let mask = GrayImage::from_fn(input.width(), input.height(), |x, y| {
+            Luma([if input.get_pixel(x, y)[0] > 128 {
+                255
+            } else {
+                0
+            }])
+        });
+        let result = if output.as_deref() == Some("mask") {
+            DynamicImage::ImageLuma8(mask)
+        } else {
+            let mut rgba = DynamicImage::ImageRgb8(input).to_rgba8();
+            for (pixel, alpha) in rgba.pixels_mut().zip(mask.pixels()) {
+                pixel[3] = alpha[0];
+            }
+            DynamicImage::ImageRgba8(rgba)
+        };
+        (
+            [(header::CONTENT_TYPE, "image/png")],
+            common::encode(&result, ImageFormat::Png),
+        )
+    }
+
+    let seen = Seen::default();
+    let app = Router::new()
+        .route("/", post(handler))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(seen.clone());
+    (common::spawn(app).await, seen)
+}
+
+/// A bright disc of radius 300 on a dark background.
+fn disc(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 - 600.0, y as f32 - 400.0);
+        if (dx * dx + dy * dy).sqrt() < 300.0 {
+            Rgb([230, 120 + (x % 7) as u8, 40])
+        } else {
+            Rgb([20, 30, 40])
+        }
+    }))
+}
+
+async fn remove_bg(gateway: &str, image: &DynamicImage) -> reqwest::Response {
+    let form = Form::new().part(
+        "image",
+        Part::bytes(common::encode(image, ImageFormat::Png)).file_name("a.png"),
+    );
+    reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_large_images_use_the_mask_path() {
+    let (worker, seen) = spawn_segmenting_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_removebg_url: worker,
+        removebg_mask_threshold_megapixels: 0.5,
+        removebg_mask_size: 300,
+        ..Config::default()
+    })
+    .await;
+
+    let input = disc(1200, 800);
+    let res = remove_bg(&gateway, &input).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        Some(((300, 200), Some("mask".to_string())))
+    );
+
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(output.dimensions(), (1200, 800));
+
+    // Full-resolution colors are kept, and the edge follows the original
+    // closely despite the 4x smaller mask.
+    let original = input.to_rgb8();
+    assert_eq!(
+        output.get_pixel(600, 400).0[..3],
+        original.get_pixel(600, 400).0
+    );
+    assert_eq!(output.get_pixel(600, 400)[3], 255);
+    assert_eq!(output.get_pixel(20, 20)[3], 0);
+    for x in [304u32, 306] {
+        assert!(output.get_pixel(x, 400)[3] > 200, "x={}", x);
+    }
+    for x in [294u32, 296] {
+        assert!(output.get_pixel(x, 400)[3] < 55, "x={}", x);
+    }
+}
+
+#[tokio::test]
+async fn test_small_images_are_sent_whole() {
+    let (worker, seen) = spawn_segmenting_worker().await;
+    let gateway = common::spawn_removebg_gateway(worker).await;
+
+    let res = remove_bg(&gateway, &disc(1200, 800)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(*seen.lock().unwrap(), Some(((1200, 800), None)));
+}
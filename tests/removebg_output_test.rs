@@ -0,0 +1,136 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use std::io::{Cursor, Read};
+
+async fn remove_bg(
+    gateway: &str,
+    fields: &[(&str, &str)],
+    accept: Option<&str>,
+) -> reqwest::Response {
+    let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 4, Rgba([255, 0, 0, 255])));
+    let mut form = Form::new().part(
+        "image",
+        Part::bytes(common::encode(&red, ImageFormat::Png)).file_name("photo.png"),
+    );
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(form);
+    if let Some(accept) = accept {
+        request = request.header("Accept", accept);
+    }
+    request.send().await.unwrap()
+}
+
+async fn gateway() -> String {
+    common::spawn_gateway(Config {
+        modal_removebg_url: common::spawn_half_removebg_worker().await,
+        ..Config::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_mask_and_matte_outputs() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, &[("output", "mask")], None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "inline; filename=\"photo-mask.png\""
+    );
+    let mask = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert!(matches!(mask, DynamicImage::ImageLuma8(_)));
+    let mask = mask.to_luma8();
+    assert_eq!(mask.get_pixel(1, 1)[0], 255);
+    assert_eq!(mask.get_pixel(6, 1)[0], 0);
+
+    let res = remove_bg(
+        &gateway,
+        &[("output", "matte"), ("matte_color", "#0f0")],
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let matte = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert!(!matte.color().has_alpha());
+    let matte = matte.to_rgb8();
+    assert_eq!(matte.get_pixel(1, 1).0, [255, 0, 0]);
+    assert_eq!(matte.get_pixel(6, 1).0, [0, 255, 0]);
+}
+
+#[tokio::test]
+async fn test_both_output_as_multipart_or_zip() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, &[("output", "both")], None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let content_type = res.headers()["content-type"].to_str().unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/mixed; boundary=")
+        .unwrap()
+        .to_string();
+    let body = res.bytes().await.unwrap();
+    let text = String::from_utf8_lossy(&body);
+    assert_eq!(text.matches(&format!("--{}\r\n", boundary)).count(), 2);
+    assert!(text.ends_with(&format!("--{}--\r\n", boundary)));
+    assert!(text.contains("filename=\"photo-nobg.png\""));
+    assert!(text.contains("filename=\"photo-mask.png\""));
+
+    let res = remove_bg(
+        &gateway,
+        &[("output", "both"), ("format", "webp")],
+        Some("application/zip"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/zip");
+    assert_eq!(
+        res.headers()["content-disposition"],
+        "attachment; filename=\"photo-nobg.zip\""
+    );
+    let mut archive = zip::ZipArchive::new(Cursor::new(res.bytes().await.unwrap())).unwrap();
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    assert_eq!(names, ["photo-mask.png", "photo-nobg.webp"]);
+
+    let mut mask = Vec::new();
+    archive
+        .by_name("photo-mask.png")
+        .unwrap()
+        .read_to_end(&mut mask)
+        .unwrap();
+    let mask = image::load_from_memory(&mask).unwrap().to_luma8();
+    assert_eq!(mask.get_pixel(6, 1)[0], 0);
+}
+
+#[tokio::test]
+async fn test_invalid_output_options_are_rejected() {
+    let gateway = gateway().await;
+
+    let res = remove_bg(&gateway, &[("output", "outline")], None).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(
+        res.text()
+            .await
+            .unwrap()
+            .contains("cutout, mask, matte or both")
+    );
+
+    let res = remove_bg(
+        &gateway,
+        &[("output", "matte"), ("matte_color", "#12345")],
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(res.text().await.unwrap().contains("Invalid color '#12345'"));
+}
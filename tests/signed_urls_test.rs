@@ -0,0 +1,217 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::{Config, StorageBackend};
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_pixel(20, 10, Rgb([200, 100, 50]));
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+fn signed_config(dir: &tempfile::TempDir, keys: &[(&str, &str)]) -> Config {
+    Config {
+        result_storage: StorageBackend::Local,
+        result_storage_dir: dir.path().to_str().unwrap().to_string(),
+        result_signing_keys: keys
+            .iter()
+            .map(|(id, secret)| (id.to_string(), secret.to_string()))
+            .collect(),
+        ..Config::default()
+    }
+}
+
+/// Runs a resize through `/pipeline?store=true` and returns the stored result.
+async fn store(gateway: &str) -> Value {
+    let url = common::spawn_image_source(photo()).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline?store=true", gateway))
+        .json(&json!({"url": url, "steps": [{"op": "resize", "width": 5}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    res.json().await.unwrap()
+}
+
+/// Mints a download URL for `stored` with `options`.
+async fn mint(gateway: &str, stored: &Value, options: Value) -> reqwest::Response {
+    let url = stored["url"].as_str().unwrap();
+    let (path, query) = url.split_once('?').unwrap();
+    reqwest::Client::new()
+        .post(format!("{}{}/url?{}", gateway, path, query))
+        .json(&options)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_results_require_valid_signed_urls() {
+    let dir = tempfile::tempdir().unwrap();
+    let gateway = common::spawn_gateway(signed_config(&dir, &[("k1", "first-secret")])).await;
+
+    let stored = store(&gateway).await;
+    let key = stored["key"].as_str().unwrap();
+    let url = stored["url"].as_str().unwrap();
+    assert!(url.starts_with(&format!("/results/{}?expires=", key)));
+    assert!(url.contains("&kid=k1&sig="));
+    let url_expires_at = stored["url_expires_at"].as_u64().unwrap();
+    assert!(url_expires_at <= stored["expires_at"].as_u64().unwrap());
+
+    let res = reqwest::get(format!("{}{}", gateway, url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let max_age: u64 = res.headers()["cache-control"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("private, max-age=")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((890..=900).contains(&max_age));
+    image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+
+    let tampered = url.replace(
+        &format!("expires={}", url_expires_at),
+        &format!("expires={}", url_expires_at + 3600),
+    );
+    let unsigned = format!("/results/{}", key);
+    for (path, message) in [
+        (unsigned.as_str(), "Download URL must be signed"),
+        (tampered.as_str(), "Invalid download URL signature"),
+    ] {
+        let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.text().await.unwrap(), message);
+    }
+
+    let res = mint(&gateway, &stored, json!({"expires_in": 0})).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let expired: Value = res.json().await.unwrap();
+    let res = reqwest::get(format!("{}{}", gateway, expired["url"].as_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.text().await.unwrap(), "Download URL has expired");
+
+    let res = reqwest::Client::new()
+        .post(format!("{}{}/url", gateway, unsigned))
+        .json(&json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_single_use_and_address_bound_urls() {
+    let dir = tempfile::tempdir().unwrap();
+    let gateway = common::spawn_gateway(signed_config(&dir, &[("k1", "first-secret")])).await;
+    let stored = store(&gateway).await;
+
+    let res = mint(&gateway, &stored, json!({"single_use": true})).await;
+    let once: Value = res.json().await.unwrap();
+    let url = format!("{}{}", gateway, once["url"].as_str().unwrap());
+
+    // Conditional and range requests get the whole result, which uses the
+    // URL up.
+    let res = reqwest::Client::new()
+        .get(&url)
+        .header("if-none-match", "*")
+        .header("range", "bytes=0-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["cache-control"], "no-store");
+    assert_eq!(res.headers()["accept-ranges"], "none");
+    image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    let res = reqwest::get(&url).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Download URL has already been used"
+    );
+
+    // Used URLs are recorded in the storage, so another instance sharing it,
+    // or a restarted one, refuses them too.
+    let other = common::spawn_gateway(signed_config(&dir, &[("k1", "first-secret")])).await;
+    let res = reqwest::get(url.replace(&gateway, &other)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = mint(&gateway, &stored, json!({"ip": "127.0.0.1"})).await;
+    let bound: Value = res.json().await.unwrap();
+    let res = reqwest::get(format!("{}{}", gateway, bound["url"].as_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = mint(&gateway, &stored, json!({"ip": "192.0.2.7"})).await;
+    let elsewhere: Value = res.json().await.unwrap();
+    assert!(
+        elsewhere["url"]
+            .as_str()
+            .unwrap()
+            .contains("&ip=192.0.2.7&")
+    );
+    let res = reqwest::get(format!("{}{}", gateway, elsewhere["url"].as_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Download URL is not valid from this address"
+    );
+
+    // Binding to another address is part of the signature.
+    let spoofed = elsewhere["url"]
+        .as_str()
+        .unwrap()
+        .replace("192.0.2.7", "127.0.0.1");
+    let res = reqwest::get(format!("{}{}", gateway, spoofed))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.text().await.unwrap(), "Invalid download URL signature");
+}
+
+#[tokio::test]
+async fn test_signing_keys_can_be_rotated() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = common::spawn_gateway(signed_config(&dir, &[("k1", "first-secret")])).await;
+    let stored = store(&old).await;
+    let url = stored["url"].as_str().unwrap();
+
+    // A new key signs, while URLs signed with the old key keep working.
+    let rotated = common::spawn_gateway(signed_config(
+        &dir,
+        &[("k2", "second-secret"), ("k1", "first-secret")],
+    ))
+    .await;
+    let res = reqwest::get(format!("{}{}", rotated, url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = mint(&rotated, &stored, json!({})).await;
+    let fresh: Value = res.json().await.unwrap();
+    let fresh_url = fresh["url"].as_str().unwrap();
+    assert!(fresh_url.contains("&kid=k2&"));
+    let res = reqwest::get(format!("{}{}", rotated, fresh_url))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Once the old key is retired, its URLs are refused.
+    let retired = common::spawn_gateway(signed_config(&dir, &[("k2", "second-secret")])).await;
+    let res = reqwest::get(format!("{}{}", retired, url)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.text().await.unwrap(), "Invalid download URL signature");
+    let res = reqwest::get(format!("{}{}", retired, fresh_url))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let unsigned = common::spawn_gateway(signed_config(&dir, &[])).await;
+    let res = mint(&unsigned, &stored, json!({})).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.text().await.unwrap(), "Signed URLs are not configured");
+}
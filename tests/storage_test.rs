@@ -0,0 +1,400 @@
+mod common;
+
+use aws_lc_rs::digest;
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode as AxumStatus, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::{Config, StorageBackend};
+use nijika_api::storage::Storage;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_pixel(20, 10, Rgb([200, 100, 50]));
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+/// Objects held by the S3 stand-in: key to (content type, body).
+type Bucket = Arc<Mutex<BTreeMap<String, (String, Bytes)>>>;
+
+/// A minimal S3-compatible server for the `results` bucket.
+///
+/// It rejects requests that are not signed with Signature Version 4 or
+/// whose payload hash does not match the body.
+async fn spawn_s3() -> (String, Bucket) {
+    fn signed(headers: &HeaderMap, body: &[u8]) -> bool {
+        let authorization = headers[header::AUTHORIZATION].to_str().unwrap();
+        let hash: String = digest::digest(&digest::SHA256, body)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        authorization.starts_with("AWS4-HMAC-SHA256 Credential=minio/")
+            && authorization.contains("SignedHeaders=")
+            && headers["x-amz-content-sha256"] == hash.as_str()
+    }
+
+    async fn object(
+        State(bucket): State<Bucket>,
+        Path((_, key)): Path<(String, String)>,
+        method: axum::http::Method,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        if !signed(&headers, &body) {
+            return AxumStatus::FORBIDDEN.into_response();
+        }
+        let mut objects = bucket.lock().unwrap();
+        match method.as_str() {
+            "PUT" => {
+                if headers.get(header::IF_NONE_MATCH).is_some() && objects.contains_key(&key) {
+                    return AxumStatus::PRECONDITION_FAILED.into_response();
+                }
+                let content_type = headers
+                    .get(header::CONTENT_TYPE)
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                objects.insert(key, (content_type, body));
+                AxumStatus::OK.into_response()
+            }
+            "DELETE" => {
+                objects.remove(&key);
+                AxumStatus::NO_CONTENT.into_response()
+            }
+            _ => {
+                let Some((content_type, data)) = objects.get(&key).cloned() else {
+                    return AxumStatus::NOT_FOUND.into_response();
+                };
+                let meta = [
+                    (header::CONTENT_TYPE, content_type),
+                    (header::ETAG, format!("\"{}\"", data.len())),
+                ];
+                if method == axum::http::Method::HEAD {
+                    return (meta, [(header::CONTENT_LENGTH, data.len().to_string())])
+                        .into_response();
+                }
+                let range = headers
+                    .get(header::RANGE)
+                    .and_then(|value| value.to_str().ok()?.strip_prefix("bytes="))
+                    .and_then(|range| range.split_once('-'))
+                    .map(|(start, end)| {
+                        (
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        )
+                    });
+                match range {
+                    Some((start, end)) => (
+                        AxumStatus::PARTIAL_CONTENT,
+                        meta,
+                        data.slice(start..end + 1),
+                    )
+                        .into_response(),
+                    None => (meta, data).into_response(),
+                }
+            }
+        }
+    }
+
+    async fn list(State(bucket): State<Bucket>, headers: HeaderMap) -> Response {
+        if !signed(&headers, b"") {
+            return AxumStatus::FORBIDDEN.into_response();
+        }
+        let contents: String = bucket
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|key| {
+                format!(
+                    "<Contents><Key>{}</Key><LastModified>2020-01-02T03:04:05.000Z</LastModified></Contents>",
+                    key
+                )
+            })
+            .collect();
+        format!(
+            "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+            contents
+        )
+        .into_response()
+    }
+
+    let bucket = Bucket::default();
+    let app = Router::new()
+        .route("/{bucket}", get(list))
+        .route(
+            "/{bucket}/{*key}",
+            get(object).head(object).put(object).delete(object),
+        )
+        .with_state(bucket.clone());
+    (common::spawn(app).await, bucket)
+}
+
+fn s3_config(endpoint: String) -> Config {
+    Config {
+        result_storage: StorageBackend::S3,
+        result_s3_endpoint: endpoint,
+        result_s3_bucket: "results".to_string(),
+        result_s3_access_key: "minio".to_string(),
+        result_s3_secret_key: "minio-secret".to_string(),
+        ..Config::default()
+    }
+}
+
+/// Runs a resize through `/pipeline?store=true` and returns the stored result.
+async fn store(gateway: &str) -> Value {
+    let url = common::spawn_image_source(photo()).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline?store=true", gateway))
+        .json(&json!({
+            "url": url,
+            "steps": [{"op": "resize", "width": 5}, {"op": "format", "format": "png"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let stored: Value = res.json().await.unwrap();
+    assert_eq!(stored["url"], location.as_str());
+    assert_eq!(stored["content_type"], "image/png");
+    stored
+}
+
+#[tokio::test]
+async fn test_local_results_support_etag_and_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let gateway = common::spawn_gateway(Config {
+        result_storage: StorageBackend::Local,
+        result_storage_dir: dir.path().to_str().unwrap().to_string(),
+        modal_removebg_url: common::spawn_half_removebg_worker().await,
+        webhook_secret: Some("s3cret".to_string()),
+        webhook_max_attempts: 1,
+        ..Config::default()
+    })
+    .await;
+
+    let stored = store(&gateway).await;
+    let url = format!("{}{}", gateway, stored["url"].as_str().unwrap());
+    let res = reqwest::get(&url).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["accept-ranges"], "bytes");
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let max_age: u64 = res.headers()["cache-control"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("private, max-age=")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((86390..=86400).contains(&max_age));
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+    let full = res.bytes().await.unwrap();
+    assert_eq!(full.len() as u64, stored["size"].as_u64().unwrap());
+    let output = image::load_from_memory(&full).unwrap();
+    assert_eq!((output.width(), output.height()), (5, 3));
+
+    let client = reqwest::Client::new();
+    let size = full.len();
+    for (range, expected) in [("bytes=0-7", 0..8), ("bytes=-4", size - 4..size)] {
+        let res = client
+            .get(&url)
+            .header("range", range)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers()["content-range"],
+            format!("bytes {}-{}/{}", expected.start, expected.end - 1, size).as_str()
+        );
+        assert_eq!(res.bytes().await.unwrap(), full.slice(expected));
+    }
+    let res = client
+        .get(&url)
+        .header("range", format!("bytes={}-", size))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    let res = client
+        .get(&url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    // Multipart results would lose their boundary, so only the ZIP form of
+    // `output=both` can be stored.
+    let both = json!({"url": common::spawn_image_source(photo()).await, "output": "both"});
+    let res = client
+        .post(format!("{}/removebg?store=true", gateway))
+        .json(&both)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Results of type multipart/mixed cannot be stored"
+    );
+    let res = client
+        .post(format!("{}/removebg?store=true", gateway))
+        .header("accept", "application/zip")
+        .json(&both)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let zipped: Value = res.json().await.unwrap();
+    assert!(zipped["key"].as_str().unwrap().ends_with(".zip"));
+
+    // Background jobs persist their result too.
+    let res = client
+        .post(format!("{}/pipeline", gateway))
+        .json(&json!({
+            "url": common::spawn_image_source(photo()).await,
+            "steps": [{"op": "resize", "width": 5}],
+            "callback_url": "http://127.0.0.1:9/hook",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let accepted: Value = res.json().await.unwrap();
+    let mut job = Value::Null;
+    for _ in 0..200 {
+        job = reqwest::get(format!(
+            "{}{}",
+            gateway,
+            accepted["status_url"].as_str().unwrap()
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        if job["deliveries"].as_array().is_some_and(|d| !d.is_empty()) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(job["result_url"].as_str().unwrap().starts_with("/results/"));
+    let res = reqwest::get(format!(
+        "{}/jobs/{}/result",
+        gateway,
+        job["id"].as_str().unwrap()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.url().path(), job["result_url"].as_str().unwrap());
+}
+
+#[tokio::test]
+async fn test_s3_backend_round_trip_and_sweep() {
+    let (endpoint, bucket) = spawn_s3().await;
+    let gateway = common::spawn_gateway(s3_config(endpoint.clone())).await;
+
+    let stored = store(&gateway).await;
+    let key = stored["url"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("/results/")
+        .unwrap()
+        .to_string();
+    {
+        let objects = bucket.lock().unwrap();
+        let (content_type, body) = &objects[&format!("results/{}", key)];
+        assert_eq!(content_type, "image/png");
+        assert_eq!(body.len() as u64, stored["size"].as_u64().unwrap());
+    }
+
+    let url = format!("{}/results/{}", gateway, key);
+    let res = reqwest::Client::new()
+        .get(&url)
+        .header("range", "bytes=1-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(res.bytes().await.unwrap().as_ref(), b"PNG");
+
+    // Used single-use nonces are recorded with conditional writes.
+    let storage = Storage::from_config(&s3_config(endpoint), reqwest::Client::new()).unwrap();
+    let nonce = "c".repeat(32);
+    assert!(storage.mark_used(&nonce).await.unwrap());
+    assert!(!storage.mark_used(&nonce).await.unwrap());
+
+    // Objects stored before the TTL are deleted by the sweeper.
+    assert_eq!(storage.sweep().await.unwrap(), 2);
+    assert!(bucket.lock().unwrap().is_empty());
+    let res = reqwest::get(&url).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_expired_and_unknown_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        result_storage: StorageBackend::Local,
+        result_storage_dir: dir.path().to_str().unwrap().to_string(),
+        result_ttl_secs: 0,
+        ..Config::default()
+    };
+    let gateway = common::spawn_gateway(config.clone()).await;
+
+    let stored = store(&gateway).await;
+    let res = reqwest::get(format!("{}{}", gateway, stored["url"].as_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let storage = Storage::from_config(&config, reqwest::Client::new()).unwrap();
+    assert_eq!(storage.sweep().await.unwrap(), 1);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    // Files left by interrupted writes are deleted once they are stale.
+    let stale = dir.path().join(format!(".{}.png.partial", "a".repeat(32)));
+    let fresh = dir.path().join(format!(".{}.png.partial", "b".repeat(32)));
+    std::fs::write(&stale, b"half").unwrap();
+    std::fs::write(&fresh, b"half").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&stale)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(7200))
+        .unwrap();
+    assert_eq!(storage.sweep().await.unwrap(), 1);
+    assert!(!stale.exists());
+    assert!(fresh.exists());
+
+    for key in ["..%2F..%2Fetc%2Fpasswd", "0123.png", "unknown"] {
+        let res = reqwest::get(format!("{}/results/{}", gateway, key))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", key);
+    }
+
+    let gateway = common::spawn_gateway(Config::default()).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline?store=true", gateway))
+        .json(&json!({"url": "http://example.com/a.png", "steps": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Result storage is not configured"
+    );
+}
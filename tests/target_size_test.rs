@@ -0,0 +1,128 @@
+mod common;
+
+use image::ImageFormat;
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+async fn gateway() -> String {
+    let worker = common::spawn_upscale_worker().await;
+    common::spawn_gateway(Config {
+        modal_upscaler_url: worker,
+        ..Config::default()
+    })
+    .await
+}
+
+async fn upscale(gateway: &str, image: Vec<u8>, fields: &[(&str, &str)]) -> reqwest::Response {
+    let mut form = Form::new().part("image", Part::bytes(image).file_name("image.png"));
+    for (name, value) in fields {
+        form = form.text(name.to_string(), value.to_string());
+    }
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn output_size(gateway: &str, fields: &[(&str, &str)]) -> (u32, u32) {
+    let res = upscale(gateway, common::png(40, 30), fields).await;
+    assert_eq!(res.status(), StatusCode::OK, "{:?}", fields);
+    let image = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    (image.width(), image.height())
+}
+
+#[tokio::test]
+async fn test_target_dimensions_and_fit_modes() {
+    let gateway = gateway().await;
+
+    assert_eq!(output_size(&gateway, &[("width", "100")]).await, (100, 75));
+    assert_eq!(output_size(&gateway, &[("height", "45")]).await, (60, 45));
+    assert_eq!(output_size(&gateway, &[("scale", "1.5")]).await, (60, 45));
+    assert_eq!(output_size(&gateway, &[("scale", "2")]).await, (80, 60));
+    assert_eq!(
+        output_size(&gateway, &[("width", "100"), ("height", "100")]).await,
+        (100, 75)
+    );
+    assert_eq!(
+        output_size(
+            &gateway,
+            &[("width", "100"), ("height", "100"), ("fit", "cover")]
+        )
+        .await,
+        (100, 100)
+    );
+    assert_eq!(
+        output_size(
+            &gateway,
+            &[("width", "90"), ("height", "50"), ("fit", "exact")]
+        )
+        .await,
+        (90, 50)
+    );
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&serde_json::json!({
+            "url": common::spawn_image_source(common::png(40, 30)).await,
+            "width": 64,
+            "height": 64,
+            "fit": "contain",
+            "format": "png"
+        }))
+        .send()
+        .await
+        .unwrap();
+    let image = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 48));
+}
+
+#[tokio::test]
+async fn test_target_dimensions_keep_alpha() {
+    let gateway = gateway().await;
+
+    let input = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(40, 30, |x, _| {
+        image::Rgba([10, 20, 30, if x < 20 { 0 } else { 255 }])
+    }));
+    let res = upscale(
+        &gateway,
+        common::encode(&input, ImageFormat::Png),
+        &[("width", "100")],
+    )
+    .await;
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(output.dimensions(), (100, 75));
+    assert_eq!(output.get_pixel(5, 40)[3], 0);
+    assert_eq!(output.get_pixel(95, 40)[3], 255);
+}
+
+#[tokio::test]
+async fn test_invalid_size_parameters() {
+    let gateway = gateway().await;
+    let image = || common::png(40, 30);
+
+    let res = upscale(&gateway, image(), &[("scale", "2"), ("width", "100")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = upscale(&gateway, image(), &[("width", "100"), ("fit", "cover")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = upscale(&gateway, image(), &[("scale", "7")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = upscale(&gateway, image(), &[("scale", "abc")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.text().await.unwrap(), "Scale must be between 1 and 6");
+
+    let res = upscale(&gateway, image(), &[("width", "1000")]).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Reaching the requested size from 40x30 needs a scale of 25.00x, above the maximum of 6x"
+    );
+}
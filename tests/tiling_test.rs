@@ -0,0 +1,161 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::header,
+    response::IntoResponse,
+    routing::post,
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, imageops::FilterType};
+use nijika_api::config::Config;
+use nijika_api::imaging::tiling;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn tiled_config(worker: String) -> Config {
+    Config {
+        modal_upscaler_url: worker,
+        upscale_tile_threshold_megapixels: 0.01,
+        upscale_tile_size: 128,
+        upscale_tile_overlap: 16,
+        upscale_tile_concurrency: 2,
+        ..Config::default()
+    }
+}
+
+fn gradient(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+    }))
+}
+
+async fn upscale(gateway: &str, image: &DynamicImage) -> reqwest::Response {
+    let form = Form::new()
+        .part(
+            "image",
+            Part::bytes(common::encode(image, ImageFormat::Png)).file_name("a.png"),
+        )
+        .text("scale", "2")
+        .text("format", "png");
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[test]
+fn test_tiles_cover_the_image() {
+    let tiles = tiling::split(300, 200, 128, 16);
+    assert_eq!(tiles.len(), 6);
+    for y in 0..200 {
+        for x in 0..300 {
+            assert!(
+                tiles
+                    .iter()
+                    .any(|t| x >= t.x && x < t.x + t.width && y >= t.y && y < t.y + t.height)
+            );
+        }
+    }
+    assert!(tiles.iter().all(|t| t.width == 128 && t.height == 128));
+    assert_eq!(tiling::split(100, 80, 128, 16).len(), 1);
+}
+
+#[tokio::test]
+async fn test_large_input_is_upscaled_in_tiles() {
+    let worker = common::spawn_upscale_worker().await;
+    let gateway = common::spawn_gateway(tiled_config(worker)).await;
+
+    let input = gradient(300, 200);
+    let res = upscale(&gateway, &input).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-upscale-tiles"], "6");
+    let output = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_rgb8();
+    assert_eq!(output.dimensions(), (600, 400));
+
+    // The stitched result matches upscaling the whole image at once.
+    let reference = input.resize_exact(600, 400, FilterType::Triangle).to_rgb8();
+    let mut total = 0u64;
+    for (a, b) in output.pixels().zip(reference.pixels()) {
+        for c in 0..3 {
+            let diff = (i32::from(a[c]) - i32::from(b[c])).unsigned_abs();
+            assert!(diff < 32, "seam visible: {} vs {}", a[c], b[c]);
+            total += u64::from(diff);
+        }
+    }
+    assert!(total as f64 / (600.0 * 400.0 * 3.0) < 3.0);
+
+    // Small inputs are sent whole.
+    let res = upscale(&gateway, &gradient(100, 80)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-upscale-tiles").is_none());
+
+    // Faces would be cut apart by the tiles.
+    let form = Form::new()
+        .part(
+            "image",
+            Part::bytes(common::encode(&input, ImageFormat::Png)).file_name("a.png"),
+        )
+        .text("face_enhance", "true");
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "face_enhance is not supported for images large enough to be upscaled in tiles"
+    );
+
+    // With the defaults, inputs small enough for a 4x upscale are tiled too.
+    let config = Config::default();
+    assert!(config.upscale_tile_threshold_megapixels * 16.0 < config.max_upscale_output_megapixels);
+}
+
+#[tokio::test]
+async fn test_tile_concurrency_is_limited() {
+    #[derive(Clone, Default)]
+    struct Counter {
+        current: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    async fn slow_echo(State(counter): State<Counter>, body: Bytes) -> impl IntoResponse {
+        let now = counter.current.fetch_add(1, Ordering::SeqCst) + 1;
+        counter.peak.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        counter.current.fetch_sub(1, Ordering::SeqCst);
+        let input = image::load_from_memory(&body).unwrap();
+        let output = input.resize_exact(input.width() * 2, input.height() * 2, FilterType::Nearest);
+        (
+            [(header::CONTENT_TYPE, "image/png")],
+            common::encode(&output, ImageFormat::Png),
+        )
+    }
+
+    let counter = Counter::default();
+    let worker = common::spawn(
+        Router::new()
+            .route("/", post(slow_echo))
+            .layer(DefaultBodyLimit::disable())
+            .with_state(counter.clone()),
+    )
+    .await;
+    let gateway = common::spawn_gateway(tiled_config(worker)).await;
+
+    let input = gradient(300, 200);
+    let (a, b) = tokio::join!(upscale(&gateway, &input), upscale(&gateway, &input));
+    assert_eq!(a.status(), StatusCode::OK);
+    assert_eq!(b.status(), StatusCode::OK);
+    assert_eq!(counter.peak.load(Ordering::SeqCst), 2);
+}
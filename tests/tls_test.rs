@@ -0,0 +1,183 @@
+use axum::{Extension, Router, extract::ConnectInfo, middleware, routing::get};
+use nijika_api::config::{Config, TlsVersion};
+use nijika_api::server::{PeerInfo, forward_peer_info};
+use nijika_api::tls::{ClientIdentity, ReloadableTlsConfig, TlsListener};
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+struct Pki {
+    dir: PathBuf,
+    ca_pem: String,
+    ca_issuer: Issuer<'static, KeyPair>,
+}
+
+impl Pki {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("nijika-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Nijika Test CA");
+        let key = KeyPair::generate().unwrap();
+        let ca_pem = params.self_signed(&key).unwrap().pem();
+
+        Self {
+            dir,
+            ca_pem,
+            ca_issuer: Issuer::new(params, key),
+        }
+    }
+
+    /// Issues a certificate signed by the test CA and returns (cert, key) PEM.
+    fn issue(&self, common_name: &str, sans: &[&str]) -> (String, String) {
+        let sans: Vec<String> = sans.iter().map(|s| s.to_string()).collect();
+        let mut params = CertificateParams::new(sans).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.ca_issuer).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.dir.join(file);
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+}
+
+async fn whoami(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Option<Extension<ClientIdentity>>,
+) -> String {
+    assert!(addr.ip().is_loopback());
+    identity
+        .and_then(|Extension(id)| id.tenant)
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+async fn spawn_tls_server(config: Arc<Config>) -> SocketAddr {
+    let tls = ReloadableTlsConfig::load(&config).unwrap();
+    tls.spawn_reloader(config.clone());
+
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let listener = TlsListener::new(tcp, tls, Arc::new(config.tls_client_tenants.clone())).unwrap();
+
+    let app = Router::new()
+        .route("/whoami", get(whoami))
+        .layer(middleware::from_fn(forward_peer_info));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<PeerInfo>(),
+        )
+        .await
+        .unwrap()
+    });
+
+    addr
+}
+
+fn client(pki: &Pki, identity: Option<(String, String)>) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .tls_certs_only([reqwest::Certificate::from_pem(pki.ca_pem.as_bytes()).unwrap()])
+        .tls_info(true);
+    if let Some((cert, key)) = identity {
+        builder = builder
+            .identity(reqwest::Identity::from_pem(format!("{}{}", cert, key).as_bytes()).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+#[tokio::test]
+async fn test_mutual_tls_maps_subject_to_tenant() {
+    let pki = Pki::new("mtls");
+    let (cert, key) = pki.issue("localhost", &["localhost"]);
+    let config = Arc::new(Config {
+        tls_cert_path: Some(pki.write("server.pem", &cert)),
+        tls_key_path: Some(pki.write("server.key", &key)),
+        tls_client_ca_path: Some(pki.write("ca.pem", &pki.ca_pem)),
+        tls_client_auth_required: false,
+        tls_client_tenants: HashMap::from([("acme-backend".to_string(), "acme".to_string())]),
+        ..Config::default()
+    });
+    let addr = spawn_tls_server(config).await;
+    let url = format!("https://localhost:{}/whoami", addr.port());
+
+    let res = client(&pki, Some(pki.issue("acme-backend", &[])))
+        .get(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.version(), reqwest::Version::HTTP_2);
+    assert_eq!(res.text().await.unwrap(), "acme");
+
+    let res = client(&pki, None).get(&url).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "anonymous");
+}
+
+#[tokio::test]
+async fn test_required_client_auth_rejects_anonymous_clients() {
+    let pki = Pki::new("required");
+    let (cert, key) = pki.issue("localhost", &["localhost"]);
+    let config = Arc::new(Config {
+        tls_cert_path: Some(pki.write("server.pem", &cert)),
+        tls_key_path: Some(pki.write("server.key", &key)),
+        tls_client_ca_path: Some(pki.write("ca.pem", &pki.ca_pem)),
+        tls_min_version: TlsVersion::Tls13,
+        ..Config::default()
+    });
+    let addr = spawn_tls_server(config).await;
+    let url = format!("https://localhost:{}/whoami", addr.port());
+
+    assert!(client(&pki, None).get(&url).send().await.is_err());
+
+    let res = client(&pki, Some(pki.issue("unmapped", &[])))
+        .get(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), "anonymous");
+}
+
+#[tokio::test]
+async fn test_certificate_is_reloaded_when_files_change() {
+    let pki = Pki::new("reload");
+    let (cert, key) = pki.issue("localhost", &["localhost"]);
+    let config = Arc::new(Config {
+        tls_cert_path: Some(pki.write("server.pem", &cert)),
+        tls_key_path: Some(pki.write("server.key", &key)),
+        tls_reload_interval_secs: 1,
+        ..Config::default()
+    });
+    let addr = spawn_tls_server(config).await;
+    let url = format!("https://localhost:{}/whoami", addr.port());
+
+    let peer_cert = |res: &reqwest::Response| {
+        res.extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(<[u8]>::to_vec)
+            .unwrap()
+    };
+
+    let before = peer_cert(&client(&pki, None).get(&url).send().await.unwrap());
+
+    let (cert, key) = pki.issue("localhost", &["localhost"]);
+    pki.write("server.pem", &cert);
+    pki.write("server.key", &key);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let after = peer_cert(&client(&pki, None).get(&url).send().await.unwrap());
+    assert_ne!(before, after);
+}
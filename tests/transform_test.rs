@@ -0,0 +1,155 @@
+mod common;
+
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use nijika_api::handlers::transform::signed_path;
+use reqwest::StatusCode;
+
+/// Red on the left, blue on the right.
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_fn(40, 20, |x, _| {
+        if x < 20 {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([0, 0, 255])
+        }
+    });
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+async fn gateway(keys: &[&str]) -> String {
+    common::spawn_gateway(Config {
+        modal_removebg_url: common::spawn_half_removebg_worker().await,
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        transform_signing_keys: keys.iter().map(|key| key.to_string()).collect(),
+        ..Config::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_signed_transformations_are_cacheable() {
+    let gateway = gateway(&["t0p-secret"]).await;
+    let source = common::spawn_image_source(photo()).await;
+    let client = reqwest::Client::new();
+
+    let path = signed_path("t0p-secret", "upscale,scale:2,format:webp", &source);
+    let res = client
+        .get(format!("{}{}", gateway, path))
+        .header("accept", "image/jpeg")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/webp");
+    assert_eq!(
+        res.headers()["cache-control"],
+        "public, max-age=31536000, immutable"
+    );
+    assert!(res.headers().get("vary").is_none());
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+    let output = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((output.width(), output.height()), (80, 40));
+
+    let res = client
+        .get(format!("{}{}", gateway, path))
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers()["etag"], etag.as_str());
+    assert!(res.bytes().await.unwrap().is_empty());
+
+    // Revalidation is answered from the signature alone, without the model.
+    let offline = common::spawn_gateway(Config {
+        modal_upscaler_url: "http://127.0.0.1:9".to_string(),
+        transform_signing_keys: vec!["t0p-secret".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let res = client
+        .get(format!("{}{}", offline, path))
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(
+        res.headers()["cache-control"],
+        "public, max-age=31536000, immutable"
+    );
+
+    let path = signed_path("t0p-secret", "removebg,output:mask", &source);
+    let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    let mask = image::load_from_memory(&res.bytes().await.unwrap())
+        .unwrap()
+        .to_luma8();
+    assert_eq!(mask.get_pixel(5, 5)[0], 255);
+    assert_eq!(mask.get_pixel(35, 5)[0], 0);
+}
+
+#[tokio::test]
+async fn test_signatures_are_checked() {
+    let gateway = gateway(&["new-secret", "old-secret"]).await;
+    let source = common::spawn_image_source(photo()).await;
+
+    // Every listed key is accepted, so keys can be rotated.
+    for secret in ["new-secret", "old-secret"] {
+        let path = signed_path(secret, "upscale,scale:2", &source);
+        let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK, "{}", secret);
+    }
+
+    let path = signed_path("new-secret", "upscale,scale:2", &source);
+    let tampered = path.replace("scale:2", "scale:4");
+    let unknown = signed_path("guessed", "upscale,scale:2", &source);
+    let garbage = path.replacen("/t/", "/t/!!", 1);
+    for path in [tampered, unknown, garbage] {
+        let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", path);
+        assert_eq!(res.headers()["cache-control"], "no-store");
+        assert_eq!(
+            res.text().await.unwrap(),
+            "Invalid transformation URL signature"
+        );
+    }
+
+    let disabled = common::spawn_gateway(Config::default()).await;
+    let res = reqwest::get(format!("{}{}", disabled, path)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_invalid_options_and_failures_are_not_cached() {
+    let gateway = gateway(&["t0p-secret"]).await;
+    let source = common::spawn_image_source(photo()).await;
+
+    for (options, message) in [
+        (
+            "resize,width:10",
+            "Options must start with removebg or upscale, got 'resize'",
+        ),
+        ("upscale,scale", "Option 'scale' must look like name:value"),
+        ("upscale,url:example.png", "Option 'url' cannot be set here"),
+    ] {
+        let path = signed_path("t0p-secret", options, &source);
+        let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", options);
+        assert_eq!(res.text().await.unwrap(), message);
+    }
+
+    // Errors from the endpoint itself are passed on, uncached.
+    let path = signed_path("t0p-secret", "upscale,denoise_strength:2", &source);
+    let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.headers()["cache-control"], "no-store");
+    assert!(res.headers().get("etag").is_none());
+
+    let path = signed_path("t0p-secret", "removebg", "http://127.0.0.1:9/missing.png");
+    let res = reqwest::get(format!("{}{}", gateway, path)).await.unwrap();
+    assert!(!res.status().is_success());
+    assert_eq!(res.headers()["cache-control"], "no-store");
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use nijika_api::config::Config;
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+
+fn image_form(image: Vec<u8>) -> Form {
+    Form::new().part("image", Part::bytes(image).file_name("image.png"))
+}
+
+#[tokio::test]
+async fn test_declared_body_over_route_limit_is_rejected_early() {
+    let gateway = common::spawn_gateway(Config {
+        max_body_bytes_upscale: 1024,
+        ..Config::default()
+    })
+    .await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(image_form(vec![0u8; 4096]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .json(&serde_json::json!({ "url": "x".repeat(30 * 1024 * 1024) }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn test_field_limits_return_413() {
+    let gateway = common::spawn_gateway(Config {
+        max_image_field_bytes: 1024,
+        max_text_field_bytes: 16,
+        ..Config::default()
+    })
+    .await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(image_form(vec![0u8; 2048]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(res.text().await.unwrap().contains("'image'"));
+
+    let form = image_form(vec![0u8; 16]).text("model", "x".repeat(64));
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(res.text().await.unwrap().contains("'model'"));
+}
+
+#[tokio::test]
+async fn test_spooled_upload_is_streamed_to_worker_intact() {
+    let worker = common::spawn_echo_worker().await;
+    let gateway = common::spawn_gateway(Config {
+        modal_removebg_url: worker,
+        upload_spool_threshold_bytes: 1024,
+        ..Config::default()
+    })
+    .await;
+
+    let image = common::png(512, 512);
+    assert!(image.len() > 1024);
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg", gateway))
+        .multipart(image_form(image.clone()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.bytes().await.unwrap().as_ref(), image.as_slice());
+}
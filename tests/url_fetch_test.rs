@@ -0,0 +1,99 @@
+mod common;
+
+use axum::{
+    Router,
+    http::{StatusCode as AxumStatus, header},
+    response::IntoResponse,
+    routing::get,
+};
+use nijika_api::config::Config;
+use nijika_api::upload::fetch_client;
+use reqwest::StatusCode;
+use serde_json::json;
+
+async fn upscale(gateway: &str, url: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(&json!({"url": url}))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_redirects_and_upstream_statuses_are_not_passed_on() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+    let app =
+        Router::new()
+            .route(
+                "/moved",
+                get(move || async move {
+                    (AxumStatus::FOUND, [(header::LOCATION, image)]).into_response()
+                }),
+            )
+            .route("/teapot", get(|| async { AxumStatus::IM_A_TEAPOT }));
+    let source = common::spawn(app).await;
+    let gateway = common::spawn_gateway(Config {
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        ..Config::default()
+    })
+    .await;
+
+    for path in ["moved", "teapot"] {
+        let res = upscale(&gateway, &format!("{}/{}", source, path)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", path);
+        assert_eq!(
+            res.text().await.unwrap(),
+            "Failed to fetch image: the source returned an error"
+        );
+    }
+
+    let res = upscale(&gateway, "http://127.0.0.1:9/closed.png").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Failed to fetch image: the source could not be reached"
+    );
+}
+
+#[tokio::test]
+async fn test_private_addresses_are_refused() {
+    let gateway = common::spawn_guarded_gateway(Config {
+        modal_upscaler_url: common::spawn_upscale_worker().await,
+        ..Config::default()
+    })
+    .await;
+
+    for url in [
+        "http://169.254.169.254/latest/meta-data/",
+        "http://127.0.0.1:8081/healthz",
+        "http://[::ffff:10.0.0.1]/image.png",
+        "http://[64:ff9b::a00:1]/image.png",
+        "http://localhost/image.png",
+    ] {
+        let res = upscale(&gateway, url).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", url);
+        assert!(
+            res.text()
+                .await
+                .unwrap()
+                .contains("private or local address"),
+            "{}",
+            url
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_client_connects_only_to_checked_addresses() {
+    let image = common::spawn_image_source(common::png(8, 8)).await;
+    let by_name = image.replace("127.0.0.1", "localhost");
+
+    // The check is made when connecting, so a name resolving to a private
+    // address is refused even if it resolved elsewhere when first checked.
+    let err = fetch_client(true).get(&by_name).send().await.unwrap_err();
+    assert!(err.is_connect(), "{:?}", err);
+
+    let res = fetch_client(false).get(&by_name).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
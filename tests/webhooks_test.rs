@@ -0,0 +1,262 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode as AxumStatus},
+    routing::post,
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use nijika_api::config::Config;
+use nijika_api::upload::check_url;
+use nijika_api::webhooks::{SIGNATURE_HEADER, sign};
+use reqwest::StatusCode;
+use reqwest::multipart::{Form, Part};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// A webhook receiver forwarding every delivery to the returned channel.
+///
+/// The first `failures` deliveries are answered with `500`.
+async fn spawn_receiver(failures: usize) -> (String, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+    type Receiver = (Arc<AtomicUsize>, mpsc::UnboundedSender<(HeaderMap, Bytes)>);
+
+    async fn receive(
+        State((calls, events)): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> AxumStatus {
+        let _ = events.send((headers, body));
+        let failing = calls
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            AxumStatus::INTERNAL_SERVER_ERROR
+        } else {
+            AxumStatus::NO_CONTENT
+        }
+    }
+
+    let (sender, events) = mpsc::unbounded_channel();
+    let state = (Arc::new(AtomicUsize::new(failures)), sender);
+    let app = Router::new()
+        .route("/hook", post(receive))
+        .with_state(state);
+    (format!("{}/hook", common::spawn(app).await), events)
+}
+
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_pixel(20, 10, Rgb([200, 100, 50]));
+    common::encode(&DynamicImage::ImageRgb8(image), ImageFormat::Png)
+}
+
+fn config() -> Config {
+    Config {
+        webhook_secret: Some("s3cret".to_string()),
+        webhook_retry_base_ms: 10,
+        ..Config::default()
+    }
+}
+
+/// Polls `GET /jobs/{id}` until `done` holds for the job.
+async fn wait_for(gateway: &str, id: &str, done: impl Fn(&Value) -> bool) -> Value {
+    for _ in 0..200 {
+        let job: Value = reqwest::get(format!("{}/jobs/{}", gateway, id))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if done(&job) {
+            return job;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("job {} did not reach the expected state", id);
+}
+
+#[tokio::test]
+async fn test_signed_event_is_delivered() {
+    let gateway = common::spawn_gateway(config()).await;
+    let (hook, mut events) = spawn_receiver(0).await;
+    let url = common::spawn_image_source(photo()).await;
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/pipeline", gateway))
+        .json(&json!({
+            "url": url,
+            "steps": [{"op": "resize", "width": 5}, {"op": "format", "format": "png"}],
+            "callback_url": hook,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let accepted: Value = res.json().await.unwrap();
+    assert_eq!(accepted["status"], "pending");
+    let id = accepted["job_id"].as_str().unwrap();
+    assert_eq!(location, format!("/jobs/{}", id));
+
+    let (headers, body) = tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(headers["x-nijika-event"], "job.succeeded");
+    let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+    let timestamp: u64 = signature
+        .strip_prefix("t=")
+        .and_then(|rest| rest.split(',').next())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(signature, sign("s3cret", timestamp, &body));
+    assert_ne!(signature, sign("other", timestamp, &body));
+
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event["job_id"], id);
+    assert_eq!(event["endpoint"], "/pipeline");
+    assert_eq!(event["status"], "succeeded");
+    assert!(event["error"].is_null());
+    assert_eq!(event["output"]["content_type"], "image/png");
+    assert!(
+        event["timing"]["completed_at"].as_u64().unwrap()
+            >= event["timing"]["created_at"].as_u64().unwrap()
+    );
+
+    let res = reqwest::get(format!(
+        "{}{}",
+        gateway,
+        event["output"]["url"].as_str().unwrap()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let output = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((output.width(), output.height()), (5, 3));
+
+    let job = wait_for(&gateway, id, |job| {
+        job["deliveries"][0]["delivered"] == true
+    })
+    .await;
+    assert_eq!(job["http_status"], 200);
+    assert_eq!(job["deliveries"][0]["http_status"], 204);
+}
+
+#[tokio::test]
+async fn test_failed_deliveries_are_retried_and_redelivered() {
+    let gateway = common::spawn_gateway(Config {
+        webhook_max_attempts: 2,
+        ..config()
+    })
+    .await;
+    let (hook, mut events) = spawn_receiver(2).await;
+
+    // Multipart requests pass the callback URL as a query parameter.
+    let form = Form::new().part(
+        "image",
+        Part::bytes(b"not an image".to_vec()).file_name("a.png"),
+    );
+    let res = reqwest::Client::new()
+        .post(format!("{}/removebg?callback_url={}", gateway, hook))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let accepted: Value = res.json().await.unwrap();
+    let id = accepted["job_id"].as_str().unwrap();
+
+    let job = wait_for(&gateway, id, |job| {
+        job["deliveries"].as_array().is_some_and(|d| d.len() == 2)
+    })
+    .await;
+    assert_eq!(job["status"], "failed");
+    assert_eq!(job["http_status"], 422);
+    assert!(!job["error"].as_str().unwrap().is_empty());
+    for (index, attempt) in job["deliveries"].as_array().unwrap().iter().enumerate() {
+        assert_eq!(attempt["attempt"], index + 1);
+        assert_eq!(attempt["http_status"], 500);
+        assert_eq!(attempt["delivered"], false);
+    }
+    let (_, body) = events.recv().await.unwrap();
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event["event"], "job.failed");
+    assert_eq!(event["error"]["code"], 422);
+    assert!(event["output"].is_null());
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/jobs/{}/redeliver", gateway, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let attempt: Value = res.json().await.unwrap();
+    assert_eq!(attempt["attempt"], 3);
+    assert_eq!(attempt["delivered"], true);
+    assert_eq!(attempt["manual"], true);
+
+    let res = reqwest::get(format!("{}/jobs/{}/result", gateway, id))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_callback_urls_are_checked() {
+    // Private networks are denied by default.
+    assert!(Config::default().fetch_deny_private_networks);
+    let gateway = common::spawn_guarded_gateway(config()).await;
+
+    for callback_url in [
+        "http://127.0.0.1:9/hook",
+        "http://169.254.169.254/latest",
+        "http://[::ffff:10.0.0.1]/hook",
+        "http://localhost:9/hook",
+        "ftp://example.com/hook",
+    ] {
+        let res = reqwest::Client::new()
+            .post(format!("{}/upscale", gateway))
+            .json(&json!({"url": "http://example.com/a.png", "callback_url": callback_url}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", callback_url);
+        assert!(
+            res.text()
+                .await
+                .unwrap()
+                .starts_with("Invalid callback URL: "),
+            "{}",
+            callback_url
+        );
+    }
+    assert!(check_url("http://[fd00::1]/hook", true).await.is_err());
+    assert!(check_url("http://[fd00::1]/hook", false).await.is_ok());
+    assert!(check_url("https://8.8.8.8/hook", true).await.is_ok());
+
+    // Without a webhook secret, callbacks are refused.
+    let gateway = common::spawn_gateway(Config::default()).await;
+    let res = reqwest::Client::new()
+        .post(format!("{}/upscale", gateway))
+        .json(
+            &json!({"url": "http://example.com/a.png", "callback_url": "http://example.com/hook"}),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Callbacks are not enabled for this client"
+    );
+
+    let res = reqwest::get(format!("{}/jobs/unknown", gateway))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
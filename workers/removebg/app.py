@@ -119,7 +119,8 @@ class Model:
         """
         FastAPI endpoint to remove the background from an image.
         Accepts image URL in JSON or binary image data in request body.
-        Returns a PNG image with a transparent background.
+        Returns a PNG image with a transparent background, or only the
+        grayscale mask when the X-Output header (or JSON "output") is "mask".
         """
         from fastapi import Response, HTTPException
         from PIL import Image
@@ -127,7 +128,8 @@ class Model:
         import io
 
         content_type = request.headers.get("content-type", "")
-        
+        output = request.headers.get("X-Output", "cutout")
+
         try:
             if "application/json" in content_type:
                 body = await request.json()
@@ -135,6 +137,7 @@ class Model:
                     raise HTTPException(status_code=400, detail="JSON body must contain 'url' field")
                 
                 image_url = body["url"]
+                output = body.get("output", output)
                 print(f"Fetching image from URL: {image_url}")
                 async with httpx.AsyncClient() as client:
                     resp = await client.get(image_url, follow_redirects=True)
@@ -160,11 +163,13 @@ class Model:
             
             pred_pil = self.transforms.ToPILImage()(pred)
             mask = pred_pil.resize(original_size)
-            
-            image.putalpha(mask)
-            
+
             output_buffer = io.BytesIO()
-            image.save(output_buffer, format="PNG")
+            if output == "mask":
+                mask.convert("L").save(output_buffer, format="PNG")
+            else:
+                image.putalpha(mask)
+                image.save(output_buffer, format="PNG")
             output_buffer.seek(0)
             
             return Response(content=output_buffer.getvalue(), media_type="image/png")
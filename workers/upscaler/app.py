@@ -212,7 +212,7 @@ class Upscaler:
         """
         FastAPI endpoint to upscale and restore images.
         Supports various models, custom scales, and face enhancement.
-        Accepts parameters via JSON or X-Scale/X-Model/X-Face-Enhance headers for raw body.
+        Accepts parameters via JSON or X-Scale/X-Model/X-Face-Enhance/X-Denoise-Strength headers for raw body.
         """
         import io
         import cv2
@@ -239,6 +239,7 @@ class Upscaler:
                 model_name = body.get("model", model_name)
                 face_enhance = body.get("face_enhance", face_enhance)
                 outscale = body.get("scale", outscale)
+                denoise_strength = float(body.get("denoise_strength", denoise_strength))
                 
                 print(f"Fetching image from URL: {image_url}")
                 async with httpx.AsyncClient() as client:
@@ -250,6 +251,10 @@ class Upscaler:
                 model_name = request.headers.get("X-Model", model_name)
                 face_enhance = request.headers.get("X-Face-Enhance", "false").lower() == "true"
                 outscale = int(request.headers.get("X-Scale", str(outscale)))
+                denoise_strength = float(request.headers.get("X-Denoise-Strength", str(denoise_strength)))
+
+            if not 0 <= denoise_strength <= 1:
+                raise HTTPException(status_code=400, detail="denoise_strength must be between 0 and 1")
 
             if not image_bytes:
                 raise HTTPException(status_code=400, detail="Empty image data")